@@ -0,0 +1,134 @@
+//! Authentication against the registry server. The openhabx cloud uses the OAuth device flow,
+//! self-hosted registries use a static API key or a client certificate (mutual TLS).
+//! The method is configured with `auth` in the configuration file, for the configured `registry-url`.
+use crate::config::ConfigFile;
+use crate::error_codes;
+use crate::login::{self, UserSession};
+use log::error;
+use std::path::{Path, PathBuf};
+
+pub(crate) const AUTH_METHODS: [&str; 3] = ["oauth-device", "static-token", "mutual-tls"];
+
+/// The static token of self-hosted registries. Takes precedence over `api-key-file`.
+pub(crate) const API_KEY_ENV: &str = "OHX_API_KEY";
+
+pub(crate) trait Authenticator {
+    fn name(&self) -> &'static str;
+    /// Extra http client configuration, like the client certificate
+    fn configure(&self, builder: reqwest::ClientBuilder) -> Option<reqwest::ClientBuilder> {
+        Some(builder)
+    }
+    /// A session for the registry requests. The reason is logged if there is none.
+    /// The QR code of an interactive login is stored in `qr_code_dir`, if given.
+    fn login(&self, client: &reqwest::Client, qr_code_dir: Option<&Path>, interactive: bool) -> Option<UserSession>;
+}
+
+/// The openhabx.com OAuth device authorization flow. The session is stored and refreshed.
+pub(crate) struct OAuthDevice;
+
+impl Authenticator for OAuthDevice {
+    fn name(&self) -> &'static str {
+        "oauth-device"
+    }
+
+    fn login(&self, client: &reqwest::Client, qr_code_dir: Option<&Path>, interactive: bool) -> Option<UserSession> {
+        login::perform_login(client, qr_code_dir, interactive)
+    }
+}
+
+/// A session without user information, for requests that are authorized by other means
+fn anonymous_session(access_token: String) -> UserSession {
+    UserSession {
+        refresh_token: None,
+        access_token,
+        access_token_expires: i64::MAX,
+        user_id: String::new(),
+        user_email: String::new(),
+        user_display_name: String::new(),
+    }
+}
+
+/// A fixed API key, sent as bearer token
+pub(crate) struct StaticToken {
+    api_key_file: Option<PathBuf>,
+}
+
+impl Authenticator for StaticToken {
+    fn name(&self) -> &'static str {
+        "static-token"
+    }
+
+    fn login(&self, _client: &reqwest::Client, _qr_code_dir: Option<&Path>, _interactive: bool) -> Option<UserSession> {
+        if let Ok(api_key) = std::env::var(API_KEY_ENV) {
+            return Some(anonymous_session(api_key));
+        }
+        let api_key_file = match &self.api_key_file {
+            Some(v) => v,
+            None => {
+                error!("{} No API key. Set {} or api-key-file in the configuration file.", error_codes::NOT_LOGGED_IN, API_KEY_ENV);
+                return None;
+            }
+        };
+        match std::fs::read_to_string(api_key_file) {
+            Ok(api_key) => Some(anonymous_session(api_key.trim().to_owned())),
+            Err(e) => {
+                error!("{} Failed to read the API key file {}: {:?}", error_codes::FILE_ACCESS, api_key_file.display(), e);
+                None
+            }
+        }
+    }
+}
+
+/// A client certificate. The registry server authorizes the TLS connection, requests carry no token.
+pub(crate) struct MutualTls {
+    /// PEM file with the certificate and the private key
+    client_certificate: PathBuf,
+}
+
+impl Authenticator for MutualTls {
+    fn name(&self) -> &'static str {
+        "mutual-tls"
+    }
+
+    fn configure(&self, builder: reqwest::ClientBuilder) -> Option<reqwest::ClientBuilder> {
+        let pem = match std::fs::read(&self.client_certificate) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{} Failed to read the client certificate {}: {:?}", error_codes::FILE_ACCESS, self.client_certificate.display(), e);
+                return None;
+            }
+        };
+        // reqwest only reads PEM identities with rustls
+        #[cfg(feature = "rustls-tls")]
+        let identity = reqwest::Identity::from_pem(&pem).map_err(|e| format!("{:?}", e));
+        #[cfg(not(feature = "rustls-tls"))]
+        let identity: Result<reqwest::Identity, String> = {
+            let _ = pem;
+            Err("Client certificates require the rustls TLS backend".to_owned())
+        };
+        match identity {
+            Ok(identity) => Some(builder.identity(identity)),
+            Err(e) => {
+                error!("{} Invalid client certificate {}: {}", error_codes::HTTP_CLIENT, self.client_certificate.display(), e);
+                None
+            }
+        }
+    }
+
+    fn login(&self, _client: &reqwest::Client, _qr_code_dir: Option<&Path>, _interactive: bool) -> Option<UserSession> {
+        Some(anonymous_session(String::new()))
+    }
+}
+
+/// The authentication method of the configuration. The OAuth device flow by default.
+pub(crate) fn authenticator(config: &ConfigFile) -> Result<Box<dyn Authenticator>, String> {
+    match config.auth.as_deref() {
+        None | Some("oauth-device") => Ok(Box::new(OAuthDevice)),
+        Some("static-token") => Ok(Box::new(StaticToken { api_key_file: config.api_key_file.clone() })),
+        Some("mutual-tls") => match &config.client_certificate {
+            Some(client_certificate) => Ok(Box::new(MutualTls { client_certificate: client_certificate.clone() })),
+            None => Err("auth mutual-tls requires client-certificate".to_owned()),
+        },
+        Some(other) => Err(format!("Unknown auth method {}. Methods: {}", other, AUTH_METHODS.join(", "))),
+    }
+}
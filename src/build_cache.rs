@@ -0,0 +1,46 @@
+//! Content hashes of the build inputs per architecture. Unchanged images do not need to be build and pushed again.
+use crate::dto::BuildInstruction;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Collects all files of the build context, sorted. The build directory, the git directory and
+/// Dockerfiles of other architectures are not part of the image and skipped.
+fn context_files(dir: &Path, skip: &[PathBuf], own_dockerfile: &str, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)?.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    entries.sort();
+    for path in entries {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        if name == ".git" || skip.iter().any(|s| path.canonicalize().map(|p| &p == s).unwrap_or(false)) {
+            continue;
+        }
+        if path.is_dir() {
+            context_files(&path, skip, own_dockerfile, files)?;
+        } else if !name.starts_with("Dockerfile") || name == own_dockerfile {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The sha256 of the build context, the architecture and the recipe and build arguments, if any
+pub(crate) fn content_hash(addon_dir: &Path, build_directory: &Path, build_instruction: &BuildInstruction) -> Option<String> {
+    // The parent of a plain file name like "addons.yml" is empty
+    let addon_dir = if addon_dir.as_os_str().is_empty() { Path::new(".") } else { addon_dir };
+    let skip: Vec<PathBuf> = build_directory.canonicalize().into_iter().collect();
+    let mut files = Vec::new();
+    context_files(addon_dir, &skip, &build_instruction.filename, &mut files).ok()?;
+
+    let mut hasher = Sha256::new();
+    hasher.input(build_instruction.arch.as_bytes());
+    if let Some(recipe) = &build_instruction.recipe {
+        hasher.input(serde_json::to_vec(recipe).ok()?);
+    }
+    if !build_instruction.build_args.is_empty() {
+        hasher.input(serde_json::to_vec(&build_instruction.build_args).ok()?);
+    }
+    for file in files {
+        hasher.input(file.strip_prefix(addon_dir).unwrap_or(&file).to_string_lossy().as_bytes());
+        hasher.input(std::fs::read(&file).ok()?);
+    }
+    Some(format!("{:x}", hasher.result()))
+}
@@ -0,0 +1,149 @@
+//! Builds images from an addons.yml build recipe with buildah (`buildah from/run/copy/config/commit`).
+//! This allows fine grained layer labels and reproducible timestamps without a Dockerfile.
+//! buildah can also be used as container engine (`--engine buildah`).
+use crate::error_codes;
+use crate::dto::addons::BuildRecipe;
+use crate::image_checks::ImageConfig;
+use log::{debug, error};
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Command;
+
+/// Returns true if the buildah executable is available
+pub(crate) fn available() -> bool {
+    Command::new("buildah").arg("--version").output().map(|o| o.status.success()).unwrap_or(false)
+}
+
+#[derive(Default, Deserialize)]
+struct DockerImage {
+    #[serde(default)]
+    config: ImageConfig,
+}
+
+#[derive(Deserialize)]
+struct Descriptor {
+    size: i64,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    config: Descriptor,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Deserialize)]
+struct Inspect {
+    #[serde(rename = "Docker", default)]
+    docker: DockerImage,
+    /// The image manifest as JSON string
+    #[serde(rename = "Manifest", default)]
+    manifest: String,
+}
+
+/// The configuration and the size of a local image. buildah has no `image inspect` like podman and docker.
+/// The size is the sum of the (compressed) layers from the image manifest.
+pub(crate) fn inspect_image(image_name: &str) -> Option<(ImageConfig, i64)> {
+    let output = Command::new("buildah").args(["inspect", "--type", "image", image_name]).output().ok()?;
+    let inspect: Inspect = serde_json::from_slice(&output.stdout).ok()?;
+    let size = serde_json::from_str::<Manifest>(&inspect.manifest).ok()
+        .map(|m| m.config.size + m.layers.iter().map(|l| l.size).sum::<i64>())
+        .unwrap_or(0);
+    Some((inspect.docker.config, size))
+}
+
+/// Maps the addon architecture names to OCI platform architectures
+pub(crate) fn oci_arch(arch: &str) -> &str {
+    match arch {
+        "aarch64" => "arm64",
+        "armhf" | "armv6" => "arm",
+        "i386" => "386",
+        v => v,
+    }
+}
+
+/// The OCI platform variant of the 32 bit ARM architectures
+pub(crate) fn oci_variant(arch: &str) -> Option<&'static str> {
+    match arch {
+        "armhf" => Some("v7"),
+        "armv6" => Some("v6"),
+        _ => None,
+    }
+}
+
+/// The platform like "linux/arm/v6" of an addon architecture
+pub(crate) fn platform(arch: &str) -> String {
+    match oci_variant(arch) {
+        Some(variant) => format!("linux/{}/{}", oci_arch(arch), variant),
+        None => format!("linux/{}", oci_arch(arch)),
+    }
+}
+
+/// Executes buildah with the given arguments. Returns the trimmed stdout on success.
+fn buildah(args: &[&str], context: &Path) -> Option<String> {
+    debug!("buildah {}", args.join(" "));
+    match Command::new("buildah").args(args).current_dir(context).output() {
+        Ok(output) if output.status.success() => Some(String::from_utf8_lossy(&output.stdout).trim().to_owned()),
+        Ok(output) => {
+            error!("{} buildah {} failed: {}", error_codes::BUILD_FAILED, args.first().unwrap_or(&""), String::from_utf8_lossy(&output.stderr));
+            None
+        }
+        Err(e) => {
+            error!("{} Failed to execute buildah: {:?}", error_codes::ENGINE_MISSING, e);
+            None
+        }
+    }
+}
+
+/// Build the image `image_name` for `arch` from the recipe. The working container is always removed.
+/// With `pull_always` the base image is pulled even if it exists locally.
+pub(crate) fn build_recipe(recipe: &BuildRecipe, arch: &str, image_name: &str, context: &Path, pull_always: bool) -> bool {
+    let pull = if pull_always { "--pull-always" } else { "--pull" };
+    let mut args = vec!["from", "--arch", oci_arch(arch)];
+    if let Some(variant) = oci_variant(arch) {
+        args.extend(&["--variant", variant]);
+    }
+    args.extend(&[pull, recipe.from.as_str()]);
+    let container = match buildah(&args, context) {
+        Some(v) => v,
+        None => return false,
+    };
+    let result = build_in_container(recipe, &container, image_name, context);
+    let _ = buildah(&["rm", &container], context);
+    result
+}
+
+fn build_in_container(recipe: &BuildRecipe, container: &str, image_name: &str, context: &Path) -> bool {
+    for run in &recipe.run {
+        if buildah(&["run", container, "--", "sh", "-c", run], context).is_none() {
+            return false;
+        }
+    }
+    for copy in &recipe.copy {
+        let parts: Vec<&str> = copy.split(":").collect();
+        if buildah(&["copy", container, parts[0], parts[1]], context).is_none() {
+            return false;
+        }
+    }
+    for (key, value) in &recipe.labels {
+        if buildah(&["config", "--label", &format!("{}={}", key, value), container], context).is_none() {
+            return false;
+        }
+    }
+    if !recipe.entrypoint.is_empty() {
+        let entrypoint = serde_json::to_string(&recipe.entrypoint).unwrap();
+        if buildah(&["config", "--entrypoint", &entrypoint, container], context).is_none() {
+            return false;
+        }
+    }
+
+    let timestamp = recipe.timestamp.map(|v| v.to_string());
+    let mut args = vec!["commit"];
+    if let Some(timestamp) = &timestamp {
+        args.push("--timestamp");
+        args.push(timestamp);
+    }
+    args.push(container);
+    args.push(image_name);
+    buildah(&args, context).is_some()
+}
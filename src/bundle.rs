@@ -0,0 +1,271 @@
+//! The `verify-bundle` subcommand: Checks a downloaded addon bundle against the registry before it is sideloaded.
+//! A bundle is a tar archive with the `addons.yml` file, an OCI image archive per architecture
+//! (`images/<arch>.tar`, as written by `podman save --format oci-archive`) and the `x-attachments` files
+//! below `attachments/`.
+use crate::endpoints;
+use crate::error_codes::{self, Exit};
+use crate::docker_registry::{self, ImageManifest, ManifestDescriptor};
+use crate::dto::addons::{self, AddonFileEntry, AttachmentKind};
+use crate::registry;
+use log::error;
+use prettytable::{cell, row, Cell, Row, Table};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::path::Path;
+
+/// Manifests and configs are small. Larger blobs are only hashed.
+const MAX_KEPT_BLOB_SIZE: u64 = 1024 * 1024;
+
+#[derive(Deserialize)]
+struct OciIndex {
+    manifests: Vec<ManifestDescriptor>,
+}
+
+/// The image config digest of an OCI image archive
+struct ImageArchive {
+    config_digest: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Trust {
+    Ok,
+    Warning,
+    Failed,
+}
+
+struct TrustLine {
+    check: String,
+    trust: Trust,
+    details: String,
+}
+
+fn line(check: &str, trust: Trust, details: String) -> TrustLine {
+    TrustLine { check: check.to_owned(), trust, details }
+}
+
+/// Reads an OCI image archive. Every blob must match its digest.
+fn read_image_archive<R: Read>(reader: R) -> Result<ImageArchive, String> {
+    let mut archive = tar::Archive::new(reader);
+    let mut index: Option<OciIndex> = None;
+    let mut blobs: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    let mut hashed: BTreeSet<String> = BTreeSet::new();
+
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let mut entry = entry.map_err(|e| e.to_string())?;
+        let path = entry.path().map_err(|e| e.to_string())?.to_string_lossy().trim_start_matches("./").to_owned();
+        if path == "index.json" {
+            index = Some(serde_json::from_reader(&mut entry).map_err(|e| format!("index.json: {}", e))?);
+        } else if let Some(hash) = path.strip_prefix("blobs/sha256/") {
+            let expected = format!("sha256:{}", hash);
+            let keep = entry.header().size().unwrap_or(0) <= MAX_KEPT_BLOB_SIZE;
+            let mut sha = Sha256::new();
+            let mut data = Vec::new();
+            let mut buffer = [0u8; 64 * 1024];
+            loop {
+                let n = entry.read(&mut buffer).map_err(|e| e.to_string())?;
+                if n == 0 {
+                    break;
+                }
+                sha.input(&buffer[..n]);
+                if keep {
+                    data.extend_from_slice(&buffer[..n]);
+                }
+            }
+            let digest = format!("sha256:{:x}", sha.result());
+            if digest != expected {
+                return Err(format!("Blob {} does not match its digest {}", expected, digest));
+            }
+            if keep {
+                blobs.insert(digest.clone(), data);
+            }
+            hashed.insert(digest);
+        }
+    }
+
+    let index = index.ok_or_else(|| "index.json missing".to_owned())?;
+    let manifest_digest = match index.manifests.as_slice() {
+        [manifest] => manifest.digest.clone(),
+        _ => return Err(format!("Expected one image, found {}", index.manifests.len())),
+    };
+    let manifest: ImageManifest = match blobs.get(&manifest_digest) {
+        Some(data) => serde_json::from_slice(data).map_err(|e| format!("Manifest {}: {}", manifest_digest, e))?,
+        None => return Err(format!("Manifest {} missing", manifest_digest)),
+    };
+    if !hashed.contains(&manifest.config.digest) {
+        return Err(format!("Image config {} missing", manifest.config.digest));
+    }
+    Ok(ImageArchive { config_digest: manifest.config.digest })
+}
+
+/// The content of a bundle. The image archives are verified while reading.
+struct Bundle {
+    addons_file: Option<Vec<u8>>,
+    images: BTreeMap<String, Result<ImageArchive, String>>,
+    attachments: BTreeSet<String>,
+}
+
+fn read_bundle(file: &Path) -> Result<Bundle, String> {
+    let file = std::fs::File::open(file).map_err(|e| e.to_string())?;
+    let mut archive = tar::Archive::new(file);
+    let mut bundle = Bundle { addons_file: None, images: BTreeMap::new(), attachments: BTreeSet::new() };
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let mut entry = entry.map_err(|e| e.to_string())?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().map_err(|e| e.to_string())?.to_string_lossy().trim_start_matches("./").to_owned();
+        if path == "addons.yml" {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).map_err(|e| e.to_string())?;
+            bundle.addons_file = Some(data);
+        } else if path.starts_with("images/") && path.ends_with(".tar") {
+            let arch = path[7..path.len() - 4].to_owned();
+            bundle.images.insert(arch, read_image_archive(&mut entry));
+        } else if let Some(attachment) = path.strip_prefix("attachments/") {
+            bundle.attachments.insert(attachment.to_owned());
+        }
+    }
+    Ok(bundle)
+}
+
+/// Validates the bundled addons.yml file. It is written to a temporary directory for that.
+fn validate_descriptor(data: &[u8]) -> Result<AddonFileEntry, String> {
+    let dir = std::env::temp_dir().join(format!("ohx-bundle-{}", std::process::id()));
+    let file = dir.join("addons.yml");
+    let result = std::fs::create_dir_all(&dir)
+        .and_then(|_| std::fs::write(&file, data))
+        .map_err(|e| e.to_string())
+        .and_then(|_| addons::open_validate_addons_file(file.to_str().unwrap()).map_err(|e| e.to_string()));
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+/// The cosign signature tag of an image manifest, like "sha256-abc.sig"
+fn signature_tag(manifest_digest: &str) -> String {
+    format!("{}.sig", manifest_digest.replace(':', "-"))
+}
+
+/// Compares the published image of the architecture with the bundled one and looks for its signature
+fn check_image(client: &reqwest::Client, input_file: &AddonFileEntry, arch: &str, image: &ImageArchive, lines: &mut Vec<TrustLine>) {
+    let repository = endpoints::repository(&format!("{}_{}", input_file.x_ohx_registry.id, arch));
+    let check = format!("Image {}", arch);
+    let image_name = endpoints::image_name(&format!("{}_{}", input_file.x_ohx_registry.id, arch), &input_file.x_ohx_registry.version);
+    let manifest_digest = match docker_registry::remote_config_digest(client, &image_name, "") {
+        Ok(Some((manifest_digest, config_digest))) => {
+            if config_digest == image.config_digest {
+                lines.push(line(&check, Trust::Ok, format!("Matches the published image {}", config_digest)));
+            } else {
+                lines.push(line(&check, Trust::Failed, format!("Bundled {}, published {}", image.config_digest, config_digest)));
+            }
+            manifest_digest
+        }
+        Ok(None) => {
+            lines.push(line(&check, Trust::Failed, format!("{} is not published", repository)));
+            return;
+        }
+        Err(e) => {
+            lines.push(line(&check, Trust::Failed, format!("Docker registry not reachable: {}", e)));
+            return;
+        }
+    };
+
+    let check = format!("Signature {}", arch);
+    let signature = format!("{}:{}", repository, signature_tag(&manifest_digest));
+    match docker_registry::remote_manifest_digest(client, &signature, "") {
+        Ok(Some(_)) => lines.push(line(&check, Trust::Ok, format!("Published as {}. Verify the signer with `cosign verify`.", signature))),
+        Ok(None) => lines.push(line(&check, Trust::Warning, "Not signed".to_owned())),
+        Err(e) => lines.push(line(&check, Trust::Warning, format!("Not checked: {}", e))),
+    }
+}
+
+fn print_summary(lines: &[TrustLine]) {
+    let mut table = Table::new();
+    table.add_row(row!["Check", "Result", "Details"]);
+    for l in lines {
+        table.add_row(Row::new(vec![
+            Cell::new(&l.check),
+            match l.trust {
+                Trust::Ok => Cell::new("ok").style_spec("bFg"),
+                Trust::Warning => Cell::new("warning").style_spec("Fy"),
+                Trust::Failed => Cell::new("failed").style_spec("BriH2"),
+            },
+            Cell::new(&l.details)]));
+    }
+    table.printstd();
+}
+
+/// Checks the digests, signatures, SBOM and descriptor of the bundle and prints a trust summary.
+/// Returns the exit category if a check failed. Warnings, like a missing signature, are only reported.
+pub(crate) fn verify_bundle(client: &reqwest::Client, file: &Path) -> Result<(), Exit> {
+    let bundle = match read_bundle(file) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} Failed to read the bundle {}: {}", error_codes::BUNDLE_INVALID, file.display(), e);
+            return Err(error_codes::BUNDLE_INVALID.exit);
+        }
+    };
+    let input_file = match bundle.addons_file.as_ref().map(|data| validate_descriptor(data)) {
+        Some(Ok(v)) => v,
+        Some(Err(e)) => {
+            error!("{} The addons.yml file of the bundle is invalid: {}", error_codes::BUNDLE_INVALID, e);
+            return Err(error_codes::BUNDLE_INVALID.exit);
+        }
+        None => {
+            error!("{} The bundle contains no addons.yml file", error_codes::BUNDLE_INVALID);
+            return Err(error_codes::BUNDLE_INVALID.exit);
+        }
+    };
+    let id = &input_file.x_ohx_registry.id;
+    let version = &input_file.x_ohx_registry.version;
+    let mut lines = vec![line("Descriptor", Trust::Ok, format!("{} {} is valid", id, version))];
+
+    // Only published versions can be checked. The registry index lists the latest version.
+    match registry::addon_registry(client).as_ref().and_then(|r| r.get(id)) {
+        Some(entry) if &entry.entry.version != version =>
+            lines.push(line("Registry entry", Trust::Failed, format!("Published version is {}", entry.entry.version))),
+        Some(entry) => {
+            lines.push(line("Registry entry", Trust::Ok, format!("Owned by {}", entry.owner)));
+            let bundled: BTreeSet<&String> = bundle.images.keys().collect();
+            let published: BTreeSet<&String> = entry.archs.iter().collect();
+            if !entry.archs.is_empty() && bundled != published {
+                lines.push(line("Architectures", Trust::Failed, format!("Bundled {:?}, published {:?}", bundled, published)));
+            }
+        }
+        None => lines.push(line("Registry entry", Trust::Failed, format!("{} is not in the registry", id))),
+    }
+
+    if bundle.images.is_empty() {
+        lines.push(line("Images", Trust::Failed, "The bundle contains no image".to_owned()));
+    }
+    for (arch, image) in &bundle.images {
+        match image {
+            Ok(image) => check_image(client, &input_file, arch, image, &mut lines),
+            Err(e) => lines.push(line(&format!("Image {}", arch), Trust::Failed, e.clone())),
+        }
+    }
+
+    let sboms: Vec<&String> = input_file.x_attachments.iter().filter(|a| a.kind == AttachmentKind::Sbom).map(|a| &a.path).collect();
+    if sboms.is_empty() {
+        lines.push(line("SBOM", Trust::Warning, "No SBOM declared".to_owned()));
+    }
+    for path in sboms {
+        match bundle.attachments.contains(path.trim_start_matches("./")) {
+            true => lines.push(line("SBOM", Trust::Ok, path.clone())),
+            false => lines.push(line("SBOM", Trust::Failed, format!("{} declared, but not bundled", path))),
+        }
+    }
+
+    print_summary(&lines);
+    if lines.iter().any(|l| l.trust == Trust::Failed) {
+        error!("{} Do not sideload {}. It does not match the published addon.", error_codes::BUNDLE_MISMATCH, file.display());
+        return Err(error_codes::BUNDLE_MISMATCH.exit);
+    }
+    Ok(())
+}
+
+#[test]
+fn signature_tag_test() {
+    assert_eq!(signature_tag("sha256:abc"), "sha256-abc.sig");
+}
@@ -0,0 +1,176 @@
+//! A small file cache for http GET responses and validation results, shared by all subcommands.
+//! Entries are json files in `~/.cache/ohx/<namespace>/`, named by the sha256 of their key.
+//! Repeated `search`, `info` and `validate` runs, like those of editor integrations, do not repeat the work.
+//! If `~/.cache` belongs to another user, like with a shared HOME, the cache is kept in the user's temporary directory.
+use crate::dto::addons::ValidatedAddonFile;
+use crate::dto::{docs, extends, interpolate, lint};
+use crate::dto::schema::Positions;
+use crate::dto::addons::ValidationOptions;
+use crate::error_codes;
+use crate::http;
+use crate::namespace;
+use crate::registry;
+use log::{debug, error, info};
+use prettytable::{cell, row, Table};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub(crate) const HTTP: &str = "http";
+pub(crate) const VALIDATION: &str = "validation";
+const NAMESPACES: [&str; 2] = [HTTP, VALIDATION];
+
+/// Http responses are reused for this long. Offline they are reused regardless of their age.
+const HTTP_MAX_AGE: Duration = Duration::from_secs(300);
+
+pub(crate) fn directory() -> PathBuf {
+    let cache_dir = match dirs::cache_dir() {
+        Some(v) => v,
+        None => return namespace::user_directory().join("cache"),
+    };
+    let directory = cache_dir.join("ohx");
+    let existing = if directory.exists() { &directory } else { &cache_dir };
+    match namespace::is_foreign(existing) {
+        true => namespace::user_directory().join("cache"),
+        false => directory,
+    }
+}
+
+fn entry_file(namespace: &str, key: &str) -> PathBuf {
+    directory().join(namespace).join(format!("{:x}.json", Sha256::digest(key.as_bytes())))
+}
+
+fn age(path: &Path) -> Option<Duration> {
+    path.metadata().and_then(|m| m.modified()).ok().and_then(|m| SystemTime::now().duration_since(m).ok())
+}
+
+/// The cached value of the key, if it is younger than `max_age`
+pub(crate) fn get<T: DeserializeOwned>(namespace: &str, key: &str, max_age: Option<Duration>) -> Option<T> {
+    let file = entry_file(namespace, key);
+    if let Some(max_age) = max_age {
+        if !http::is_offline() && age(&file).map(|age| age > max_age).unwrap_or(true) {
+            return None;
+        }
+    }
+    let data = std::fs::read(&file).ok()?;
+    // Entries of older CLI versions may not deserialize anymore. They are overwritten on the next put.
+    serde_json::from_slice(&data).ok()
+}
+
+/// Stores a value. A failure is not an error, the value is just not cached.
+pub(crate) fn put<T: Serialize>(namespace: &str, key: &str, value: &T) {
+    let file = entry_file(namespace, key);
+    let result = std::fs::create_dir_all(file.parent().unwrap())
+        .map_err(|e| e.to_string())
+        .and_then(|_| serde_json::to_vec(value).map_err(|e| e.to_string()))
+        .and_then(|data| std::fs::write(&file, data).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        debug!("Failed to cache {}: {}", file.display(), e);
+    }
+}
+
+/// The response of a GET request to `url`, from the cache if it is recent enough
+pub(crate) fn cached_get<T, F>(url: &str, fetch: F) -> Result<T, failure::Error>
+    where T: Serialize + DeserializeOwned, F: FnOnce() -> Result<T, failure::Error> {
+    if let Some(value) = get(HTTP, url, Some(HTTP_MAX_AGE)) {
+        debug!("Cached: {}", url);
+        return Ok(value);
+    }
+    if http::is_offline() {
+        return Err(failure::err_msg(format!("{} is not cached", url)));
+    }
+    let value = fetch()?;
+    put(HTTP, url, &value);
+    Ok(value)
+}
+
+/// The key of a validation result: The CLI version, the validation options, the file with its comments,
+/// all files it extends, the Dockerfiles next to it and the names of the `x-docs` files
+pub(crate) fn validation_key(filename: &str, options: &ValidationOptions) -> Option<String> {
+    let text = std::fs::read(filename).ok()?;
+    let mut merged = extends::load_with_extends(Path::new(filename)).ok()?;
+    interpolate::apply(&mut merged, &options.variables, options.require_variables, &Positions::default());
+    let mut hasher = Sha256::new();
+    hasher.input(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.input(serde_json::to_vec(options).ok()?);
+    hasher.input(&text);
+    hasher.input(serde_json::to_vec(&merged).ok()?);
+    let addon_dir = Path::new(filename).parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    for dockerfile in lint::dockerfiles(addon_dir) {
+        hasher.input(dockerfile.to_string_lossy().as_bytes());
+        hasher.input(std::fs::read(&dockerfile).ok()?);
+    }
+    if let Some(directory) = merged.get("x-docs").and_then(|d| d.get("directory")).and_then(|d| d.as_str()) {
+        for file in docs::files(&addon_dir.join(directory)) {
+            hasher.input(file.to_string_lossy().as_bytes());
+        }
+    }
+    Some(format!("{:x}", hasher.result()))
+}
+
+/// The validation result of an unchanged file. Only valid files are cached.
+pub(crate) fn validated_addons_file(key: &str) -> Option<ValidatedAddonFile> {
+    get(VALIDATION, key, None)
+}
+
+/// The number of entries, their total size and the age of the oldest entry of a namespace
+fn namespace_stats(namespace: &str) -> (usize, u64, Option<Duration>) {
+    let entries: Vec<PathBuf> = match std::fs::read_dir(directory().join(namespace)) {
+        Ok(v) => v.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_file()).collect(),
+        Err(_) => return (0, 0, None),
+    };
+    let size = entries.iter().filter_map(|p| p.metadata().ok()).map(|m| m.len()).sum();
+    (entries.len(), size, entries.iter().filter_map(|p| age(p)).max())
+}
+
+fn format_age(age: Option<Duration>) -> String {
+    match age.map(|a| a.as_secs()) {
+        None => "-".to_owned(),
+        Some(s) if s < 120 => format!("{} s", s),
+        Some(s) if s < 7200 => format!("{} min", s / 60),
+        Some(s) if s < 172800 => format!("{} h", s / 3600),
+        Some(s) => format!("{} days", s / 86400),
+    }
+}
+
+/// Prints the entries, size and oldest entry of each cache
+pub(crate) fn stats() -> bool {
+    let mut table = Table::new();
+    table.add_row(row!["Cache", "Entries", "Size", "Oldest"]);
+    for namespace in NAMESPACES.iter() {
+        let (entries, size, oldest) = namespace_stats(namespace);
+        let size = format!("{:.1} KiB", size as f64 / 1024.0);
+        table.add_row(row![namespace, entries, size, format_age(oldest)]);
+    }
+    let index = registry::registry_cache_file();
+    let (entries, size) = match index.metadata() {
+        Ok(m) => (1, format!("{:.1} KiB", m.len() as f64 / 1024.0)),
+        Err(_) => (0, "0.0 KiB".to_owned()),
+    };
+    table.add_row(row!["registry index", entries, size, format_age(age(&index))]);
+    table.printstd();
+    println!("Directory: {}", directory().display());
+    true
+}
+
+/// Removes all cached responses, validation results and the cached registry index
+pub(crate) fn clear() -> bool {
+    let dir = directory();
+    if dir.exists() {
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            error!("{} Failed to remove {}: {}", error_codes::FILE_ACCESS, dir.display(), e);
+            return false;
+        }
+    }
+    let index = registry::registry_cache_file();
+    if index.exists() {
+        if let Err(e) = std::fs::remove_file(&index) {
+            error!("{} Failed to remove {}: {}", error_codes::FILE_ACCESS, index.display(), e);
+            return false;
+        }
+    }
+    info!("Cleared {}", dir.display());
+    true
+}
@@ -0,0 +1,59 @@
+//! The `cleanup-remote` subcommand: Deletes the images and sandbox entries that sandbox and failed
+//! publishes left behind. They are tracked in the build state file.
+use crate::error_codes;
+use crate::docker_registry;
+use crate::endpoints;
+use crate::login::UserSession;
+use crate::registry;
+use crate::state::{self, RemoteArtifact};
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Deletes all tracked remote artifacts. Returns false if one could not be deleted. It stays tracked.
+pub(crate) fn cleanup_remote(client: &reqwest::Client, session: &UserSession, build_directory: &Path, dry_run: bool) -> bool {
+    let artifacts = state::remote_artifacts(build_directory);
+    if artifacts.is_empty() {
+        println!("Nothing to clean up");
+        return true;
+    }
+    if dry_run {
+        for artifact in &artifacts {
+            println!("Would delete {}", artifact);
+        }
+        return true;
+    }
+
+    // Docker credentials per addon id. None if the vault has none.
+    let mut credentials: BTreeMap<String, Option<String>> = BTreeMap::new();
+    let mut deleted = Vec::new();
+    for artifact in &artifacts {
+        let success = match artifact {
+            RemoteArtifact::Image { addon_id, reference } => {
+                let docker_credentials = credentials.entry(addon_id.clone())
+                    .or_insert_with(|| docker_registry::get_access_credentials(client, &endpoints::vault_url(), session, addon_id));
+                match docker_credentials {
+                    Some(docker_credentials) => match docker_registry::delete_remote_tag(client, reference, docker_credentials) {
+                        Ok(true) => true,
+                        Ok(false) => {
+                            info!("{} does not exist anymore", reference);
+                            true
+                        }
+                        Err(e) => {
+                            error!("{} Failed to delete {}: {:?}", error_codes::REMOTE_IMAGE_CHECK, reference, e);
+                            false
+                        }
+                    },
+                    None => false,
+                }
+            }
+            RemoteArtifact::SandboxEntry { addon_id, version } => registry::delete_sandbox_entry(client, session, addon_id, version, build_directory),
+        };
+        if success {
+            println!("Deleted {}", artifact);
+            deleted.push(artifact.clone());
+        }
+    }
+    state::untrack_remote(build_directory, &deleted, true);
+    deleted.len() == artifacts.len()
+}
@@ -0,0 +1,539 @@
+//! The `config` subcommand: Shows the effective configuration and where each value comes from, gets and sets
+//! values of the configuration files.
+//! Defaults are read from the user file `~/.config/ohx/cli.toml` and the project file `.ohxcli.toml` in the current
+//! directory. Project values win, command line flags win over both.
+use crate::auth;
+use crate::cache;
+use crate::docker_registry;
+use crate::error_codes;
+use crate::http;
+use crate::login;
+use crate::output::OutputFormat;
+use crate::provenance;
+use crate::registry;
+use crate::state;
+use crate::{Opt, ALLOWED_ARCHITECTURES};
+use log::error;
+use prettytable::{cell, row, Table};
+use serde::{Deserialize, Serialize};
+use crate::endpoints::{self, Endpoints};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The project configuration file, relative to the current directory
+pub(crate) const PROJECT_CONFIG_FILE: &str = ".ohxcli.toml";
+
+/// The keys of the configuration files
+pub(crate) const CONFIG_KEYS: [&str; 15] = ["build-directory", "registry-url", "oauth-url", "vault-url",
+    "docker-namespace", "index-url", "stats-url", "archs", "engine", "verbose", "auth", "api-key-file",
+    "client-certificate", "mirrors", "build-storage"];
+
+/// The keys of a profile, like `profiles.staging.registry-url`
+pub(crate) const PROFILE_KEYS: [&str; 6] = ["oauth-url", "registry-url", "vault-url", "docker-namespace",
+    "index-url", "stats-url"];
+
+/// The endpoint urls must be http(s) urls
+fn check_url(key: &str, url: &str) -> Result<(), String> {
+    match url.starts_with("https://") || url.starts_with("http://") {
+        true => Ok(()),
+        false => Err(format!("{} must be a http(s) url, not {}", key, url)),
+    }
+}
+
+pub(crate) fn user_config_file() -> PathBuf {
+    dirs::config_dir().expect("config_dir to exist").join("ohx").join("cli.toml")
+}
+
+/// The content of a configuration file. All values are optional.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) build_directory: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) registry_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) oauth_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) vault_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) docker_namespace: Option<String>,
+    /// The registry index file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) index_url: Option<String>,
+    /// The registry stats file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) stats_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) archs: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) engine: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) verbose: Option<u8>,
+    /// One of `auth::AUTH_METHODS`, for the registry-url
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) auth: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) api_key_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) client_certificate: Option<PathBuf>,
+    /// Registry mirrors for base image pulls
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) mirrors: Option<Vec<String>>,
+    /// The storage url of the build directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) build_storage: Option<String>,
+    /// Tables are written after the values
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) profiles: BTreeMap<String, Profile>,
+}
+
+/// A named set of endpoints, like a staging registry. Selected with `--profile`.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Profile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) oauth_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) registry_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) vault_url: Option<String>,
+    /// Like "docker.io/openhabx-staging"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) docker_namespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) index_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) stats_url: Option<String>,
+}
+
+impl Profile {
+    fn value(&mut self, key: &str) -> Option<&mut Option<String>> {
+        match key {
+            "oauth-url" => Some(&mut self.oauth_url),
+            "registry-url" => Some(&mut self.registry_url),
+            "vault-url" => Some(&mut self.vault_url),
+            "docker-namespace" => Some(&mut self.docker_namespace),
+            "index-url" => Some(&mut self.index_url),
+            "stats-url" => Some(&mut self.stats_url),
+            _ => None,
+        }
+    }
+
+    fn check(&self, name: &str) -> Result<(), String> {
+        let urls = [("oauth-url", &self.oauth_url), ("registry-url", &self.registry_url), ("vault-url", &self.vault_url),
+            ("index-url", &self.index_url), ("stats-url", &self.stats_url)];
+        for (key, url) in urls.iter() {
+            if let Some(url) = url {
+                check_url(key, url).map_err(|e| format!("Profile {}: {}", name, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits a key like "profiles.staging.registry-url" into profile name and profile key
+fn profile_key(key: &str) -> Option<(&str, &str)> {
+    let mut parts = key.splitn(3, '.');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("profiles"), Some(name), Some(key)) if !name.is_empty() => Some((name, key)),
+        _ => None,
+    }
+}
+
+impl ConfigFile {
+    fn get(&self, key: &str) -> Option<String> {
+        if let Some((name, key)) = profile_key(key) {
+            let mut profile = self.profiles.get(name)?.clone();
+            return profile.value(key).and_then(|v| v.clone());
+        }
+        match key {
+            "build-directory" => self.build_directory.as_ref().map(|v| v.display().to_string()),
+            "registry-url" => self.registry_url.clone(),
+            "oauth-url" => self.oauth_url.clone(),
+            "vault-url" => self.vault_url.clone(),
+            "docker-namespace" => self.docker_namespace.clone(),
+            "index-url" => self.index_url.clone(),
+            "stats-url" => self.stats_url.clone(),
+            "archs" => self.archs.as_ref().map(|v| v.join(",")),
+            "engine" => self.engine.clone(),
+            "verbose" => self.verbose.map(|v| v.to_string()),
+            "auth" => self.auth.clone(),
+            "api-key-file" => self.api_key_file.as_ref().map(|v| v.display().to_string()),
+            "client-certificate" => self.client_certificate.as_ref().map(|v| v.display().to_string()),
+            "mirrors" => self.mirrors.as_ref().map(|v| v.join(",")),
+            "build-storage" => self.build_storage.clone(),
+            _ => None,
+        }
+    }
+
+    /// Sets or, without a value, removes a key. Values are checked like the command line flags.
+    fn set(&mut self, key: &str, value: Option<&str>) -> Result<(), String> {
+        let mut next = self.clone();
+        if let Some((name, profile_key)) = profile_key(key) {
+            let mut profile = next.profiles.remove(name).unwrap_or_default();
+            match profile.value(profile_key) {
+                Some(v) => *v = value.map(|v| v.to_owned()),
+                None => return Err(format!("Unknown profile key {}. Keys: {}", profile_key, PROFILE_KEYS.join(", "))),
+            }
+            if profile != Profile::default() {
+                next.profiles.insert(name.to_owned(), profile);
+            }
+            next.check()?;
+            *self = next;
+            return Ok(());
+        }
+        match key {
+            "build-directory" => next.build_directory = value.map(PathBuf::from),
+            "registry-url" => next.registry_url = value.map(|v| v.to_owned()),
+            "oauth-url" => next.oauth_url = value.map(|v| v.to_owned()),
+            "vault-url" => next.vault_url = value.map(|v| v.to_owned()),
+            "docker-namespace" => next.docker_namespace = value.map(|v| v.to_owned()),
+            "index-url" => next.index_url = value.map(|v| v.to_owned()),
+            "stats-url" => next.stats_url = value.map(|v| v.to_owned()),
+            "archs" => next.archs = value.map(|v| v.split(',').map(|a| a.trim().to_owned()).collect()),
+            "engine" => next.engine = value.map(|v| v.to_owned()),
+            "verbose" => next.verbose = match value {
+                Some(v) => Some(v.parse().map_err(|_| format!("verbose must be a number, not {}", v))?),
+                None => None,
+            },
+            "auth" => next.auth = value.map(|v| v.to_owned()),
+            "api-key-file" => next.api_key_file = value.map(PathBuf::from),
+            "client-certificate" => next.client_certificate = value.map(PathBuf::from),
+            "mirrors" => next.mirrors = value.map(|v| v.split(',').map(|m| m.trim().to_owned()).collect()),
+            "build-storage" => next.build_storage = value.map(|v| v.to_owned()),
+            _ => return Err(format!("Unknown key {}. Keys: {}", key, CONFIG_KEYS.join(", "))),
+        }
+        next.check()?;
+        *self = next;
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if let Some(arch) = self.archs.iter().flatten().find(|a| !ALLOWED_ARCHITECTURES.contains(&a.as_str())) {
+            return Err(format!("Unknown architecture {}. Architectures: {}", arch, ALLOWED_ARCHITECTURES.join(", ")));
+        }
+        if let Some(engine) = self.engine.as_ref().filter(|e| !docker_registry::ENGINES.contains(&e.as_str())) {
+            return Err(format!("Unknown engine {}. Engines: {}", engine, docker_registry::ENGINES.join(", ")));
+        }
+        let urls = [("registry-url", &self.registry_url), ("oauth-url", &self.oauth_url), ("vault-url", &self.vault_url),
+            ("index-url", &self.index_url), ("stats-url", &self.stats_url)];
+        for (key, url) in urls.iter() {
+            if let Some(url) = url {
+                check_url(key, url)?;
+            }
+        }
+        if let Some(auth) = self.auth.as_ref().filter(|a| !auth::AUTH_METHODS.contains(&a.as_str())) {
+            return Err(format!("Unknown auth method {}. Methods: {}", auth, auth::AUTH_METHODS.join(", ")));
+        }
+        for (name, profile) in &self.profiles {
+            profile.check(name)?;
+        }
+        Ok(())
+    }
+}
+
+fn read(path: &Path) -> Result<ConfigFile, String> {
+    if !path.exists() {
+        return Ok(ConfigFile::default());
+    }
+    let config: ConfigFile = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| toml::from_str(&data).map_err(|e| e.to_string()))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    config.check().map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(config)
+}
+
+/// The user and the project configuration file
+#[derive(Default)]
+pub(crate) struct ConfigFiles {
+    pub(crate) user: ConfigFile,
+    pub(crate) project: ConfigFile,
+}
+
+impl ConfigFiles {
+    /// The value of a key and the file that sets it. The project file wins.
+    pub(crate) fn get(&self, key: &str) -> Option<(String, PathBuf)> {
+        self.project.get(key).map(|v| (v, PathBuf::from(PROJECT_CONFIG_FILE)))
+            .or_else(|| self.user.get(key).map(|v| (v, user_config_file())))
+    }
+
+    /// The merged content of both files
+    pub(crate) fn merged(&self) -> ConfigFile {
+        ConfigFile {
+            build_directory: self.project.build_directory.clone().or_else(|| self.user.build_directory.clone()),
+            registry_url: self.project.registry_url.clone().or_else(|| self.user.registry_url.clone()),
+            oauth_url: self.project.oauth_url.clone().or_else(|| self.user.oauth_url.clone()),
+            vault_url: self.project.vault_url.clone().or_else(|| self.user.vault_url.clone()),
+            docker_namespace: self.project.docker_namespace.clone().or_else(|| self.user.docker_namespace.clone()),
+            index_url: self.project.index_url.clone().or_else(|| self.user.index_url.clone()),
+            stats_url: self.project.stats_url.clone().or_else(|| self.user.stats_url.clone()),
+            archs: self.project.archs.clone().or_else(|| self.user.archs.clone()),
+            engine: self.project.engine.clone().or_else(|| self.user.engine.clone()),
+            verbose: self.project.verbose.or(self.user.verbose),
+            auth: self.project.auth.clone().or_else(|| self.user.auth.clone()),
+            api_key_file: self.project.api_key_file.clone().or_else(|| self.user.api_key_file.clone()),
+            client_certificate: self.project.client_certificate.clone().or_else(|| self.user.client_certificate.clone()),
+            mirrors: self.project.mirrors.clone().or_else(|| self.user.mirrors.clone()),
+            build_storage: self.project.build_storage.clone().or_else(|| self.user.build_storage.clone()),
+            // Profiles of the project file replace those of the same name
+            profiles: self.user.profiles.clone().into_iter().chain(self.project.profiles.clone()).collect(),
+        }
+    }
+
+    /// The endpoints of the configuration and the given profile
+    pub(crate) fn endpoints(&self, profile: Option<&str>) -> Result<Endpoints, String> {
+        let config = self.merged();
+        let defaults = Endpoints::default();
+        let mut endpoints = Endpoints {
+            profile: None,
+            oauth_url: config.oauth_url.unwrap_or(defaults.oauth_url),
+            registry_url: config.registry_url.unwrap_or(defaults.registry_url),
+            vault_url: config.vault_url.unwrap_or(defaults.vault_url),
+            docker_namespace: config.docker_namespace.unwrap_or(defaults.docker_namespace),
+            index_url: config.index_url.unwrap_or(defaults.index_url),
+            stats_url: config.stats_url.unwrap_or(defaults.stats_url),
+        };
+        let name = match profile {
+            Some(v) => v,
+            None => return Ok(endpoints),
+        };
+        let profile = match config.profiles.get(name) {
+            Some(v) => v.clone(),
+            None => return Err(format!("Unknown profile {}. Profiles: {}", name,
+                                       config.profiles.keys().cloned().collect::<Vec<_>>().join(", "))),
+        };
+        endpoints.profile = Some(name.to_owned());
+        endpoints.oauth_url = profile.oauth_url.unwrap_or(endpoints.oauth_url);
+        endpoints.registry_url = profile.registry_url.unwrap_or(endpoints.registry_url);
+        endpoints.vault_url = profile.vault_url.unwrap_or(endpoints.vault_url);
+        endpoints.docker_namespace = profile.docker_namespace.unwrap_or(endpoints.docker_namespace);
+        endpoints.index_url = profile.index_url.unwrap_or(endpoints.index_url);
+        endpoints.stats_url = profile.stats_url.unwrap_or(endpoints.stats_url);
+        Ok(endpoints)
+    }
+}
+
+/// Reads both configuration files. Missing files are empty.
+pub(crate) fn load() -> Result<ConfigFiles, String> {
+    Ok(ConfigFiles { user: read(&user_config_file())?, project: read(Path::new(PROJECT_CONFIG_FILE))? })
+}
+
+/// Where a configuration value comes from
+pub(crate) enum Source {
+    Default,
+    /// A cargo feature of this binary
+    Feature(&'static str),
+    /// An environment variable
+    Env(&'static str),
+    /// A configuration file
+    File(PathBuf),
+    /// The profile selected with --profile
+    Profile(String),
+    CommandLine,
+}
+
+impl Source {
+    fn is_default(&self) -> bool {
+        matches!(self, Source::Default)
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::Feature(name) => write!(f, "build feature {}", name),
+            Source::Env(name) => write!(f, "env {}", name),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::Profile(name) => write!(f, "profile {}", name),
+            Source::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+pub(crate) struct ConfigValue {
+    pub(crate) key: &'static str,
+    pub(crate) value: String,
+    pub(crate) source: Source,
+}
+
+fn value(key: &'static str, value: impl ToString, source: Source) -> ConfigValue {
+    ConfigValue { key, value: value.to_string(), source }
+}
+
+fn env_value(key: &'static str, env: &'static str, hide: bool) -> ConfigValue {
+    match std::env::var(env) {
+        Ok(v) => value(key, if hide { "(hidden)".to_owned() } else { v }, Source::Env(env)),
+        Err(_) => value(key, "-", Source::Default),
+    }
+}
+
+/// A value of the configuration files, or the default
+fn file_value(files: &ConfigFiles, key: &'static str, default: &str) -> ConfigValue {
+    match files.get(key) {
+        Some((v, path)) => value(key, v, Source::File(path)),
+        None => value(key, default, Source::Default),
+    }
+}
+
+/// All configuration values, with defaults, build features, configuration files, environment and command line merged
+pub(crate) fn resolve(opt: &Opt, files: &ConfigFiles) -> Vec<ConfigValue> {
+    let fips = http::fips_enabled(opt.fips);
+    let verbose_source = match files.get("verbose") {
+        Some((v, path)) if v == opt.verbose.to_string() => Source::File(path),
+        _ if opt.verbose > 0 => Source::CommandLine,
+        _ => Source::Default,
+    };
+    let mut values = vec![
+        value("verbose", opt.verbose, verbose_source),
+        match opt.fips {
+            true => value("fips", true, Source::CommandLine),
+            false if fips => value("fips", true, Source::Feature("fips")),
+            false => value("fips", false, Source::Default),
+        },
+        value("tls-backend", http::resolve_backend(opt.tls_backend, fips).name(),
+              if opt.tls_backend.is_some() { Source::CommandLine } else { Source::Default }),
+        value("ci", opt.ci, if opt.ci { Source::CommandLine } else { Source::Default }),
+        value("output", opt.output.unwrap_or(OutputFormat::Human).name(),
+              if opt.output.is_some() { Source::CommandLine } else { Source::Default }),
+        match opt.ci {
+            true => value("http-timeout", format!("{} s", crate::CI_HTTP_TIMEOUT), Source::CommandLine),
+            false => value("http-timeout", "30 s", Source::Default),
+        },
+        match (&opt.proxy, ["HTTPS_PROXY", "HTTP_PROXY"].iter().find(|n| std::env::var(n).is_ok())) {
+            (Some(proxy), _) => value("proxy", provenance::strip_credentials(proxy), Source::CommandLine),
+            (None, Some(name)) => value("proxy", provenance::strip_credentials(&std::env::var(name).unwrap_or_default()), Source::Env(name)),
+            (None, None) => value("proxy", "-", Source::Default),
+        },
+        env_value("no-proxy", "NO_PROXY", false),
+        env_value("username", "OHX_USERNAME", false),
+        env_value("password", "OHX_PASSWORD", true),
+        file_value(files, "build-directory", "out"),
+        file_value(files, "archs", "all"),
+        file_value(files, "engine", "detected"),
+        file_value(files, "mirrors", "-"),
+        match files.get("build-storage") {
+            Some((v, path)) => value("build-storage", provenance::strip_credentials(&v), Source::File(path)),
+            None => value("build-storage", "-", Source::Default),
+        },
+        file_value(files, "auth", "oauth-device"),
+        file_value(files, "api-key-file", "-"),
+        env_value("api-key", auth::API_KEY_ENV, true),
+        file_value(files, "client-certificate", "-"),
+    ];
+    let current = endpoints::current();
+    let profile = files.merged().profiles.get(current.profile.as_deref().unwrap_or_default()).cloned().unwrap_or_default();
+    let endpoint_source = |key: &str, flag: &Option<String>, env: &'static str, in_profile: bool| match (flag, &current.profile, in_profile) {
+        (Some(flag), _, _) if std::env::var(env).ok().as_ref() == Some(flag) => Source::Env(env),
+        (Some(_), _, _) => Source::CommandLine,
+        (None, Some(name), true) => Source::Profile(name.clone()),
+        _ => files.get(key).map(|(_, path)| Source::File(path)).unwrap_or(Source::Default),
+    };
+    values.push(value("profile", current.profile.as_deref().unwrap_or("-"),
+                      if current.profile.is_some() { Source::CommandLine } else { Source::Default }));
+    values.push(value("oauth-url", &current.oauth_url,
+                      endpoint_source("oauth-url", &opt.oauth_url, "OHX_OAUTH_URL", profile.oauth_url.is_some())));
+    values.push(value("registry-url", &current.registry_url,
+                      endpoint_source("registry-url", &opt.registry_url, "OHX_REGISTRY_URL", profile.registry_url.is_some())));
+    values.push(value("vault-url", &current.vault_url,
+                      endpoint_source("vault-url", &opt.vault_url, "OHX_VAULT_URL", profile.vault_url.is_some())));
+    values.push(value("docker-namespace", &current.docker_namespace,
+                      endpoint_source("docker-namespace", &None, "", profile.docker_namespace.is_some())));
+    values.push(value("index-url", &current.index_url,
+                      endpoint_source("index-url", &opt.index_url, "OHX_INDEX_URL", profile.index_url.is_some())));
+    values.push(value("stats-url", &current.stats_url,
+                      endpoint_source("stats-url", &opt.stats_url, "OHX_STATS_URL", profile.stats_url.is_some())));
+    values
+}
+
+fn file_state(path: PathBuf) -> String {
+    format!("{} ({})", path.display(), if path.exists() { "exists" } else { "not found" })
+}
+
+/// Prints the configuration values that differ from the defaults, or all values with `resolved`
+pub(crate) fn show(opt: &Opt, files: &ConfigFiles, resolved: bool) -> bool {
+    let mut table = Table::new();
+    table.add_row(row!["Key", "Value", "Source"]);
+    for v in resolve(opt, files).into_iter().filter(|v| resolved || !v.source.is_default()) {
+        table.add_row(row![v.key, v.value, v.source]);
+    }
+    if table.len() == 1 {
+        println!("All values are defaults. Use --resolved to show them.");
+    } else {
+        table.printstd();
+    }
+
+    println!("\nFiles:");
+    println!("  User config:    {}", file_state(user_config_file()));
+    println!("  Project config: {}", file_state(PathBuf::from(PROJECT_CONFIG_FILE)));
+    match login::uses_session_file() {
+        true => println!("  Session:        {}", file_state(login::user_session_file())),
+        false => println!("  Session:        Secret store of the platform"),
+    }
+    println!("  Registry cache: {}", file_state(registry::registry_cache_file()));
+    println!("  Cache:          {}", file_state(cache::directory()));
+    println!("  Build state:    {}", file_state(state::state_file(Path::new("out"))));
+    true
+}
+
+/// Prints the effective value of a key
+pub(crate) fn get(opt: &Opt, files: &ConfigFiles, key: &str) -> bool {
+    match resolve(opt, files).into_iter().find(|v| v.key == key) {
+        Some(v) => {
+            println!("{}", v.value);
+            true
+        }
+        None => {
+            error!("{} Unknown key {}", error_codes::CONFIG_INVALID, key);
+            false
+        }
+    }
+}
+
+/// Sets a key of the user or, with `project`, of the project configuration file. Without a value the key is removed.
+pub(crate) fn set(key: &str, value: Option<&str>, project: bool) -> bool {
+    let path = if project { PathBuf::from(PROJECT_CONFIG_FILE) } else { user_config_file() };
+    let mut config = match read(&path) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} {}", error_codes::CONFIG_INVALID, e);
+            return false;
+        }
+    };
+    if let Err(e) = config.set(key, value) {
+        error!("{} {}", error_codes::CONFIG_INVALID, e);
+        return false;
+    }
+    let data = toml::to_string(&config).expect("serializable configuration");
+    let written = path.parent().filter(|p| !p.as_os_str().is_empty()).map(std::fs::create_dir_all).unwrap_or(Ok(()))
+        .and_then(|_| std::fs::write(&path, data));
+    if let Err(e) = written {
+        error!("{} Failed to write {}: {:?}", error_codes::FILE_ACCESS, path.display(), e);
+        return false;
+    }
+    match value {
+        Some(value) => println!("{} = {} ({})", key, value, path.display()),
+        None => println!("{} removed ({})", key, path.display()),
+    }
+    true
+}
+
+#[test]
+fn config_file_set_test() {
+    let mut config = ConfigFile::default();
+    assert!(config.set("archs", Some("amd64, aarch64")).is_ok());
+    assert_eq!(config.archs, Some(vec!["amd64".to_owned(), "aarch64".to_owned()]));
+    assert!(config.set("engine", Some("rkt")).is_err());
+    assert!(config.set("colour", Some("true")).is_err());
+    assert!(config.set("verbose", Some("1")).is_ok());
+    assert!(config.set("archs", None).is_ok());
+    assert_eq!(toml::to_string(&config).unwrap(), "verbose = 1\n");
+    assert!(config.set("profiles.staging.registry-url", Some("https://staging.example.com")).is_ok());
+    assert_eq!(config.get("profiles.staging.registry-url").as_deref(), Some("https://staging.example.com"));
+    assert!(config.set("profiles.staging.color", Some("red")).is_err());
+    assert_eq!(toml::to_string(&config).unwrap(),
+               "verbose = 1\n[profiles.staging]\nregistry-url = \"https://staging.example.com\"\n");
+}
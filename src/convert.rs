@@ -0,0 +1,220 @@
+//! `convert --from docker-compose.yml`: A starting addons.yml from a compose file. Services with their image or build
+//! context, ports, volumes, depends_on, capabilities and devices are taken over. Everything else is listed as not
+//! converted, the addon metadata must be filled in by the author.
+use crate::dto::addons::{AddonEntryCommon, AddonFileEntry, AddonRuntimeRequirements, AddonService, BuildContext};
+use crate::dto::migrate;
+use crate::error_codes;
+use crate::registry;
+use log::{error, warn};
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Top level keys without addon meaning
+const IGNORED_KEYS: [&str; 2] = ["version", "name"];
+
+/// Compose files allow strings and numbers for most list entries
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(v) => Some(v.clone()),
+        Value::Number(v) => Some(v.to_string()),
+        _ => None,
+    }
+}
+
+fn string_list(value: &Value) -> Option<Vec<String>> {
+    match value {
+        Value::Sequence(list) => list.iter().map(scalar).collect(),
+        other => scalar(other).map(|v| vec![v]),
+    }
+}
+
+/// "8080:80", "127.0.0.1:8080:80/udp" or the long syntax `{target: 80, published: 8080, protocol: udp}`
+fn port(value: &Value, path: &str, unsupported: &mut Vec<String>) -> Option<String> {
+    if let Some(port) = scalar(value) {
+        let parts: Vec<&str> = port.split(':').collect();
+        if parts.len() == 3 {
+            unsupported.push(format!("{}: The host address {} is dropped", path, parts[0]));
+            return Some(parts[1..].join(":"));
+        }
+        return Some(port);
+    }
+    let target = value.get("target").and_then(scalar)?;
+    let mut port = match value.get("published").and_then(scalar) {
+        Some(published) => format!("{}:{}", published, target),
+        None => target,
+    };
+    if let Some(protocol) = value.get("protocol").and_then(scalar).filter(|p| p != "tcp") {
+        port = format!("{}/{}", port, protocol);
+    }
+    Some(port)
+}
+
+/// "data:/var/lib/app" or the long syntax `{type: volume, source: data, target: /var/lib/app, read_only: true}`
+fn volume(value: &Value) -> Option<String> {
+    if let Some(volume) = scalar(value) {
+        return Some(volume);
+    }
+    let target = value.get("target").and_then(scalar)?;
+    let mut volume = match value.get("source").and_then(scalar) {
+        Some(source) => format!("{}:{}", source, target),
+        None => target,
+    };
+    if value.get("read_only").and_then(|v| v.as_bool()) == Some(true) {
+        volume.push_str(":ro");
+    }
+    Some(volume)
+}
+
+fn convert_service(id: &str, service: &Value, unsupported: &mut Vec<String>) -> AddonService {
+    let path = format!("services.{}", id);
+    let mut converted = AddonService::default();
+    let map = match service.as_mapping() {
+        Some(v) => v,
+        None => {
+            unsupported.push(format!("{}: Not a mapping", path));
+            return converted;
+        }
+    };
+    for (key, value) in map {
+        let key = key.as_str().unwrap_or_default();
+        let field_path = format!("{}.{}", path, key);
+        match key {
+            "image" => converted.image = scalar(value),
+            "build" => {
+                let context = scalar(value).or_else(|| value.get("context").and_then(scalar)).unwrap_or_else(|| ".".to_owned());
+                if value.is_mapping() && value.as_mapping().map(|m| m.len()).unwrap_or(0) > 1 {
+                    unsupported.push(format!("{}: Only the context is converted. Dockerfiles are found by name, \
+                                              like Dockerfile and Dockerfile.armv6, next to addons.yml", field_path));
+                }
+                converted.build = Some(BuildContext { context, ..Default::default() });
+            }
+            "ports" => converted.ports = value.as_sequence().map(|list| list.iter().enumerate()
+                .filter_map(|(index, p)| port(p, &format!("{}[{}]", field_path, index), unsupported)).collect()),
+            "volumes" => converted.volumes = value.as_sequence().map(|list| list.iter().filter_map(volume).collect()),
+            "depends_on" => {
+                // The long syntax is a mapping of service names to conditions
+                converted.depends_on = match value {
+                    Value::Mapping(conditions) => {
+                        unsupported.push(format!("{}: The start conditions are dropped", field_path));
+                        Some(conditions.iter().filter_map(|(k, _)| k.as_str().map(|s| s.to_owned())).collect())
+                    }
+                    other => string_list(other),
+                };
+            }
+            "pid" => converted.pid = scalar(value),
+            "ipc" => converted.ipc = scalar(value),
+            "cap_add" | "cap_drop" | "devices" => {
+                let list = string_list(value);
+                if list.is_none() {
+                    unsupported.push(format!("{}: Expected a list of strings", field_path));
+                }
+                match key {
+                    "cap_add" => converted.cap_add = list,
+                    "cap_drop" => converted.cap_drop = list,
+                    _ => converted.devices = list,
+                }
+            }
+            _ => unsupported.push(format!("{}: Not supported by addons", field_path)),
+        }
+    }
+    converted
+}
+
+/// The services of a compose file and a description of everything that is not converted
+fn convert_services(compose: &Value) -> (HashMap<String, AddonService>, Vec<String>) {
+    let mut unsupported = Vec::new();
+    let mut services = HashMap::new();
+    for (key, value) in compose.as_mapping().into_iter().flatten() {
+        let key = key.as_str().unwrap_or_default();
+        match key {
+            "services" => {
+                for (id, service) in value.as_mapping().into_iter().flatten() {
+                    let id = id.as_str().unwrap_or_default();
+                    services.insert(id.to_owned(), convert_service(id, service, &mut unsupported));
+                }
+            }
+            _ if IGNORED_KEYS.contains(&key) || key.starts_with("x-") => {}
+            _ => unsupported.push(format!("{}: Not supported by addons", key)),
+        }
+    }
+    (services, unsupported)
+}
+
+/// Writes the addons.yml for the compose file and lists the features that are not converted
+pub(crate) fn convert(from: &Path, output: &Path, force: bool) -> bool {
+    if output.exists() && !force {
+        error!("{} {} already exists. Use --force to overwrite it.", error_codes::FILE_EXISTS, output.display());
+        return false;
+    }
+    let compose: Value = match std::fs::read(from).map_err(failure::Error::from)
+        .and_then(|v| serde_yaml::from_slice(&v).map_err(failure::Error::from)) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} Failed to read {}: {}", error_codes::FILE_ACCESS, from.display(), e);
+            return false;
+        }
+    };
+    let (services, unsupported) = convert_services(&compose);
+    if services.is_empty() {
+        error!("{} {} has no services", error_codes::INPUT_FILE_INVALID, from.display());
+        return false;
+    }
+    let directory = from.canonicalize().ok().and_then(|f| f.parent().and_then(|d| d.file_name().map(|n| n.to_string_lossy().to_lowercase())));
+    let id = directory.unwrap_or_else(|| "my-addon".to_owned());
+    let descriptor = AddonFileEntry {
+        schema_version: migrate::CURRENT_SCHEMA_VERSION,
+        services,
+        x_ohx_registry: AddonEntryCommon {
+            title: id.clone(),
+            type_field: registry::type_profiles().keys().next().cloned().unwrap_or_else(|| "binding".to_owned()),
+            license: "MIT".to_owned(),
+            id,
+            version: "0.1.0".to_owned(),
+            ..Default::default()
+        },
+        x_runtime: AddonRuntimeRequirements { memory_min: 16, memory_max: 64 },
+        ..Default::default()
+    };
+    let written = serde_yaml::to_string(&descriptor).map_err(failure::Error::from)
+        .and_then(|v| std::fs::write(output, v).map_err(failure::Error::from));
+    if let Err(e) = written {
+        error!("{} Failed to write {}: {}", error_codes::FILE_ACCESS, output.display(), e);
+        return false;
+    }
+    for entry in &unsupported {
+        warn!("Not converted: {}", entry);
+    }
+    println!("Created {}. Complete the x-ohx-registry section, like the description, authors and license, \
+              and the x-runtime memory requirements, then run `validate`.", output.display());
+    true
+}
+
+#[test]
+fn convert_test() {
+    let compose: Value = serde_yaml::from_str(r#"
+version: "3.7"
+services:
+  app:
+    build: {context: ., dockerfile: Dockerfile.prod}
+    ports: ["127.0.0.1:8080:80", {target: 53, published: 5353, protocol: udp}]
+    volumes: [{type: volume, source: data, target: /data, read_only: true}]
+    depends_on: {db: {condition: service_healthy}}
+    cap_drop: ALL
+    environment: {DEBUG: "1"}
+  db:
+    image: postgres:12
+networks: {default: {}}
+"#).unwrap();
+    let (services, unsupported) = convert_services(&compose);
+    let app = &services["app"];
+    assert_eq!(app.build.as_ref().map(|b| b.context.as_str()), Some("."));
+    assert_eq!(app.ports, Some(vec!["8080:80".to_owned(), "5353:53/udp".to_owned()]));
+    assert_eq!(app.volumes, Some(vec!["data:/data:ro".to_owned()]));
+    assert_eq!(app.depends_on, Some(vec!["db".to_owned()]));
+    assert_eq!(app.cap_drop, Some(vec!["ALL".to_owned()]));
+    assert_eq!(services["db"].image.as_deref(), Some("postgres:12"));
+    assert_eq!(unsupported.len(), 5);
+    assert!(unsupported.iter().any(|u| u.starts_with("services.app.environment")));
+    assert!(unsupported.iter().any(|u| u.starts_with("networks")));
+}
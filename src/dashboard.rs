@@ -0,0 +1,312 @@
+//! `workspace publish --dashboard`: A live grid of the addons and their publish stages. While it is shown, the
+//! output and the log records of each addon are collected per addon instead of being printed, so that the
+//! publishes of several addons do not interleave. After the run the log of an addon can be selected, and
+//! `--dashboard-export` writes the grid and the logs as JSON.
+//!
+//! The grid is drawn with `console`, which the CLI already uses for its spinners. Key input is only read after
+//! the run, because the publishes run on the main thread.
+use crate::error_codes;
+use crate::output;
+use crate::workspace::{Outcome, Status};
+use console::{style, Key, Term};
+use log::{error, warn};
+use ohx_addon_publish::pipeline::Reporter;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Log lines of the running addon below the grid, for example the code of a device login
+const TAIL_LINES: usize = 4;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// Log records are also collected from other threads, like the vault credentials thread of a publish
+static DASHBOARD: Mutex<Option<Dashboard>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum State {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+struct Row {
+    label: String,
+    stages: Vec<(String, State)>,
+    /// None while the addon is not done
+    status: Option<Status>,
+    log: Vec<String>,
+}
+
+struct Dashboard {
+    rows: Vec<Row>,
+    /// The stages of all addons. A stage that only some addons run is placed after the stage it follows.
+    columns: Vec<String>,
+    current: usize,
+    /// The number of lines to clear before the grid is drawn again
+    drawn: usize,
+}
+
+impl Dashboard {
+    fn new(root: &Path, files: &[PathBuf]) -> Self {
+        let rows = files.iter().map(|file| Row {
+            label: file.strip_prefix(root).unwrap_or(file).display().to_string(),
+            stages: Vec::new(),
+            status: None,
+            log: Vec::new(),
+        }).collect();
+        Dashboard { rows, columns: Vec::new(), current: 0, drawn: 0 }
+    }
+
+    fn started(&mut self, stage: &str) {
+        let columns = &mut self.columns;
+        let row = &mut self.rows[self.current];
+        if !columns.iter().any(|c| c == stage) {
+            let previous = row.stages.last().and_then(|(name, _)| columns.iter().position(|c| c == name));
+            columns.insert(previous.map_or(0, |index| index + 1), stage.to_owned());
+        }
+        row.stages.push((stage.to_owned(), State::Running));
+    }
+
+    fn finished(&mut self, stage: &str, success: bool) {
+        let row = &mut self.rows[self.current];
+        if let Some(entry) = row.stages.iter_mut().rev().find(|(name, _)| name == stage) {
+            entry.1 = if success { State::Succeeded } else { State::Failed };
+        }
+    }
+
+    fn render(&self, width: usize) -> Vec<String> {
+        let label_width = self.rows.iter().map(|r| r.label.chars().count()).max().unwrap_or(0).max("Addon".len());
+        let mut header = format!("{:width$}", "Addon", width = label_width);
+        for number in 1..=self.columns.len() {
+            header.push_str(&format!(" {:>2}", number));
+        }
+        let mut lines = vec![style(header).bold().to_string()];
+        for (index, row) in self.rows.iter().enumerate() {
+            let mut line = format!("{:width$}", row.label, width = label_width);
+            for column in &self.columns {
+                let cell = match row.stages.iter().rev().find(|(name, _)| name == column).map(|(_, state)| *state) {
+                    None => style("·").dim(),
+                    Some(State::Running) => style("…").yellow(),
+                    Some(State::Succeeded) => style("✔").green(),
+                    Some(State::Failed) => style("✘").red(),
+                };
+                line.push_str(&format!("  {}", cell));
+            }
+            let status = match (row.status, row.stages.last()) {
+                (Some(Status::Failed), _) => style(Status::Failed.name().to_owned()).red(),
+                (Some(status), _) => style(status.name().to_owned()).green(),
+                (None, Some((stage, _))) if index == self.current => style(stage.clone()).yellow(),
+                (None, _) if index == self.current => style("validate".to_owned()).yellow(),
+                (None, _) => style("waiting".to_owned()).dim(),
+            };
+            lines.push(format!("{}  {}", line, status));
+        }
+
+        // Wrapped, so that the number of drawn lines is known when they are cleared
+        let mut legend = String::new();
+        for (index, column) in self.columns.iter().enumerate() {
+            let item = format!("{} {}", index + 1, column);
+            if !legend.is_empty() && legend.len() + item.len() + 2 > width {
+                lines.push(style(std::mem::take(&mut legend)).dim().to_string());
+            }
+            if !legend.is_empty() {
+                legend.push_str("  ");
+            }
+            legend.push_str(&item);
+        }
+        if !legend.is_empty() {
+            lines.push(style(legend).dim().to_string());
+        }
+
+        let log = &self.rows[self.current].log;
+        for entry in &log[log.len().saturating_sub(TAIL_LINES)..] {
+            let entry: String = entry.replace('\t', "  ").chars().take(width.saturating_sub(2)).collect();
+            lines.push(format!("  {}", style(entry).dim()));
+        }
+        lines
+    }
+
+    fn draw(&mut self) {
+        let term = Term::stderr();
+        if self.drawn > 0 {
+            let _ = term.clear_last_lines(self.drawn);
+        }
+        let lines = self.render(term.size().1 as usize);
+        for line in &lines {
+            let _ = term.write_line(line);
+        }
+        self.drawn = lines.len();
+    }
+}
+
+fn update<F: FnOnce(&mut Dashboard)>(f: F) {
+    if let Some(dashboard) = DASHBOARD.lock().expect("dashboard lock").as_mut() {
+        f(dashboard);
+        dashboard.draw();
+    }
+}
+
+/// Shows the grid for the given addon description files. Returns false, and the dashboard is not shown,
+/// if stderr is no terminal or the output is plain.
+pub(crate) fn start(root: &Path, files: &[PathBuf]) -> bool {
+    if output::is_plain() || !Term::stderr().is_term() {
+        warn!("The dashboard requires a terminal and no plain or JSON output. The output is printed instead.");
+        return false;
+    }
+    let mut dashboard = Dashboard::new(root, files);
+    dashboard.draw();
+    *DASHBOARD.lock().expect("dashboard lock") = Some(dashboard);
+    ACTIVE.store(true, Ordering::SeqCst);
+    true
+}
+
+pub(crate) fn is_active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+/// The addon with the given index of the description files is processed now
+pub(crate) fn select(index: usize) {
+    update(|d| d.current = index);
+}
+
+/// Records the result of the processed addon
+pub(crate) fn finish_addon(status: Status) {
+    update(|d| {
+        let current = d.current;
+        d.rows[current].status = Some(status);
+    });
+}
+
+/// Adds an output line or log record to the log of the processed addon. Returns false if the dashboard
+/// is not shown and the message is to be printed.
+pub(crate) fn capture(message: &str) -> bool {
+    if !is_active() {
+        return false;
+    }
+    update(|d| {
+        let current = d.current;
+        d.rows[current].log.extend(message.lines().map(|l| l.to_owned()));
+    });
+    true
+}
+
+/// Reports the publish stages of the processed addon
+pub(crate) struct StageReporter;
+
+impl Reporter for StageReporter {
+    fn started(&self, stage: &str, _index: usize, _count: usize) {
+        if is_active() {
+            update(|d| d.started(stage));
+        }
+    }
+
+    fn finished(&self, stage: &str, success: bool) {
+        if is_active() {
+            update(|d| d.finished(stage, success));
+        }
+    }
+}
+
+fn export(dashboard: &Dashboard, outcomes: &[Outcome], file: &Path) -> bool {
+    let addons: Vec<serde_json::Value> = dashboard.rows.iter().zip(outcomes).map(|(row, outcome)| {
+        let stages: Vec<serde_json::Value> = row.stages.iter()
+            .map(|(stage, state)| serde_json::json!({ "stage": stage, "state": state }))
+            .collect();
+        serde_json::json!({
+            "input_file": outcome.input_file,
+            "id": outcome.id,
+            "version": outcome.version,
+            "status": outcome.status,
+            "stages": stages,
+            "log": row.log,
+        })
+    }).collect();
+    let document = serde_json::json!({ "stages": dashboard.columns, "addons": addons });
+    match std::fs::write(file, serde_json::to_string_pretty(&document).expect("serializable dashboard")) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("{} Failed to write the dashboard export {}: {}", error_codes::FILE_ACCESS, file.display(), e);
+            false
+        }
+    }
+}
+
+/// Stops collecting the output. The final grid stays on the terminal. Writes the export file, if given,
+/// and returns false if that failed.
+pub(crate) fn stop(outcomes: &[Outcome], export_file: Option<&Path>) -> bool {
+    ACTIVE.store(false, Ordering::SeqCst);
+    let guard = DASHBOARD.lock().expect("dashboard lock");
+    match (guard.as_ref(), export_file) {
+        (Some(dashboard), Some(file)) => export(dashboard, outcomes, file),
+        _ => true,
+    }
+}
+
+/// Lets the user select addons to show their log, until q or Escape is pressed. Not interactive runs skip this.
+pub(crate) fn browse_logs() {
+    let dashboard = match DASHBOARD.lock().expect("dashboard lock").take() {
+        Some(v) => v,
+        None => return,
+    };
+    let term = Term::stderr();
+    if !output::is_interactive() || !term.is_term() {
+        return;
+    }
+    let mut selected = 0;
+    loop {
+        let mut lines = vec![style("Show the log of an addon: ↑/↓ to select, Enter to show, q to quit").dim().to_string()];
+        for (index, row) in dashboard.rows.iter().enumerate() {
+            let marker = if index == selected { style(">").bold() } else { style(" ") };
+            lines.push(format!("{} {}", marker, row.label));
+        }
+        for line in &lines {
+            let _ = term.write_line(line);
+        }
+        let key = match term.read_key() {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        let _ = term.clear_last_lines(lines.len());
+        match key {
+            Key::ArrowUp => selected = selected.saturating_sub(1),
+            Key::ArrowDown => selected = (selected + 1).min(dashboard.rows.len() - 1),
+            Key::Enter => {
+                let row = &dashboard.rows[selected];
+                let _ = term.write_line(&style(format!("Log of {}", row.label)).bold().to_string());
+                for entry in &row.log {
+                    let _ = term.write_line(entry);
+                }
+                let _ = term.write_line("");
+            }
+            Key::Escape | Key::Char('q') => return,
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn dashboard_test() {
+    let root = Path::new("repo");
+    let mut dashboard = Dashboard::new(root, &[root.join("mqtt").join("addons.yml"), root.join("zigbee").join("addons.yml")]);
+    dashboard.started("validate");
+    dashboard.finished("validate", true);
+    dashboard.started("registry");
+    dashboard.finished("registry", false);
+
+    // A stage of the second addon only is placed after the stage it follows
+    dashboard.current = 1;
+    dashboard.started("validate");
+    dashboard.finished("validate", true);
+    dashboard.started("image-references");
+    assert_eq!(dashboard.columns, vec!["validate", "image-references", "registry"]);
+    assert_eq!(dashboard.rows[0].label, Path::new("mqtt").join("addons.yml").display().to_string());
+    assert_eq!(dashboard.rows[0].stages, vec![("validate".to_owned(), State::Succeeded), ("registry".to_owned(), State::Failed)]);
+    assert_eq!(dashboard.rows[1].stages[1], ("image-references".to_owned(), State::Running));
+
+    dashboard.rows[1].log = (0..6).map(|n| format!("line {}", n)).collect();
+    let lines = dashboard.render(80);
+    assert_eq!(lines.len(), 1 + 2 + 1 + TAIL_LINES);
+    assert!(lines.last().unwrap().contains("line 5"));
+}
@@ -1,4 +1,7 @@
 use indicatif::{ProgressBar, ProgressStyle};
+use crate::error_codes;
+use crate::http;
+use crate::output;
 use tokio::codec::{FramedRead, LinesCodec};
 use tokio::{prelude::*, runtime::Runtime};
 use tokio_net::process::Command;
@@ -6,10 +9,13 @@ use tokio_net::process::Command;
 use crate::dto::{BuildInstruction};
 use serde::{Deserialize};
 
-use log::{error};
+use log::{debug, error, info, warn};
 use crate::login::UserSession;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use crate::podman::{self, PodmanCapabilities};
+use crate::buildah;
 
 #[allow(non_snake_case)]
 #[derive(Deserialize)]
@@ -18,129 +24,918 @@ struct DockerCredentials {
     Secret: String,
 }
 
-pub fn get_access_credentials(client: &reqwest::Client, session: &UserSession) -> Option<String> {
-    let docker_credentials: DockerCredentials = match client.get("https://vault.openhabx.com/get/docker-access.json").bearer_auth(&session.access_token).send() {
-        Ok(mut response) => {
-            let response: Result<DockerCredentials, _> = response.json();
-            if let Ok(response) = response {
-                response
-            } else {
-                error!("Unexpected response!\n{:?}", response.err().unwrap());
+/// Get the docker registry credentials ("username:secret") from the vault.
+/// An addon specific secret is preferred, so that credentials can be scoped and rotated per addon.
+/// The shared secret is used if the vault has no entry for the addon.
+pub fn get_access_credentials(client: &reqwest::Client, vault_url: &str, session: &UserSession, addon_id: &str) -> Option<String> {
+    let addon_url = format!("{}/get/docker-access/{}.json", vault_url, addon_id);
+    let shared_url = format!("{}/get/docker-access.json", vault_url);
+    for url in &[addon_url, shared_url] {
+        let docker_credentials: DockerCredentials = match session.authorize(client.get(url)).send() {
+            Ok(ref response) if response.status() == 404 => {
+                debug!("No docker access credentials at {}", url);
+                continue;
+            }
+            Ok(mut response) => {
+                let response: Result<DockerCredentials, _> = response.json();
+                if let Ok(response) = response {
+                    response
+                } else {
+                    error!("{} Unexpected response!\n{:?}", error_codes::DOCKER_CREDENTIALS, response.err().unwrap());
+                    return None;
+                }
+            }
+            Err(err) => {
+                error!("{} Failed to contact {}!\n{:?}", error_codes::REGISTRY_UNREACHABLE, url, err);
                 return None;
             }
+        };
+        return Some(docker_credentials.Username + ":" + &docker_credentials.Secret);
+    }
+    error!("{} No docker access credentials found in the vault", error_codes::DOCKER_CREDENTIALS);
+    None
+}
+
+/// Returns true if the engine output indicates rejected registry credentials
+fn is_auth_failure(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    stderr.contains("unauthorized") || stderr.contains("authentication required") || stderr.contains("denied")
+}
+
+#[derive(Deserialize)]
+struct RegistryToken {
+    token: String,
+}
+
+const MANIFEST_MEDIA_TYPES: &str = "application/vnd.docker.distribution.manifest.v2+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.oci.image.index.v1+json";
+
+/// Splits an image name like "docker.io/openhabx/addon_amd64:1.0" into repository and tag
+fn repository_and_tag(image_name: &str) -> (&str, &str) {
+    let image = image_name.trim_start_matches("docker.io/");
+    match image.rfind(':') {
+        Some(pos) => (&image[..pos], &image[pos + 1..]),
+        None => (image, "latest"),
+    }
+}
+
+/// A pull token for the repository. Anonymous if the credentials are empty, which works for public images.
+fn pull_token(client: &reqwest::Client, repository: &str, docker_credentials: &str) -> Result<String, failure::Error> {
+    let token_url = format!("https://auth.docker.io/token?service=registry.docker.io&scope=repository:{}:pull", repository);
+    let mut request = client.get(&token_url);
+    if !docker_credentials.is_empty() {
+        let mut credentials = docker_credentials.splitn(2, ':');
+        let username = credentials.next().unwrap_or_default();
+        request = request.basic_auth(username, credentials.next());
+    }
+    let token: RegistryToken = request.send()?.error_for_status()?.json()?;
+    Ok(token.token)
+}
+
+/// Asks the docker registry for the manifest of an image like "docker.io/openhabx/addon_amd64:1.0".
+/// Returns the manifest digest, or None if the image does not exist.
+pub(crate) fn remote_manifest_digest(client: &reqwest::Client, image_name: &str, docker_credentials: &str) -> Result<Option<String>, failure::Error> {
+    let (repository, tag) = repository_and_tag(image_name);
+    let token = pull_token(client, repository, docker_credentials)?;
+
+    let response = client.head(&format!("https://registry-1.docker.io/v2/{}/manifests/{}", repository, tag))
+        .bearer_auth(&token)
+        .header(reqwest::header::ACCEPT, MANIFEST_MEDIA_TYPES)
+        .send()?;
+    if response.status() == 404 {
+        return Ok(None);
+    }
+    let response = response.error_for_status()?;
+    let digest = response.headers().get("docker-content-digest")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    Ok(Some(digest))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ManifestDescriptor {
+    pub(crate) digest: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ImageManifest {
+    pub(crate) config: ManifestDescriptor,
+}
+
+/// Like `remote_manifest_digest`, but downloads the manifest. Returns the manifest and the image config digest.
+/// Unlike the manifest digest, the config digest does not depend on the layer compression and can be compared
+/// with exported images.
+pub(crate) fn remote_config_digest(client: &reqwest::Client, image_name: &str, docker_credentials: &str) -> Result<Option<(String, String)>, failure::Error> {
+    let (repository, tag) = repository_and_tag(image_name);
+    let token = pull_token(client, repository, docker_credentials)?;
+
+    let response = client.get(&format!("https://registry-1.docker.io/v2/{}/manifests/{}", repository, tag))
+        .bearer_auth(&token)
+        .header(reqwest::header::ACCEPT, "application/vnd.docker.distribution.manifest.v2+json, application/vnd.oci.image.manifest.v1+json")
+        .send()?;
+    if response.status() == 404 {
+        return Ok(None);
+    }
+    let mut response = response.error_for_status()?;
+    let digest = response.headers().get("docker-content-digest")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    let manifest: ImageManifest = response.json()?;
+    Ok(Some((digest, manifest.config.digest)))
+}
+
+/// The container engine executable and its global options, like the connection to a remote host
+#[derive(Clone)]
+pub(crate) struct EngineCli {
+    /// "podman", "docker" or "buildah"
+    pub(crate) binary: &'static str,
+    pub(crate) global_args: Vec<String>,
+}
+
+impl EngineCli {
+    pub(crate) fn local(binary: &'static str) -> Self {
+        EngineCli { binary, global_args: Vec::new() }
+    }
+
+    /// A command for short engine calls like `image inspect`
+    pub(crate) fn command(&self) -> std::process::Command {
+        let mut command = std::process::Command::new(self.binary);
+        command.args(&self.global_args);
+        command
+    }
+
+    /// A command with streamed output, like `build` and `push`
+    fn streamed_command(&self) -> Command {
+        let mut command = Command::new(self.binary);
+        command.args(&self.global_args);
+        command
+    }
+}
+
+/// A container engine, that builds and pushes images. podman and docker share the same command line
+/// for inspecting, creating and exporting images, so only building and pushing is abstracted.
+pub(crate) trait ContainerEngine {
+    /// The executable and its global options
+    fn cli(&self) -> &EngineCli;
+
+    /// The command that builds the image of the build instruction in the addon directory
+    fn build_command(&self, build_instruction: &BuildInstruction, docker_credentials: Option<&str>) -> Command;
+
+    /// Makes the credentials available for `push_command`
+    fn login(&self, _docker_credentials: &str) -> bool {
+        true
+    }
+
+    /// The command that pushes an image. The digest is written to `digest_file`, if given.
+    fn push_command(&self, image_name: &str, docker_credentials: &str, digest_file: Option<&Path>) -> Command;
+
+    /// Returns true if `push_command` is able to write a digest file
+    fn digest_file(&self) -> bool;
+
+    /// Returns true if the engine creates and pushes multi-arch manifest lists (`manifest create/add/push`)
+    fn manifest_lists(&self) -> bool {
+        false
+    }
+
+    /// Returns true if `build_command` passes the `build.secrets` of addons.yml
+    fn build_secrets(&self) -> bool {
+        false
+    }
+
+    /// Returns true if `save_oci_archive` is able to export images
+    fn oci_archive(&self) -> bool {
+        false
+    }
+}
+
+/// The `--build-arg` options of a variant. podman, docker and buildah share them.
+fn build_args(command: &mut Command, build_instruction: &BuildInstruction) {
+    for (key, value) in &build_instruction.build_args {
+        command.arg("--build-arg").arg(format!("{}={}", key, value));
+    }
+}
+
+/// The `--secret` options for the `build.secrets` of addons.yml. A file starting with `~/` is in the home directory.
+fn secret_args(command: &mut Command, build_instruction: &BuildInstruction) {
+    for (id, file) in &build_instruction.secrets {
+        let file = match (file.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(file), Some(home)) => home.join(file).display().to_string(),
+            _ => file.to_owned(),
+        };
+        command.arg("--secret").arg(format!("id={},src={}", id, file));
+    }
+}
+
+/// `--platform` for the 32 bit ARM architectures. A multi-arch base image would otherwise resolve to the ARM variant
+/// of the build host, like an ARMv7 image on a Raspberry Pi 3 for the armv6 Dockerfile.
+fn arm_platform(command: &mut Command, build_instruction: &BuildInstruction) {
+    if buildah::oci_variant(&build_instruction.arch).is_some() {
+        command.arg("--platform").arg(buildah::platform(&build_instruction.arch));
+    }
+}
+
+pub(crate) struct Podman {
+    pub(crate) capabilities: PodmanCapabilities,
+    pub(crate) cli: EngineCli,
+}
+
+impl ContainerEngine for Podman {
+    fn cli(&self) -> &EngineCli {
+        &self.cli
+    }
+
+    fn build_command(&self, build_instruction: &BuildInstruction, docker_credentials: Option<&str>) -> Command {
+        let mut command = self.cli.streamed_command();
+        command.arg("build")
+            .arg("-t")
+            .arg(&build_instruction.image_name)
+            .arg("-f")
+            .arg(&build_instruction.filename);
+        arm_platform(&mut command, build_instruction);
+        build_args(&mut command, build_instruction);
+        secret_args(&mut command, build_instruction);
+        if build_instruction.pull {
+            command.arg("--pull-always");
         }
-        Err(err) => {
-            error!("Failed to contact https://vault.openhabx.com/get/docker-access.json!\n{:?}", err);
-            return None;
+        if let Some(docker_credentials) = docker_credentials {
+            command.arg(format!("--creds={}", docker_credentials));
+        }
+        command
+    }
+
+    fn push_command(&self, image_name: &str, docker_credentials: &str, digest_file: Option<&Path>) -> Command {
+        let mut command = self.cli.streamed_command();
+        command.arg("push")
+            .arg(image_name)
+            .arg(format!("--creds={}", &docker_credentials));
+        if let Some(digest_file) = digest_file {
+            command.arg("--digestfile").arg(digest_file);
+        }
+        command
+    }
+
+    fn digest_file(&self) -> bool {
+        self.capabilities.digest_file
+    }
+
+    fn manifest_lists(&self) -> bool {
+        self.capabilities.manifest_lists
+    }
+
+    fn build_secrets(&self) -> bool {
+        self.capabilities.build_secrets
+    }
+
+    fn oci_archive(&self) -> bool {
+        self.capabilities.oci_archive
+    }
+}
+
+/// The docker CLI. Images for other architectures are build with `docker buildx`, if available.
+pub(crate) struct Docker {
+    pub(crate) buildx: bool,
+    pub(crate) cli: EngineCli,
+}
+
+impl ContainerEngine for Docker {
+    fn cli(&self) -> &EngineCli {
+        &self.cli
+    }
+
+    /// Docker has no per command credentials. Base images are pulled with the users docker login.
+    fn build_command(&self, build_instruction: &BuildInstruction, _docker_credentials: Option<&str>) -> Command {
+        let mut command = self.cli.streamed_command();
+        if self.buildx {
+            command.arg("buildx")
+                .arg("build")
+                .arg("--load")
+                .arg("--platform")
+                .arg(buildah::platform(&build_instruction.arch));
+        } else {
+            command.arg("build");
+        }
+        command.arg("-t")
+            .arg(&build_instruction.image_name)
+            .arg("-f")
+            .arg(&build_instruction.filename);
+        build_args(&mut command, build_instruction);
+        if self.buildx {
+            secret_args(&mut command, build_instruction);
+        }
+        if build_instruction.pull {
+            command.arg("--pull");
+        }
+        // podman and buildah forward the proxy variables to RUN instructions by default, docker only as build args
+        for name in http::PROXY_ENV.iter().flat_map(|n| vec![n.to_string(), n.to_lowercase()]) {
+            if std::env::var_os(&name).is_some() {
+                command.arg("--build-arg").arg(name);
+            }
+        }
+        command.arg(".");
+        command
+    }
+
+    /// `docker login` for the docker hub. The password is passed via stdin.
+    fn login(&self, docker_credentials: &str) -> bool {
+        let mut parts = docker_credentials.splitn(2, ':');
+        let (username, password) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+        let child = self.cli.command()
+            .args(["login", "--username", username, "--password-stdin", "docker.io"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{} Failed to start docker login: {:?}", error_codes::ENGINE_MISSING, e);
+                return false;
+            }
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            use std::io::Write;
+            let _ = stdin.write_all(password.as_bytes());
+        }
+        match child.wait_with_output() {
+            Ok(output) if output.status.success() => {
+                // Remember the login, so that only a login of this tool is removed on logout
+                if let Err(e) = std::fs::write(docker_login_marker(), username) {
+                    warn!("Failed to record the docker.io login: {:?}", e);
+                }
+                true
+            }
+            Ok(output) => {
+                error!("{} docker login failed: {}", error_codes::PUSH_FAILED, String::from_utf8_lossy(&output.stderr).trim());
+                false
+            }
+            Err(e) => {
+                error!("{} docker login failed: {:?}", error_codes::PUSH_FAILED, e);
+                false
+            }
+        }
+    }
+
+    fn push_command(&self, image_name: &str, _docker_credentials: &str, _digest_file: Option<&Path>) -> Command {
+        let mut command = self.cli.streamed_command();
+        command.arg("push").arg(image_name);
+        command
+    }
+
+    fn digest_file(&self) -> bool {
+        false
+    }
+
+    /// Build secrets require BuildKit, which `docker buildx` always uses
+    fn build_secrets(&self) -> bool {
+        self.buildx
+    }
+}
+
+/// Written by [`Docker::login`]. A docker.io login of the user without this file is not touched on logout.
+fn docker_login_marker() -> PathBuf {
+    dirs::config_dir().expect("config_dir to exist").join(".ohx_docker_login")
+}
+
+/// Removes the docker hub login that [`Docker::login`] stored for docker. Returns true if there was one.
+/// Logins that this tool did not make, and docker credentials without docker, are left alone.
+pub(crate) fn docker_logout() -> Result<bool, String> {
+    let marker = docker_login_marker();
+    if !marker.exists() {
+        return Ok(false);
+    }
+    let output = match std::process::Command::new("docker").args(["logout", "docker.io"]).output() {
+        Ok(v) => v,
+        Err(_) => return Ok(false),
+    };
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_owned());
+    }
+    let _ = std::fs::remove_file(&marker);
+    // docker prints "Not logged in to docker.io" if there is no login
+    Ok(String::from_utf8_lossy(&output.stdout).contains("Removing login credentials"))
+}
+
+/// buildah without podman, for example in CI images. `buildah bud` understands Dockerfiles.
+pub(crate) struct Buildah {
+    pub(crate) cli: EngineCli,
+    /// `buildah manifest`, like podman
+    pub(crate) manifest_lists: bool,
+    /// `buildah bud --secret`
+    pub(crate) build_secrets: bool,
+}
+
+impl ContainerEngine for Buildah {
+    fn cli(&self) -> &EngineCli {
+        &self.cli
+    }
+
+    fn build_command(&self, build_instruction: &BuildInstruction, docker_credentials: Option<&str>) -> Command {
+        let mut command = self.cli.streamed_command();
+        command.arg("bud")
+            .arg("-t")
+            .arg(&build_instruction.image_name)
+            .arg("-f")
+            .arg(&build_instruction.filename);
+        arm_platform(&mut command, build_instruction);
+        build_args(&mut command, build_instruction);
+        secret_args(&mut command, build_instruction);
+        if build_instruction.pull {
+            command.arg("--pull-always");
+        }
+        if let Some(docker_credentials) = docker_credentials {
+            command.arg(format!("--creds={}", docker_credentials));
+        }
+        command.arg(".");
+        command
+    }
+
+    fn push_command(&self, image_name: &str, docker_credentials: &str, digest_file: Option<&Path>) -> Command {
+        let mut command = self.cli.streamed_command();
+        command.arg("push").arg(format!("--creds={}", &docker_credentials));
+        if let Some(digest_file) = digest_file {
+            command.arg("--digestfile").arg(digest_file);
         }
+        command.arg(image_name).arg(format!("docker://{}", image_name));
+        command
+    }
+
+    fn digest_file(&self) -> bool {
+        true
+    }
+
+    fn manifest_lists(&self) -> bool {
+        self.manifest_lists
+    }
+
+    fn build_secrets(&self) -> bool {
+        self.build_secrets
+    }
+
+    /// `buildah push <image> oci-archive:<file>`
+    fn oci_archive(&self) -> bool {
+        true
+    }
+}
+
+/// The supported container engines for `--engine`
+pub(crate) const ENGINES: [&str; 3] = ["podman", "docker", "buildah"];
+
+/// Returns true if `<binary> --version` runs
+fn installed(binary: &str) -> bool {
+    std::process::Command::new(binary).arg("--version").output().map(|o| o.status.success()).unwrap_or(false)
+}
+
+/// Returns true if the help of the engine subcommand mentions the option or subcommand
+fn help_contains(cli: &EngineCli, args: &[&str], needle: &str) -> bool {
+    cli.command().args(args).arg("--help").output()
+        .map(|o| o.status.success() && String::from_utf8_lossy(&o.stdout).contains(needle))
+        .unwrap_or(false)
+}
+
+/// The installed container engine executable. podman is preferred, then docker and buildah.
+pub(crate) fn installed_binary() -> Option<&'static str> {
+    ENGINES.iter().cloned().find(|b| installed(b))
+}
+
+/// Detects the container engine, or checks the given one (one of `ENGINES`).
+/// Without a given engine podman is preferred, then docker and buildah.
+/// With a `remote_host` like "ssh://builder@host/run/podman/podman.sock" podman and docker build on that host.
+/// Prints a targeted error message if no engine or only an outdated podman is found.
+pub(crate) fn detect_engine(engine: Option<&str>, remote_host: Option<&str>) -> Option<Box<dyn ContainerEngine>> {
+    let binary = match engine {
+        Some(v) => ENGINES.iter().cloned().find(|e| *e == v),
+        None => installed_binary(),
     };
+    let engine: Box<dyn ContainerEngine> = match binary {
+        Some("buildah") => {
+            if remote_host.is_some() {
+                error!("{} buildah can not build on a remote host. Use podman or docker.", error_codes::REMOTE_HOST);
+                return None;
+            }
+            if !buildah::available() {
+                error!("{} 'buildah' is not installed. Please check https://github.com/containers/buildah/blob/master/install.md.", error_codes::ENGINE_MISSING);
+                return None;
+            }
+            info!("Using buildah");
+            let cli = EngineCli::local("buildah");
+            let manifest_lists = help_contains(&cli, &["manifest"], "create");
+            let build_secrets = help_contains(&cli, &["bud"], "--secret");
+            Box::new(Buildah { cli, manifest_lists, build_secrets })
+        }
+        Some("docker") => {
+            if !installed("docker") {
+                error!("{} 'docker' is not installed. Please check https://docs.docker.com/install/.", error_codes::ENGINE_MISSING);
+                return None;
+            }
+            let mut cli = EngineCli::local("docker");
+            if let Some(remote_host) = remote_host {
+                cli.global_args = vec!["--host".to_owned(), remote_host.to_owned()];
+            }
+            let buildx = cli.command().args(["buildx", "version"]).output()
+                .map(|o| o.status.success()).unwrap_or(false);
+            info!("Using docker{}", if buildx { " with buildx" } else { "" });
+            if !buildx {
+                warn!("docker buildx is not available. Images for other architectures require qemu and matching base images.");
+            }
+            Box::new(Docker { buildx, cli })
+        }
+        // Also reports a missing podman. For remote builds the local podman client talks to the remote host.
+        _ => {
+            let capabilities = podman::detect()?;
+            if remote_host.is_none() && !podman::check_storage() {
+                return None;
+            }
+            let mut cli = EngineCli::local("podman");
+            if let Some(remote_host) = remote_host {
+                cli.global_args = vec!["--remote".to_owned(), "--url".to_owned(), remote_host.to_owned()];
+            }
+            Box::new(Podman { capabilities, cli })
+        }
+    };
+
+    if let Some(remote_host) = remote_host {
+        match engine.cli().command().arg("info").output() {
+            Ok(ref output) if output.status.success() => info!("Building on {}", remote_host),
+            Ok(output) => {
+                error!("{} The container engine on {} is not reachable: {}", error_codes::REMOTE_HOST, remote_host,
+                       String::from_utf8_lossy(&output.stderr).trim());
+                return None;
+            }
+            Err(e) => {
+                error!("{} The container engine on {} is not reachable: {:?}", error_codes::REMOTE_HOST, remote_host, e);
+                return None;
+            }
+        }
+    }
+    Some(engine)
+}
+
+/// The containers-registries.conf(5) content that pulls docker.io base images from the mirrors, in order.
+/// Pushes still go to docker.io.
+fn registries_conf(mirrors: &[String]) -> String {
+    let mut conf = "# Generated by ohx-addon-publish from the configured mirrors\n\
+        unqualified-search-registries = [\"docker.io\"]\n\n\
+        [[registry]]\nprefix = \"docker.io\"\nlocation = \"docker.io\"\n".to_owned();
+    for mirror in mirrors {
+        conf += &format!("\n[[registry.mirror]]\nlocation = {:?}\n", mirror);
+    }
+    conf
+}
+
+/// Pulls base images through registry mirrors or pull-through caches, like a local Harbor proxy.
+/// A registries.conf is written to the build directory. podman, buildah and build recipes find it via
+/// CONTAINERS_REGISTRIES_CONF. docker only reads mirrors from its daemon configuration.
+pub(crate) fn use_mirrors(engine: &dyn ContainerEngine, mirrors: &[String], build_directory: &Path, remote: bool) -> bool {
+    if engine.cli().binary == "docker" {
+        warn!("docker ignores --mirror. Configure \"registry-mirrors\" in the docker daemon.json instead.");
+        return true;
+    }
+    if remote {
+        warn!("--mirror only applies to local builds. Configure the mirrors in the registries.conf of the remote host.");
+        return true;
+    }
+    let file = build_directory.join("registries.conf");
+    let written = std::fs::create_dir_all(build_directory)
+        .and_then(|_| std::fs::write(&file, registries_conf(mirrors)));
+    if let Err(e) = written {
+        error!("{} Failed to write {}: {:?}", error_codes::FILE_ACCESS, file.display(), e);
+        return false;
+    }
+    // Absolute, because recipes run in the addon directory
+    let file = file.canonicalize().unwrap_or(file);
+    info!("Pulling base images via {}", mirrors.join(", "));
+    std::env::set_var("CONTAINERS_REGISTRIES_CONF", &file);
+    true
+}
+
+#[derive(Deserialize)]
+struct HubToken {
+    token: String,
+}
+
+/// Deletes a tag like "docker.io/openhabx/addon_amd64:1.0" via the Docker Hub API.
+/// Returns false if the tag did not exist (anymore).
+pub(crate) fn delete_remote_tag(client: &reqwest::Client, image_name: &str, docker_credentials: &str) -> Result<bool, failure::Error> {
+    let image = image_name.trim_start_matches("docker.io/");
+    let (repository, tag) = match image.rfind(':') {
+        Some(pos) => (&image[..pos], &image[pos + 1..]),
+        None => (image, "latest"),
+    };
+    let mut credentials = docker_credentials.splitn(2, ':');
+    let login = serde_json::json!({
+        "username": credentials.next().unwrap_or_default(),
+        "password": credentials.next().unwrap_or_default(),
+    });
+    let token: HubToken = client.post("https://hub.docker.com/v2/users/login/")
+        .json(&login)
+        .send()?
+        .error_for_status()?
+        .json()?;
+
+    let response = client.delete(&format!("https://hub.docker.com/v2/repositories/{}/tags/{}/", repository, tag))
+        .header(reqwest::header::AUTHORIZATION, format!("JWT {}", token.token))
+        .send()?;
+    if response.status() == 404 {
+        return Ok(false);
+    }
+    response.error_for_status()?;
+    Ok(true)
+}
 
-    Some(docker_credentials.Username + ":" + &docker_credentials.Secret)
+/// A new sandbox id, used as tag namespace for trial publishes
+pub(crate) fn sandbox_id() -> String {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    format!("sandbox-{}", now)
 }
 
-pub(crate) fn build_images(runtime:&Runtime, docker_credentials: &str, build_instructions: &mut Vec<BuildInstruction>,
-                    input_file_name:&PathBuf) {
+/// Moves the tag of an image or artifact reference into the sandbox namespace,
+/// for example `openhabx/addon:1.0` to `openhabx/addon:sandbox-123-1.0`
+pub(crate) fn sandbox_reference(reference: &str, sandbox: &str) -> String {
+    match reference.rfind(':') {
+        Some(pos) if !reference[pos..].contains('/') => format!("{}:{}-{}", &reference[..pos], sandbox, &reference[pos + 1..]),
+        _ => format!("{}:{}", reference, sandbox),
+    }
+}
+
+/// The size of an image of the engine in bytes
+pub(crate) fn inspect_image_size(cli: &EngineCli, image_name: &str) -> Option<i64> {
+    if cli.binary == "buildah" {
+        return buildah::inspect_image(image_name).map(|(_, size)| size).filter(|size| *size > 0);
+    }
+    let size_output = cli.command()
+        .arg("image")
+        .arg("inspect")
+        .arg(image_name)
+        .arg("--format={{.Size}}")
+        .output()
+        .ok()?;
+    String::from_utf8(size_output.stdout).ok()?.trim().parse().ok()
+}
+
+/// The registry digest of a pushed image, as recorded by the engine in the local image
+fn repo_digest(cli: &EngineCli, image_name: &str) -> Option<String> {
+    let output = cli.command()
+        .arg("image")
+        .arg("inspect")
+        .arg(image_name)
+        .arg("--format={{index .RepoDigests 0}}")
+        .output()
+        .ok()?;
+    let digest = String::from_utf8(output.stdout).ok()?;
+    digest.trim().rsplit('@').next().filter(|d| d.starts_with("sha256:")).map(|d| d.to_owned())
+}
+
+/// Build all images. The docker registry credentials are used for pulling base images, if given.
+pub(crate) fn build_images(runtime:&Runtime, engine: &dyn ContainerEngine, docker_credentials: Option<&str>,
+                           build_instructions: &mut Vec<BuildInstruction>, input_file_name:&PathBuf) {
     let spinner_style = ProgressStyle::default_spinner()
         .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
         .template("{prefix:.bold.dim} {spinner} {wide_msg}");
 
-    let pb = ProgressBar::new(build_instructions.len() as u64);
+    let pb = output::progress_bar(build_instructions.len() as u64);
     pb.set_style(spinner_style.clone());
     pb.set_prefix("[4/6]");
 
-    for build_instruction in build_instructions {
-        pb.set_message(&format!("Building {} - arch {}", &build_instruction.filename, &build_instruction.arch));
-
-        let mut child = Command::new("podman")
-            .arg("build")
-            .arg("-t")
-            .arg(&build_instruction.image_name)
-            .arg("-f")
-            .arg(&build_instruction.filename)
-            .arg(format!("--creds={}", &docker_credentials))
-            .current_dir(input_file_name.parent().unwrap())
-            .stdout(Stdio::piped())
-            .spawn().unwrap();
+    // Images restored by --resume are already build
+    for build_instruction in build_instructions.iter_mut().filter(|b| !b.skipped && !b.build) {
+        output::progress(&pb, "[4/6]", &format!("Building {} - arch {}", &build_instruction.filename, build_instruction.key()));
 
-        let stdout = child.stdout().take().expect("no stdout");
+        build_instruction.build = match &build_instruction.recipe {
+            Some(recipe) => buildah::build_recipe(recipe, &build_instruction.arch, &build_instruction.image_name,
+                                                  input_file_name.parent().unwrap(), build_instruction.pull),
+            None => {
+                let mut child = engine.build_command(build_instruction, docker_credentials)
+                    .current_dir(input_file_name.parent().unwrap())
+                    .stdout(Stdio::piped())
+                    .spawn().unwrap();
 
-        let mut reader = FramedRead::new(stdout, LinesCodec::new());
-        let pb_output = pb.clone();
-        runtime.spawn(async move {
-            while let Some(line) = reader.next().await {
-                pb_output.set_message(&line.unwrap());
-            }
-        });
+                let stdout = child.stdout().take().expect("no stdout");
 
-        let result = runtime.block_on(child).expect("To block on podman until it finished");
+                let mut reader = FramedRead::new(stdout, LinesCodec::new());
+                let pb_output = pb.clone();
+                runtime.spawn(async move {
+                    while let Some(line) = reader.next().await {
+                        output::progress(&pb_output, "     ", &line.unwrap());
+                    }
+                });
 
-        build_instruction.build = result.success();
+                let result = runtime.block_on(child).expect("To block on the container engine until it finished");
+                result.success()
+            }
+        };
 
         // Determine the size
-        let size_output = std::process::Command::new("podman")
-            .arg("image")
-            .arg("inspect")
-            .arg(&build_instruction.image_name)
-            .arg("--format={{.Size}}")
-            .output();
-        if let Ok(size_output) = size_output {
-            let size = String::from_utf8(size_output.stdout).unwrap();
-            let size = size.trim();
-            if let Ok(size) = size.parse() {
-                build_instruction.image_size = size;
-            }
+        if let Some(size) = inspect_image_size(engine.cli(), &build_instruction.image_name) {
+            build_instruction.image_size = size;
         }
 
         pb.inc(1);
         if !build_instruction.build {
-            error!("Failed to build {} - arch {}", build_instruction.filename, build_instruction.arch);
+            error!("{} Failed to build {} - arch {}", error_codes::BUILD_FAILED, build_instruction.filename, build_instruction.key());
         }
     }
     pb.finish();
 }
 
-pub(crate) fn upload_images(runtime:&Runtime,docker_credentials: &str, build_instructions: &mut Vec<BuildInstruction>,
-                     input_file_name:&PathBuf) {
+/// Push one image. Returns if the push succeeded and the stderr output of the engine.
+fn push_image(runtime: &Runtime, engine: &dyn ContainerEngine, pb: &ProgressBar, image_name: &str, docker_credentials: &str,
+              input_file_name: &PathBuf, digest_file: Option<&Path>) -> (bool, String) {
+    if !engine.login(docker_credentials) {
+        return (false, "login failed: unauthorized".to_owned());
+    }
+    let mut child = engine.push_command(image_name, docker_credentials, digest_file)
+        .current_dir(input_file_name.parent().unwrap())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn().expect("starting the container engine for pushing images");
+
+    let stdout = child.stdout().take().expect("no stdout");
+    let stderr = child.stderr().take().expect("no stderr");
+
+    let pb_output = pb.clone();
+    let mut reader = FramedRead::new(stdout, LinesCodec::new());
+    runtime.spawn(async move {
+        while let Some(line) = reader.next().await {
+            output::progress(&pb_output, "     ", &line.unwrap());
+        }
+    });
+
+    // stderr reaches its end when the engine exits. stdout is consumed concurrently, so this cannot block.
+    let mut stderr_reader = FramedRead::new(stderr, LinesCodec::new());
+    let (result, stderr) = runtime.block_on(async move {
+        let mut stderr = String::new();
+        while let Some(Ok(line)) = stderr_reader.next().await {
+            stderr.push_str(&line);
+            stderr.push('\n');
+        }
+        (child.await, stderr)
+    });
+    let result = result.expect("To block on the container engine until it finished");
+    (result.success(), stderr)
+}
+
+/// Push all build images. If the registry rejects the credentials, `refresh_credentials` is called to get
+/// fresh credentials from the vault and the push is retried once. `docker_credentials` is updated in that case.
+pub(crate) fn upload_images(runtime:&Runtime, engine: &dyn ContainerEngine, docker_credentials: &mut String,
+                            build_instructions: &mut Vec<BuildInstruction>, input_file_name:&PathBuf, build_directory: &Path,
+                            refresh_credentials: &dyn Fn() -> Option<String>) {
     let spinner_style = ProgressStyle::default_spinner()
         .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
         .template("{prefix:.bold.dim} {spinner} {wide_msg}");
 
-    let pb = ProgressBar::new(build_instructions.len() as u64);
+    let pb = output::progress_bar(build_instructions.len() as u64);
     pb.set_style(spinner_style.clone());
     pb.set_prefix("[5/6]");
 
     for build_instruction in build_instructions {
-        if !build_instruction.build {
+        if !build_instruction.build || build_instruction.uploaded {
             pb.inc(1);
             continue;
         }
-        pb.set_message(&format!("Upload Image {}", &build_instruction.image_name));
-        let mut child = Command::new("podman")
-            .arg("push")
-            .arg(&build_instruction.image_name)
-            .arg(format!("--creds={}", &docker_credentials))
-            .current_dir(input_file_name.parent().unwrap())
-            .stdout(Stdio::piped())
-            .spawn().expect("starting podman for pushing images");
+        output::progress(&pb, "[5/6]", &format!("Upload Image {}", &build_instruction.image_name));
 
-        let stdout = child.stdout().take().expect("no stdout");
+        // Record the digest of the pushed image if the engine supports it
+        let digest_file = build_directory.join(format!("digest_{}", build_instruction.key()));
+        let digest_file = match engine.digest_file() && std::fs::create_dir_all(build_directory).is_ok() {
+            true => Some(digest_file.as_path()),
+            false => None
+        };
 
-        let pb_output = pb.clone();
-        let mut reader = FramedRead::new(stdout, LinesCodec::new());
-        runtime.spawn(async move {
-            while let Some(line) = reader.next().await {
-                pb_output.set_message(&line.unwrap());
+        let (mut success, stderr) = push_image(runtime, engine, &pb, &build_instruction.image_name, docker_credentials,
+                                               input_file_name, digest_file);
+        // The shared secret might have been rotated in the meantime. Fetch fresh credentials and retry once.
+        if !success && is_auth_failure(&stderr) {
+            warn!("Registry credentials rejected. Fetching fresh credentials from the vault.");
+            if let Some(credentials) = refresh_credentials() {
+                *docker_credentials = credentials;
+                success = push_image(runtime, engine, &pb, &build_instruction.image_name, docker_credentials,
+                                     input_file_name, digest_file).0;
             }
-        });
-
-        let result = runtime.block_on(child).expect("To block on podman until it finished");
+        }
 
-        build_instruction.uploaded = result.success();
+        build_instruction.uploaded = success;
+        if success {
+            build_instruction.digest = match digest_file {
+                Some(digest_file) => std::fs::read_to_string(digest_file).ok().map(|v| v.trim().to_owned()),
+                None => repo_digest(engine.cli(), &build_instruction.image_name),
+            };
+        }
         pb.inc(1);
         if !build_instruction.uploaded {
-            error!("Failed to push {}", build_instruction.image_name);
+            error!("{} Failed to push {}", error_codes::PUSH_FAILED, build_instruction.image_name);
         }
     }
 
     pb.finish();
-}
\ No newline at end of file
+}
+
+/// The manifest list of an architecture image, `openhabx/addon_amd64:1.0` is part of `openhabx/addon:1.0`
+fn manifest_list_name(image_name: &str, arch: &str) -> String {
+    let suffix = format!("_{}", arch);
+    match image_name.rfind(':') {
+        Some(pos) if !image_name[pos..].contains('/') =>
+            format!("{}{}", image_name[..pos].trim_end_matches(suffix.as_str()), &image_name[pos..]),
+        _ => image_name.trim_end_matches(suffix.as_str()).to_owned(),
+    }
+}
+
+/// Calls `<engine> manifest` with the given arguments. Returns stderr on failure.
+fn manifest_command(cli: &EngineCli, args: &[String]) -> Result<(), String> {
+    debug!("{} manifest {}", cli.binary, args.iter().filter(|a| !a.starts_with("--creds")).cloned().collect::<Vec<_>>().join(" "));
+    match cli.command().arg("manifest").args(args).output() {
+        Ok(ref output) if output.status.success() => Ok(()),
+        Ok(output) => Err(String::from_utf8_lossy(&output.stderr).trim().to_owned()),
+        Err(e) => Err(format!("{:?}", e)),
+    }
+}
+
+/// Pushes a manifest list per tag for the pushed architecture images, so that `openhabx/addon:1.0` resolves to
+/// the image of the pulling architecture. Variants get a list per variant tag. Returns the pushed lists.
+/// Engines without manifest lists push nothing, the architecture images are used by their own names then.
+pub(crate) fn push_manifest_lists(engine: &dyn ContainerEngine, docker_credentials: &str,
+                                  build_instructions: &[BuildInstruction]) -> Option<Vec<String>> {
+    let mut lists: BTreeMap<String, Vec<&BuildInstruction>> = BTreeMap::new();
+    for build_instruction in build_instructions.iter().filter(|b| b.uploaded && !b.skipped) {
+        lists.entry(manifest_list_name(&build_instruction.image_name, &build_instruction.arch))
+            .or_default().push(build_instruction);
+    }
+    if lists.is_empty() {
+        return Some(Vec::new());
+    }
+    if !engine.manifest_lists() {
+        info!("{} has no manifest lists. The images are only available by their architecture names.", engine.cli().binary);
+        return Some(Vec::new());
+    }
+
+    let creds = format!("--creds={}", docker_credentials);
+    for (list, images) in &lists {
+        // A list of an earlier, failed run would otherwise keep its images
+        let _ = manifest_command(engine.cli(), &["rm".to_owned(), list.clone()]);
+        let mut result = manifest_command(engine.cli(), &["create".to_owned(), list.clone()]);
+        for image in images {
+            if result.is_err() {
+                break;
+            }
+            let mut args = vec!["add".to_owned(), "--arch".to_owned(), buildah::oci_arch(&image.arch).to_owned()];
+            if let Some(variant) = buildah::oci_variant(&image.arch) {
+                args.push("--variant".to_owned());
+                args.push(variant.to_owned());
+            }
+            args.extend(vec![creds.clone(), list.clone(), format!("docker://{}", image.image_name)]);
+            result = manifest_command(engine.cli(), &args);
+        }
+        if result.is_ok() {
+            result = manifest_command(engine.cli(), &["push".to_owned(), "--all".to_owned(), creds.clone(), list.clone(),
+                format!("docker://{}", list)]);
+        }
+        if let Err(e) = result {
+            error!("{} Failed to push the manifest list {}: {}", error_codes::PUSH_FAILED, list, e);
+            return None;
+        }
+        info!("Pushed the manifest list {}", list);
+    }
+    Some(lists.keys().cloned().collect())
+}
+
+/// Exports an image as OCI archive, the image format of an addon bundle
+pub(crate) fn save_oci_archive(engine: &dyn ContainerEngine, image_name: &str, file: &Path) -> bool {
+    let mut command = engine.cli().command();
+    match engine.cli().binary {
+        "buildah" => command.arg("push").arg(image_name).arg(format!("oci-archive:{}", file.display())),
+        _ => command.args(["save", "--format", "oci-archive", "-o"]).arg(file).arg(image_name),
+    };
+    match command.output() {
+        Ok(ref output) if output.status.success() => true,
+        Ok(output) => {
+            error!("{} Failed to export {} to {}: {}", error_codes::IMAGE_EXPORT_FAILED, image_name, file.display(),
+                   String::from_utf8_lossy(&output.stderr).trim());
+            false
+        }
+        Err(e) => {
+            error!("{} Failed to export {} to {}: {:?}", error_codes::IMAGE_EXPORT_FAILED, image_name, file.display(), e);
+            false
+        }
+    }
+}
+
+#[test]
+fn registries_conf_test() {
+    let conf = registries_conf(&["harbor.local/dockerhub".to_owned()]);
+    assert!(conf.contains("[[registry]]\nprefix = \"docker.io\""));
+    assert!(conf.ends_with("[[registry.mirror]]\nlocation = \"harbor.local/dockerhub\"\n"));
+}
+
+#[test]
+fn manifest_list_name_test() {
+    assert_eq!(manifest_list_name("docker.io/openhabx/addon_armhf:1.0", "armhf"), "docker.io/openhabx/addon:1.0");
+    assert_eq!(manifest_list_name("docker.io/openhabx/addon_amd64:sandbox-1-1.0_cloud", "amd64"), "docker.io/openhabx/addon:sandbox-1-1.0_cloud");
+}
+
+#[test]
+fn sandbox_reference_test() {
+    assert_eq!(sandbox_reference("docker.io/openhabx/addon_amd64:1.0", "sandbox-1"), "docker.io/openhabx/addon_amd64:sandbox-1-1.0");
+    assert_eq!(sandbox_reference("localhost:5000/addon", "sandbox-1"), "localhost:5000/addon:sandbox-1");
+}
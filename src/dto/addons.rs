@@ -1,19 +1,50 @@
 use std::collections::{BTreeMap, HashMap};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::Read;
+use std::path::Path;
+use super::{lint, schema, type_profiles};
 
-pub const REGISTRY_DATA_URL : &'static str ="https://raw.githubusercontent.com/openhab-nodes/addons-registry/master/extensions.json";
-pub const REGISTRY_METADATA_URL : &'static str ="https://raw.githubusercontent.com/openhab-nodes/addons-registry/master/extensions_stats.json";
+/// The index of the openhabx.com registry. Self-hosted registries publish their own.
+pub const REGISTRY_DATA_URL : &str ="https://raw.githubusercontent.com/openhab-nodes/addons-registry/master/extensions.json";
+pub const REGISTRY_METADATA_URL : &str ="https://raw.githubusercontent.com/openhab-nodes/addons-registry/master/extensions_stats.json";
 
+/// Fetches the registry index from `index_url`, like [`REGISTRY_DATA_URL`]
 #[cfg(feature = "reqwest")]
-pub fn get_addons_registry(client: &reqwest::Client) -> Result<AddonEntryMap, failure::Error> {
-    Ok(client.get(REGISTRY_DATA_URL).send()?.json()?)
+pub fn get_addons_registry(client: &reqwest::Client, index_url: &str) -> Result<AddonEntryMap, failure::Error> {
+    Ok(client.get(index_url).send()?.json()?)
 }
 
+/// The detailed registry information of an addon (services, archs, sizes) from the registry server at `registry_url`
 #[cfg(feature = "reqwest")]
-pub fn get_addons_registry_metadata(client: &reqwest::Client) -> Result<AddonMapStats, failure::Error> {
-    Ok(client.get(REGISTRY_METADATA_URL).send()?.json()?)
+pub fn get_addon_details(client: &reqwest::Client, registry_url: &str, addon_id: &str) -> Result<AddonDetailedInfo, failure::Error> {
+    Ok(client.get(&format!("{}/addon/{}", registry_url, addon_id)).send()?.error_for_status()?.json()?)
+}
+
+/// Fetches the registry stats from `stats_url`, like [`REGISTRY_METADATA_URL`].
+/// Malformed entries are skipped and reported in [`AddonMapStatsPartial::invalid`].
+/// The stats are not required for publishing, so callers should treat an error as "no stats available".
+#[cfg(feature = "reqwest")]
+pub fn get_addons_registry_metadata(client: &reqwest::Client, stats_url: &str) -> Result<AddonMapStatsPartial, failure::Error> {
+    use std::io::Read;
+    let mut response = client.get(stats_url).send()?;
+    let mut buffer = Vec::new();
+    response.read_to_end(&mut buffer)?;
+    parse_addons_registry_metadata(&buffer)
+}
+
+/// Parse the registry stats file entry by entry. Only a file that is not a json object at all is an error.
+pub fn parse_addons_registry_metadata(data: &[u8]) -> Result<AddonMapStatsPartial, failure::Error> {
+    let entries: BTreeMap<String, serde_json::Value> = serde_json::from_slice(data)?;
+    let mut result = AddonMapStatsPartial::default();
+    for (addon_id, value) in entries {
+        match serde_json::from_value(value) {
+            Ok(stats) => {
+                result.stats.insert(addon_id, stats);
+            }
+            Err(e) => result.invalid.push(format!("{}: {}", addon_id, e)),
+        }
+    }
+    Ok(result)
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -28,9 +59,23 @@ pub struct AddonPermission {
 
 pub type AddonPermissions = BTreeMap<String, AddonPermission>;
 
+/// The permission catalogue that was current when this CLI was released
+pub fn bundled_permissions() -> AddonPermissions {
+    serde_json::from_str(include_str!("../../addon-permissions.json")).expect("valid bundled permissions")
+}
+
 pub type AddonMapStats = BTreeMap<String, AddonStats>;
 
-#[derive(Serialize, Deserialize)]
+/// Registry stats with malformed entries removed
+#[derive(Default)]
+pub struct AddonMapStatsPartial {
+    pub stats: AddonMapStats,
+    /// Addon ids and parser errors of skipped entries
+    pub invalid: Vec<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AddonStats {
     // voters
     pub v: u64,
@@ -48,7 +93,7 @@ pub struct AddonStats {
 
 pub type AddonEntryMap = BTreeMap<String, AddonRegistryEntry>;
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct AddonEntryCommon {
     // Descriptive
     pub title: String,
@@ -82,15 +127,100 @@ pub struct AddonRegistryEntry {
     pub entry: AddonEntryCommon,
     pub owner: String,
     pub last_updated: i64,
+    #[serde(default)]
+    pub archs: Vec<String>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct AddonFileEntry {
+    /// The version of the file format, see [`super::migrate`]. Files without one are version 1.
+    #[serde(default = "current_schema_version")]
+    pub schema_version: u32,
     pub services: HashMap<String, AddonService>,
     #[serde(rename = "x-ohx-registry")]
     pub x_ohx_registry: AddonEntryCommon,
     #[serde(rename = "x-runtime")]
     pub x_runtime: AddonRuntimeRequirements,
+    /// Auxiliary files that are attached to the images as OCI artifacts
+    #[serde(rename = "x-attachments", default, skip_serializing_if = "Vec::is_empty")]
+    pub x_attachments: Vec<Attachment>,
+    /// Per-language documentation, published as an OCI artifact
+    #[serde(rename = "x-docs", default, skip_serializing_if = "Option::is_none")]
+    pub x_docs: Option<Docs>,
+    /// Build argument sets. Every Dockerfile is build once per variant.
+    #[serde(rename = "x-variants", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub x_variants: BTreeMap<String, Variant>,
+}
+
+fn current_schema_version() -> u32 {
+    super::migrate::CURRENT_SCHEMA_VERSION
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Variant {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub build_args: BTreeMap<String, String>,
+    /// Appended to the version tag, "-<variant name>" by default.
+    /// The variant with an empty suffix is the default variant of the addon.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag_suffix: Option<String>,
+}
+
+impl Variant {
+    pub fn tag_suffix(&self, name: &str) -> String {
+        match &self.tag_suffix {
+            Some(v) => v.clone(),
+            None => format!("-{}", name),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Attachment {
+    pub kind: AttachmentKind,
+    /// Path to the file, relative to the addons.yml directory
+    pub path: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Docs {
+    /// Relative to the addons.yml directory. One subdirectory per language, like "docs/de/index.md".
+    pub directory: String,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum AttachmentKind {
+    ConfigSchema,
+    Sbom,
+    Seccomp,
+    #[default]
+    Docs,
+    /// The tar archive of the `x-docs` directory. Created by the CLI.
+    DocsBundle,
+}
+
+impl AttachmentKind {
+    /// The OCI artifact type that is used for the referrer manifest
+    pub fn artifact_type(&self) -> &'static str {
+        match self {
+            AttachmentKind::ConfigSchema => "application/vnd.ohx.config-schema.v1+json",
+            AttachmentKind::Sbom => "application/spdx+json",
+            AttachmentKind::Seccomp => "application/vnd.ohx.seccomp.v1+json",
+            AttachmentKind::Docs => "application/vnd.ohx.docs.v1+markdown",
+            AttachmentKind::DocsBundle => "application/vnd.ohx.docs-bundle.v1.tar",
+        }
+    }
+}
+
+/// The digest of an attachment referrer manifest, pushed for the image of one architecture
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttachmentReferrer {
+    pub kind: AttachmentKind,
+    pub arch: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    pub digest: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -102,7 +232,51 @@ pub struct AddonFileEntryPlusStats {
     pub x_runtime: AddonRuntimeRequirements,
 
     pub archs: Vec<String>,
+    /// The average image size over all architectures. Kept for registry servers without per-arch sizes.
     pub size: i64,
+    /// Image size per architecture
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sizes: BTreeMap<String, i64>,
+    /// The OCI platform per architecture, like "linux/arm/v6" for armv6
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub platforms: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub referrers: Vec<AttachmentReferrer>,
+    /// The source revision of the published version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// Set for rebuilds of a published version, like "base-image-update"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rebuild_reason: Option<String>,
+    /// The `# ohx-allow:` annotations of addons.yml, with their justification for reviewers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suppressions: Vec<lint::Suppression>,
+    /// The published variants. `archs`, `size` and `sizes` describe the default variant.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variants: BTreeMap<String, VariantEntry>,
+    /// The service ids in the order the runtime starts them, dependencies first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub startup_order: Vec<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VariantEntry {
+    pub tag_suffix: String,
+    pub archs: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sizes: BTreeMap<String, i64>,
+}
+
+/// The git revision of the directory containing addons.yml
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    pub commit: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_url: Option<String>,
+    /// True if the addon directory has uncommitted changes
+    pub dirty: bool,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -112,6 +286,9 @@ pub struct AddonDetailedInfo {
     pub reviewed_by: Vec<String>,
     pub archs: Vec<String>,
     pub size: i64,
+    /// Image size per architecture. Empty for entries published by older CLI versions
+    #[serde(default)]
+    pub sizes: BTreeMap<String, i64>,
 
     // Runtime
     #[serde(flatten)]
@@ -119,7 +296,7 @@ pub struct AddonDetailedInfo {
     pub services: HashMap<String, AddonService>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct AddonService {
     // Security
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -147,26 +324,83 @@ pub struct AddonService {
     pub depends_on: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub volumes: Option<Vec<String>>,
+
+    /// The runtime kind. Hubs route wasm services to the WASM runtime instead of the container engine.
+    #[serde(default)]
+    pub kind: ServiceKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wasm: Option<WasmModule>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceKind {
+    #[default]
+    Container,
+    Wasm,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct WasmModule {
+    /// Path to the .wasm module, relative to the addons.yml directory
+    pub module: String,
+    /// An optional shell command to compile the module, for example "cargo build --release --target wasm32-wasi"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct BuildContext {
     pub context: String,
+    /// Build the image with buildah from this recipe instead of a Dockerfile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipe: Option<BuildRecipe>,
+    /// Build secrets by id, like `npmrc: ~/.npmrc`, for `RUN --mount=type=secret,id=npmrc` in the Dockerfiles.
+    /// The file is relative to the addons.yml directory and never part of an image layer.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub secrets: BTreeMap<String, String>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// A simple image recipe, declared entirely in addons.yml
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BuildRecipe {
+    /// The base image, for example "alpine:3.10"
+    pub from: String,
+    /// Target architectures. Defaults to amd64
+    #[serde(default = "default_recipe_archs")]
+    pub archs: Vec<String>,
+    /// Shell commands, each executed in its own layer
+    #[serde(default)]
+    pub run: Vec<String>,
+    /// Files to copy into the image in the form "source:destination". The source is relative to the addons.yml directory.
+    #[serde(default)]
+    pub copy: Vec<String>,
+    #[serde(default)]
+    pub entrypoint: Vec<String>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// A fixed image creation timestamp (unix seconds) for reproducible images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+}
+
+fn default_recipe_archs() -> Vec<String> {
+    vec!["amd64".to_owned()]
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct AddonRuntimeRequirements {
     pub memory_min: i64,
     pub memory_max: i64,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Permissions {
     pub mandatory: Vec<String>,
     pub optional: Vec<String>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Status {
     pub code: StatusCode,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -175,131 +409,532 @@ pub struct Status {
     pub descriptions: Option<HashMap<String, String>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum StatusCode {
+    #[default]
     AVAILABLE,
     REPLACED,
     REMOVED,
     UNMAINTAINED,
 }
 
-impl Default for StatusCode {
+/// Serializes into the canonical JSON form: Object keys are sorted, no whitespace,
+/// integers and floats are formatted by serde_json in a stable way.
+/// Two semantically equal payloads always result in the same bytes.
+pub fn to_canonical_json<T: Serialize>(value: &T) -> Result<Vec<u8>, failure::Error> {
+    // serde_json::Map is backed by a BTreeMap, so converting to a Value sorts all keys
+    let value = serde_json::to_value(value)?;
+    Ok(serde_json::to_vec(&value)?)
+}
+
+/// A valid addons.yml file with its lint warnings, including the suppressed ones
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidatedAddonFile {
+    pub data: AddonFileEntry,
+    pub warnings: Vec<schema::ValidationIssue>,
+    pub suppressions: Vec<lint::Suppression>,
+}
+
+/// Reads and validates an addons.yml file, see [`validate_addons_file`]
+pub fn open_validate_addons_file(filename: &str) -> Result<AddonFileEntry, failure::Error> {
+    validate_addons_file(filename).map(|v| v.data)
+}
+
+/// Devices that give access to the whole system. Reviewers reject them.
+const BROAD_DEVICES: [&str; 4] = ["/dev", "/dev/mem", "/dev/kmem", "/dev/port"];
+
+/// Checks a device mapping like "/dev/ttyUSB0:/dev/zwave:rw". Returns true for a broad device like "/dev/mem".
+fn check_device(device: &str) -> Result<bool, String> {
+    let parts: Vec<&str> = device.split(':').collect();
+    if parts.len() > 3 {
+        return Err(format!("A device mapping looks like /dev/ttyUSB0[:container path[:permissions]]: {}", device));
+    }
+    let host = parts[0];
+    if !host.starts_with("/dev/") && host != "/dev" {
+        return Err(format!("A device must be an absolute path below /dev: {}", host));
+    }
+    if let Some(container) = parts.get(1).filter(|c| !c.starts_with('/')) {
+        return Err(format!("The container path of a device must be absolute: {}", container));
+    }
+    if let Some(permissions) = parts.get(2).filter(|p| p.is_empty() || p.chars().any(|c| !"rwm".contains(c))) {
+        return Err(format!("Device permissions are a combination of r, w and m: {}", permissions));
+    }
+    Ok(BROAD_DEVICES.contains(&host.trim_end_matches('/')) || host == "/dev/")
+}
+
+/// Checks a port declaration like "8080:80/tcp" or "5000-5010:5000-5010". Returns the privileged host port, if any.
+fn check_port(port: &str) -> Result<Option<u16>, String> {
+    // Check for protocol "6060:6060/udp"
+    let parts: Vec<&str> = port.split("/").collect();
+    if let Some(protocol) = parts.get(1) {
+        if *protocol != "udp" && *protocol != "tcp" {
+            return Err(format!("Ports pattern invalid. The part after / must be tcp or udp: {}", &port));
+        }
+    }
+    let port = parts[0];
+    // Check for mapping "5000-5010:5000-5010"
+    let parts: Vec<&str> = port.split(":").collect();
+    if parts.len() > 2 {
+        return Err(format!("Ports pattern invalid. Maximum of two colon separated segments allowed: {}", &port));
+    }
+    let mut privileged = None;
+    // Check for ranges "5000-5010"
+    for ports_maybe_range in parts {
+        let ports_maybe_range_segments: Vec<&str> = ports_maybe_range.split("-").collect();
+        if ports_maybe_range_segments.len() > 2 {
+            return Err(format!("Ports pattern invalid. A range can have only two segments: {}", &ports_maybe_range));
+        }
+        let mut in_host_range = false;
+        // Check if port is in range and if the user didn't try to map to a port below 1024
+        for a_port in ports_maybe_range_segments {
+            match a_port.parse::<u16>() {
+                Ok(v) => {
+                    if in_host_range && v < 1024 {
+                        privileged = Some(v);
+                    }
+                }
+                Err(_) => return Err(format!("A port must be a number! {}", &a_port))
+            };
+            in_host_range = true;
+        }
+    }
+    Ok(privileged)
+}
+
+/// The host ports of a mapping like "8080:80/udp" or "5000-5001:5000-5001", as "8080/udp".
+/// A port without mapping gets a random host port. Invalid declarations have none, [`check_port`] reports them.
+fn host_ports(declaration: &str) -> Vec<String> {
+    let mut parts = declaration.splitn(2, '/');
+    let mapping = parts.next().unwrap_or_default();
+    let protocol = parts.next().unwrap_or("tcp");
+    let mut mapping = mapping.splitn(2, ':');
+    let host = match (mapping.next(), mapping.next()) {
+        (Some(host), Some(_)) => host,
+        _ => return Vec::new(),
+    };
+    let mut range = host.splitn(2, '-').map(|p| p.parse::<u16>());
+    match (range.next(), range.next()) {
+        (Some(Ok(start)), Some(Ok(end))) => (start..=end).map(|p| format!("{}/{}", p, protocol)).collect(),
+        (Some(Ok(port)), None) => vec![format!("{}/{}", port, protocol)],
+        _ => Vec::new(),
+    }
+}
+
+/// Host ports that are mapped more than once, within a service or by several services. The runtime cannot start
+/// such an addon. Returns the path and message of each port entry that maps an already mapped host port.
+fn host_port_collisions(services: &HashMap<String, AddonService>) -> Vec<(String, String)> {
+    let mut ids: Vec<&String> = services.keys().collect();
+    ids.sort();
+    let mut mapped: BTreeMap<String, String> = BTreeMap::new();
+    let mut collisions = Vec::new();
+    for id in ids {
+        for (index, port) in services[id].ports.iter().flatten().enumerate() {
+            let path = format!("services.{}.ports[{}]", id, index);
+            // The colliding ports by the entry that maps them first
+            let mut taken: BTreeMap<String, Vec<String>> = BTreeMap::new();
+            for host_port in host_ports(port) {
+                match mapped.get(&host_port) {
+                    Some(first) => taken.entry(first.clone()).or_default().push(host_port),
+                    None => {
+                        mapped.insert(host_port, path.clone());
+                    }
+                }
+            }
+            for (first, ports) in taken {
+                collisions.push((path.clone(), format!("The host port {} is already mapped by {}", ports.join(", "), first)));
+            }
+        }
+    }
+    collisions
+}
+
+/// The services in startup order: Each service after the services it depends on, otherwise sorted by id.
+/// Returns the cycle, like `["a", "b", "a"]`, if services depend on each other. Unknown services are ignored.
+pub fn startup_order(services: &HashMap<String, AddonService>) -> Result<Vec<String>, Vec<String>> {
+    fn visit<'a>(id: &'a str, services: &'a HashMap<String, AddonService>, path: &mut Vec<&'a str>,
+                 order: &mut Vec<String>) -> Result<(), Vec<String>> {
+        if order.iter().any(|o| o == id) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|p| *p == id) {
+            let mut cycle: Vec<String> = path[start..].iter().map(|p| p.to_string()).collect();
+            cycle.push(id.to_owned());
+            return Err(cycle);
+        }
+        path.push(id);
+        let mut depends_on: Vec<&String> = services[id].depends_on.iter().flatten().filter(|d| services.contains_key(*d)).collect();
+        depends_on.sort();
+        for depends in depends_on {
+            visit(depends, services, path, order)?;
+        }
+        path.pop();
+        order.push(id.to_owned());
+        Ok(())
+    }
+    let mut ids: Vec<&String> = services.keys().collect();
+    ids.sort();
+    let mut order = Vec::new();
+    for id in ids {
+        visit(id, services, &mut Vec::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
+/// What [`validate_addons_file_with`] checks besides the schema
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationOptions {
+    pub type_profiles: type_profiles::TypeProfiles,
+    /// The known permissions, usually the catalogue of the registry
+    pub permissions: AddonPermissions,
+    /// Languages that need a translated title and description, like "de"
+    pub required_languages: Vec<String>,
+    /// A `memory_max` above this many MiB is a warning. The target devices are small single board computers.
+    pub memory_warning: i64,
+    /// Values that replace those of the file, by path like "services.app.ports[0]". See [`super::overrides`].
+    pub overrides: Vec<(String, String)>,
+    /// The values of `${VAR}` references, see [`super::interpolate`]. Not part of the cache key,
+    /// which contains the interpolated file instead.
+    #[serde(skip)]
+    pub variables: BTreeMap<String, String>,
+    /// A reference to a variable without value and default is an error instead of a warning
+    pub require_variables: bool,
+}
+
+/// The default of [`ValidationOptions::memory_warning`]: 2 GiB
+pub const DEFAULT_MEMORY_WARNING: i64 = 2048;
+
+impl Default for ValidationOptions {
     fn default() -> Self {
-        StatusCode::AVAILABLE
+        ValidationOptions { type_profiles: type_profiles::bundled(), permissions: bundled_permissions(), required_languages: Vec::new(), memory_warning: DEFAULT_MEMORY_WARNING,
+            overrides: Vec::new(), variables: std::env::vars().collect(), require_variables: false }
     }
 }
 
-pub fn open_validate_addons_file(filename: &str) -> Result<AddonFileEntry, failure::Error> {
-    let addon_permissions: AddonPermissions = serde_json::from_str(include_str!("../../addon-permissions.json"))?;
+/// Reads and validates an addons.yml file with the bundled type profiles, see [`validate_addons_file_with`]
+pub fn validate_addons_file(filename: &str) -> Result<ValidatedAddonFile, failure::Error> {
+    validate_addons_file_with(filename, &ValidationOptions::default())
+}
 
-    let mut f = File::open(filename)?;
-    let mut buffer = Vec::new();
-    f.read_to_end(&mut buffer)?;
-    let data: AddonFileEntry = serde_yaml::from_slice(buffer.as_slice())?;
+/// Reads and validates an addons.yml file. The document is checked against the [`schema::addon_file_schema`] first.
+/// The rules that a schema cannot express, the profile of the addon type and the translations are checked afterwards,
+/// for a document that matches the schema.
+/// All errors and warnings are collected. If there is an error, they are returned as [`schema::ValidationIssues`],
+/// with the path and the line and column of each failing value.
+pub fn validate_addons_file_with(filename: &str, options: &ValidationOptions) -> Result<ValidatedAddonFile, failure::Error> {
+    let addon_permissions = &options.permissions;
+
+    // Files referenced by "extends" are merged before validation. Their values have no position.
+    let mut merged = super::extends::load_with_extends(Path::new(filename))?;
+    let yaml = std::fs::read_to_string(filename)?;
+    let positions = schema::Positions::parse(&yaml);
+    let mut issues = Vec::new();
+    let suppressions = lint::suppressions(&yaml, &positions, &mut issues);
+    let failed = |mut issues: Vec<schema::ValidationIssue>| -> failure::Error {
+        lint::apply(&suppressions, &mut issues);
+        schema::ValidationIssues { file: filename.to_owned(), issues }.into()
+    };
+    for (path, message) in super::extends::merge_services(&mut merged) {
+        issues.push(positions.error(path, message));
+    }
+    issues.extend(super::interpolate::apply(&mut merged, &options.variables, options.require_variables, &positions));
+    // Older files are validated as migrated to the current schema version
+    match super::migrate::migrate(&mut merged) {
+        Ok(changes) => if !changes.is_empty() {
+            issues.push(positions.warning("schema/outdated", "schema_version".to_owned(), format!(
+                "The file was migrated in memory to schema version {}. Run `migrate --write` to update it: {}",
+                super::migrate::CURRENT_SCHEMA_VERSION, changes.join("; "))));
+        },
+        Err(message) => {
+            issues.push(positions.error("schema_version".to_owned(), message));
+            return Err(failed(issues));
+        }
+    }
+    let schema = schema::addon_file_schema();
+    issues.extend(super::overrides::apply(&mut merged, &options.overrides, &schema, &positions));
+    issues.extend(schema::validate(&schema, &serde_json::to_value(&merged)?, &positions));
+    if issues.iter().any(|i| i.is_error()) {
+        return Err(failed(issues));
+    }
+    let data: AddonFileEntry = serde_yaml::from_value(merged)?;
+    let mut error = |path: String, message: String| issues.push(positions.error(path, message));
     if data.services.is_empty() {
-        return Err(failure::err_msg("No services defined"));
+        error("services".to_owned(), "No services defined".to_owned());
+    }
+    if let Err(e) = semver::Version::parse(&data.x_ohx_registry.version) {
+        error("x-ohx-registry.version".to_owned(), format!("Not a semantic version like 1.2.0: {}", e));
+    }
+
+    // Memory requirements in MiB
+    let runtime = &data.x_runtime;
+    for (field, value) in &[("memory_min", runtime.memory_min), ("memory_max", runtime.memory_max)] {
+        if *value <= 0 {
+            error(format!("x-runtime.{}", field), format!("The memory requirement must be positive, not {} MiB", value));
+        }
+    }
+    if runtime.memory_min > runtime.memory_max {
+        error("x-runtime.memory_min".to_owned(), format!("memory_min ({} MiB) is above memory_max ({} MiB)", runtime.memory_min, runtime.memory_max));
     }
 
     use regex::Regex;
     let pattern_registry = Regex::new(r"^[^:]*([:]\d+)?$").unwrap();
     let pattern_image_name = Regex::new(r"^[_\-a-z0-9]+(:[a-z0-9]+)?$").unwrap();
+    let mut warnings = Vec::new();
+    if runtime.memory_max > options.memory_warning {
+        warnings.push(positions.warning("runtime/memory-max", "x-runtime.memory_max".to_owned(),
+                                        format!("memory_max is {} MiB. Many devices have {} MiB or less.", runtime.memory_max, options.memory_warning)));
+    }
 
     for (service_id, service) in &data.services {
+        let path = |field: &str| format!("services.{}.{}", service_id, field);
         if let Some(service_image) = &service.image {
             // Check image name
             let parts: Vec<&str> = service_image.split("/").collect();
             let image_name = if parts.len() == 2 {
-                let registry_address = parts.get(0).unwrap();
+                let registry_address = parts.first().unwrap();
                 if !pattern_registry.is_match(registry_address) {
-                    return Err(failure::err_msg(format!("Service registry address invalid for {}: {}", service_id, &service_image)));
+                    error(path("image"), format!("Registry address invalid: {}", &service_image));
                 }
                 parts.get(1).unwrap()
             } else {
-                parts.get(0).unwrap()
+                parts.first().unwrap()
             };
             if !pattern_image_name.is_match(image_name) {
-                return Err(failure::err_msg(format!("Service image name invalid for {}: {}", service_id, image_name)));
+                error(path("image"), format!("Image name invalid: {}", image_name));
             }
         }
 
         // Permissions
         if let Some(permissions) = &service.permissions {
-            for permission in &permissions.mandatory {
+            for (index, permission) in permissions.mandatory.iter().enumerate() {
                 if !addon_permissions.contains_key(permission) {
-                    return Err(failure::err_msg(format!("Mandatory permission unknown for {}: {}", service_id, &permission)));
+                    error(path(&format!("permissions.mandatory[{}]", index)), format!("Unknown permission {}", &permission));
                 }
             }
-            for permission in &permissions.optional {
+            for (index, permission) in permissions.optional.iter().enumerate() {
                 if !addon_permissions.contains_key(permission) {
-                    return Err(failure::err_msg(format!("Optional permission unknown for {}: {}", service_id, &permission)));
+                    error(path(&format!("permissions.optional[{}]", index)), format!("Unknown permission {}", &permission));
                 }
             }
         }
 
         // Ports
         if let Some(ports) = &service.ports {
-            for port in ports {
-                // Check for protocol "6060:6060/udp"
-                let parts: Vec<&str> = port.split("/").collect();
-                let port = if let Some(protocol) = parts.get(1) {
-                    if *protocol != "udp" && *protocol != "tcp" {
-                        return Err(failure::err_msg(format!("Ports pattern invalid. The part after / must be tcp or udp for {}: {}", service_id, &port)));
-                    }
-                    *protocol
-                } else {
-                    port
-                };
-                // Check for mapping "5000-5010:5000-5010"
-                let parts: Vec<&str> = port.split(":").collect();
-                if parts.len() > 2 {
-                    return Err(failure::err_msg(format!("Ports pattern invalid. Maximum of two colon separated segments allowed for {}: {}", service_id, &port)));
-                }
-                // Check for ranges "5000-5010"
-                for ports_maybe_range in parts {
-                    let ports_maybe_range_segments: Vec<&str> = ports_maybe_range.split("-").collect();
-                    if ports_maybe_range_segments.len() > 2 {
-                        return Err(failure::err_msg(format!("Ports pattern invalid. A range can have only two segments {}: {}", service_id, &ports_maybe_range)));
-                    }
-                    let mut in_host_range = false;
-                    // Check if port is in range and if the user didn't try to map to a port below 1024
-                    for a_port in ports_maybe_range_segments {
-                        match a_port.parse::<u16>() {
-                            Ok(v) => {
-                                if in_host_range && v < 1024 {
-                                    return Err(failure::err_msg(format!("You cannot map to a port below 1024. Those are for privileged services only! For {}: {}", service_id, &a_port)));
-                                }
-                            }
-                            Err(_) => return Err(failure::err_msg(format!("A port must be a number! For {}: {}", service_id, &a_port)))
-                        };
-                        in_host_range = true;
-                    }
+            for (index, port) in ports.iter().enumerate() {
+                let port_path = path(&format!("ports[{}]", index));
+                match check_port(port) {
+                    Ok(Some(privileged)) => warnings.push(positions.warning("ports/privileged-mapping", port_path,
+                        format!("Port {} is below 1024. Those are for privileged services only!", privileged))),
+                    Ok(None) => {}
+                    Err(message) => error(port_path, message),
                 }
             }
         }
 
         // Depends on
         if let Some(depends_on) = &service.depends_on {
-            for depends in depends_on {
+            for (index, depends) in depends_on.iter().enumerate() {
                 if !data.services.contains_key(depends) {
-                    return Err(failure::err_msg(format!("For now you can only depend on services defined in your own addon.yml. For {}: Did not find '{}'!", service_id, &depends)));
+                    error(path(&format!("depends_on[{}]", index)),
+                          format!("For now you can only depend on services defined in your own addon.yml. Did not find '{}'!", &depends));
                 }
             }
         }
 
+        if let Some(devices) = &service.devices {
+            for (index, device) in devices.iter().enumerate() {
+                let device_path = path(&format!("devices[{}]", index));
+                match check_device(device) {
+                    Ok(true) => warnings.push(positions.warning("devices/broad", device_path,
+                        format!("{} gives access to the whole system. Map only the device the addon needs.", device))),
+                    Ok(false) => {}
+                    Err(message) => error(device_path, message),
+                }
+            }
+        }
+
+        for issue in super::capabilities::check(service_id, service, &positions) {
+            error(issue.path, issue.message);
+        }
+
         if let Some(volumes) = &service.volumes {
-            for volume in volumes {
+            for (index, volume) in volumes.iter().enumerate() {
                 let parts: Vec<&str> = volume.split(":").collect();
-                if *parts.get(0).unwrap() != "logvolume" {
-                    return Err(failure::err_msg(format!("There is currently only 'logvolume' supported. For {}. You requested volume: '{}'!", service_id, &volume)));
+                if *parts.first().unwrap() != "logvolume" {
+                    error(path(&format!("volumes[{}]", index)),
+                          format!("There is currently only 'logvolume' supported. You requested volume: '{}'!", &volume));
+                }
+            }
+        }
+
+        // WASM services
+        match service.kind {
+            ServiceKind::Wasm => {
+                if service.wasm.is_none() {
+                    error(path("kind"), "A wasm service requires a 'wasm' section with the module path".to_owned());
+                }
+                if service.image.is_some() || service.build.is_some() {
+                    error(path("kind"), "A wasm service cannot have an 'image' or 'build'".to_owned());
+                }
+            }
+            ServiceKind::Container => {
+                if service.wasm.is_some() {
+                    error(path("wasm"), "The 'wasm' section requires 'kind: wasm'".to_owned());
+                }
+            }
+        }
+
+        // Build recipe
+        if let Some(recipe) = service.build.as_ref().and_then(|b| b.recipe.as_ref()) {
+            if recipe.from.is_empty() {
+                error(path("build.recipe.from"), "A build recipe requires a base image in 'from'".to_owned());
+            }
+            for (index, copy) in recipe.copy.iter().enumerate() {
+                if copy.split(":").count() != 2 {
+                    error(path(&format!("build.recipe.copy[{}]", index)),
+                          format!("A recipe copy entry must look like 'source:destination': {}", &copy));
                 }
             }
         }
     }
-    Ok(data)
+    if let Err(cycle) = startup_order(&data.services) {
+        error(format!("services.{}.depends_on", cycle[0]), format!("The services depend on each other: {}", cycle.join(" -> ")));
+    }
+    for (path, message) in host_port_collisions(&data.services) {
+        error(path, message);
+    }
+
+    for issue in type_profiles::check(&data, &options.type_profiles, &positions) {
+        error(issue.path, issue.message);
+    }
+    for issue in super::metadata::check(&data.x_ohx_registry, &positions) {
+        error(issue.path, issue.message);
+    }
+    // The parent of a plain file name like "addons.yml" is empty
+    let addon_dir = Path::new(filename).parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if let Some(docs) = &data.x_docs {
+        for issue in super::docs::check(docs, addon_dir, &positions) {
+            error(issue.path, issue.message);
+        }
+    }
+    for (index, attachment) in data.x_attachments.iter().enumerate().filter(|(_, a)| a.kind == AttachmentKind::DocsBundle) {
+        error(format!("x-attachments[{}].kind", index), format!("The docs bundle is created from x-docs, not attached: {}", attachment.path));
+    }
+    for issue in super::translations::check(&data.x_ohx_registry, &options.required_languages, &positions) {
+        match issue.is_error() {
+            true => error(issue.path, issue.message),
+            false => warnings.push(issue),
+        }
+    }
+
+    // Variants
+    if !data.x_variants.is_empty() {
+        let pattern_variant = Regex::new(r"^[a-z0-9][\-a-z0-9]*$").unwrap();
+        let pattern_tag_suffix = Regex::new(r"^([\-_.][\-_.a-z0-9]*)?$").unwrap();
+        let mut suffixes = BTreeMap::new();
+        for (name, variant) in &data.x_variants {
+            let path = format!("x-variants.{}", name);
+            if !pattern_variant.is_match(name) {
+                error(path.clone(), "A variant name may only contain a-z, 0-9 and '-'".to_owned());
+            }
+            let tag_suffix = variant.tag_suffix(name);
+            if !pattern_tag_suffix.is_match(&tag_suffix) {
+                error(format!("{}.tag_suffix", path), format!("A variant tag suffix must start with '-', '_' or '.': {}", tag_suffix));
+            }
+            if let Some(other) = suffixes.insert(tag_suffix.clone(), name) {
+                error(path, format!("The variants {} and {} have the same tag suffix '{}'", other, name, tag_suffix));
+            }
+        }
+        if !suffixes.contains_key("") {
+            error("x-variants".to_owned(), "One variant must be the default variant with an empty tag_suffix".to_owned());
+        }
+    }
+
+    warnings.extend(lint::quality(&data, addon_dir, &positions));
+
+    issues.extend(warnings);
+    if issues.iter().any(|i| i.is_error()) {
+        return Err(failed(issues));
+    }
+    lint::apply(&suppressions, &mut issues);
+    Ok(ValidatedAddonFile { data, warnings: issues, suppressions })
+}
+
+#[test]
+fn parse_addons_registry_metadata_test() {
+    let data = br#"{"a": {"v": 2, "p": 9, "d": 10, "s": 1, "iss": 0, "t": 0}, "b": {"v": "x"}, "c": {"d": 3}}"#;
+    let result = parse_addons_registry_metadata(data).unwrap();
+    assert_eq!(result.stats.len(), 2);
+    assert_eq!(result.stats.get("c").unwrap().d, 3);
+    assert_eq!(result.invalid.len(), 1);
 }
 
 #[test]
 fn open_validate_addons_file_test() {
     let d = open_validate_addons_file("tests/addon.yml").unwrap();
     assert_eq!(d.x_ohx_registry.id, "ohx-ci-test-addon");
-}
\ No newline at end of file
+}
+
+#[test]
+fn check_device_test() {
+    assert_eq!(check_device("/dev/ttyUSB0:/dev/zwave:rw"), Ok(false));
+    assert_eq!(check_device("/dev/mem"), Ok(true));
+    assert_eq!(check_device("/dev/"), Ok(true));
+    assert!(check_device("ttyUSB0").is_err());
+    assert!(check_device("/dev/ttyUSB0:zwave").is_err());
+    assert!(check_device("/dev/ttyUSB0:/dev/zwave:rx").is_err());
+}
+
+#[test]
+fn startup_order_test() {
+    let service = |depends_on: &[&str]| AddonService {
+        depends_on: Some(depends_on.iter().map(|d| d.to_string()).collect()), ..Default::default()
+    };
+    let mut services = HashMap::new();
+    services.insert("web".to_owned(), service(&["db", "mqtt"]));
+    services.insert("db".to_owned(), service(&[]));
+    services.insert("mqtt".to_owned(), service(&["db", "unknown"]));
+    assert_eq!(startup_order(&services), Ok(vec!["db".to_owned(), "mqtt".to_owned(), "web".to_owned()]));
+    services.insert("db".to_owned(), service(&["web"]));
+    assert_eq!(startup_order(&services), Err(vec!["db".to_owned(), "web".to_owned(), "db".to_owned()]));
+}
+
+#[test]
+fn host_port_collisions_test() {
+    let service = |ports: &[&str]| AddonService { ports: Some(ports.iter().map(|p| p.to_string()).collect()), ..Default::default() };
+    let mut services = HashMap::new();
+    services.insert("web".to_owned(), service(&["8080:80", "53:53/udp", "9000"]));
+    services.insert("api".to_owned(), service(&["8079-8081:8079-8081", "53:53", "9000"]));
+    let collisions = host_port_collisions(&services);
+    assert_eq!(collisions, vec![("services.web.ports[0]".to_owned(),
+                                 "The host port 8080/tcp is already mapped by services.api.ports[0]".to_owned())]);
+    assert!(host_ports("80").is_empty());
+    assert_eq!(host_ports("5000-5001:80/udp"), vec!["5000/udp", "5001/udp"]);
+}
+
+#[test]
+fn variant_tag_suffix_test() {
+    let variant = Variant::default();
+    assert_eq!(variant.tag_suffix("with-cloud-connector"), "-with-cloud-connector");
+    let variant = Variant { tag_suffix: Some(String::new()), ..Default::default() };
+    assert_eq!(variant.tag_suffix("standard"), "");
+}
+
+#[test]
+fn to_canonical_json_test() {
+    let entries = [("zwave", 3), ("mqtt", 1), ("http", 2), ("bluetooth", 4)];
+    let mut first: HashMap<String, HashMap<String, u32>> = HashMap::new();
+    for (name, value) in entries.iter() {
+        first.entry("services".to_owned()).or_default().insert(name.to_string(), *value);
+        first.entry(name.to_string()).or_default().insert("v".to_owned(), *value);
+    }
+    let mut second: HashMap<String, HashMap<String, u32>> = HashMap::new();
+    for (name, value) in entries.iter().rev() {
+        second.entry(name.to_string()).or_default().insert("v".to_owned(), *value);
+        second.entry("services".to_owned()).or_default().insert(name.to_string(), *value);
+    }
+    let first = to_canonical_json(&first).unwrap();
+    let second = to_canonical_json(&second).unwrap();
+    // Equal bytes, so the content hash of both is equal as well
+    assert_eq!(first, second);
+    assert!(String::from_utf8(first).unwrap().starts_with(r#"{"bluetooth":{"v":4},"http":{"v":2},"mqtt":{"v":1},"services":{"bluetooth":4,"#));
+}
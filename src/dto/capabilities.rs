@@ -0,0 +1,61 @@
+//! The Linux capabilities of `cap_add` and `cap_drop`. Names are matched case-insensitive, with or without
+//! the "CAP_" prefix. Capabilities that allow to take over the host require a mandatory permission.
+use super::addons::AddonService;
+use super::schema::{Positions, ValidationIssue};
+
+/// The capabilities of capabilities(7), without the "CAP_" prefix. "ALL" stands for all of them.
+pub const CAPABILITIES: [&str; 42] = ["ALL", "AUDIT_CONTROL", "AUDIT_READ", "AUDIT_WRITE", "BLOCK_SUSPEND", "BPF",
+    "CHECKPOINT_RESTORE", "CHOWN", "DAC_OVERRIDE", "DAC_READ_SEARCH", "FOWNER", "FSETID", "IPC_LOCK", "IPC_OWNER", "KILL",
+    "LEASE", "LINUX_IMMUTABLE", "MAC_ADMIN", "MAC_OVERRIDE", "MKNOD", "NET_ADMIN", "NET_BIND_SERVICE", "NET_BROADCAST",
+    "NET_RAW", "PERFMON", "SETFCAP", "SETGID", "SETPCAP", "SETUID", "SYSLOG", "SYS_ADMIN", "SYS_BOOT", "SYS_CHROOT",
+    "SYS_MODULE", "SYS_NICE", "SYS_PACCT", "SYS_PTRACE", "SYS_RAWIO", "SYS_RESOURCE", "SYS_TIME", "SYS_TTY_CONFIG",
+    "WAKE_ALARM"];
+
+/// Added capabilities that allow to take over the host
+pub const PRIVILEGED: [&str; 10] = ["ALL", "SYS_ADMIN", "SYS_MODULE", "SYS_RAWIO", "SYS_PTRACE", "SYS_BOOT", "DAC_READ_SEARCH",
+    "MAC_ADMIN", "MAC_OVERRIDE", "BPF"];
+
+/// The mandatory permission of services with a [`PRIVILEGED`] capability
+pub const PERMISSION_PRIVILEGED_CAPABILITIES: &str = "PRIVILEGED_CAPABILITIES";
+
+/// The capability name without prefix, upper case: "cap_sys_admin" is "SYS_ADMIN"
+pub fn normalize(capability: &str) -> String {
+    let upper = capability.trim().to_uppercase();
+    upper.trim_start_matches("CAP_").to_owned()
+}
+
+/// The errors of the capabilities of a service
+pub fn check(service_id: &str, service: &AddonService, positions: &Positions) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    for (field, capabilities) in &[("cap_add", &service.cap_add), ("cap_drop", &service.cap_drop)] {
+        for (index, capability) in capabilities.iter().flatten().enumerate() {
+            let name = normalize(capability);
+            let path = format!("services.{}.{}[{}]", service_id, field, index);
+            if !CAPABILITIES.contains(&name.as_str()) {
+                issues.push(positions.error(path, format!("Unknown Linux capability {}", capability)));
+                continue;
+            }
+            let permitted = service.permissions.as_ref()
+                .map(|p| p.mandatory.iter().any(|p| p == PERMISSION_PRIVILEGED_CAPABILITIES)).unwrap_or(false);
+            if *field == "cap_add" && PRIVILEGED.contains(&name.as_str()) && !permitted {
+                issues.push(positions.error(path, format!("The capability {} requires the mandatory permission {}",
+                                                          name, PERMISSION_PRIVILEGED_CAPABILITIES)));
+            }
+        }
+    }
+    issues
+}
+
+#[test]
+fn capabilities_test() {
+    assert_eq!(normalize("cap_sys_admin"), "SYS_ADMIN");
+    assert_eq!(normalize("NET_RAW"), "NET_RAW");
+    let service = AddonService {
+        cap_add: Some(vec!["cap_net_raw".to_owned(), "SYS_ADMIN".to_owned(), "FLY".to_owned()]),
+        cap_drop: Some(vec!["all".to_owned()]),
+        ..Default::default()
+    };
+    let issues = check("app", &service, &Positions::default());
+    let paths: Vec<&str> = issues.iter().map(|i| i.path.as_str()).collect();
+    assert_eq!(paths, vec!["services.app.cap_add[1]", "services.app.cap_add[2]"]);
+}
@@ -0,0 +1,66 @@
+//! The per-language documentation of `x-docs`: A directory with one subdirectory per BCP-47 language tag,
+//! each with an `index.md` and further markdown files and images, like `docs/de/index.md`.
+//! The directory is published as a tar OCI artifact, so that the store shows localized instructions.
+use super::addons::Docs;
+use super::schema::{Positions, ValidationIssue};
+use super::translations;
+use std::path::{Path, PathBuf};
+
+/// The entry page of each language
+pub const INDEX: &str = "index.md";
+
+const EXTENSIONS: [&str; 6] = ["md", "png", "jpg", "jpeg", "gif", "svg"];
+
+fn entries(directory: &Path) -> Vec<PathBuf> {
+    let mut entries: Vec<PathBuf> = match std::fs::read_dir(directory) {
+        Ok(v) => v.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort();
+    entries
+}
+
+/// All files of the docs directory, sorted
+pub fn files(docs_dir: &Path) -> Vec<PathBuf> {
+    entries(docs_dir).into_iter().flat_map(|e| match e.is_dir() {
+        true => entries(&e),
+        false => vec![e],
+    }).collect()
+}
+
+/// The errors of a docs directory that does not have the expected structure
+pub fn check(docs: &Docs, addon_dir: &Path, positions: &Positions) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let path = "x-docs.directory".to_owned();
+    let docs_dir = addon_dir.join(&docs.directory);
+    if !docs_dir.is_dir() {
+        issues.push(positions.error(path, format!("The docs directory {} does not exist", docs_dir.display())));
+        return issues;
+    }
+    let name = |p: &Path| p.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut languages = 0;
+    for entry in entries(&docs_dir) {
+        let language = name(&entry);
+        if !entry.is_dir() {
+            issues.push(positions.error(path.clone(), format!("{} is not a language directory like \"de\"", entry.display())));
+            continue;
+        }
+        if !translations::is_language_tag(&language) {
+            issues.push(positions.error(path.clone(), format!("The docs directory '{}' is not a BCP-47 language tag like \"de\" or \"pt-BR\"", language)));
+        }
+        languages += 1;
+        if !entry.join(INDEX).is_file() {
+            issues.push(positions.error(path.clone(), format!("The {} documentation has no {}", language, INDEX)));
+        }
+        for file in entries(&entry) {
+            let extension = file.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            if file.is_dir() || !EXTENSIONS.contains(&extension.as_str()) {
+                issues.push(positions.error(path.clone(), format!("{} is not a markdown file or image ({})", file.display(), EXTENSIONS.join(", "))));
+            }
+        }
+    }
+    if languages == 0 {
+        issues.push(positions.error(path, format!("The docs directory {} has no language directories", docs_dir.display())));
+    }
+    issues
+}
@@ -0,0 +1,237 @@
+//! Descriptor inheritance: `extends: ../common.yml` deep merges a shared base descriptor into an addons.yml file.
+//! Within a file, a service can extend another service, and YAML merge keys (`<<: *defaults`) are resolved.
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const EXTENDS: &str = "extends";
+
+const MERGE_KEY: &str = "<<";
+
+/// Merges `overlay` into `base`. Mappings are merged recursively, all other values of the overlay
+/// replace the base value. Lists are replaced as well, not concatenated.
+pub fn deep_merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// The base files of a descriptor. `extends` is a single path or a list of paths, relative to the descriptor.
+fn extended_files(data: &mut Value, dir: &Path) -> Result<Vec<PathBuf>, failure::Error> {
+    let extends = match data {
+        Value::Mapping(map) => map.remove(&Value::String(EXTENDS.to_owned())),
+        _ => None,
+    };
+    let paths = match extends {
+        None => Vec::new(),
+        Some(Value::String(path)) => vec![path],
+        Some(Value::Sequence(list)) => list.into_iter()
+            .map(|v| v.as_str().map(|s| s.to_owned()).ok_or_else(|| failure::err_msg("'extends' entries must be file paths")))
+            .collect::<Result<Vec<_>, _>>()?,
+        Some(_) => return Err(failure::err_msg("'extends' must be a file path or a list of file paths")),
+    };
+    Ok(paths.into_iter().map(|p| dir.join(p)).collect())
+}
+
+fn load(filename: &Path, stack: &mut Vec<PathBuf>) -> Result<Value, failure::Error> {
+    let canonical = filename.canonicalize()?;
+    if let Some(pos) = stack.iter().position(|p| p == &canonical) {
+        let cycle: Vec<String> = stack[pos..].iter().chain(std::iter::once(&canonical)).map(|p| p.display().to_string()).collect();
+        return Err(failure::err_msg(format!("Cyclic 'extends': {}", cycle.join(" -> "))));
+    }
+
+    let mut data: Value = serde_yaml::from_slice(&std::fs::read(filename)?)?;
+    resolve_merge_keys(&mut data);
+    let dir = filename.parent().unwrap_or(Path::new("."));
+    let bases = extended_files(&mut data, dir)?;
+    if bases.is_empty() {
+        return Ok(data);
+    }
+
+    stack.push(canonical);
+    let mut merged = Value::Mapping(Mapping::new());
+    for base in bases {
+        let base = load(&base, stack).map_err(|e| failure::err_msg(format!("In {}: {}", base.display(), e)))?;
+        deep_merge(&mut merged, base);
+    }
+    stack.pop();
+    deep_merge(&mut merged, data);
+    Ok(merged)
+}
+
+/// Reads a descriptor and all files it extends. Later files in an `extends` list override earlier ones,
+/// the descriptor itself overrides all of them.
+pub fn load_with_extends(filename: &Path) -> Result<Value, failure::Error> {
+    load(filename, &mut Vec::new())
+}
+
+/// YAML merge keys: `<<: *defaults` or `<<: [*a, *b]` adds the keys of the anchored mappings that the mapping
+/// does not have itself. Earlier mappings of a list take precedence.
+pub fn resolve_merge_keys(data: &mut Value) {
+    match data {
+        Value::Mapping(map) => {
+            if let Some(merged) = map.remove(&Value::String(MERGE_KEY.to_owned())) {
+                let sources = match merged {
+                    Value::Sequence(list) => list,
+                    other => vec![other],
+                };
+                for source in sources {
+                    if let Value::Mapping(source) = source {
+                        for (key, value) in source {
+                            if !map.contains_key(&key) {
+                                map.insert(key, value);
+                            }
+                        }
+                    }
+                }
+            }
+            for (_, value) in map.iter_mut() {
+                resolve_merge_keys(value);
+            }
+        }
+        Value::Sequence(list) => list.iter_mut().for_each(resolve_merge_keys),
+        _ => {}
+    }
+}
+
+/// The service that a service extends: `extends: base` or compose style `extends: {service: base}`
+fn extended_service(service: &mut Value) -> Result<Option<String>, String> {
+    let extends = match service {
+        Value::Mapping(map) => map.remove(&Value::String(EXTENDS.to_owned())),
+        _ => None,
+    };
+    match extends {
+        None => Ok(None),
+        Some(Value::String(base)) => Ok(Some(base)),
+        Some(Value::Mapping(map)) => match (map.get(&Value::String("service".to_owned())), map.len()) {
+            (Some(Value::String(base)), 1) => Ok(Some(base.clone())),
+            _ => Err("Only `extends: <service>` or `extends: {service: <service>}` is supported. \
+                      Base files are extended at the top of the file.".to_owned()),
+        },
+        Some(_) => Err("'extends' must name another service".to_owned()),
+    }
+}
+
+fn resolve_service(id: &str, original: &BTreeMap<String, Value>, bases: &BTreeMap<String, String>,
+                   resolved: &mut BTreeMap<String, Value>, stack: &mut Vec<String>, errors: &mut Vec<(String, String)>) {
+    if resolved.contains_key(id) {
+        return;
+    }
+    let base = match bases.get(id) {
+        Some(v) => v,
+        None => {
+            resolved.insert(id.to_owned(), original[id].clone());
+            return;
+        }
+    };
+    let path = format!("services.{}.extends", id);
+    if let Some(pos) = stack.iter().position(|s| s == id) {
+        let cycle: Vec<&str> = stack[pos..].iter().map(|s| s.as_str()).chain(std::iter::once(id)).collect();
+        errors.push((path, format!("Cyclic 'extends': {}", cycle.join(" -> "))));
+        return;
+    }
+    if !original.contains_key(base) {
+        errors.push((path, format!("The extended service {} does not exist", base)));
+        return;
+    }
+    stack.push(id.to_owned());
+    resolve_service(base, original, bases, resolved, stack, errors);
+    stack.pop();
+    // The base failed to resolve, its error is already reported
+    let mut merged = match resolved.get(base) {
+        Some(v) => v.clone(),
+        None => return,
+    };
+    deep_merge(&mut merged, original[id].clone());
+    resolved.insert(id.to_owned(), merged);
+}
+
+/// Merges the services that a service extends into it, like the shared `cap_drop` and `permissions` of a base service.
+/// Like for base files, the values of the service take precedence and lists are replaced.
+/// Returns the path and message of each invalid `extends`.
+pub fn merge_services(data: &mut Value) -> Vec<(String, String)> {
+    let mut errors = Vec::new();
+    let services = match data.get_mut("services") {
+        Some(Value::Mapping(v)) => v,
+        _ => return errors,
+    };
+    let mut original = BTreeMap::new();
+    let mut bases = BTreeMap::new();
+    for (id, service) in services.iter_mut() {
+        let id = id.as_str().unwrap_or_default().to_owned();
+        match extended_service(service) {
+            Ok(Some(base)) => {
+                bases.insert(id.clone(), base);
+            }
+            Ok(None) => {}
+            Err(message) => errors.push((format!("services.{}.extends", id), message)),
+        }
+        original.insert(id, service.clone());
+    }
+    if bases.is_empty() {
+        return errors;
+    }
+    let mut resolved = BTreeMap::new();
+    for id in bases.keys() {
+        resolve_service(id, &original, &bases, &mut resolved, &mut Vec::new(), &mut errors);
+    }
+    for (id, service) in services.iter_mut() {
+        if let Some(merged) = id.as_str().and_then(|id| resolved.remove(id)) {
+            *service = merged;
+        }
+    }
+    errors
+}
+
+#[test]
+fn merge_services_test() {
+    let mut data: Value = serde_yaml::from_str(r#"
+x-defaults: &defaults
+  cap_drop: [ALL]
+services:
+  base:
+    <<: *defaults
+    permissions: {mandatory: [THINGS]}
+    image: base
+  app:
+    extends: base
+    image: app
+  worker:
+    extends: {service: app}
+    cap_drop: [NET_RAW]
+"#).unwrap();
+    resolve_merge_keys(&mut data);
+    assert!(merge_services(&mut data).is_empty());
+    let services = &data["services"];
+    assert_eq!(services["base"]["cap_drop"][0].as_str(), Some("ALL"));
+    assert_eq!(services["app"]["image"].as_str(), Some("app"));
+    assert_eq!(services["app"]["permissions"]["mandatory"][0].as_str(), Some("THINGS"));
+    assert_eq!(services["worker"]["image"].as_str(), Some("app"));
+    assert_eq!(services["worker"]["cap_drop"][0].as_str(), Some("NET_RAW"));
+    assert!(services["worker"].get("extends").is_none());
+
+    let mut cyclic: Value = serde_yaml::from_str("services:\n  a: {extends: b}\n  b: {extends: a}\n  c: {extends: d}\n").unwrap();
+    let errors = merge_services(&mut cyclic);
+    assert_eq!(errors.len(), 3);
+    assert_eq!(errors[2], ("services.c.extends".to_owned(), "The extended service d does not exist".to_owned()));
+}
+
+#[test]
+fn deep_merge_test() {
+    let mut base: Value = serde_yaml::from_str("x-runtime: {memory_min: 10, memory_max: 100}\nauthors: [a]").unwrap();
+    let overlay: Value = serde_yaml::from_str("x-runtime: {memory_max: 200}\nauthors: [b]").unwrap();
+    deep_merge(&mut base, overlay);
+    assert_eq!(base["x-runtime"]["memory_min"].as_i64(), Some(10));
+    assert_eq!(base["x-runtime"]["memory_max"].as_i64(), Some(200));
+    assert_eq!(base["authors"].as_sequence().unwrap().len(), 1);
+}
@@ -0,0 +1,143 @@
+//! Corrects recoverable mistakes in an addons.yml file, before it is deserialized.
+//! Each applied fix is reported, so that the author can review the changes.
+use serde_yaml::{Mapping, Value};
+
+/// Service fields that expect a list of strings
+const SERVICE_LIST_FIELDS: [&str; 7] = ["ports", "firewall_allow", "cap_add", "cap_drop", "devices", "depends_on", "volumes"];
+/// Registry fields that expect a list of strings
+const REGISTRY_LIST_FIELDS: [&str; 3] = ["authors", "manufacturers", "products"];
+
+fn key(name: &str) -> Value {
+    Value::String(name.to_owned())
+}
+
+/// Converts a single string or number into a list with one entry
+fn to_list(map: &mut Mapping, field: &str, path: &str, fixes: &mut Vec<String>) {
+    let value = match map.get_mut(&key(field)) {
+        Some(v) => v,
+        None => return,
+    };
+    let single = match value {
+        Value::String(v) => v.clone(),
+        Value::Number(v) => v.to_string(),
+        _ => return,
+    };
+    *value = Value::Sequence(vec![Value::String(single)]);
+    fixes.push(format!("{}.{}: Converted the single value into a list", path, field));
+}
+
+/// Adds a missing list
+fn fill_list(map: &mut Mapping, field: &str, path: &str, fixes: &mut Vec<String>) {
+    if !map.contains_key(&key(field)) {
+        map.insert(key(field), Value::Sequence(Vec::new()));
+        fixes.push(format!("{}.{}: Added the missing empty list", path, field));
+    }
+}
+
+/// Removes whitespace and lower cases the protocol: `8080 : 80/TCP` becomes `8080:80/tcp`
+fn normalize_port(port: &str) -> String {
+    port.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_lowercase()
+}
+
+fn fix_ports(service: &mut Mapping, path: &str, fixes: &mut Vec<String>) {
+    if let Some(Value::Sequence(ports)) = service.get_mut(&key("ports")) {
+        for port in ports.iter_mut() {
+            let (original, is_number) = match port {
+                Value::String(v) => (v.clone(), false),
+                Value::Number(v) => (v.to_string(), true),
+                _ => continue,
+            };
+            let normalized = normalize_port(&original);
+            if is_number || normalized != original {
+                fixes.push(format!("{}.ports: Normalized \"{}\" to \"{}\"", path, original, normalized));
+                *port = Value::String(normalized);
+            }
+        }
+    }
+}
+
+fn fix_permissions(service: &mut Mapping, path: &str, fixes: &mut Vec<String>) {
+    let permissions = match service.get_mut(&key("permissions")) {
+        Some(Value::Mapping(v)) => v,
+        _ => return,
+    };
+    let path = format!("{}.permissions", path);
+    for field in &["mandatory", "optional"] {
+        fill_list(permissions, field, &path, fixes);
+        to_list(permissions, field, &path, fixes);
+        if let Some(Value::Sequence(list)) = permissions.get_mut(&key(field)) {
+            let before = list.clone();
+            let mut entries: Vec<String> = list.iter().filter_map(|v| v.as_str().map(|s| s.to_owned())).collect();
+            if entries.len() != list.len() {
+                // Not all entries are strings. Validation will report this.
+                continue;
+            }
+            entries.sort();
+            entries.dedup();
+            *list = entries.into_iter().map(Value::String).collect();
+            if *list != before {
+                fixes.push(format!("{}.{}: Sorted and removed duplicates", path, field));
+            }
+        }
+    }
+}
+
+/// Applies all fixes to the parsed addons file and returns a description of each applied fix
+pub fn fix_addons_file(data: &mut Value) -> Vec<String> {
+    let mut fixes = Vec::new();
+    let root = match data {
+        Value::Mapping(v) => v,
+        _ => return fixes,
+    };
+
+    if let Some(Value::Mapping(services)) = root.get_mut(&key("services")) {
+        for (service_id, service) in services.iter_mut() {
+            let service = match service {
+                Value::Mapping(v) => v,
+                _ => continue,
+            };
+            let path = format!("services.{}", service_id.as_str().unwrap_or_default());
+            for field in SERVICE_LIST_FIELDS.iter() {
+                to_list(service, field, &path, &mut fixes);
+            }
+            fix_ports(service, &path, &mut fixes);
+            fix_permissions(service, &path, &mut fixes);
+        }
+    }
+
+    if let Some(Value::Mapping(registry)) = root.get_mut(&key("x-ohx-registry")) {
+        let path = "x-ohx-registry";
+        for field in REGISTRY_LIST_FIELDS.iter() {
+            fill_list(registry, field, path, &mut fixes);
+            to_list(registry, field, path, &mut fixes);
+        }
+        if !registry.contains_key(&key("status")) {
+            let mut status = Mapping::new();
+            status.insert(key("code"), key("AVAILABLE"));
+            registry.insert(key("status"), Value::Mapping(status));
+            fixes.push(format!("{}.status: Added the missing status AVAILABLE", path));
+        }
+    }
+    fixes
+}
+
+#[test]
+fn fix_addons_file_test() {
+    let mut data: Value = serde_yaml::from_str(r#"
+services:
+  main:
+    ports: "8080 : 80/TCP"
+    permissions:
+      mandatory: [THINGS, HW_GPIO, THINGS]
+x-ohx-registry:
+  authors: David
+"#).unwrap();
+    let fixes = fix_addons_file(&mut data);
+    assert_eq!(fixes.len(), 8);
+
+    let service = &data["services"]["main"];
+    assert_eq!(service["ports"][0].as_str(), Some("8080:80/tcp"));
+    assert_eq!(service["permissions"]["mandatory"].as_sequence().unwrap().len(), 2);
+    assert!(service["permissions"]["optional"].as_sequence().unwrap().is_empty());
+    assert_eq!(data["x-ohx-registry"]["authors"][0].as_str(), Some("David"));
+}
@@ -0,0 +1,142 @@
+//! Compose style `${VAR}` references in string values, like `version: ${ADDON_VERSION}` or
+//! `image: registry.local/app:${TAG:-latest}`. Replaced after `extends` and before validation, so that CI variables
+//! can drive versions and image names. `${VAR:-default}` applies to unset and empty variables, `${VAR-default}`
+//! only to unset ones, and `$$` is a literal `$`. Only strings are replaced, they stay strings.
+use super::schema::{Positions, ValidationIssue};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+
+/// Replaces the references of one string. Variables without a value and default are collected and replaced by "".
+fn substitute(text: &str, variables: &BTreeMap<String, String>, unresolved: &mut Vec<String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(pos) = rest.find('$') {
+        result.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if rest.starts_with("$$") {
+            result.push('$');
+            rest = &rest[2..];
+            continue;
+        }
+        if !rest.starts_with("${") {
+            result.push('$');
+            rest = &rest[1..];
+            continue;
+        }
+        let end = rest.find('}').ok_or_else(|| format!("The variable reference {} is not closed", rest))?;
+        let expression = &rest[2..end];
+        rest = &rest[end + 1..];
+        let name_length = expression.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(expression.len());
+        let (name, operator) = expression.split_at(name_length);
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(format!("${{{}}} is not a variable name", expression));
+        }
+        let value = variables.get(name);
+        let replacement = if operator.is_empty() {
+            value.cloned()
+        } else if operator.starts_with(":-") {
+            Some(value.filter(|v| !v.is_empty()).cloned().unwrap_or_else(|| operator[2..].to_owned()))
+        } else if operator.starts_with('-') {
+            Some(value.cloned().unwrap_or_else(|| operator[1..].to_owned()))
+        } else {
+            return Err(format!("${{{}}}: Only ${{VAR}}, ${{VAR:-default}} and ${{VAR-default}} are supported", expression));
+        };
+        match replacement {
+            Some(v) => result.push_str(&v),
+            None => unresolved.push(name.to_owned()),
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn walk(value: &mut Value, path: &str, variables: &BTreeMap<String, String>, require: bool, positions: &Positions,
+        issues: &mut Vec<ValidationIssue>) {
+    match value {
+        Value::String(text) if text.contains('$') => {
+            let mut unresolved = Vec::new();
+            match substitute(text, variables, &mut unresolved) {
+                Ok(v) => *text = v,
+                Err(message) => issues.push(positions.error(path.to_owned(), message)),
+            }
+            for name in unresolved {
+                let message = format!("The variable {} is not set and has no default. It is replaced by an empty string.", name);
+                issues.push(match require {
+                    true => positions.error(path.to_owned(), format!("The variable {} is not set and has no default", name)),
+                    false => positions.warning("env/unresolved", path.to_owned(), message),
+                });
+            }
+        }
+        Value::Mapping(map) => {
+            for (key, child) in map.iter_mut() {
+                let key = key.as_str().unwrap_or_default();
+                let child_path = if path.is_empty() { key.to_owned() } else { format!("{}.{}", path, key) };
+                walk(child, &child_path, variables, require, positions, issues);
+            }
+        }
+        Value::Sequence(list) => {
+            for (index, child) in list.iter_mut().enumerate() {
+                walk(child, &format!("{}[{}]", path, index), variables, require, positions, issues);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replaces the references of all string values. Unresolved variables are warnings, or errors with `require`.
+pub fn apply(data: &mut Value, variables: &BTreeMap<String, String>, require: bool, positions: &Positions) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    walk(data, "", variables, require, positions, &mut issues);
+    issues
+}
+
+/// Parses a dotenv file: `KEY=value` lines, optionally with `export `, quoted values and `#` comments
+pub fn parse_env_file(text: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut variables = BTreeMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_start_matches("export ").trim_start();
+        let mut parts = line.splitn(2, '=');
+        let name = parts.next().unwrap_or_default().trim();
+        let value = parts.next().ok_or_else(|| format!("Line {}: Expected KEY=value", number + 1))?.trim();
+        if name.is_empty() || name.contains(|c: char| !(c.is_ascii_alphanumeric() || c == '_')) {
+            return Err(format!("Line {}: {} is not a variable name", number + 1, name));
+        }
+        let quoted = value.len() >= 2 && ((value.starts_with('"') && value.ends_with('"')) || (value.starts_with('\'') && value.ends_with('\'')));
+        let value = match quoted {
+            true => value[1..value.len() - 1].to_owned(),
+            false => value.split(" #").next().unwrap_or_default().trim_end().to_owned(),
+        };
+        variables.insert(name.to_owned(), value);
+    }
+    Ok(variables)
+}
+
+#[test]
+fn interpolate_test() {
+    let mut variables = BTreeMap::new();
+    variables.insert("TAG".to_owned(), "2.0".to_owned());
+    variables.insert("EMPTY".to_owned(), String::new());
+    let mut unresolved = Vec::new();
+    assert_eq!(substitute("app:${TAG}", &variables, &mut unresolved), Ok("app:2.0".to_owned()));
+    assert_eq!(substitute("${EMPTY:-a}${EMPTY-b}${UNSET-c}", &variables, &mut unresolved), Ok("ac".to_owned()));
+    assert_eq!(substitute("$$HOME costs $5", &variables, &mut unresolved), Ok("$HOME costs $5".to_owned()));
+    assert!(unresolved.is_empty());
+    assert_eq!(substitute("v${UNSET}", &variables, &mut unresolved), Ok("v".to_owned()));
+    assert_eq!(unresolved, vec!["UNSET".to_owned()]);
+    assert!(substitute("${TAG", &variables, &mut unresolved).is_err());
+    assert!(substitute("${TAG:?required}", &variables, &mut unresolved).is_err());
+
+    let mut data: Value = serde_yaml::from_str("services:\n  app:\n    image: \"app:${TAG}\"\n    ports: [\"${PORT}\"]\n").unwrap();
+    let issues = apply(&mut data, &variables, false, &Positions::default());
+    assert_eq!(data["services"]["app"]["image"].as_str(), Some("app:2.0"));
+    assert_eq!(issues.iter().map(|i| (i.path.as_str(), i.is_error())).collect::<Vec<_>>(), vec![("services.app.ports[0]", false)]);
+
+    let env = parse_env_file("# CI\nexport TAG=\"1.0 beta\"\nPORT=8080 # web\n").unwrap();
+    assert_eq!(env.get("TAG").map(|v| v.as_str()), Some("1.0 beta"));
+    assert_eq!(env.get("PORT").map(|v| v.as_str()), Some("8080"));
+    assert!(parse_env_file("NO VALUE").is_err());
+}
@@ -0,0 +1,400 @@
+//! Lint warnings of addons.yml files and the annotations that suppress them.
+//! `# ohx-allow: ports/privileged-mapping "The web UI must be reachable on port 80"` suppresses a rule for the value
+//! on the same line or, for a comment on its own line, the value on the next line. Annotations above the first value
+//! apply to the whole file. The justification is required: Suppressions are published with the addon for reviewers.
+use super::addons::AddonFileEntry;
+use super::schema::{Positions, Severity, ValidationIssue};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// The rule ids and what they warn about
+pub const RULES: &[(&str, &str)] = &[
+    ("ports/privileged-mapping", "A port is mapped to a privileged host port below 1024"),
+    ("ports/undeclared", "The image exposes a port that is not declared in addons.yml"),
+    ("ports/not-exposed", "A declared port is not exposed by the image"),
+    ("user/root", "The image runs as root without the ROOT_USER permission"),
+    ("image/size", "A build image is larger than 500 MiB, see --max-image-size"),
+    ("meta/homepage", "The addon has no homepage"),
+    ("meta/translations", "The title or description has no translations"),
+    ("meta/short-description", "The description is too short to tell users what the addon does"),
+    ("image/latest-tag", "A service image has no tag or the \"latest\" tag"),
+    ("image/unpinned-base", "A Dockerfile or build recipe uses a base image without a fixed tag"),
+    ("image/no-armv6", "The armv6 Dockerfile or build recipe uses a base image that is not published for ARMv6"),
+    ("dockerfile/expose", "A Dockerfile exposes a port that is not declared in addons.yml"),
+    ("dockerfile/volume", "A Dockerfile declares a volume that is not mounted in addons.yml"),
+    ("devices/broad", "A service maps a device like /dev/mem that gives access to the whole system"),
+    ("runtime/memory-max", "The addon may use more memory than small devices have, see --memory-warning"),
+    ("dockerfile/arch-mismatch", "The base image of a Dockerfile is for another architecture than the file name suffix"),
+    ("env/unresolved", "A ${VAR} reference has no value and no default and is replaced by an empty string"),
+    ("schema/outdated", "The file uses an older schema version and needs changes, see `migrate --write`"),
+];
+
+/// Descriptions need at least this many characters
+pub const MIN_DESCRIPTION_LENGTH: usize = 40;
+
+const ANNOTATION: &str = "ohx-allow:";
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Suppression {
+    pub rule: String,
+    /// The annotated value. None for the whole file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub justification: String,
+}
+
+impl Suppression {
+    /// Warnings without a path are suppressed by every annotation of their rule
+    pub fn matches(&self, rule: &str, path: &str) -> bool {
+        if self.rule != rule {
+            return false;
+        }
+        match &self.path {
+            Some(scope) if !path.is_empty() => {
+                path == scope || path.starts_with(&format!("{}.", scope)) || path.starts_with(&format!("{}[", scope))
+            }
+            _ => true,
+        }
+    }
+}
+
+/// The `# ohx-allow:` annotations of an addons.yml file. Invalid annotations are added to `issues`.
+pub fn suppressions(yaml: &str, positions: &Positions, issues: &mut Vec<ValidationIssue>) -> Vec<Suppression> {
+    let pattern = regex::Regex::new(r#"^\s*([a-z0-9\-]+/[a-z0-9\-]+)\s+"([^"]*)"\s*$"#).unwrap();
+    let lines: Vec<&str> = yaml.lines().collect();
+    let first_line = positions.find("").map(|(line, _)| line).unwrap_or(usize::MAX);
+    let mut result = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let start = match line.find(ANNOTATION) {
+            Some(v) => v,
+            None => continue,
+        };
+        let line_number = index + 1;
+        let error = |message: String| ValidationIssue {
+            severity: Severity::Error,
+            rule: None,
+            path: String::new(),
+            message,
+            location: Some((line_number, start + 1)),
+            suppressed: None,
+        };
+        let comment = line[..start].trim_end();
+        if !comment.ends_with('#') {
+            continue;
+        }
+        let captures = match pattern.captures(&line[start + ANNOTATION.len()..]) {
+            Some(v) => v,
+            None => {
+                issues.push(error(format!("Expected '# {} <rule> \"<justification>\"'", ANNOTATION)));
+                continue;
+            }
+        };
+        let rule = captures[1].to_owned();
+        if !RULES.iter().any(|(id, _)| *id == rule) {
+            let rules: Vec<&str> = RULES.iter().map(|(id, _)| *id).collect();
+            issues.push(error(format!("Unknown rule {}. Rules: {}", rule, rules.join(", "))));
+            continue;
+        }
+        let justification = captures[2].trim().to_owned();
+        if justification.is_empty() {
+            issues.push(error(format!("The suppression of {} requires a justification", rule)));
+            continue;
+        }
+
+        let own_line = comment.trim_end_matches('#').trim().is_empty();
+        let path = match own_line {
+            true if line_number < first_line => None,
+            // The next line with a value
+            true => match (index + 1..lines.len())
+                .find(|i| !lines[*i].trim().is_empty() && !lines[*i].trim_start().starts_with('#'))
+                .and_then(|i| positions.at_line(i + 1)) {
+                Some(v) => Some(v),
+                None => {
+                    issues.push(error("The annotation is not followed by a value".to_owned()));
+                    continue;
+                }
+            },
+            false => match positions.at_line(line_number) {
+                Some(v) => Some(v),
+                None => {
+                    issues.push(error("The annotation is not next to a value".to_owned()));
+                    continue;
+                }
+            },
+        };
+        result.push(Suppression { rule, path, justification });
+    }
+    result
+}
+
+/// Marks the warnings that an annotation suppresses
+pub fn apply(suppressions: &[Suppression], issues: &mut [ValidationIssue]) {
+    for issue in issues.iter_mut() {
+        if let Some(rule) = &issue.rule {
+            issue.suppressed = suppressions.iter().find(|s| s.matches(rule, &issue.path)).map(|s| s.justification.clone());
+        }
+    }
+}
+
+/// An image reference without a tag or digest, or with the "latest" tag
+fn is_unpinned(image: &str) -> bool {
+    if image.contains('@') {
+        return false;
+    }
+    // A registry port is not a tag: "registry:5000/image"
+    let name = image.rsplit('/').next().unwrap_or(image);
+    match name.rfind(':') {
+        Some(index) => &name[index + 1..] == "latest",
+        None => true,
+    }
+}
+
+/// The container ports of a port declaration like "8080:80/udp" or "5000-5010", as "80/udp", "5000/tcp" etc.
+pub fn container_ports(declaration: &str) -> Vec<String> {
+    let mut parts = declaration.splitn(2, '/');
+    let mapping = parts.next().unwrap_or_default();
+    let protocol = parts.next().unwrap_or("tcp");
+    let container = mapping.rsplit(':').next().unwrap_or_default();
+    let mut range = container.splitn(2, '-').map(|p| p.parse::<u16>());
+    match (range.next(), range.next()) {
+        (Some(Ok(start)), Some(Ok(end))) => (start..=end).map(|p| format!("{}/{}", p, protocol)).collect(),
+        (Some(Ok(port)), None) => vec![format!("{}/{}", port, protocol)],
+        _ => Vec::new(),
+    }
+}
+
+/// The declared container ports of all services that are build from the addon image.
+/// If no service declares a build context, all services are considered.
+pub fn declared_ports(input_file: &AddonFileEntry) -> BTreeSet<String> {
+    let build_services = input_file.services.values().filter(|s| s.build.is_some()).count();
+    input_file.services.values()
+        .filter(|s| build_services == 0 || s.build.is_some())
+        .filter_map(|s| s.ports.as_ref())
+        .flat_map(|ports| ports.iter().flat_map(|p| container_ports(p)))
+        .collect()
+}
+
+/// Docker hub repositories of a single architecture, like "arm64v8/alpine"
+const ARCH_REPOSITORIES: &[(&str, &str)] = &[("amd64/", "amd64"), ("arm64v8/", "aarch64"), ("arm32v7/", "armhf"),
+    ("arm32v6/", "armv6"), ("i386/", "i386")];
+
+/// The architecture of an image from a single architecture repository. None for multi-arch images.
+fn image_arch(image: &str) -> Option<&'static str> {
+    let image = image.trim_start_matches("docker.io/");
+    ARCH_REPOSITORIES.iter().find(|(prefix, _)| image.starts_with(prefix)).map(|(_, arch)| *arch)
+}
+
+/// Images that are not published for ARMv6: Repositories of other architectures and official images with
+/// ARMv5 and ARMv7 builds only
+const NO_ARMV6_IMAGES: &[&str] = &["arm32v5/", "arm32v7/", "arm64v8/", "amd64/", "i386/", "debian", "ubuntu", "fedora", "centos"];
+
+/// A base image that crashes or does not exist on a Raspberry Pi Zero or 1
+fn lacks_armv6(image: &str) -> bool {
+    let image = image.trim_start_matches("docker.io/").trim_start_matches("library/");
+    let name = image.split([':', '@']).next().unwrap_or(image);
+    NO_ARMV6_IMAGES.iter().any(|i| match i.ends_with('/') {
+        true => image.starts_with(i),
+        false => name == *i,
+    })
+}
+
+/// The Dockerfiles next to the addons.yml file
+pub fn dockerfiles(addon_dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = match std::fs::read_dir(addon_dir) {
+        Ok(v) => v.filter_map(|e| e.ok()).map(|e| e.path())
+            .filter(|p| p.is_file() && p.file_name().map(|n| n.to_string_lossy().starts_with("Dockerfile")).unwrap_or(false))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    files
+}
+
+/// The arguments of all `keyword` instructions of a Dockerfile, like "EXPOSE", with their line.
+/// The JSON form `VOLUME ["/data"]` is split like the shell form.
+fn instructions<'a>(dockerfile: &'a str, keyword: &str) -> Vec<(usize, Vec<&'a str>)> {
+    dockerfile.lines().enumerate().filter_map(|(index, line)| {
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some(v) if v.eq_ignore_ascii_case(keyword) => {
+                let args = parts.map(|p| p.trim_matches(|c| c == '[' || c == ']' || c == '"' || c == ','))
+                    .filter(|p| !p.is_empty()).collect();
+                Some((index + 1, args))
+            }
+            _ => None,
+        }
+    }).collect()
+}
+
+/// Compares the `EXPOSE` and `VOLUME` instructions and the base images of a Dockerfile with addons.yml
+fn check_dockerfile(name: &str, content: &str, declared_ports: &BTreeSet<String>, mounts: &BTreeSet<&str>,
+                    positions: &Positions, warnings: &mut Vec<ValidationIssue>) {
+    // Variables like "$PORT" are only known at build time
+    for (line, args) in instructions(content, "EXPOSE") {
+        for port in args.iter().filter(|p| !p.contains('$')) {
+            let port = if port.contains('/') { port.to_string() } else { format!("{}/tcp", port) };
+            if !declared_ports.contains(&port) {
+                warnings.push(positions.warning("dockerfile/expose", String::new(),
+                                                format!("{}:{}: Port {} is exposed, but not declared in the service ports", name, line, port)));
+            }
+        }
+    }
+    for (line, args) in instructions(content, "VOLUME") {
+        for volume in args.iter().filter(|v| !v.contains('$')) {
+            if !mounts.contains(volume.trim_end_matches('/')) {
+                warnings.push(positions.warning("dockerfile/volume", String::new(),
+                                                format!("{}:{}: The volume {} is not mounted by a service. Only 'logvolume' volumes are supported.", name, line, volume)));
+            }
+        }
+    }
+
+    // "Dockerfile" is for amd64, "Dockerfile_armv6" or "Dockerfile.armv6" for armv6
+    let arch = name.get(11..).unwrap_or("amd64");
+    for (line, image) in base_images(content) {
+        if is_unpinned(&image) {
+            warnings.push(positions.warning("image/unpinned-base", String::new(),
+                                            format!("{}:{}: The base image {} is not pinned. Use a version tag or digest.", name, line, image)));
+        }
+        match image_arch(&image) {
+            Some(other) if other != arch => {
+                warnings.push(positions.warning("dockerfile/arch-mismatch", String::new(),
+                                                format!("{}:{}: The base image {} is for {}, the Dockerfile for {}", name, line, image, other, arch)));
+            }
+            _ if arch == "armv6" && lacks_armv6(&image) => {
+                warnings.push(positions.warning("image/no-armv6", String::new(),
+                                                format!("{}:{}: The base image {} has no ARMv6 build. Use an image like arm32v6/alpine.", name, line, image)));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The base images of a Dockerfile with their line. Build stages that later stages use as base are skipped.
+fn base_images(dockerfile: &str) -> Vec<(usize, String)> {
+    let mut stages = Vec::new();
+    let mut images = Vec::new();
+    for (index, line) in dockerfile.lines().enumerate() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 2 || !parts[0].eq_ignore_ascii_case("FROM") {
+            continue;
+        }
+        // Options like "--platform=$BUILDPLATFORM" come before the image
+        let image = match parts[1..].iter().find(|p| !p.starts_with("--")) {
+            Some(v) => *v,
+            None => continue,
+        };
+        if image != "scratch" && !image.starts_with('$') && !stages.iter().any(|s: &String| s.eq_ignore_ascii_case(image)) {
+            images.push((index + 1, image.to_owned()));
+        }
+        if let Some(position) = parts.iter().position(|p| p.eq_ignore_ascii_case("AS")) {
+            if let Some(stage) = parts.get(position + 1) {
+                stages.push(stage.to_string());
+            }
+        }
+    }
+    images
+}
+
+/// The quality warnings of a valid addons.yml file and the Dockerfiles next to it
+pub fn quality(data: &AddonFileEntry, addon_dir: &Path, positions: &Positions) -> Vec<ValidationIssue> {
+    let mut warnings = Vec::new();
+    let registry = &data.x_ohx_registry;
+    if registry.homepage.as_ref().map(|h| h.trim().is_empty()).unwrap_or(true) {
+        warnings.push(positions.warning("meta/homepage", "x-ohx-registry.homepage".to_owned(),
+                                        "No homepage. Users look there for documentation and support.".to_owned()));
+    }
+    for (field, translations) in &[("titles", &registry.titles), ("descriptions", &registry.descriptions)] {
+        if translations.as_ref().map(|t| t.is_empty()).unwrap_or(true) {
+            warnings.push(positions.warning("meta/translations", format!("x-ohx-registry.{}", field),
+                                            format!("No translations in '{}'", field)));
+        }
+    }
+    let length = registry.description.trim().chars().count();
+    if length < MIN_DESCRIPTION_LENGTH {
+        warnings.push(positions.warning("meta/short-description", "x-ohx-registry.description".to_owned(),
+                                        format!("The description has {} characters. Describe the addon in at least {}.", length, MIN_DESCRIPTION_LENGTH)));
+    }
+
+    let mut services: Vec<_> = data.services.iter().collect();
+    services.sort_by(|a, b| a.0.cmp(b.0));
+    for (service_id, service) in services {
+        if let Some(image) = service.image.as_ref().filter(|i| is_unpinned(i)) {
+            warnings.push(positions.warning("image/latest-tag", format!("services.{}.image", service_id),
+                                            format!("{} is not pinned. Use a version tag or digest.", image)));
+        }
+        if let Some(recipe) = service.build.as_ref().and_then(|b| b.recipe.as_ref()) {
+            let path = format!("services.{}.build.recipe.from", service_id);
+            if is_unpinned(&recipe.from) {
+                warnings.push(positions.warning("image/unpinned-base", path.clone(),
+                                                format!("The base image {} is not pinned. Use a version tag or digest.", recipe.from)));
+            }
+            if recipe.archs.iter().any(|a| a == "armv6") && lacks_armv6(&recipe.from) {
+                warnings.push(positions.warning("image/no-armv6", path,
+                                                format!("The base image {} has no ARMv6 build. Use an image like arm32v6/alpine.", recipe.from)));
+            }
+        }
+    }
+    let declared_ports = declared_ports(data);
+    // The container paths of "logvolume:/var/log"
+    let mounts: BTreeSet<&str> = data.services.values().filter_map(|s| s.volumes.as_ref()).flatten()
+        .filter_map(|v| v.split_once(':').map(|(_, path)| path)).map(|p| p.trim_end_matches('/')).collect();
+    for dockerfile in dockerfiles(addon_dir) {
+        let content = match std::fs::read_to_string(&dockerfile) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        let name = dockerfile.file_name().unwrap().to_string_lossy().into_owned();
+        check_dockerfile(&name, &content, &declared_ports, &mounts, positions, &mut warnings);
+    }
+    warnings
+}
+
+#[test]
+fn suppressions_test() {
+    let yaml = "# ohx-allow: user/root \"Needs raw sockets\"\nservices:\n  webui:\n    ports:\n      # ohx-allow: ports/privileged-mapping \"Web UI\"\n      - \"80:80\"\n";
+    let mut issues = Vec::new();
+    let found = suppressions(yaml, &Positions::parse(yaml), &mut issues);
+    assert!(issues.is_empty());
+    assert_eq!(found[0].path, None);
+    assert_eq!(found[1].path.as_deref(), Some("services.webui.ports[0]"));
+    assert!(found[1].matches("ports/privileged-mapping", "services.webui.ports[0]"));
+    assert!(!found[1].matches("ports/privileged-mapping", "services.webui.ports[1]"));
+    suppressions(" # ohx-allow: ports/privileged-mapping", &Positions::default(), &mut issues);
+    assert_eq!(issues.len(), 1);
+}
+
+#[test]
+fn base_images_test() {
+    let dockerfile = "FROM --platform=$BUILDPLATFORM rust:1.38 AS build\nRUN cargo build\nFROM alpine\nCOPY --from=build /app /app\nFROM build\n";
+    let images = base_images(dockerfile);
+    assert_eq!(images, vec![(1, "rust:1.38".to_owned()), (3, "alpine".to_owned())]);
+    assert!(!is_unpinned("registry:5000/rust:1.38"));
+    assert!(is_unpinned("registry:5000/rust"));
+    assert!(is_unpinned("alpine:latest"));
+    assert!(!is_unpinned("alpine@sha256:abc"));
+    assert!(lacks_armv6("arm32v7/alpine:3.10"));
+    assert!(lacks_armv6("docker.io/library/debian:buster"));
+    assert!(!lacks_armv6("arm32v6/alpine:3.10"));
+    assert!(!lacks_armv6("debian-tools:1.0"));
+}
+
+#[test]
+fn check_dockerfile_test() {
+    let dockerfile = "FROM arm64v8/alpine:3.10\nEXPOSE 8080 53/udp $PORT\nVOLUME [\"/var/log\", \"/data\"]\n";
+    let declared: BTreeSet<String> = vec!["8080/tcp".to_owned()].into_iter().collect();
+    let mounts: BTreeSet<&str> = vec!["/var/log"].into_iter().collect();
+    let mut warnings = Vec::new();
+    check_dockerfile("Dockerfile.armhf", dockerfile, &declared, &mounts, &Positions::default(), &mut warnings);
+    let rules: Vec<&str> = warnings.iter().filter_map(|w| w.rule.as_ref()).map(|r| r.as_str()).collect();
+    assert_eq!(rules, vec!["dockerfile/expose", "dockerfile/volume", "dockerfile/arch-mismatch"]);
+    assert!(warnings[0].message.contains("53/udp"));
+    assert!(warnings[1].message.contains("/data"));
+}
+
+#[test]
+fn container_ports_test() {
+    assert_eq!(container_ports("8080:80/udp"), vec!["80/udp"]);
+    assert_eq!(container_ports("5000-5001"), vec!["5000/tcp", "5001/tcp"]);
+    assert!(container_ports("abc").is_empty());
+}
@@ -0,0 +1,52 @@
+//! Checks of the descriptive metadata of `x-ohx-registry`: Authors, links and the description.
+//! Broken links and anonymous authors leave users without documentation and support.
+use super::addons::AddonEntryCommon;
+use super::schema::{Positions, ValidationIssue};
+use regex::Regex;
+
+/// Shorter descriptions are an error. Descriptions below [`super::lint::MIN_DESCRIPTION_LENGTH`] are a warning.
+pub const REQUIRED_DESCRIPTION_LENGTH: usize = 10;
+
+/// An https URL with a host name like "https://example.com/docs"
+pub fn is_https_url(url: &str) -> bool {
+    Regex::new(r"^https://[a-zA-Z0-9]([a-zA-Z0-9\-]*[a-zA-Z0-9])?(\.[a-zA-Z0-9]([a-zA-Z0-9\-]*[a-zA-Z0-9])?)+(:[0-9]+)?([/?#]\S*)?$")
+        .unwrap().is_match(url)
+}
+
+/// "Name <name@example.com>" or a profile URL like "https://github.com/name"
+pub fn is_author(author: &str) -> bool {
+    Regex::new(r"^[^<>@\s][^<>@]*\s<[^<>@\s]+@[^<>@\s]+\.[^<>@\s]+>$").unwrap().is_match(author.trim()) || is_https_url(author.trim())
+}
+
+/// The errors of the authors, the links and the description
+pub fn check(common: &AddonEntryCommon, positions: &Positions) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    for (index, author) in common.authors.iter().enumerate().filter(|(_, a)| !is_author(a)) {
+        issues.push(positions.error(format!("x-ohx-registry.authors[{}]", index),
+                                    format!("'{}' is neither like \"Name <name@example.com>\" nor a https profile URL", author)));
+    }
+    for (field, url) in &[("homepage", &common.homepage), ("github", &common.github), ("changelog_url", &common.changelog_url)] {
+        // An empty link is only a lint warning, like a missing one
+        if let Some(url) = url.as_ref().filter(|u| !u.trim().is_empty() && !is_https_url(u)) {
+            issues.push(positions.error(format!("x-ohx-registry.{}", field), format!("'{}' is not a https URL", url)));
+        }
+    }
+    let length = common.description.trim().chars().count();
+    if length < REQUIRED_DESCRIPTION_LENGTH {
+        issues.push(positions.error("x-ohx-registry.description".to_owned(),
+                                    format!("The description needs at least {} characters", REQUIRED_DESCRIPTION_LENGTH)));
+    }
+    issues
+}
+
+#[test]
+fn metadata_test() {
+    assert!(is_author("David Gräff <david.graeff@web.de>"));
+    assert!(is_author("https://github.com/davidgraeff"));
+    assert!(!is_author("David Gräff"));
+    assert!(!is_author("<david.graeff@web.de>"));
+    assert!(is_https_url("https://example.com:8443/docs?lang=de"));
+    assert!(!is_https_url("http://example.com"));
+    assert!(!is_https_url("https://localhost"));
+    assert!(!is_https_url("https://exa mple.com"));
+}
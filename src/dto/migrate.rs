@@ -0,0 +1,72 @@
+//! The `schema_version` of addons.yml files. Older files are migrated in memory before validation,
+//! `migrate --write` rewrites them. Files of a newer version than this CLI knows are rejected.
+//!
+//! Version 1 files have no `schema_version`. They were written for a lenient schema that accepted single values
+//! for lists and no status. Their migration applies the corrections of [`super::fix`].
+use serde_yaml::{Mapping, Value};
+
+/// The version of files that this CLI writes and understands
+pub const CURRENT_SCHEMA_VERSION: u32 = 2;
+
+const FIELD: &str = "schema_version";
+
+/// Migrates a file of the given version to the next one and describes each change
+type Step = fn(&mut Value) -> Vec<String>;
+
+/// The step at index i migrates from version i + 1
+const STEPS: [Step; 1] = [super::fix::fix_addons_file];
+
+/// The version of the file. Files without `schema_version` are version 1.
+pub fn version(data: &Value) -> Result<u32, String> {
+    match data.get(FIELD) {
+        None => Ok(1),
+        Some(Value::Number(v)) => v.as_u64().filter(|v| *v >= 1 && *v <= u32::MAX as u64).map(|v| v as u32)
+            .ok_or_else(|| format!("The schema version must be a positive number, not {}", v)),
+        Some(v) => Err(format!("The schema version must be a number, not {:?}", v)),
+    }
+}
+
+/// Migrates the file to [`CURRENT_SCHEMA_VERSION`] and returns a description of each change besides the new version.
+/// Fails for files of a newer version.
+pub fn migrate(data: &mut Value) -> Result<Vec<String>, String> {
+    let from = version(data)?;
+    if from > CURRENT_SCHEMA_VERSION {
+        return Err(format!("The file is written for schema version {}, this CLI only understands versions up to {}. \
+            Update the CLI.", from, CURRENT_SCHEMA_VERSION));
+    }
+    if from == CURRENT_SCHEMA_VERSION {
+        return Ok(Vec::new());
+    }
+    let mut changes = Vec::new();
+    for step in &STEPS[from as usize - 1..] {
+        changes.extend(step(data));
+    }
+    // The version goes first, like a header of the file
+    if let Value::Mapping(root) = data {
+        let mut migrated = Mapping::new();
+        migrated.insert(Value::String(FIELD.to_owned()), Value::Number(CURRENT_SCHEMA_VERSION.into()));
+        for (key, value) in std::mem::replace(root, Mapping::new()) {
+            if key.as_str() != Some(FIELD) {
+                migrated.insert(key, value);
+            }
+        }
+        *root = migrated;
+    }
+    Ok(changes)
+}
+
+#[test]
+fn migrate_test() {
+    let mut data: Value = serde_yaml::from_str("services:\n  main:\n    ports: \"80\"\nx-ohx-registry:\n  authors: [David]\n").unwrap();
+    assert_eq!(version(&data), Ok(1));
+    let changes = migrate(&mut data).unwrap();
+    assert_eq!(changes.len(), 4);
+    assert_eq!(data["services"]["main"]["ports"][0].as_str(), Some("80"));
+    assert_eq!(data.as_mapping().unwrap().iter().next().unwrap().0.as_str(), Some(FIELD));
+    assert_eq!(version(&data), Ok(CURRENT_SCHEMA_VERSION));
+    assert!(migrate(&mut data).unwrap().is_empty());
+
+    let mut newer: Value = serde_yaml::from_str("schema_version: 99\nservices: {}\n").unwrap();
+    assert!(migrate(&mut newer).is_err());
+    assert!(version(&serde_yaml::from_str("schema_version: two").unwrap()).is_err());
+}
@@ -1,6 +1,23 @@
 pub mod addons;
+pub mod capabilities;
+pub mod docs;
+pub mod extends;
+pub mod fix;
+pub mod interpolate;
+pub mod lint;
+pub mod metadata;
+pub mod migrate;
+pub mod overrides;
+pub mod schema;
+pub mod search;
+pub mod translations;
+pub mod type_profiles;
 
-// Determine docker files and architectures
+use addons::BuildRecipe;
+use std::collections::BTreeMap;
+
+// Determine docker files and architectures. Only the binary builds images, the library does not use them.
+#[allow(dead_code)]
 pub(crate) struct BuildInstruction {
     pub(crate) filename: String,
     pub(crate) arch: String,
@@ -8,4 +25,31 @@ pub(crate) struct BuildInstruction {
     pub(crate) build: bool,
     pub(crate) uploaded: bool,
     pub(crate) image_size: i64,
+    /// The digest of the pushed image, if the container engine reports it
+    pub(crate) digest: Option<String>,
+    /// The image is build by buildah from this recipe instead of the Dockerfile
+    pub(crate) recipe: Option<BuildRecipe>,
+    /// The architecture is excluded by the `--arch` filter and neither build nor published
+    pub(crate) skipped: bool,
+    /// The hash of the build inputs, see `build_cache`
+    pub(crate) content_hash: Option<String>,
+    /// The `x-variants` entry this image is build for
+    pub(crate) variant: Option<String>,
+    /// Passed as `--build-arg` to the container engine
+    pub(crate) build_args: BTreeMap<String, String>,
+    /// Pull the base image even if it exists locally
+    pub(crate) pull: bool,
+    /// Passed as `--secret id=<id>,src=<file>` to the container engine
+    pub(crate) secrets: BTreeMap<String, String>,
+}
+
+#[allow(dead_code)]
+impl BuildInstruction {
+    /// Identifies the image within an addon version, like "amd64" or "amd64_with-cloud-connector"
+    pub(crate) fn key(&self) -> String {
+        match &self.variant {
+            Some(variant) => format!("{}_{}", self.arch, variant),
+            None => self.arch.clone(),
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,159 @@
+//! `--set services.app.ports[0]=8080:8080`: Overrides of single values of the descriptor, applied after `extends`
+//! and before validation, like channel specific tweaks in a CI pipeline. The path is checked against the schema.
+//! Values of string fields are taken as they are, all other values are YAML, like `memory_max=512` or `ports=["80"]`.
+use super::schema::{Positions, ValidationIssue};
+use serde_json::Value as JsonValue;
+use serde_yaml::{Mapping, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// "services.app.ports[0]" is `services`, `app`, `ports`, `0`
+fn segments(path: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let key = part.split('[').next().unwrap_or_default();
+        if key.is_empty() {
+            return Err(format!("'{}' is not a path like services.app.ports[0]", path));
+        }
+        segments.push(Segment::Key(key.to_owned()));
+        for index in part[key.len()..].split('[').skip(1) {
+            match index.trim_end_matches(']').parse::<usize>() {
+                Ok(v) if index.ends_with(']') => segments.push(Segment::Index(v)),
+                _ => return Err(format!("'{}' is not a path like services.app.ports[0]", path)),
+            }
+        }
+    }
+    Ok(segments)
+}
+
+/// Parses a `--set` argument, "path=value"
+pub fn parse(argument: &str) -> Result<(String, String), String> {
+    let mut parts = argument.splitn(2, '=');
+    let path = parts.next().unwrap_or_default().trim();
+    let value = parts.next().ok_or_else(|| format!("Expected path=value, like services.app.ports[0]=8080:8080, not '{}'", argument))?;
+    segments(path)?;
+    Ok((path.to_owned(), value.to_owned()))
+}
+
+fn resolve<'a>(root: &'a JsonValue, schema: &'a JsonValue) -> &'a JsonValue {
+    match schema.get("$ref").and_then(|r| r.as_str()) {
+        Some(reference) => reference.trim_start_matches("#/").split('/')
+            .try_fold(root, |v, part| v.get(part))
+            .map(|definition| resolve(root, definition)).unwrap_or(schema),
+        None => schema,
+    }
+}
+
+/// The schema of the value at the path. None if the schema does not describe the path.
+fn schema_at<'a>(root: &'a JsonValue, schema: &'a JsonValue, segments: &[Segment]) -> Option<&'a JsonValue> {
+    let schema = resolve(root, schema);
+    let (first, rest) = match segments.split_first() {
+        Some(v) => v,
+        None => return Some(schema),
+    };
+    // Optional values are "anyOf" the value schema and null
+    for keyword in &["anyOf", "oneOf", "allOf"] {
+        if let Some(list) = schema.get(*keyword).and_then(|v| v.as_array()) {
+            return list.iter().filter_map(|s| schema_at(root, s, segments)).next();
+        }
+    }
+    let child = match first {
+        Segment::Key(key) => schema.get("properties").and_then(|p| p.get(key))
+            .or_else(|| schema.get("additionalProperties").filter(|a| a.is_object())),
+        Segment::Index(_) => schema.get("items"),
+    }?;
+    schema_at(root, child, rest)
+}
+
+fn is_string(root: &JsonValue, schema: &JsonValue) -> bool {
+    let schema = resolve(root, schema);
+    let string_type = match schema.get("type") {
+        Some(JsonValue::String(v)) => v == "string",
+        Some(JsonValue::Array(list)) => list.iter().any(|v| v == "string"),
+        _ => false,
+    };
+    string_type || ["anyOf", "oneOf"].iter()
+        .filter_map(|keyword| schema.get(*keyword).and_then(|v| v.as_array()))
+        .any(|list| list.iter().any(|s| is_string(root, s)))
+}
+
+fn set(data: &mut Value, segments: &[Segment], value: Value) -> Result<(), String> {
+    let (first, rest) = match segments.split_first() {
+        Some(v) => v,
+        None => {
+            *data = value;
+            return Ok(());
+        }
+    };
+    match first {
+        Segment::Key(key) => {
+            if data.is_null() {
+                *data = Value::Mapping(Mapping::new());
+            }
+            let map = data.as_mapping_mut().ok_or_else(|| format!("The parent of '{}' is not a mapping", key))?;
+            let key = Value::String(key.clone());
+            if !map.contains_key(&key) {
+                map.insert(key.clone(), Value::Null);
+            }
+            set(map.get_mut(&key).expect("inserted key"), rest, value)
+        }
+        Segment::Index(index) => {
+            if data.is_null() {
+                *data = Value::Sequence(Vec::new());
+            }
+            let list = data.as_sequence_mut().ok_or_else(|| format!("[{}] does not follow a list", index))?;
+            // The index after the last entry appends one
+            if *index == list.len() {
+                list.push(Value::Null);
+            }
+            let length = list.len();
+            let item = list.get_mut(*index).ok_or_else(|| format!("Index {} is out of range, the list has {} entries", index, length))?;
+            set(item, rest, value)
+        }
+    }
+}
+
+/// Applies the overrides in order. Returns the errors of paths that the schema does not describe and of values
+/// that are not YAML.
+pub fn apply(data: &mut Value, overrides: &[(String, String)], schema: &JsonValue, positions: &Positions) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    for (path, raw) in overrides {
+        let result = segments(path).and_then(|segments| {
+            let field = schema_at(schema, schema, &segments).ok_or_else(|| format!("{} is not a field of the addon description", path))?;
+            let value = match is_string(schema, field) {
+                true => Value::String(raw.clone()),
+                false => serde_yaml::from_str(raw).map_err(|e| format!("The value '{}' is not YAML: {}", raw, e))?,
+            };
+            set(data, &segments, value)
+        });
+        if let Err(message) = result {
+            issues.push(positions.error(path.clone(), format!("--set {}: {}", path, message)));
+        }
+    }
+    issues
+}
+
+#[test]
+fn overrides_test() {
+    assert_eq!(segments("services.app.ports[0]"), Ok(vec![Segment::Key("services".to_owned()), Segment::Key("app".to_owned()),
+                                                        Segment::Key("ports".to_owned()), Segment::Index(0)]));
+    assert!(segments("services..app").is_err());
+    assert!(segments("ports[a]").is_err());
+    assert_eq!(parse("x-runtime.memory_max=512"), Ok(("x-runtime.memory_max".to_owned(), "512".to_owned())));
+    assert!(parse("x-runtime.memory_max").is_err());
+
+    let schema = super::schema::addon_file_schema();
+    let mut data: Value = serde_yaml::from_str("services:\n  app:\n    ports: [\"80:80\"]\nx-runtime:\n  memory_max: 256\n").unwrap();
+    let overrides = vec![("services.app.ports[0]".to_owned(), "8080:8080".to_owned()),
+                         ("services.app.ports[1]".to_owned(), "9090".to_owned()),
+                         ("x-runtime.memory_max".to_owned(), "512".to_owned()),
+                         ("services.app.prots".to_owned(), "80".to_owned())];
+    let issues = apply(&mut data, &overrides, &schema, &Positions::default());
+    assert_eq!(issues.iter().map(|i| i.path.as_str()).collect::<Vec<_>>(), vec!["services.app.prots"]);
+    let expected: Value = serde_yaml::from_str("services:\n  app:\n    ports: [\"8080:8080\", \"9090\"]\nx-runtime:\n  memory_max: 512\n").unwrap();
+    assert_eq!(data, expected);
+}
@@ -0,0 +1,369 @@
+//! The JSON Schema of the addons.yml file, generated from [`AddonFileEntry`], and a validator for it.
+//! Validation issues carry the path of the failing value, like `services.webui.ports[1]`, and its YAML line and column.
+use super::addons::AddonFileEntry;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A failed check of the addons.yml file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    /// The lint rule of a warning, like "ports/privileged-mapping"
+    pub rule: Option<String>,
+    /// Like `services.webui.ports[1]`. Empty for the document itself.
+    pub path: String,
+    pub message: String,
+    /// Line and column, both starting at 1. None for values that come from an `extends` base file.
+    pub location: Option<(usize, usize)>,
+    /// The justification of the `# ohx-allow:` annotation that suppresses the warning
+    pub suppressed: Option<String>,
+}
+
+impl ValidationIssue {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((line, column)) = self.location {
+            write!(f, "{}:{}: ", line, column)?;
+        }
+        if let Some(rule) = &self.rule {
+            write!(f, "[{}] ", rule)?;
+        }
+        match self.path.is_empty() {
+            true => f.write_str(&self.message),
+            false => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+/// All issues of a validation run with at least one error
+#[derive(Debug)]
+pub struct ValidationIssues {
+    pub file: String,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl fmt::Display for ValidationIssues {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: Vec<String> = self.issues.iter()
+            .filter(|i| i.suppressed.is_none())
+            .map(|i| match i.severity {
+                Severity::Error => format!("{}:{}", self.file, i),
+                Severity::Warning => format!("warning: {}:{}", self.file, i),
+            })
+            .collect();
+        f.write_str(&lines.join("\n"))
+    }
+}
+
+impl std::error::Error for ValidationIssues {}
+
+/// The JSON Schema (draft 7) of the addons.yml file
+pub fn addon_file_schema() -> Value {
+    serde_json::to_value(schemars::schema_for!(AddonFileEntry)).expect("schema serializable")
+}
+
+/// The line and column of every value of a YAML document, by path
+#[derive(Default)]
+pub struct Positions(BTreeMap<String, (usize, usize)>);
+
+enum Frame {
+    Mapping { path: String, key: Option<String> },
+    Sequence { path: String, index: usize },
+}
+
+fn child(path: &str, key: &str) -> String {
+    match path.is_empty() {
+        true => key.to_owned(),
+        false => format!("{}.{}", path, key),
+    }
+}
+
+/// The path of the enclosing value: `services.webui` for `services.webui.ports[1]`
+fn parent(path: &str) -> Option<&str> {
+    path.rfind(['.', '[']).map(|pos| &path[..pos])
+}
+
+#[derive(Default)]
+struct PositionReceiver {
+    stack: Vec<Frame>,
+    positions: BTreeMap<String, (usize, usize)>,
+}
+
+impl PositionReceiver {
+    /// The path of the next node. None for mapping keys, which are remembered in the mapping frame.
+    /// The values of a mapping are located at their key.
+    fn node_path(&mut self, event: &Event, position: (usize, usize)) -> Option<String> {
+        match self.stack.last_mut() {
+            None => Some(String::new()),
+            Some(Frame::Sequence { path, index }) => {
+                *index += 1;
+                Some(format!("{}[{}]", path, *index - 1))
+            }
+            Some(Frame::Mapping { path, key }) => match key.take() {
+                Some(key) => Some(child(path, &key)),
+                None => {
+                    let name = match event {
+                        Event::Scalar(v, ..) => v.clone(),
+                        _ => "?".to_owned(),
+                    };
+                    self.positions.entry(child(path, &name)).or_insert(position);
+                    *key = Some(name);
+                    None
+                }
+            },
+        }
+    }
+}
+
+impl MarkedEventReceiver for PositionReceiver {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(..) | Event::Alias(_) | Event::SequenceStart(_) | Event::MappingStart(_) => {
+                let position = (mark.line(), mark.col() + 1);
+                let path = match self.node_path(&event, position) {
+                    Some(v) => v,
+                    None => return,
+                };
+                self.positions.entry(path.clone()).or_insert(position);
+                match event {
+                    Event::SequenceStart(_) => self.stack.push(Frame::Sequence { path, index: 0 }),
+                    Event::MappingStart(_) => self.stack.push(Frame::Mapping { path, key: None }),
+                    _ => {}
+                }
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Positions {
+    /// Positions of a YAML document. Empty if it is no valid YAML.
+    pub fn parse(yaml: &str) -> Positions {
+        let mut receiver = PositionReceiver::default();
+        match Parser::new(yaml.chars()).load(&mut receiver, false) {
+            Ok(_) => Positions(receiver.positions),
+            Err(_) => Positions::default(),
+        }
+    }
+
+    /// The position of the value at `path` or, if it does not exist, of the closest enclosing value
+    pub fn find(&self, path: &str) -> Option<(usize, usize)> {
+        let mut path = path;
+        loop {
+            if let Some(v) = self.0.get(path) {
+                return Some(*v);
+            }
+            path = parent(path)?;
+        }
+    }
+
+    /// The outermost value that starts on the line
+    pub fn at_line(&self, line: usize) -> Option<String> {
+        self.0.iter()
+            .filter(|(path, (l, _))| !path.is_empty() && *l == line)
+            .map(|(path, _)| path)
+            .min_by_key(|path| path.len())
+            .cloned()
+    }
+
+    pub fn error(&self, path: String, message: String) -> ValidationIssue {
+        ValidationIssue { severity: Severity::Error, rule: None, location: self.find(&path), path, message, suppressed: None }
+    }
+
+    pub fn warning(&self, rule: &'static str, path: String, message: String) -> ValidationIssue {
+        ValidationIssue { severity: Severity::Warning, rule: Some(rule.to_owned()), location: self.find(&path), path, message, suppressed: None }
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn is_type(value: &Value, schema_type: &str) -> bool {
+    match (schema_type, value) {
+        ("number", Value::Number(_)) => true,
+        (schema_type, value) => type_name(value) == schema_type,
+    }
+}
+
+/// A validator for the JSON Schema subset that schemars generates
+struct Validator<'a> {
+    root: &'a Value,
+    positions: &'a Positions,
+    errors: Vec<ValidationIssue>,
+}
+
+impl<'a> Validator<'a> {
+    fn error(&mut self, path: &str, message: String) {
+        let error = self.positions.error(path.to_owned(), message);
+        self.errors.push(error);
+    }
+
+    /// Resolves "#/definitions/Name"
+    fn resolve(&self, reference: &str) -> Option<&'a Value> {
+        let root: &'a Value = self.root;
+        reference.trim_start_matches("#/").split('/').try_fold(root, |v, part| v.get(part))
+    }
+
+    /// True if the value is valid. Errors are not recorded.
+    fn matches(&self, schema: &Value, value: &Value, path: &str) -> bool {
+        let mut validator = Validator { root: self.root, positions: self.positions, errors: Vec::new() };
+        validator.validate(schema, value, path);
+        validator.errors.is_empty()
+    }
+
+    fn validate(&mut self, schema: &Value, value: &Value, path: &str) {
+        // "true" accepts everything
+        let schema = match schema.as_object() {
+            Some(v) => v,
+            None => return,
+        };
+        if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+            match self.resolve(reference) {
+                Some(definition) => self.validate(definition, value, path),
+                None => self.error(path, format!("Unknown schema reference {}", reference)),
+            }
+        }
+        if let Some(all_of) = schema.get("allOf").and_then(|v| v.as_array()) {
+            for sub_schema in all_of {
+                self.validate(sub_schema, value, path);
+            }
+        }
+        for keyword in &["anyOf", "oneOf"] {
+            if let Some(any_of) = schema.get(*keyword).and_then(|v| v.as_array()) {
+                self.validate_any_of(any_of, value, path);
+            }
+        }
+
+        if let Some(schema_type) = schema.get("type") {
+            let types: Vec<&str> = match schema_type {
+                Value::String(v) => vec![v.as_str()],
+                Value::Array(list) => list.iter().filter_map(|v| v.as_str()).collect(),
+                _ => Vec::new(),
+            };
+            if !types.is_empty() && !types.iter().any(|t| is_type(value, t)) {
+                self.error(path, format!("Expected {}, found {}", types.join(" or "), type_name(value)));
+                return;
+            }
+        }
+        if let Some(allowed) = schema.get("enum").and_then(|v| v.as_array()) {
+            if !allowed.contains(value) {
+                let allowed: Vec<String> = allowed.iter().map(|v| v.to_string()).collect();
+                self.error(path, format!("Expected one of {}, found {}", allowed.join(", "), value));
+            }
+        }
+
+        match value {
+            Value::Object(map) => {
+                if let Some(required) = schema.get("required").and_then(|v| v.as_array()) {
+                    for field in required.iter().filter_map(|v| v.as_str()) {
+                        if !map.contains_key(field) {
+                            self.error(path, format!("Missing field '{}'", field));
+                        }
+                    }
+                }
+                let properties = schema.get("properties").and_then(|v| v.as_object());
+                for (key, field_value) in map {
+                    let field_path = child(path, key);
+                    match properties.and_then(|p| p.get(key)) {
+                        Some(field_schema) => self.validate(field_schema, field_value, &field_path),
+                        None => match schema.get("additionalProperties") {
+                            Some(Value::Bool(false)) => self.error(&field_path, "Unknown field".to_owned()),
+                            Some(additional) => self.validate(additional, field_value, &field_path),
+                            None => {}
+                        },
+                    }
+                }
+            }
+            Value::Array(list) => {
+                if let Some(items) = schema.get("items") {
+                    for (index, item) in list.iter().enumerate() {
+                        self.validate(items, item, &format!("{}[{}]", path, index));
+                    }
+                }
+            }
+            Value::Number(number) => {
+                if let (Some(minimum), Some(v)) = (schema.get("minimum").and_then(|v| v.as_f64()), number.as_f64()) {
+                    if v < minimum {
+                        self.error(path, format!("{} is lower than the minimum {}", number, minimum));
+                    }
+                }
+                if let (Some(maximum), Some(v)) = (schema.get("maximum").and_then(|v| v.as_f64()), number.as_f64()) {
+                    if v > maximum {
+                        self.error(path, format!("{} is greater than the maximum {}", number, maximum));
+                    }
+                }
+            }
+            Value::String(string) => {
+                if let Some(pattern) = schema.get("pattern").and_then(|v| v.as_str()) {
+                    if let Ok(regex) = regex::Regex::new(pattern) {
+                        if !regex.is_match(string) {
+                            self.error(path, format!("'{}' does not match the pattern {}", string, pattern));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// schemars describes optional values as "anyOf" the value schema and null. The errors of the value schema are
+    /// reported for other values, so that they point to the failing field instead of the optional value.
+    fn validate_any_of(&mut self, any_of: &[Value], value: &Value, path: &str) {
+        if any_of.iter().any(|s| self.matches(s, value, path)) {
+            return;
+        }
+        let candidates: Vec<&Value> = any_of.iter().filter(|s| s.get("type").and_then(|t| t.as_str()) != Some("null")).collect();
+        match candidates.as_slice() {
+            [schema] => self.validate(schema, value, path),
+            _ => self.error(path, "Does not match any of the allowed forms".to_owned()),
+        }
+    }
+}
+
+/// Validates the document against the schema. Returns all errors.
+pub fn validate(schema: &Value, document: &Value, positions: &Positions) -> Vec<ValidationIssue> {
+    let mut validator = Validator { root: schema, positions, errors: Vec::new() };
+    validator.validate(schema, document, "");
+    validator.errors
+}
+
+#[test]
+fn validate_test() {
+    let yaml = "services:\n  webui:\n    ports: [\"8080\", 80]\nx-runtime:\n  memory_min: \"a lot\"\n";
+    let positions = Positions::parse(yaml);
+    assert_eq!(positions.find("services.webui.ports[1]"), Some((3, 21)));
+    let document: Value = serde_json::to_value(serde_yaml::from_str::<serde_yaml::Value>(yaml).unwrap()).unwrap();
+    let errors = validate(&addon_file_schema(), &document, &positions);
+    let paths: Vec<&str> = errors.iter().map(|e| e.path.as_str()).collect();
+    assert!(paths.contains(&"services.webui.ports[1]"));
+    assert!(paths.contains(&"x-runtime.memory_min"));
+    assert!(paths.contains(&""), "x-ohx-registry is missing");
+}
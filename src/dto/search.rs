@@ -0,0 +1,172 @@
+use super::addons::{AddonEntryMap, AddonMapStats, AddonRegistryEntry, AddonStats, StatusCode};
+use std::str::FromStr;
+
+/// A structured `key=value` filter over registry entries, for example `license=MIT` or `arch=aarch64`.
+/// Values are compared case insensitive. Several filters are combined with AND semantics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddonFilter {
+    pub key: FilterKey,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterKey {
+    Id,
+    License,
+    Type,
+    Status,
+    Arch,
+    Author,
+    Owner,
+    Manufacturer,
+}
+
+impl FromStr for AddonFilter {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        let key = parts.next().unwrap_or_default().trim();
+        let value = match parts.next() {
+            Some(v) if !v.trim().is_empty() => v.trim().to_lowercase(),
+            _ => return Err(failure::err_msg(format!("A filter must look like key=value. Got: {}", s))),
+        };
+        let key = match key {
+            "id" => FilterKey::Id,
+            "license" => FilterKey::License,
+            "type" => FilterKey::Type,
+            "status" => FilterKey::Status,
+            "arch" => FilterKey::Arch,
+            "author" => FilterKey::Author,
+            "owner" => FilterKey::Owner,
+            "manufacturer" => FilterKey::Manufacturer,
+            _ => {
+                return Err(failure::err_msg(format!(
+                    "Unknown filter key '{}'. Allowed: id, license, type, status, arch, author, owner, manufacturer",
+                    key
+                )))
+            }
+        };
+        Ok(AddonFilter { key, value })
+    }
+}
+
+impl AddonFilter {
+    pub fn matches(&self, entry: &AddonRegistryEntry) -> bool {
+        let eq = |v: &str| v.to_lowercase() == self.value;
+        let common = &entry.entry;
+        match self.key {
+            FilterKey::Id => eq(&common.id),
+            FilterKey::License => eq(&common.license),
+            FilterKey::Type => eq(&common.type_field),
+            FilterKey::Status => eq(match common.status.code {
+                StatusCode::AVAILABLE => "available",
+                StatusCode::REPLACED => "replaced",
+                StatusCode::REMOVED => "removed",
+                StatusCode::UNMAINTAINED => "unmaintained",
+            }),
+            FilterKey::Arch => entry.archs.iter().any(|v| eq(v)),
+            // Authors are usually "Name <email>", a substring match is more useful here
+            FilterKey::Author => common.authors.iter().any(|v| v.to_lowercase().contains(&self.value)),
+            FilterKey::Owner => eq(&entry.owner),
+            FilterKey::Manufacturer => common.manufacturers.iter().any(|v| eq(v)),
+        }
+    }
+}
+
+/// Sort order for search results. Rating and downloads require the registry stats.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum AddonSort {
+    #[default]
+    Id,
+    Title,
+    Updated,
+    Downloads,
+    Rating,
+}
+
+impl FromStr for AddonSort {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "id" => AddonSort::Id,
+            "title" => AddonSort::Title,
+            "updated" => AddonSort::Updated,
+            "downloads" => AddonSort::Downloads,
+            "rating" => AddonSort::Rating,
+            _ => {
+                return Err(failure::err_msg(format!(
+                    "Unknown sort order '{}'. Allowed: id, title, updated, downloads, rating",
+                    s
+                )))
+            }
+        })
+    }
+}
+
+/// The average rating or 0 if nobody voted yet
+pub fn average_rating(stats: &AddonStats) -> f64 {
+    if stats.v == 0 {
+        0.0
+    } else {
+        stats.p as f64 / stats.v as f64
+    }
+}
+
+/// Returns true if the id, title, description or one of the products contains the search term (case insensitive)
+pub fn matches_term(entry: &AddonRegistryEntry, term: &str) -> bool {
+    let term = term.to_lowercase();
+    let common = &entry.entry;
+    common.id.to_lowercase().contains(&term)
+        || common.title.to_lowercase().contains(&term)
+        || common.description.to_lowercase().contains(&term)
+        || common.products.iter().any(|p| p.to_lowercase().contains(&term))
+}
+
+/// Returns all registry entries matching the optional search term and all of the given filters, sorted by `sort`.
+/// Sorting by downloads and rating is descending, all other orders are ascending.
+pub fn filter_addons<'a>(
+    registry: &'a AddonEntryMap,
+    stats: Option<&AddonMapStats>,
+    term: Option<&str>,
+    filters: &[AddonFilter],
+    sort: AddonSort,
+) -> Vec<&'a AddonRegistryEntry> {
+    let mut result: Vec<&AddonRegistryEntry> = registry
+        .values()
+        .filter(|entry| term.map(|t| matches_term(entry, t)).unwrap_or(true))
+        .filter(|entry| filters.iter().all(|f| f.matches(entry)))
+        .collect();
+
+    let stat = |entry: &AddonRegistryEntry| stats.and_then(|s| s.get(&entry.entry.id));
+    match sort {
+        AddonSort::Id => result.sort_by(|a, b| a.entry.id.cmp(&b.entry.id)),
+        AddonSort::Title => result.sort_by_key(|a| a.entry.title.to_lowercase()),
+        AddonSort::Updated => result.sort_by_key(|e| std::cmp::Reverse(e.last_updated)),
+        AddonSort::Downloads => result.sort_by_key(|e| std::cmp::Reverse(stat(e).map(|s| s.d).unwrap_or_default())),
+        AddonSort::Rating => result.sort_by(|a, b| {
+            let a = stat(a).map(average_rating).unwrap_or_default();
+            let b = stat(b).map(average_rating).unwrap_or_default();
+            b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal)
+        }),
+    }
+    result
+}
+
+#[test]
+fn filter_addons_test() {
+    let mut registry = AddonEntryMap::new();
+    for (id, license, arch) in &[("a", "MIT", "aarch64"), ("b", "MIT", "amd64"), ("c", "GPL", "aarch64")] {
+        let mut entry = AddonRegistryEntry::default();
+        entry.entry.id = id.to_string();
+        entry.entry.license = license.to_string();
+        entry.archs = vec![arch.to_string()];
+        registry.insert(id.to_string(), entry);
+    }
+    let filters: Vec<AddonFilter> = vec!["license=mit".parse().unwrap(), "arch=aarch64".parse().unwrap()];
+    let result = filter_addons(&registry, None, None, &filters, AddonSort::Id);
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].entry.id, "a");
+    assert!("license".parse::<AddonFilter>().is_err());
+}
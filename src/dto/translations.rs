@@ -0,0 +1,54 @@
+//! Checks of the `titles` and `descriptions` translations. The keys are BCP-47 language tags like "de" or "pt-BR".
+//! A language should have both a title and a description.
+use super::addons::AddonEntryCommon;
+use super::schema::{Positions, ValidationIssue};
+use std::collections::{BTreeMap, HashMap};
+
+/// language[-script][-region][-variant...], or a private use tag like "x-klingon"
+const BCP47: &str = r"^(?i)(x(-[a-z0-9]{1,8})+|[a-z]{2,3}(-[a-z]{4})?(-([a-z]{2}|[0-9]{3}))?(-([a-z0-9]{5,8}|[0-9][a-z0-9]{3}))*(-x(-[a-z0-9]{1,8})+)?)$";
+
+pub fn is_language_tag(tag: &str) -> bool {
+    regex::Regex::new(BCP47).unwrap().is_match(tag)
+}
+
+/// The languages of a translation map, lower case, with the original key
+fn languages(translations: &Option<HashMap<String, String>>) -> BTreeMap<String, &String> {
+    translations.iter().flat_map(|t| t.keys()).map(|k| (k.to_lowercase(), k)).collect()
+}
+
+/// Errors for invalid language tags and missing `required` languages, warnings for languages
+/// that have only a title or only a description
+pub fn check(common: &AddonEntryCommon, required: &[String], positions: &Positions) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let maps = [("titles", languages(&common.titles)), ("descriptions", languages(&common.descriptions))];
+    for (field, languages) in maps.iter() {
+        for tag in languages.values().filter(|tag| !is_language_tag(tag)) {
+            issues.push(positions.error(format!("x-ohx-registry.{}.{}", field, tag),
+                                        format!("'{}' is not a BCP-47 language tag like \"de\" or \"pt-BR\"", tag)));
+        }
+        for language in required.iter().filter(|l| !languages.contains_key(&l.to_lowercase())) {
+            issues.push(positions.error(format!("x-ohx-registry.{}", field), format!("The required language '{}' is missing", language)));
+        }
+    }
+
+    let (titles, descriptions) = (&maps[0].1, &maps[1].1);
+    for (_, tag) in titles.iter().filter(|(l, _)| !descriptions.contains_key(*l)) {
+        issues.push(positions.warning("meta/translations", "x-ohx-registry.descriptions".to_owned(),
+                                      format!("The title is translated to '{}', the description is not", tag)));
+    }
+    for (_, tag) in descriptions.iter().filter(|(l, _)| !titles.contains_key(*l)) {
+        issues.push(positions.warning("meta/translations", "x-ohx-registry.titles".to_owned(),
+                                      format!("The description is translated to '{}', the title is not", tag)));
+    }
+    issues
+}
+
+#[test]
+fn is_language_tag_test() {
+    for tag in &["de", "pt-BR", "zh-Hant-TW", "es-419", "de-CH-1901", "x-klingon", "en-US-x-twain"] {
+        assert!(is_language_tag(tag), "{}", tag);
+    }
+    for tag in &["german", "d", "de_DE", "en-", "de-CH-19"] {
+        assert!(!is_language_tag(tag), "{}", tag);
+    }
+}
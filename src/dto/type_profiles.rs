@@ -0,0 +1,82 @@
+//! The validation profiles of the addon types, like "binding" or "ui". The registry publishes the current profiles,
+//! a copy of them is bundled for offline use. `init` creates its template service from the profile.
+use super::addons::AddonFileEntry;
+use super::schema::{Positions, ValidationIssue};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TypeProfile {
+    pub description: String,
+    /// At least one service must declare a port, for example the web port of a UI
+    pub requires_port: bool,
+    /// No service may map host devices
+    pub forbids_devices: bool,
+    /// Permissions that no service may request. "HW_*" matches all permissions starting with "HW_".
+    pub forbidden_permissions: Vec<String>,
+    /// The mandatory permissions of the `init` template service
+    pub template_permissions: Vec<String>,
+    /// The ports of the `init` template service
+    pub template_ports: Vec<String>,
+}
+
+/// The profiles by addon type
+pub type TypeProfiles = BTreeMap<String, TypeProfile>;
+
+/// The profiles that were current when this CLI was released
+pub fn bundled() -> TypeProfiles {
+    serde_json::from_str(include_str!("../../addon-type-profiles.json")).expect("valid bundled type profiles")
+}
+
+impl TypeProfile {
+    fn forbids_permission(&self, permission: &str) -> bool {
+        self.forbidden_permissions.iter().any(|p| match p.ends_with('*') {
+            true => permission.starts_with(p.trim_end_matches('*')),
+            false => p == permission,
+        })
+    }
+}
+
+/// The errors of an addon description that violates the profile of its type
+pub fn check(data: &AddonFileEntry, profiles: &TypeProfiles, positions: &Positions) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let type_field = &data.x_ohx_registry.type_field;
+    let profile = match profiles.get(type_field) {
+        Some(v) => v,
+        None => {
+            let types: Vec<&str> = profiles.keys().map(|k| k.as_str()).collect();
+            issues.push(positions.error("x-ohx-registry.type".to_owned(),
+                                        format!("Unknown addon type '{}'. Types: {}", type_field, types.join(", "))));
+            return issues;
+        }
+    };
+
+    if profile.requires_port && !data.services.values().any(|s| s.ports.as_ref().map(|p| !p.is_empty()).unwrap_or(false)) {
+        issues.push(positions.error("services".to_owned(), format!("A {} addon must declare a port in one of its services", type_field)));
+    }
+    let mut services: Vec<_> = data.services.iter().collect();
+    services.sort_by(|a, b| a.0.cmp(b.0));
+    for (service_id, service) in services {
+        if profile.forbids_devices && service.devices.as_ref().map(|d| !d.is_empty()).unwrap_or(false) {
+            issues.push(positions.error(format!("services.{}.devices", service_id), format!("A {} addon must not access devices", type_field)));
+        }
+        if let Some(permissions) = &service.permissions {
+            for (list, permissions) in &[("mandatory", &permissions.mandatory), ("optional", &permissions.optional)] {
+                for (index, permission) in permissions.iter().enumerate().filter(|(_, p)| profile.forbids_permission(p)) {
+                    issues.push(positions.error(format!("services.{}.permissions.{}[{}]", service_id, list, index),
+                                                format!("A {} addon must not request the permission {}", type_field, permission)));
+                }
+            }
+        }
+    }
+    issues
+}
+
+#[test]
+fn bundled_test() {
+    let profiles = bundled();
+    assert!(profiles.get("rule-engine").unwrap().forbids_permission("HW_USB"));
+    assert!(!profiles.get("rule-engine").unwrap().forbids_permission("RULES"));
+    assert!(profiles.get("ui").unwrap().requires_port);
+}
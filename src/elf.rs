@@ -0,0 +1,53 @@
+//! Just enough ELF header parsing to tell which machine a binary is build for.
+
+const EM_386: u16 = 3;
+const EM_ARM: u16 = 40;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+
+/// The number of bytes of a file that are required to determine the machine type
+pub(crate) const HEADER_LEN: usize = 20;
+
+/// The e_machine field of an ELF header, or None if the data is not an ELF file
+pub(crate) fn machine(head: &[u8]) -> Option<u16> {
+    if head.len() < HEADER_LEN || &head[0..4] != b"\x7fELF" {
+        return None;
+    }
+    let bytes = [head[18], head[19]];
+    // EI_DATA: 2 is big endian
+    Some(match head[5] {
+        2 => u16::from_be_bytes(bytes),
+        _ => u16::from_le_bytes(bytes),
+    })
+}
+
+/// The ELF machine type of binaries for an addon architecture
+pub(crate) fn expected_machine(arch: &str) -> Option<u16> {
+    match arch {
+        "amd64" => Some(EM_X86_64),
+        "i386" => Some(EM_386),
+        "armhf" | "armv6" => Some(EM_ARM),
+        "aarch64" => Some(EM_AARCH64),
+        _ => None,
+    }
+}
+
+/// A readable name of an ELF machine type
+pub(crate) fn machine_name(machine: u16) -> String {
+    match machine {
+        EM_X86_64 => "amd64".to_owned(),
+        EM_386 => "i386".to_owned(),
+        EM_ARM => "arm".to_owned(),
+        EM_AARCH64 => "aarch64".to_owned(),
+        _ => format!("machine type {}", machine),
+    }
+}
+
+#[test]
+fn machine_test() {
+    let mut header = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0];
+    header.resize(HEADER_LEN, 0);
+    header[18] = 183;
+    assert_eq!(machine(&header), Some(EM_AARCH64));
+    assert_eq!(machine(b"#!/bin/sh\necho hello world"), None);
+}
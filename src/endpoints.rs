@@ -0,0 +1,89 @@
+//! The service endpoints: OAuth server, registry server, vault, the registry index files and the Docker Hub namespace
+//! of the images. The defaults are the openhabx.com services. The configuration file, the profile selected with
+//! `--profile` and the endpoint flags replace them, for example for a staging or self-hosted registry.
+use crate::dto::addons;
+use std::cell::RefCell;
+
+pub(crate) const DEFAULT_REGISTRY_URL: &str = "https://registry.openhabx.com";
+pub(crate) const DEFAULT_VAULT_URL: &str = "https://vault.openhabx.com";
+pub(crate) const DEFAULT_DOCKER_NAMESPACE: &str = "docker.io/openhabx";
+
+#[derive(Clone)]
+pub(crate) struct Endpoints {
+    pub(crate) profile: Option<String>,
+    pub(crate) oauth_url: String,
+    pub(crate) registry_url: String,
+    pub(crate) vault_url: String,
+    pub(crate) docker_namespace: String,
+    /// The registry index with the latest version of every addon
+    pub(crate) index_url: String,
+    /// The download and rating statistics
+    pub(crate) stats_url: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            profile: None,
+            oauth_url: ohx_addon_publish::ohx_oauth::OAUTH_URL.to_owned(),
+            registry_url: DEFAULT_REGISTRY_URL.to_owned(),
+            vault_url: DEFAULT_VAULT_URL.to_owned(),
+            docker_namespace: DEFAULT_DOCKER_NAMESPACE.to_owned(),
+            index_url: addons::REGISTRY_DATA_URL.to_owned(),
+            stats_url: addons::REGISTRY_METADATA_URL.to_owned(),
+        }
+    }
+}
+
+thread_local! {
+    static ENDPOINTS: RefCell<Endpoints> = RefCell::new(Endpoints::default());
+}
+
+/// Set once at startup. The main thread talks to the services, other threads get the urls passed in.
+pub(crate) fn set(endpoints: Endpoints) {
+    let trim = |url: String| url.trim_end_matches('/').to_owned();
+    let endpoints = Endpoints {
+        profile: endpoints.profile,
+        oauth_url: trim(endpoints.oauth_url),
+        registry_url: trim(endpoints.registry_url),
+        vault_url: trim(endpoints.vault_url),
+        docker_namespace: trim(endpoints.docker_namespace),
+        index_url: endpoints.index_url,
+        stats_url: endpoints.stats_url,
+    };
+    ENDPOINTS.with(|e| *e.borrow_mut() = endpoints);
+}
+
+pub(crate) fn current() -> Endpoints {
+    ENDPOINTS.with(|e| e.borrow().clone())
+}
+
+pub(crate) fn registry_url() -> String {
+    ENDPOINTS.with(|e| e.borrow().registry_url.clone())
+}
+
+pub(crate) fn oauth_url() -> String {
+    ENDPOINTS.with(|e| e.borrow().oauth_url.clone())
+}
+
+pub(crate) fn vault_url() -> String {
+    ENDPOINTS.with(|e| e.borrow().vault_url.clone())
+}
+
+pub(crate) fn index_url() -> String {
+    ENDPOINTS.with(|e| e.borrow().index_url.clone())
+}
+
+pub(crate) fn stats_url() -> String {
+    ENDPOINTS.with(|e| e.borrow().stats_url.clone())
+}
+
+/// The repository of an addon image, like "docker.io/openhabx/addon_amd64" for the name "addon_amd64"
+pub(crate) fn repository(name: &str) -> String {
+    ENDPOINTS.with(|e| format!("{}/{}", e.borrow().docker_namespace, name))
+}
+
+/// Docker tags cannot contain "+". The build metadata of a version like "1.2.0+rebuild.1" is tagged "1.2.0_rebuild.1".
+pub(crate) fn image_name(name: &str, tag: &str) -> String {
+    format!("{}:{}", repository(name), tag.replace('+', "_"))
+}
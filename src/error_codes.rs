@@ -0,0 +1,580 @@
+//! Stable codes of all user facing errors and the `explain` subcommand, which shows causes and remedies offline.
+//! Codes are never reused or renumbered. Error messages start with the code, like "OHX-E010 'podman' is required".
+//! Commands return the [`Exit`] category of their failure, which main turns into the exit code of the process.
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The documented exit codes. 0 is success.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Exit {
+    /// Invalid addon description, and all errors of no other category
+    Validation = 1,
+    Auth = 2,
+    Build = 3,
+    Upload = 4,
+    Registry = 5,
+}
+
+impl Exit {
+    /// The exit code of the process
+    pub(crate) fn code(self) -> i32 {
+        self as i32
+    }
+}
+
+/// `exit` unless `success`, for commands whose errors all fall into one category
+pub(crate) fn check(success: bool, exit: Exit) -> Result<(), Exit> {
+    match success {
+        true => Ok(()),
+        false => Err(exit),
+    }
+}
+
+pub(crate) struct ErrorCode {
+    pub(crate) code: &'static str,
+    pub(crate) exit: Exit,
+    pub(crate) title: &'static str,
+    pub(crate) causes: &'static str,
+    pub(crate) remedies: &'static str,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code)
+    }
+}
+
+pub(crate) const INPUT_FILE_NOT_FOUND: ErrorCode = ErrorCode {
+    code: "OHX-E001",
+    exit: Exit::Validation,
+    title: "Addon description file not found",
+    causes: "No addons.yml, addons.yaml, ohx-addon.yml or ohx-addon.yaml file exists in the current directory, \
+             the path given with --input-file does not exist or its glob matches no file.",
+    remedies: "Change into the addon directory, pass the file with --input-file or create one with `init`.",
+};
+
+pub(crate) const INPUT_FILE_INVALID: ErrorCode = ErrorCode {
+    code: "OHX-E002",
+    exit: Exit::Validation,
+    title: "Addon description file invalid",
+    causes: "The addons.yml file is no valid YAML, misses a required field, has a field of the wrong type \
+             or fails a semantic check like an unknown permission or an invalid addon id.",
+    remedies: "Read the validation message for the failing field. `validate --fix` corrects common mistakes \
+               like single values instead of lists. Compare with the scaffolding repositories.",
+};
+
+pub(crate) const NO_BUILD_INSTRUCTIONS: ErrorCode = ErrorCode {
+    code: "OHX-E003",
+    exit: Exit::Validation,
+    title: "Nothing to build",
+    causes: "There is no Dockerfile, Dockerfile_<arch> or build recipe next to the addons.yml file, \
+             or none of them matches the architectures given with --arch.",
+    remedies: "Add a Dockerfile (amd64) or Dockerfile_<arch> like Dockerfile_aarch64, a `recipe` in the service build \
+               section or a wasm service. Check the --arch options.",
+};
+
+pub(crate) const FILE_ACCESS: ErrorCode = ErrorCode {
+    code: "OHX-E004",
+    exit: Exit::Validation,
+    title: "Local file not readable or writable",
+    causes: "A file or directory (the addons.yml file, the build directory, the session file, a git hook) \
+             could not be read or written. Permissions or a full disk are the usual causes.",
+    remedies: "Check the path in the message, its permissions and the free disk space. \
+               Delete the build directory to start clean.",
+};
+
+pub(crate) const FILE_EXISTS: ErrorCode = ErrorCode {
+    code: "OHX-E005",
+    exit: Exit::Validation,
+    title: "File already exists",
+    causes: "`init` does not overwrite existing files.",
+    remedies: "Use `init --force` to overwrite them, or run `init` in an empty directory.",
+};
+
+pub(crate) const BUNDLE_INVALID: ErrorCode = ErrorCode {
+    code: "OHX-E006",
+    exit: Exit::Validation,
+    title: "Addon bundle unreadable or corrupt",
+    causes: "The file given to `verify-bundle` is no tar archive, misses the addons.yml file or an image archive, \
+             or a blob of an image archive does not match its digest. The download may be incomplete or tampered with.",
+    remedies: "Download the bundle again. Do not sideload a bundle that fails this check.",
+};
+
+pub(crate) const CONFIG_INVALID: ErrorCode = ErrorCode {
+    code: "OHX-E007",
+    exit: Exit::Validation,
+    title: "Configuration file invalid",
+    causes: "`~/.config/ohx/cli.toml` or `.ohxcli.toml` is no valid TOML, contains an unknown key or an invalid value, \
+             or `config get` / `config set` was called with an unknown key.",
+    remedies: "Fix or delete the file named in the message. `config show --resolved` lists all keys.",
+};
+
+pub(crate) const TOO_MANY_WARNINGS: ErrorCode = ErrorCode {
+    code: "OHX-E008",
+    exit: Exit::Validation,
+    title: "Too many warnings",
+    causes: "The validation and the image checks logged more warnings than `--max-warnings` allows.",
+    remedies: "Fix the warnings or, for accepted exceptions, annotate the value in addons.yml with \
+               `# ohx-allow: <rule> \"<justification>\"`. The rule is printed in brackets before each warning.",
+};
+
+pub(crate) const EXTERNAL_IMAGE_MISSING: ErrorCode = ErrorCode {
+    code: "OHX-E009",
+    exit: Exit::Validation,
+    title: "Service image not available",
+    causes: "`--check-images` found that the `image:` of a service does not exist in its registry, \
+             or not for all architectures that the addon publishes.",
+    remedies: "Fix the image name or tag, or pick an image with a manifest list for all architectures. \
+               Restrict the architectures with --arch.",
+};
+
+pub(crate) const ENGINE_MISSING: ErrorCode = ErrorCode {
+    code: "OHX-E010",
+    exit: Exit::Build,
+    title: "Container engine not installed",
+    causes: "None of podman, docker or buildah is installed or in the PATH, or the engine selected with --engine is missing. \
+             Build recipes always require buildah.",
+    remedies: "Install podman (https://podman.io/getting-started/installation), docker or buildah. \
+               On Windows use the Windows Subsystem for Linux.",
+};
+
+pub(crate) const ENGINE_VERSION: ErrorCode = ErrorCode {
+    code: "OHX-E011",
+    exit: Exit::Build,
+    title: "Container engine too old",
+    causes: "The installed podman is older than the minimum supported version, or its version could not be read.",
+    remedies: "Update podman. `podman version` shows the installed version.",
+};
+
+pub(crate) const BUILD_FAILED: ErrorCode = ErrorCode {
+    code: "OHX-E012",
+    exit: Exit::Build,
+    title: "Image build failed",
+    causes: "A Dockerfile instruction or build recipe step failed. Typical causes are a base image for another architecture \
+             without qemu, network errors while installing packages and missing files in the build context.",
+    remedies: "Scroll up to the build output of the failing step. Build only the working architectures with --arch, \
+               install qemu-user-static for cross builds and try the Dockerfile with `podman build`.",
+};
+
+pub(crate) const PUSH_FAILED: ErrorCode = ErrorCode {
+    code: "OHX-E013",
+    exit: Exit::Upload,
+    title: "Upload to the docker registry failed",
+    causes: "An image or OCI artifact could not be pushed. The docker registry credentials were rejected \
+             or the connection was interrupted.",
+    remedies: "Retry with `publish --resume`, which only pushes the missing architectures. \
+               If the credentials are rejected repeatedly, contact the registry maintainers.",
+};
+
+pub(crate) const IMAGE_CHECK_FAILED: ErrorCode = ErrorCode {
+    code: "OHX-E014",
+    exit: Exit::Build,
+    title: "Image check failed",
+    causes: "A build image runs as root without the permission to do so, its entrypoint does not exist \
+             or it contains binaries for another architecture.",
+    remedies: "Add a USER directive or request the mandatory permission, fix ENTRYPOINT / CMD \
+               and make sure the base image matches the architecture of the Dockerfile.",
+};
+
+pub(crate) const IMAGE_EXPORT_FAILED: ErrorCode = ErrorCode {
+    code: "OHX-E015",
+    exit: Exit::Build,
+    title: "Image filesystem not readable",
+    causes: "The container engine could not create a container from the build image or export its filesystem.",
+    remedies: "Check the engine with `podman create <image>`. Free disk space in the engine storage.",
+};
+
+pub(crate) const ORAS_MISSING: ErrorCode = ErrorCode {
+    code: "OHX-E016",
+    exit: Exit::Upload,
+    title: "oras not installed",
+    causes: "Wasm services and x-attachments are pushed as OCI artifacts with oras.",
+    remedies: "Install oras: https://oras.land",
+};
+
+pub(crate) const WASM_INVALID: ErrorCode = ErrorCode {
+    code: "OHX-E017",
+    exit: Exit::Validation,
+    title: "Wasm module invalid",
+    causes: "The build command of a wasm service failed, or the module file is no WebAssembly version 1 module.",
+    remedies: "Run the build command manually in the addon directory and check the `module` path.",
+};
+
+pub(crate) const ATTACHMENT_NOT_FOUND: ErrorCode = ErrorCode {
+    code: "OHX-E018",
+    exit: Exit::Validation,
+    title: "Attachment not found",
+    causes: "A file listed in x-attachments does not exist.",
+    remedies: "Paths are relative to the addon directory. Fix the path or remove the attachment.",
+};
+
+pub(crate) const REMOTE_HOST: ErrorCode = ErrorCode {
+    code: "OHX-E019",
+    exit: Exit::Build,
+    title: "Remote build host not usable",
+    causes: "The container engine on the --remote-host is not reachable via ssh, its API socket is not running, \
+             or the build needs a local tool like buildah.",
+    remedies: "Check `podman --remote --url <url> info` (or `docker --host <url> info`). The podman URL needs the socket path, \
+               like ssh://builder@host/run/user/1000/podman/podman.sock. Start the socket with `systemctl --user enable --now podman.socket`.",
+};
+
+pub(crate) const NOT_LOGGED_IN: ErrorCode = ErrorCode {
+    code: "OHX-E020",
+    exit: Exit::Auth,
+    title: "Not logged in",
+    causes: "There is no stored session, and the terminal is not interactive (--ci), so no login can be started.",
+    remedies: "Run `login` on a workstation and provide the session file to the pipeline, \
+               or verify a token with `login --check --token`.",
+};
+
+pub(crate) const SESSION_INVALID: ErrorCode = ErrorCode {
+    code: "OHX-E021",
+    exit: Exit::Auth,
+    title: "Session or token invalid",
+    causes: "The access token is expired or revoked and could not be refreshed.",
+    remedies: "Run `logout` and `login` again.",
+};
+
+pub(crate) const LOGIN_FAILED: ErrorCode = ErrorCode {
+    code: "OHX-E022",
+    exit: Exit::Auth,
+    title: "Login failed",
+    causes: "The device authorization could not be started, was denied or expired before it was confirmed, \
+             or the user information could not be read.",
+    remedies: "Start `login` again and confirm the code on https://openhabx.com/auth within the displayed time.",
+};
+
+pub(crate) const DOCKER_CREDENTIALS: ErrorCode = ErrorCode {
+    code: "OHX-E023",
+    exit: Exit::Auth,
+    title: "Docker registry credentials not available",
+    causes: "The vault did not return docker registry credentials for your account or addon.",
+    remedies: "Check that you are logged in with the account that owns the addon. Contact the registry maintainers \
+               if the problem persists.",
+};
+
+pub(crate) const REGISTRY_UNREACHABLE: ErrorCode = ErrorCode {
+    code: "OHX-E030",
+    exit: Exit::Registry,
+    title: "Service not reachable",
+    causes: "The addon registry, the vault or another openhabx.com service could not be contacted. \
+             Network problems, proxies and timeouts are the usual causes.",
+    remedies: "Check your internet connection and retry later.",
+};
+
+pub(crate) const REGISTRY_REJECTED: ErrorCode = ErrorCode {
+    code: "OHX-E031",
+    exit: Exit::Registry,
+    title: "Request rejected by the registry",
+    causes: "The addon registry returned an unexpected response, for example because the payload failed a server side check.",
+    remedies: "Read the server response in the message. Validate and publish again with the latest CLI version.",
+};
+
+pub(crate) const NOT_OWNER: ErrorCode = ErrorCode {
+    code: "OHX-E032",
+    exit: Exit::Registry,
+    title: "Not the owner of the addon",
+    causes: "The addon id is registered by another account.",
+    remedies: "Login with the owning account, or pick another addon id. `search` shows taken ids.",
+};
+
+pub(crate) const NOT_FOUND: ErrorCode = ErrorCode {
+    code: "OHX-E033",
+    exit: Exit::Registry,
+    title: "Addon, version or image not found",
+    causes: "The addon or version is not published, or an image is not pushed to the docker registry.",
+    remedies: "Check the id and version with `search` or `list`. For `publish --skip-build` push the images first.",
+};
+
+pub(crate) const REMOTE_IMAGE_CHECK: ErrorCode = ErrorCode {
+    code: "OHX-E034",
+    exit: Exit::Upload,
+    title: "Docker registry request failed",
+    causes: "An image could not be checked in or deleted from the docker registry.",
+    remedies: "Retry later. The message contains the registry response.",
+};
+
+pub(crate) const BUNDLE_MISMATCH: ErrorCode = ErrorCode {
+    code: "OHX-E035",
+    exit: Exit::Registry,
+    title: "Addon bundle does not match the registry",
+    causes: "The id, version, architectures or image digests of the bundle differ from the published registry entry. \
+             The bundle was built locally, belongs to another version or was modified.",
+    remedies: "Compare the version with `info <addon-id>`. Only sideload bundles exported from the published version.",
+};
+
+pub(crate) const REBUILD_NOT_POSSIBLE: ErrorCode = ErrorCode {
+    code: "OHX-E036",
+    exit: Exit::Registry,
+    title: "Rebuild not possible",
+    causes: "The version of addons.yml is not the published version, the addon is not published yet, \
+             the addon directory has uncommitted changes or --dry-run or --skip-build was given.",
+    remedies: "Checkout the sources of the published version. New versions are published with `publish`.",
+};
+
+pub(crate) const OFFLINE: ErrorCode = ErrorCode {
+    code: "OHX-E037",
+    exit: Exit::Registry,
+    title: "Not available offline",
+    causes: "The command requires the registry, the vault or the OAuth server, but --offline is given, \
+             or there is no cached registry index yet.",
+    remedies: "Run the command without --offline. Run any registry command, like `search`, once online to cache the index.",
+};
+
+pub(crate) const VERSION_NOT_NEWER: ErrorCode = ErrorCode {
+    code: "OHX-E038",
+    exit: Exit::Registry,
+    title: "Version already published",
+    causes: "The version of addons.yml is equal to or lower than the version in the registry.",
+    remedies: "Increase the version in addons.yml. Use --allow-republish to publish the same or an older version anyway.",
+};
+
+pub(crate) const POLICY_MISMATCH: ErrorCode = ErrorCode {
+    code: "OHX-E039",
+    exit: Exit::Registry,
+    title: "Policy data does not match its pin or signature",
+    causes: "The policy data of the registry, like the addon type profiles, differs from the digest in .ohx-policy.lock, \
+             the signature of the policy manifest is invalid or the registry key changed. The registry may have \
+             updated its policy, or the data was tampered with on the way.",
+    remedies: "Run `policy update` to review and pin the current policy. Only use --trust-new-key if the registry \
+               announced a new key.",
+};
+
+pub(crate) const TLS_BACKEND: ErrorCode = ErrorCode {
+    code: "OHX-E040",
+    exit: Exit::Validation,
+    title: "TLS backend not available",
+    causes: "The binary was compiled without the requested TLS backend.",
+    remedies: "Use another --tls-backend or rebuild with the cargo feature named in the message.",
+};
+
+pub(crate) const FIPS: ErrorCode = ErrorCode {
+    code: "OHX-E041",
+    exit: Exit::Validation,
+    title: "FIPS mode not possible",
+    causes: "--fips requires the native-tls backend and a system crypto library in FIPS mode.",
+    remedies: "Use --tls-backend native-tls on a system with /proc/sys/crypto/fips_enabled set to 1.",
+};
+
+pub(crate) const HTTP_CLIENT: ErrorCode = ErrorCode {
+    code: "OHX-E042",
+    exit: Exit::Validation,
+    title: "HTTP client not available",
+    causes: "The http client could not be created, for example because the system certificates could not be loaded.",
+    remedies: "Check the system certificate store or try another --tls-backend.",
+};
+
+pub(crate) const NOT_INTERACTIVE: ErrorCode = ErrorCode {
+    code: "OHX-E050",
+    exit: Exit::Validation,
+    title: "Confirmation required",
+    causes: "A command asks for confirmation, but the terminal is not interactive (--ci or no tty).",
+    remedies: "Confirm on the command line with --yes.",
+};
+
+pub(crate) const GIT: ErrorCode = ErrorCode {
+    code: "OHX-E051",
+    exit: Exit::Validation,
+    title: "git not available",
+    causes: "git is not installed or the current directory is no git repository.",
+    remedies: "Install git and run `hook install` inside the repository.",
+};
+
+pub(crate) const WATCH: ErrorCode = ErrorCode {
+    code: "OHX-E052",
+    exit: Exit::Validation,
+    title: "Watching failed",
+    causes: "The file system notifications could not be set up, for example because the inotify watch limit is reached.",
+    remedies: "Raise fs.inotify.max_user_watches or watch a smaller directory.",
+};
+
+pub(crate) const VULNERABLE: ErrorCode = ErrorCode {
+    code: "OHX-E053",
+    exit: Exit::Validation,
+    title: "Vulnerabilities found",
+    causes: "Packages of the published images have known vulnerabilities of the `--fail-on` severity or higher.",
+    remedies: "Update the affected packages or the base image and publish a new version, or run `rebuild` \
+               if a base image update fixes them.",
+};
+
+pub(crate) const SNAPSHOT_NOT_FOUND: ErrorCode = ErrorCode {
+    code: "OHX-E054",
+    exit: Exit::Validation,
+    title: "No registry index snapshot",
+    causes: "`index diff` needs a snapshot on or before the given date. Snapshots are only taken by `index snapshot`.",
+    remedies: "Run `index snapshot` regularly, for example nightly from cron, and pick a date of an existing snapshot.",
+};
+
+pub(crate) const ENGINE_STORAGE: ErrorCode = ErrorCode {
+    code: "OHX-E055",
+    exit: Exit::Build,
+    title: "Container storage of another user",
+    causes: "The image storage of podman belongs to another user, for example because of a shared HOME on a build server \
+             or a CONTAINERS_STORAGE_CONF of another user in the environment.",
+    remedies: "Run the CLI with your own HOME, unset CONTAINERS_STORAGE_CONF or configure your own storage in ~/.config/containers/storage.conf. \
+               `podman info` shows the storage under store.graphRoot.",
+};
+
+pub(crate) const BUILD_STORAGE: ErrorCode = ErrorCode {
+    code: "OHX-E056",
+    exit: Exit::Validation,
+    title: "Build storage not usable",
+    causes: "The build directory could not be restored from or stored in the --build-storage: The url is unknown, \
+             the S3 credentials are missing, or the storage rejected the request.",
+    remedies: "Check the url and the AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and AWS_ENDPOINT_URL environment variables \
+               or the WebDAV credentials. The message contains the storage response.",
+};
+
+pub(crate) const INPUT_FILE_AMBIGUOUS: ErrorCode = ErrorCode {
+    code: "OHX-E057",
+    exit: Exit::Validation,
+    title: "Several addon description files found",
+    causes: "The directory contains more than one of addons.yml, addons.yaml, ohx-addon.yml and ohx-addon.yaml, \
+             or the --input-file glob matches more than one file.",
+    remedies: "Pass the file with --input-file, or remove the files that are not used.",
+};
+
+pub(crate) const BROKEN_LINK: ErrorCode = ErrorCode {
+    code: "OHX-E058",
+    exit: Exit::Validation,
+    title: "Broken link",
+    causes: "With --online-checks, the homepage, github or changelog_url of x-ohx-registry answered with 404 Not Found \
+             or 410 Gone.",
+    remedies: "Fix the URL in addons.yml, or publish the page first. Open the URL of the message in a browser.",
+};
+
+pub(crate) const SECRET_STORE: ErrorCode = ErrorCode {
+    code: "OHX-E059",
+    exit: Exit::Auth,
+    title: "Secret store not usable",
+    causes: "The session could not be stored in the secret store of the platform: secret-tool (libsecret) is not \
+             installed, no Secret Service like GNOME Keyring or KWallet runs, or the keychain is locked.",
+    remedies: "Install secret-tool and unlock the keyring, or store the session in a plain file with \
+               --insecure-session-file, for example on build servers without a desktop session.",
+};
+
+pub(crate) const ENGINE_FEATURE: ErrorCode = ErrorCode {
+    code: "OHX-E060",
+    exit: Exit::Build,
+    title: "Container engine feature not available",
+    causes: "The addon requires a feature that the container engine lacks: `build.secrets` require `podman build \
+             --secret`, `buildah bud --secret` or docker buildx. --oci-archive requires `podman save --format \
+             oci-archive` or buildah.",
+    remedies: "Update podman or buildah, or install docker buildx. `podman <command> --help` lists the supported \
+               options.",
+};
+
+pub(crate) const INTERNAL: ErrorCode = ErrorCode {
+    code: "OHX-E099",
+    exit: Exit::Validation,
+    title: "Internal error",
+    causes: "A bug in the CLI.",
+    remedies: "Please report it with the full message at https://github.com/openhab-nodes/cloud-addon-registry-cli/issues.",
+};
+
+pub(crate) const CATALOG: &[ErrorCode] = &[
+    INPUT_FILE_NOT_FOUND, INPUT_FILE_INVALID, NO_BUILD_INSTRUCTIONS, FILE_ACCESS, FILE_EXISTS, BUNDLE_INVALID, CONFIG_INVALID, TOO_MANY_WARNINGS,
+    EXTERNAL_IMAGE_MISSING,
+    ENGINE_MISSING, ENGINE_VERSION, BUILD_FAILED, PUSH_FAILED, IMAGE_CHECK_FAILED, IMAGE_EXPORT_FAILED,
+    ORAS_MISSING, WASM_INVALID, ATTACHMENT_NOT_FOUND, REMOTE_HOST,
+    NOT_LOGGED_IN, SESSION_INVALID, LOGIN_FAILED, DOCKER_CREDENTIALS,
+    REGISTRY_UNREACHABLE, REGISTRY_REJECTED, NOT_OWNER, NOT_FOUND, REMOTE_IMAGE_CHECK, BUNDLE_MISMATCH,
+    REBUILD_NOT_POSSIBLE, OFFLINE, VERSION_NOT_NEWER, POLICY_MISMATCH,
+    TLS_BACKEND, FIPS, HTTP_CLIENT,
+    NOT_INTERACTIVE, GIT, WATCH, VULNERABLE, SNAPSHOT_NOT_FOUND, ENGINE_STORAGE, BUILD_STORAGE,
+    INPUT_FILE_AMBIGUOUS, BROKEN_LINK, SECRET_STORE, ENGINE_FEATURE, INTERNAL,
+];
+
+/// The catalog index + 1 of the first logged error code. 0 if none was logged.
+static FIRST_CODE: AtomicUsize = AtomicUsize::new(0);
+
+/// Records the first logged error code for the publish job status and passes all records on to env_logger,
+/// or to the dashboard while it is shown
+struct ErrorCodeLogger(env_logger::Logger);
+
+impl log::Log for ErrorCodeLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        // Also for filtered records: RUST_LOG=off must not change the reported code
+        if record.level() == log::Level::Error {
+            let message = record.args().to_string();
+            let code = message.get(..8).and_then(find);
+            if let Some(index) = code.and_then(|c| CATALOG.iter().position(|e| e.code == c.code)) {
+                let _ = FIRST_CODE.compare_exchange(0, index + 1, Ordering::SeqCst, Ordering::SeqCst);
+            }
+        }
+        if crate::dashboard::is_active() && self.0.matches(record) {
+            crate::dashboard::capture(&format!("[{}] {}", record.level(), record.args()));
+            return;
+        }
+        self.0.log(record);
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+/// Installs the configured logger
+pub(crate) fn init_logger(mut builder: env_logger::Builder) {
+    let logger = builder.build();
+    log::set_max_level(logger.filter().max(log::LevelFilter::Error));
+    log::set_boxed_logger(Box::new(ErrorCodeLogger(logger))).expect("logger installed once");
+}
+
+/// The first logged error code, like "OHX-E030"
+pub(crate) fn first_error_code() -> Option<&'static str> {
+    match FIRST_CODE.load(Ordering::SeqCst) {
+        0 => None,
+        v => Some(CATALOG[v - 1].code),
+    }
+}
+
+/// Finds a code. "OHX-E012", "ohx-e012", "E012" and "12" are accepted.
+fn find(code: &str) -> Option<&'static ErrorCode> {
+    let number = code.trim().to_uppercase();
+    let number = number.trim_start_matches("OHX-").trim_start_matches('E');
+    let number: u32 = number.parse().ok()?;
+    CATALOG.iter().find(|e| e.code[5..].parse::<u32>().ok() == Some(number))
+}
+
+/// Prints the explanation of the given code or, without a code, the list of all codes.
+/// Returns false for an unknown code.
+pub(crate) fn explain(code: Option<&str>) -> bool {
+    let code = match code {
+        Some(v) => v,
+        None => {
+            for error_code in CATALOG {
+                println!("{}  {}", error_code.code, error_code.title);
+            }
+            return true;
+        }
+    };
+    match find(code) {
+        Some(error_code) => {
+            println!("{}: {}\n\nCauses:\n  {}\n\nRemedies:\n  {}\n\nExit code: {}", error_code.code, error_code.title,
+                     error_code.causes, error_code.remedies, error_code.exit.code());
+            true
+        }
+        None => {
+            println!("Unknown error code {}. Run `explain` without a code to list all codes.", code);
+            false
+        }
+    }
+}
+
+#[test]
+fn catalog_test() {
+    let mut codes: Vec<&str> = CATALOG.iter().map(|e| e.code).collect();
+    assert!(codes.iter().all(|c| c.len() == 8 && c.starts_with("OHX-E")));
+    codes.sort();
+    codes.dedup();
+    assert_eq!(codes.len(), CATALOG.len());
+    assert_eq!(find("e12").map(|e| e.code), Some("OHX-E012"));
+    assert_eq!(find("OHX-E012").map(|e| e.code), Some("OHX-E012"));
+    assert!(find("OHX-E999").is_none());
+    assert_eq!(find("E013").map(|e| e.exit), Some(Exit::Upload));
+}
@@ -0,0 +1,192 @@
+//! `publish --check-images`: The images of services with an `image:` instead of a build, like "eclipse-mosquitto:1.6"
+//! or "ghcr.io/owner/app:2.0", must exist in their registry for all published architectures.
+//! Registries are asked for the manifest with the distribution API, with an anonymous pull token if they require one.
+use crate::buildah;
+use crate::dto::addons::AddonFileEntry;
+use crate::error_codes;
+use log::{error, info};
+use reqwest::header::{ACCEPT, WWW_AUTHENTICATE};
+use serde::Deserialize;
+
+const MANIFEST_MEDIA_TYPES: &str = "application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.oci.image.index.v1+json, application/vnd.docker.distribution.manifest.v2+json, \
+    application/vnd.oci.image.manifest.v1+json";
+
+#[derive(Debug, PartialEq)]
+struct ImageReference {
+    /// The registry host, like "registry-1.docker.io"
+    registry: String,
+    repository: String,
+    /// A tag or a digest
+    reference: String,
+}
+
+/// "eclipse-mosquitto:1.6" is "library/eclipse-mosquitto" on the docker hub, tag "1.6"
+fn parse_reference(image: &str) -> ImageReference {
+    let (name, reference) = match image.rfind('@') {
+        Some(pos) => (&image[..pos], &image[pos + 1..]),
+        // A colon before the last slash belongs to the registry port
+        None => match image.rfind(':').filter(|pos| !image[*pos..].contains('/')) {
+            Some(pos) => (&image[..pos], &image[pos + 1..]),
+            None => (image, "latest"),
+        },
+    };
+    let mut parts = name.splitn(2, '/');
+    let first = parts.next().unwrap_or_default();
+    let (registry, repository) = match parts.next() {
+        Some(rest) if first.contains('.') || first.contains(':') || first == "localhost" => (first, rest.to_owned()),
+        _ => ("docker.io", name.to_owned()),
+    };
+    match registry {
+        "docker.io" | "index.docker.io" => ImageReference {
+            registry: "registry-1.docker.io".to_owned(),
+            repository: if repository.contains('/') { repository } else { format!("library/{}", repository) },
+            reference: reference.to_owned(),
+        },
+        _ => ImageReference { registry: registry.to_owned(), repository, reference: reference.to_owned() },
+    }
+}
+
+#[derive(Deserialize)]
+struct RegistryToken {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// A parameter of a `WWW-Authenticate: Bearer realm="...",service="..."` challenge
+fn challenge_parameter(challenge: &str, name: &str) -> Option<String> {
+    let key = format!("{}=\"", name);
+    let start = challenge.find(&key)? + key.len();
+    let end = challenge[start..].find('"')? + start;
+    Some(challenge[start..end].to_owned())
+}
+
+/// GET with an anonymous pull token, if the registry asks for one
+fn get(client: &reqwest::Client, url: &str) -> Result<reqwest::Response, failure::Error> {
+    let response = client.get(url).header(ACCEPT, MANIFEST_MEDIA_TYPES).send()?;
+    if response.status() != 401 {
+        return Ok(response);
+    }
+    let challenge = response.headers().get(WWW_AUTHENTICATE).and_then(|v| v.to_str().ok()).unwrap_or_default().to_owned();
+    let realm = challenge_parameter(&challenge, "realm").ok_or_else(|| failure::err_msg(format!("{} requires a login", url)))?;
+    let mut token_url = reqwest::Url::parse(&realm)?;
+    for name in &["service", "scope"] {
+        if let Some(value) = challenge_parameter(&challenge, name) {
+            token_url.query_pairs_mut().append_pair(name, &value);
+        }
+    }
+    let token: RegistryToken = client.get(token_url).send()?.error_for_status()?.json()?;
+    let token = token.token.or(token.access_token).ok_or_else(|| failure::err_msg(format!("No pull token for {}", url)))?;
+    Ok(client.get(url).bearer_auth(token).header(ACCEPT, MANIFEST_MEDIA_TYPES).send()?)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Platform {
+    architecture: String,
+    #[serde(default)]
+    os: String,
+    #[serde(default)]
+    variant: Option<String>,
+}
+
+impl Platform {
+    /// ARM images without a variant are accepted for all ARM variants
+    fn provides(&self, arch: &str) -> bool {
+        let variant = self.variant.as_deref();
+        (self.os.is_empty() || self.os == "linux") && self.architecture == buildah::oci_arch(arch)
+            && (variant.is_none() || buildah::oci_variant(arch).is_none() || variant == buildah::oci_variant(arch))
+    }
+}
+
+#[derive(Deserialize)]
+struct ManifestEntry {
+    platform: Option<Platform>,
+}
+
+#[derive(Deserialize)]
+struct Descriptor {
+    digest: String,
+}
+
+/// A manifest list or index, or the manifest of a single image
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    manifests: Vec<ManifestEntry>,
+    config: Option<Descriptor>,
+}
+
+/// The platforms of an image. None if the image does not exist.
+fn platforms(client: &reqwest::Client, image: &str) -> Result<Option<Vec<Platform>>, failure::Error> {
+    let image = parse_reference(image);
+    let url = format!("https://{}/v2/{}/manifests/{}", image.registry, image.repository, image.reference);
+    let response = get(client, &url)?;
+    if response.status() == 404 {
+        return Ok(None);
+    }
+    let manifest: Manifest = response.error_for_status()?.json()?;
+    if let Some(config) = manifest.config {
+        // A single image names its platform in the image configuration
+        let url = format!("https://{}/v2/{}/blobs/{}", image.registry, image.repository, config.digest);
+        let platform: Platform = get(client, &url)?.error_for_status()?.json()?;
+        return Ok(Some(vec![platform]));
+    }
+    Ok(Some(manifest.manifests.into_iter().filter_map(|m| m.platform).collect()))
+}
+
+/// Checks the images of all services without a build. Returns false and prints an error for each image
+/// that does not exist or lacks one of the architectures.
+pub(crate) fn check(client: &reqwest::Client, input_file: &AddonFileEntry, archs: &[String]) -> bool {
+    let mut services: Vec<(&String, &String)> = input_file.services.iter()
+        .filter(|(_, s)| s.build.is_none() && s.wasm.is_none())
+        .filter_map(|(id, s)| s.image.as_ref().map(|image| (id, image)))
+        .collect();
+    services.sort();
+    let mut success = true;
+    for (service_id, image) in services {
+        match platforms(client, image) {
+            Ok(Some(platforms)) => {
+                let missing: Vec<&str> = archs.iter().map(|a| a.as_str()).filter(|a| !platforms.iter().any(|p| p.provides(a))).collect();
+                match missing.is_empty() {
+                    true => info!("The image {} of service {} exists for {}", image, service_id, archs.join(", ")),
+                    false => {
+                        error!("{} The image {} of service {} is not available for {}", error_codes::EXTERNAL_IMAGE_MISSING,
+                               image, service_id, missing.join(", "));
+                        success = false;
+                    }
+                }
+            }
+            Ok(None) => {
+                error!("{} The image {} of service {} does not exist", error_codes::EXTERNAL_IMAGE_MISSING, image, service_id);
+                success = false;
+            }
+            Err(e) => {
+                error!("{} Failed to check the image {} of service {}: {}", error_codes::REMOTE_IMAGE_CHECK, image, service_id, e);
+                success = false;
+            }
+        }
+    }
+    success
+}
+
+#[test]
+fn external_images_test() {
+    let reference = |registry: &str, repository: &str, reference: &str| ImageReference {
+        registry: registry.to_owned(), repository: repository.to_owned(), reference: reference.to_owned()
+    };
+    assert_eq!(parse_reference("eclipse-mosquitto:1.6"), reference("registry-1.docker.io", "library/eclipse-mosquitto", "1.6"));
+    assert_eq!(parse_reference("docker.io/openhabx/app"), reference("registry-1.docker.io", "openhabx/app", "latest"));
+    assert_eq!(parse_reference("localhost:5000/app:2.0"), reference("localhost:5000", "app", "2.0"));
+    assert_eq!(parse_reference("ghcr.io/owner/app@sha256:abc"), reference("ghcr.io", "owner/app", "sha256:abc"));
+    assert_eq!(challenge_parameter("Bearer realm=\"https://auth.docker.io/token\",service=\"registry.docker.io\"", "service"),
+               Some("registry.docker.io".to_owned()));
+
+    let platform = |architecture: &str, variant: Option<&str>| Platform {
+        architecture: architecture.to_owned(), os: "linux".to_owned(), variant: variant.map(|v| v.to_owned())
+    };
+    assert!(platform("arm64", Some("v8")).provides("aarch64"));
+    assert!(platform("arm", Some("v6")).provides("armv6"));
+    assert!(!platform("arm", Some("v7")).provides("armv6"));
+    assert!(platform("arm", None).provides("armhf"));
+    assert!(!platform("amd64", None).provides("i386"));
+}
@@ -0,0 +1,166 @@
+//! The `hook` subcommand: Installs git hooks that validate changed addon description files.
+//! Existing hooks, for example of hook managers, are kept. The validation is inserted as marked block.
+use crate::error_codes;
+use log::error;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const BLOCK_START: &str = "# >>> ohx-addon-publish >>>";
+const BLOCK_END: &str = "# <<< ohx-addon-publish <<<";
+
+/// The validate command line, without the input file
+const VALIDATE_COMMAND: &str = "ohx-addon-publish validate --strict --offline";
+
+pub(crate) const HOOK_NAMES: [&str; 2] = ["pre-commit", "pre-push"];
+
+/// Shell code that lists the addon description files to validate
+fn changed_files_command(hook_name: &str) -> &'static str {
+    match hook_name {
+        // Only staged files
+        "pre-commit" => "git diff --cached --name-only --diff-filter=ACM",
+        // All tracked files. The pushed commit range is not known in advance.
+        _ => "git ls-files",
+    }
+}
+
+fn hook_block(hook_name: &str) -> String {
+    format!("{start}\n\
+             for file in $({files} | grep -E '(^|/)(addons|ohx-addon)\\.ya?ml$'); do\n  \
+             {validate} -i \"$file\" || exit 1\n\
+             done\n\
+             {end}\n",
+            start = BLOCK_START, files = changed_files_command(hook_name), validate = VALIDATE_COMMAND, end = BLOCK_END)
+}
+
+/// Adds the block to an existing hook script. It is inserted after the shebang line,
+/// because generated hooks often end with `exec` and code appended after that would never run.
+fn insert_block(script: &str, block: &str) -> String {
+    if script.trim().is_empty() {
+        return format!("#!/bin/sh\n{}", block);
+    }
+    match script.starts_with("#!") {
+        true => {
+            let (shebang, rest) = script.split_at(script.find('\n').map(|p| p + 1).unwrap_or(script.len()));
+            let shebang = if shebang.ends_with('\n') { shebang.to_owned() } else { format!("{}\n", shebang) };
+            format!("{}{}{}", shebang, block, rest)
+        }
+        false => format!("{}{}", block, script),
+    }
+}
+
+/// Removes the block. Returns None if nothing but the shebang is left.
+fn remove_block(script: &str) -> Option<String> {
+    let mut result = String::new();
+    let mut in_block = false;
+    for line in script.lines() {
+        if line == BLOCK_START {
+            in_block = true;
+        } else if line == BLOCK_END {
+            in_block = false;
+        } else if !in_block {
+            result.push_str(line);
+            result.push('\n');
+        }
+    }
+    let remaining = result.lines().filter(|l| !l.starts_with("#!") && !l.trim().is_empty()).count();
+    if remaining == 0 { None } else { Some(result) }
+}
+
+/// The hooks directory. `git rev-parse --git-path` respects core.hooksPath of hook managers.
+fn hooks_directory() -> Option<PathBuf> {
+    let output = match Command::new("git").args(["rev-parse", "--git-path", "hooks"]).output() {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} 'git' is required to install hooks: {:?}", error_codes::GIT, e);
+            return None;
+        }
+    };
+    if !output.status.success() {
+        error!("{} Not a git repository: {}", error_codes::GIT, String::from_utf8_lossy(&output.stderr).trim());
+        return None;
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o111);
+    std::fs::set_permissions(path, permissions)
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+pub(crate) fn install(hook_name: &str) -> bool {
+    let hook_file = match hooks_directory() {
+        Some(v) => v.join(hook_name),
+        None => return false,
+    };
+    let script = std::fs::read_to_string(&hook_file).unwrap_or_default();
+    if script.lines().any(|l| l == BLOCK_START) {
+        println!("The {} hook is already installed in {}", hook_name, hook_file.display());
+        return true;
+    }
+    let script = insert_block(&script, &hook_block(hook_name));
+    let result = hook_file.parent().map(std::fs::create_dir_all).unwrap_or(Ok(()))
+        .and_then(|_| std::fs::write(&hook_file, script))
+        .and_then(|_| make_executable(&hook_file));
+    match result {
+        Ok(_) => {
+            println!("Installed the {} hook in {}", hook_name, hook_file.display());
+            true
+        }
+        Err(e) => {
+            error!("{} Failed to write {}: {:?}", error_codes::FILE_ACCESS, hook_file.display(), e);
+            false
+        }
+    }
+}
+
+pub(crate) fn uninstall(hook_name: &str) -> bool {
+    let hook_file = match hooks_directory() {
+        Some(v) => v.join(hook_name),
+        None => return false,
+    };
+    let script = match std::fs::read_to_string(&hook_file) {
+        Ok(v) => v,
+        Err(_) => {
+            println!("No {} hook installed", hook_name);
+            return true;
+        }
+    };
+    if !script.lines().any(|l| l == BLOCK_START) {
+        println!("The {} hook in {} does not validate addons", hook_name, hook_file.display());
+        return true;
+    }
+    let result = match remove_block(&script) {
+        Some(script) => std::fs::write(&hook_file, script),
+        None => std::fs::remove_file(&hook_file),
+    };
+    match result {
+        Ok(_) => {
+            println!("Removed the {} hook from {}", hook_name, hook_file.display());
+            true
+        }
+        Err(e) => {
+            error!("{} Failed to update {}: {:?}", error_codes::FILE_ACCESS, hook_file.display(), e);
+            false
+        }
+    }
+}
+
+#[test]
+fn insert_remove_block_test() {
+    let existing = "#!/bin/sh\nexec other-hook-manager \"$@\"\n";
+    let script = insert_block(existing, &hook_block("pre-commit"));
+    assert!(script.starts_with("#!/bin/sh\n# >>> ohx-addon-publish >>>\n"));
+    assert!(script.ends_with("exec other-hook-manager \"$@\"\n"));
+    assert_eq!(remove_block(&script).unwrap(), existing);
+
+    let new = insert_block("", &hook_block("pre-push"));
+    assert_eq!(remove_block(&new), None);
+}
@@ -0,0 +1,223 @@
+//! Creation of the http client, including the TLS backend selection, the FIPS mode checks and the proxy settings.
+use crate::auth::Authenticator;
+use crate::error_codes;
+use log::{error, info, warn};
+use ohx_addon_publish::ohx_oauth::RetryPolicy;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Offline mode: No login, no registry refresh and no upload. The last cached registry index is used.
+pub(crate) fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+pub(crate) fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+static PATIENT_RETRIES: AtomicBool = AtomicBool::new(false);
+
+/// CI runners see more transient network errors. OAuth requests and registry mutations are retried more often
+/// there, with a longer backoff.
+pub(crate) fn set_patient_retries(patient: bool) {
+    PATIENT_RETRIES.store(patient, Ordering::Relaxed);
+}
+
+/// The retry policy of OAuth requests and registry mutations
+pub(crate) fn retry_policy() -> RetryPolicy {
+    match PATIENT_RETRIES.load(Ordering::Relaxed) {
+        true => RetryPolicy { attempts: 5, initial_backoff: Duration::from_secs(2) },
+        false => RetryPolicy::default(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TlsBackend {
+    /// rustls, the default. No dependency on the system crypto libraries.
+    Rustls,
+    /// The platform TLS implementation (OpenSSL, Secure Transport, SChannel).
+    Native,
+}
+
+impl TlsBackend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TlsBackend::Rustls => "rustls",
+            TlsBackend::Native => "native-tls",
+        }
+    }
+
+    /// Returns true if this backend is compiled into the binary.
+    pub fn available(&self) -> bool {
+        match self {
+            TlsBackend::Rustls => cfg!(feature = "rustls-tls"),
+            TlsBackend::Native => cfg!(feature = "native-tls"),
+        }
+    }
+}
+
+impl FromStr for TlsBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rustls" => Ok(TlsBackend::Rustls),
+            "native" | "native-tls" => Ok(TlsBackend::Native),
+            _ => Err(format!("Unknown TLS backend {}. Allowed: rustls, native", s)),
+        }
+    }
+}
+
+/// FIPS mode is enforced if the binary is build with the "fips" feature or requested on the command line.
+pub fn fips_enabled(requested: bool) -> bool {
+    requested || cfg!(feature = "fips")
+}
+
+/// Checks if the kernel runs in FIPS mode. The system OpenSSL follows this setting.
+fn system_fips_mode() -> Option<bool> {
+    std::fs::read_to_string("/proc/sys/crypto/fips_enabled").ok().map(|v| v.trim() == "1")
+}
+
+/// The TLS backend that is used if none is requested explicitly
+pub fn resolve_backend(backend: Option<TlsBackend>, fips: bool) -> TlsBackend {
+    match backend {
+        Some(backend) => backend,
+        None if fips => TlsBackend::Native,
+        None if TlsBackend::Rustls.available() => TlsBackend::Rustls,
+        None => TlsBackend::Native,
+    }
+}
+
+/// The proxy environment variables. The container engines and other child processes read them as well.
+pub(crate) const PROXY_ENV: [&str; 3] = ["HTTP_PROXY", "HTTPS_PROXY", "NO_PROXY"];
+
+/// An environment variable, upper or lower case
+fn proxy_env(name: &str) -> Option<String> {
+    std::env::var(name).or_else(|_| std::env::var(name.to_lowercase())).ok().filter(|v| !v.is_empty())
+}
+
+/// The proxies of `HTTP_PROXY` and `HTTPS_PROXY` and the hosts of `NO_PROXY` that are reached directly
+#[derive(Default)]
+pub(crate) struct ProxySettings {
+    http: Option<reqwest::Url>,
+    https: Option<reqwest::Url>,
+    no_proxy: Vec<String>,
+}
+
+impl ProxySettings {
+    pub(crate) fn from_env() -> Result<ProxySettings, String> {
+        let url = |name: &str| match proxy_env(name) {
+            Some(v) => reqwest::Url::parse(&v).map(Some).map_err(|e| format!("{} {} is invalid: {}", name, v, e)),
+            None => Ok(None),
+        };
+        Ok(ProxySettings {
+            http: url("HTTP_PROXY")?,
+            https: url("HTTPS_PROXY")?,
+            no_proxy: proxy_env("NO_PROXY").map(|v| v.split(',').map(|h| h.trim().trim_start_matches('.').to_lowercase())
+                .filter(|h| !h.is_empty()).collect()).unwrap_or_default(),
+        })
+    }
+
+    /// A "NO_PROXY" entry matches the host and its subdomains. "*" matches all hosts.
+    fn bypass(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        self.no_proxy.iter().any(|n| n == "*" || host == *n || host.ends_with(&format!(".{}", n)))
+    }
+
+    fn proxy_for(&self, url: &reqwest::Url) -> Option<reqwest::Url> {
+        if url.host_str().map(|h| self.bypass(h)).unwrap_or(false) {
+            return None;
+        }
+        match url.scheme() {
+            "https" => self.https.clone(),
+            _ => self.http.clone(),
+        }
+    }
+}
+
+/// `--proxy` replaces `HTTP_PROXY` and `HTTPS_PROXY` of this process, so that the container engine uses it too.
+pub(crate) fn set_proxy(proxy: &str) -> bool {
+    if let Err(e) = reqwest::Url::parse(proxy) {
+        error!("{} The proxy {} is invalid: {}", error_codes::HTTP_CLIENT, proxy, e);
+        return false;
+    }
+    std::env::set_var("HTTP_PROXY", proxy);
+    std::env::set_var("HTTPS_PROXY", proxy);
+    true
+}
+
+/// Create the http client with the requested TLS backend.
+/// Without an explicit backend, rustls is preferred if compiled in.
+///
+/// In FIPS mode only the platform TLS implementation is allowed,
+/// because rustls and its crypto library *ring* are not FIPS 140-2 validated.
+/// The timeout applies to each request. Without it the reqwest default is used.
+/// The authenticator may add a client certificate. Requests go through the proxy of `ProxySettings`.
+pub(crate) fn create_client(backend: Option<TlsBackend>, fips: bool, timeout: Option<Duration>, auth: &dyn Authenticator) -> Option<reqwest::Client> {
+    let fips = fips_enabled(fips);
+    let backend = resolve_backend(backend, fips);
+
+    if !backend.available() {
+        error!("{} The TLS backend {} is not compiled into this binary. Rebuild with the cargo feature '{}'.",
+               error_codes::TLS_BACKEND, backend.name(), if backend == TlsBackend::Rustls { "rustls-tls" } else { "native-tls" });
+        return None;
+    }
+
+    if fips {
+        if backend != TlsBackend::Native {
+            error!("{} FIPS mode requires the native-tls backend. rustls is not FIPS validated.", error_codes::FIPS);
+            return None;
+        }
+        match system_fips_mode() {
+            Some(true) => info!("System crypto runs in FIPS mode"),
+            Some(false) => {
+                error!("{} FIPS mode requested, but the system crypto is not in FIPS mode (/proc/sys/crypto/fips_enabled).", error_codes::FIPS);
+                return None;
+            }
+            None => warn!("FIPS mode requested, but the system FIPS state cannot be determined on this platform."),
+        }
+    }
+
+    info!("Using TLS backend {}", backend.name());
+    let builder = reqwest::Client::builder();
+    let builder = match timeout {
+        Some(timeout) => builder.timeout(timeout),
+        None => builder,
+    };
+    #[cfg(feature = "rustls-tls")]
+    let builder = if backend == TlsBackend::Rustls { builder.use_rustls_tls() } else { builder };
+    #[cfg(feature = "native-tls")]
+    let builder = if backend == TlsBackend::Native { builder.use_default_tls() } else { builder };
+    let builder = auth.configure(builder)?;
+    let proxy = match ProxySettings::from_env() {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} {}", error_codes::HTTP_CLIENT, e);
+            return None;
+        }
+    };
+    let builder = match proxy.http.is_some() || proxy.https.is_some() {
+        true => builder.proxy(reqwest::Proxy::custom(move |url| proxy.proxy_for(url))),
+        false => builder,
+    };
+
+    match builder.build() {
+        Ok(client) => Some(client),
+        Err(e) => {
+            error!("{} Failed to initialize the http client: {:?}", error_codes::HTTP_CLIENT, e);
+            None
+        }
+    }
+}
+
+#[test]
+fn proxy_bypass_test() {
+    let proxy = ProxySettings { no_proxy: vec!["example.com".to_owned(), "localhost".to_owned()], ..Default::default() };
+    assert!(proxy.bypass("registry.example.com"));
+    assert!(proxy.bypass("EXAMPLE.com"));
+    assert!(proxy.bypass("localhost"));
+    assert!(!proxy.bypass("notexample.com"));
+}
@@ -0,0 +1,255 @@
+//! Checks of the build images, based on the image configuration reported by `podman image inspect`
+//! (or `docker image inspect`)
+//! and the exported image filesystem.
+use crate::error_codes;
+use crate::buildah;
+use crate::docker_registry::EngineCli;
+use crate::dto::BuildInstruction;
+use crate::dto::addons::AddonFileEntry;
+use crate::dto::lint;
+use crate::elf;
+use crate::warnings;
+use crate::image_fs::{self, ImageFilesystem};
+use log::{error, warn};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+/// The permission that allows a service to run as root
+pub(crate) const PERMISSION_ROOT_USER: &str = "ROOT_USER";
+
+/// Larger images are a warning, unless `--max-image-size` sets a limit
+pub(crate) const IMAGE_SIZE_BUDGET_MIB: u64 = 500;
+
+/// Checks that fail the build instead of printing a warning
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Deny {
+    /// Images running as root user without the ROOT_USER permission
+    RootUser,
+}
+
+impl Deny {
+    pub(crate) const ALL: [Deny; 1] = [Deny::RootUser];
+}
+
+impl FromStr for Deny {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "root-user" => Ok(Deny::RootUser),
+            _ => Err(format!("Unknown check {}. Allowed: root-user", s)),
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct ImageConfig {
+    #[serde(default)]
+    pub(crate) user: String,
+    /// Exposed ports like "8080/tcp"
+    #[serde(default)]
+    pub(crate) exposed_ports: Option<BTreeMap<String, serde_json::Value>>,
+    #[serde(default)]
+    pub(crate) entrypoint: Option<Vec<String>>,
+    #[serde(default)]
+    pub(crate) cmd: Option<Vec<String>>,
+    #[serde(default)]
+    pub(crate) env: Option<Vec<String>>,
+    #[serde(default)]
+    pub(crate) working_dir: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct ImageInspect {
+    #[serde(default)]
+    pub(crate) config: ImageConfig,
+}
+
+/// The image configuration of a local image
+pub(crate) fn inspect_image(cli: &EngineCli, image_name: &str) -> Option<ImageInspect> {
+    if cli.binary == "buildah" {
+        return buildah::inspect_image(image_name).map(|(config, _)| ImageInspect { config });
+    }
+    let output = cli.command()
+        .arg("image")
+        .arg("inspect")
+        .arg(image_name)
+        .output()
+        .ok()?;
+    let mut images: Vec<ImageInspect> = serde_json::from_slice(&output.stdout).ok()?;
+    if images.is_empty() { None } else { Some(images.remove(0)) }
+}
+
+/// An empty user, "root" and uid 0 all mean root
+fn is_root_user(user: &str) -> bool {
+    let user = user.split(':').next().unwrap_or_default();
+    user.is_empty() || user == "root" || user == "0"
+}
+
+/// Warn about ports that are exposed by the image but not declared in the addons file and vice versa
+fn check_ports(build_instruction: &BuildInstruction, image: &ImageInspect, declared: &BTreeSet<String>) {
+    let exposed: BTreeSet<String> = match &image.config.exposed_ports {
+        Some(v) => v.keys().cloned().collect(),
+        None => BTreeSet::new(),
+    };
+    for port in exposed.difference(declared) {
+        warnings::report("ports/undeclared", "", &format!("The image for {} exposes port {}, but it is not declared in the addons file. \
+                          The firewall will block it.", build_instruction.arch, port));
+    }
+    for port in declared.difference(&exposed) {
+        warnings::report("ports/not-exposed", "", &format!("Port {} is declared in the addons file, but not exposed by {}. Is this a typo?",
+                         port, build_instruction.filename));
+    }
+}
+
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Checks that the image starts an existing executable, build for the architecture of the image.
+/// A binary for the wrong architecture would otherwise only fail with "exec format error" on the users device.
+fn check_entrypoint(build_instruction: &BuildInstruction, image: &ImageInspect, files: &ImageFilesystem) -> Result<(), String> {
+    let config = &image.config;
+    let program = match config.entrypoint.iter().chain(config.cmd.iter()).flatten().next() {
+        Some(v) => v,
+        None => return Err(format!("The image for {} defines neither ENTRYPOINT nor CMD", build_instruction.arch)),
+    };
+
+    let candidates: Vec<String> = if program.starts_with('/') {
+        vec![program.clone()]
+    } else if program.contains('/') {
+        vec![format!("{}/{}", config.working_dir, program)]
+    } else {
+        let path = config.env.iter().flatten()
+            .find(|e| e.starts_with("PATH="))
+            .map(|e| &e[5..])
+            .unwrap_or(DEFAULT_PATH);
+        path.split(':').map(|dir| format!("{}/{}", dir, program)).collect()
+    };
+    let (path, file) = match candidates.iter().find_map(|c| image_fs::resolve(files, c)) {
+        Some(v) => v,
+        None => return Err(format!("The image for {} starts {}, but it does not exist in the image", build_instruction.arch, program)),
+    };
+    if file.mode & 0o111 == 0 {
+        return Err(format!("The image for {} starts {}, but {} is not executable", build_instruction.arch, program, path));
+    }
+    if let (Some(machine), Some(expected)) = (elf::machine(&file.head), elf::expected_machine(&build_instruction.arch)) {
+        if machine != expected {
+            return Err(format!("The image for {} starts {}, but {} is build for {}",
+                               build_instruction.arch, program, path, elf::machine_name(machine)));
+        }
+    }
+    Ok(())
+}
+
+/// Lists ELF files in the image that are build for another architecture.
+/// qemu user emulation binaries, copied in for cross building, are ignored.
+fn foreign_binaries(arch: &str, files: &ImageFilesystem) -> Vec<(String, u16)> {
+    let expected = match elf::expected_machine(arch) {
+        Some(v) => v,
+        None => return Vec::new(),
+    };
+    files.iter()
+        .filter(|(path, _)| !path.rsplit('/').next().unwrap_or_default().starts_with("qemu-"))
+        .filter_map(|(path, file)| elf::machine(&file.head).map(|m| (path, m)))
+        .filter(|(_, machine)| *machine != expected)
+        .map(|(path, machine)| (path.clone(), machine))
+        .collect()
+}
+
+/// Inspects all successfully build images. Returns false if a denied check failed.
+/// Architectures with broken images are marked as not build.
+pub(crate) fn check_images(cli: &EngineCli, input_file: &AddonFileEntry, build_instructions: &mut [BuildInstruction], deny: &[Deny]) -> bool {
+    let root_permitted = input_file.services.values()
+        .filter_map(|s| s.permissions.as_ref())
+        .any(|p| p.mandatory.iter().any(|p| p == PERMISSION_ROOT_USER));
+    let declared = lint::declared_ports(input_file);
+
+    let mut result = true;
+    // Images that are already pushed were checked by the run that pushed them (--resume)
+    for build_instruction in build_instructions.iter_mut().filter(|b| b.build && !b.uploaded) {
+        let image = match inspect_image(cli, &build_instruction.image_name) {
+            Some(v) => v,
+            None => {
+                warn!("Could not inspect image {}", build_instruction.image_name);
+                continue;
+            }
+        };
+
+        check_ports(build_instruction, &image, &declared);
+
+        if !root_permitted && is_root_user(&image.config.user) {
+            let message = format!("The image for {} runs as root. Add a USER directive to {} \
+                                   or request the mandatory {} permission.",
+                                  build_instruction.arch, build_instruction.filename, PERMISSION_ROOT_USER);
+            if deny.contains(&Deny::RootUser) {
+                error!("{} {}", error_codes::IMAGE_CHECK_FAILED, message);
+                result = false;
+            } else {
+                warnings::report("user/root", "", &message);
+            }
+        }
+
+        let files = match image_fs::export(cli, &build_instruction.image_name) {
+            Some(v) => v,
+            None => {
+                warn!("Could not read the filesystem of image {}", build_instruction.image_name);
+                continue;
+            }
+        };
+        if let Err(message) = check_entrypoint(build_instruction, &image, &files) {
+            error!("{} {}", error_codes::IMAGE_CHECK_FAILED, message);
+            build_instruction.build = false;
+            result = false;
+        }
+
+        let foreign = foreign_binaries(&build_instruction.arch, &files);
+        if !foreign.is_empty() {
+            let list: Vec<String> = foreign.iter().map(|(path, machine)| format!("  {} ({})", path, elf::machine_name(*machine))).collect();
+            error!("{} The image for {} contains binaries for other architectures:\n{}", error_codes::IMAGE_CHECK_FAILED, build_instruction.arch, list.join("\n"));
+            build_instruction.build = false;
+            result = false;
+        }
+    }
+    result
+}
+
+fn exceeds(size: i64, budget_mib: u64) -> bool {
+    size > 0 && size as u64 > budget_mib * 1024 * 1024
+}
+
+/// Compares the size of each build image with the budget. Above [`IMAGE_SIZE_BUDGET_MIB`] is a warning,
+/// above `max_size_mib` an error that returns false.
+pub(crate) fn check_sizes(build_instructions: &[BuildInstruction], max_size_mib: Option<u64>) -> bool {
+    let budget = max_size_mib.unwrap_or(IMAGE_SIZE_BUDGET_MIB);
+    let mut result = true;
+    for build_instruction in build_instructions.iter().filter(|b| b.build && exceeds(b.image_size, budget)) {
+        let message = format!("The image for {} has {:.1} MiB, more than {} MiB. A slim base image like alpine \
+                               or a multi-stage build of {} keeps it small.", build_instruction.arch,
+                              build_instruction.image_size as f64 / (1024.0 * 1024.0), budget, build_instruction.filename);
+        match max_size_mib {
+            Some(_) => {
+                error!("{} {}", error_codes::IMAGE_CHECK_FAILED, message);
+                result = false;
+            }
+            None => warnings::report("image/size", "", &message),
+        }
+    }
+    result
+}
+
+#[test]
+fn exceeds_test() {
+    assert!(exceeds(501 * 1024 * 1024, IMAGE_SIZE_BUDGET_MIB));
+    assert!(!exceeds(500 * 1024 * 1024, IMAGE_SIZE_BUDGET_MIB));
+    // Unknown sizes
+    assert!(!exceeds(0, 1));
+}
+
+#[test]
+fn is_root_user_test() {
+    assert!(is_root_user(""));
+    assert!(is_root_user("0:0"));
+    assert!(!is_root_user("nobody"));
+}
@@ -0,0 +1,139 @@
+//! Read access to the filesystem of a local image. The filesystem is exported via a temporary container.
+use crate::error_codes;
+use crate::docker_registry::EngineCli;
+use crate::elf;
+use log::error;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::process::{Command, Stdio};
+
+/// A file of an exported image filesystem. Only the start of regular files is kept.
+pub(crate) struct ImageFile {
+    pub(crate) mode: u32,
+    /// The target of a symbolic or hard link
+    pub(crate) link: Option<String>,
+    pub(crate) head: Vec<u8>,
+}
+
+/// Absolute paths and the files of the image
+pub(crate) type ImageFilesystem = BTreeMap<String, ImageFile>;
+
+/// Exports the filesystem of the given image of the container engine
+pub(crate) fn export(cli: &EngineCli, image_name: &str) -> Option<ImageFilesystem> {
+    let create = if cli.binary == "buildah" { "from" } else { "create" };
+    let output = match cli.command().arg(create).arg(image_name).output() {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} Failed to create a container for {}: {:?}", error_codes::IMAGE_EXPORT_FAILED, image_name, e);
+            return None;
+        }
+    };
+    if !output.status.success() {
+        error!("{} Failed to create a container for {}: {}", error_codes::IMAGE_EXPORT_FAILED, image_name, String::from_utf8_lossy(&output.stderr));
+        return None;
+    }
+    let container = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    let files = read_export(cli, &container);
+    let _ = cli.command().arg("rm").arg(&container).output();
+    files
+}
+
+/// buildah has no `export`. The working container is mounted in a user namespace and archived with tar.
+fn export_command(cli: &EngineCli, container: &str) -> Command {
+    let mut command = cli.command();
+    match cli.binary {
+        "buildah" => command.args(["unshare", "sh", "-c", "tar -C \"$(buildah mount \"$1\")\" -cf - .", "sh", container]),
+        _ => command.arg("export").arg(container),
+    };
+    command
+}
+
+fn read_export(cli: &EngineCli, container: &str) -> Option<ImageFilesystem> {
+    let mut child = export_command(cli, container)
+        .stdout(Stdio::piped())
+        .spawn().ok()?;
+    let stdout = child.stdout.take()?;
+    let mut archive = tar::Archive::new(stdout);
+    let mut files = ImageFilesystem::new();
+    let entries = match archive.entries() {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} Failed to read the filesystem of container {}: {:?}", error_codes::IMAGE_EXPORT_FAILED, container, e);
+            return None;
+        }
+    };
+    for entry in entries {
+        let mut entry = match entry {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{} Failed to read the filesystem of container {}: {:?}", error_codes::IMAGE_EXPORT_FAILED, container, e);
+                return None;
+            }
+        };
+        let path = match entry.path() {
+            Ok(v) => absolute(&v.to_string_lossy()),
+            Err(_) => continue,
+        };
+        let mode = entry.header().mode().unwrap_or(0);
+        let entry_type = entry.header().entry_type();
+        let link = match entry.link_name() {
+            Ok(Some(v)) if entry_type.is_hard_link() => Some(absolute(&v.to_string_lossy())),
+            Ok(Some(v)) if entry_type.is_symlink() => Some(v.to_string_lossy().into_owned()),
+            _ => None,
+        };
+        let mut head = Vec::new();
+        if entry_type.is_file() {
+            let _ = entry.by_ref().take(elf::HEADER_LEN as u64).read_to_end(&mut head);
+        }
+        files.insert(path, ImageFile { mode, link, head });
+    }
+    let _ = child.wait();
+    Some(files)
+}
+
+/// Tar paths are relative, like "./usr/bin"
+fn absolute(path: &str) -> String {
+    format!("/{}", path.trim_start_matches("./").trim_start_matches('/').trim_end_matches('/'))
+}
+
+/// Returns the file at the given absolute path. Symbolic links are followed, also for directories like /bin -> usr/bin.
+pub(crate) fn resolve<'a>(files: &'a ImageFilesystem, path: &str) -> Option<(String, &'a ImageFile)> {
+    // Components that are not resolved yet, the next one on top
+    let mut pending: Vec<String> = path.split('/').filter(|c| !c.is_empty()).rev().map(String::from).collect();
+    let mut current: Vec<String> = Vec::new();
+    let mut hops = 0;
+    while let Some(component) = pending.pop() {
+        match component.as_str() {
+            "." => continue,
+            ".." => {
+                current.pop();
+                continue;
+            }
+            _ => current.push(component),
+        }
+        let joined = format!("/{}", current.join("/"));
+        if let Some(link) = files.get(&joined).and_then(|f| f.link.as_ref()) {
+            hops += 1;
+            if hops > 32 {
+                return None;
+            }
+            current.pop();
+            if link.starts_with('/') {
+                current.clear();
+            }
+            pending.extend(link.split('/').filter(|c| !c.is_empty()).rev().map(String::from));
+        }
+    }
+    let joined = format!("/{}", current.join("/"));
+    files.get(&joined).map(|f| (joined, f))
+}
+
+#[test]
+fn resolve_test() {
+    let mut files = ImageFilesystem::new();
+    files.insert("/bin".to_owned(), ImageFile { mode: 0o777, link: Some("usr/bin".to_owned()), head: Vec::new() });
+    files.insert("/usr/bin/sh".to_owned(), ImageFile { mode: 0o777, link: Some("busybox".to_owned()), head: Vec::new() });
+    files.insert("/usr/bin/busybox".to_owned(), ImageFile { mode: 0o755, link: None, head: Vec::new() });
+    assert_eq!(resolve(&files, "/bin/sh").map(|(p, _)| p), Some("/usr/bin/busybox".to_owned()));
+    assert!(resolve(&files, "/bin/bash").is_none());
+}
@@ -0,0 +1,138 @@
+//! The `info` subcommand: Complete registry information of an addon.
+use crate::cache;
+use crate::endpoints;
+use crate::error_codes;
+use crate::dto::addons::{self, AddonDetailedInfo, AddonRegistryEntry, AddonStats};
+use crate::dto::search;
+use crate::http;
+use crate::registry;
+use log::{error, warn};
+use serde::Serialize;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[derive(Serialize)]
+struct AddonInfo<'a> {
+    entry: &'a AddonRegistryEntry,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<&'a AddonStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<&'a AddonDetailedInfo>,
+}
+
+/// Formats a unix timestamp (seconds) as UTC date and time
+pub(crate) fn format_timestamp(timestamp: i64) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(timestamp.max(0) as u64);
+    chrono::DateTime::<chrono::Utc>::from(time).format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+/// Formats a size in bytes as MiB
+pub(crate) fn format_size(bytes: i64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+pub(crate) fn info(client: &reqwest::Client, addon_id: &str, json: bool) -> bool {
+    // Stats and details are fetched while the registry index is loaded
+    let stats = {
+        let (client, stats_url) = (client.clone(), endpoints::stats_url());
+        let offline = http::is_offline();
+        std::thread::spawn(move || match offline {
+            true => Err("Offline".to_owned()),
+            false => addons::get_addons_registry_metadata(&client, &stats_url).map_err(|e| e.to_string()),
+        })
+    };
+    let details = {
+        let (client, registry_url, addon_id) = (client.clone(), endpoints::registry_url(), addon_id.to_owned());
+        let url = format!("{}/addon/{}", registry_url, addon_id);
+        std::thread::spawn(move || {
+            cache::cached_get(&url, || addons::get_addon_details(&client, &registry_url, &addon_id)).map_err(|e| e.to_string())
+        })
+    };
+
+    let registry = match registry::addon_registry(client) {
+        Some(v) => v,
+        None => return false,
+    };
+    let entry = match registry.get(addon_id) {
+        Some(v) => v,
+        None => {
+            error!("{} Addon {} not found in the registry", error_codes::NOT_FOUND, addon_id);
+            return false;
+        }
+    };
+
+    let stats = stats.join().expect("stats thread").map_err(|e| warn!("Registry stats not available: {}", e)).ok();
+    let stats = stats.as_ref().and_then(|s| s.stats.get(addon_id));
+    let details = details.join().expect("details thread").map_err(|e| warn!("Addon details not available: {}", e)).ok();
+
+    if json {
+        let info = AddonInfo { entry, stats, details: details.as_ref() };
+        println!("{}", serde_json::to_string_pretty(&info).unwrap());
+        return true;
+    }
+
+    let e = &entry.entry;
+    println!("{} - {}", e.title, e.id);
+    println!("{}\n", e.description);
+    println!("Version:      {}", e.version);
+    println!("Type:         {}", e.type_field);
+    println!("License:      {}", e.license);
+    println!("Status:       {:?}{}", e.status.code, e.status.description.as_ref().map(|d| format!(" ({})", d)).unwrap_or_default());
+    println!("Owner:        {}", entry.owner);
+    println!("Last updated: {}", format_timestamp(entry.last_updated));
+    println!("Authors:      {}", e.authors.join(", "));
+    if !e.manufacturers.is_empty() {
+        println!("Manufacturers: {}", e.manufacturers.join(", "));
+    }
+    if !e.products.is_empty() {
+        println!("Products:     {}", e.products.join(", "));
+    }
+    for (label, url) in &[("Homepage:", &e.homepage), ("Github:", &e.github), ("Changelog:", &e.changelog_url)] {
+        if let Some(url) = url {
+            println!("{:<13} {}", label, url);
+        }
+    }
+    if let Some(stats) = stats {
+        println!("Rating:       {:.1} ({} votes)", search::average_rating(stats), stats.v);
+        println!("Downloads:    {}", stats.d);
+        println!("Issues:       {}", stats.iss);
+    }
+
+    let details = match details {
+        Some(v) => v,
+        None => return true,
+    };
+    println!("Architectures: {}", details.archs.join(", "));
+    if details.sizes.is_empty() {
+        println!("Size:         {} (average)", format_size(details.size));
+    } else {
+        for (arch, size) in &details.sizes {
+            println!("Size {:<8} {}", format!("{}:", arch), format_size(*size));
+        }
+    }
+    println!("Memory:       {} - {} MiB", details.runtime.memory_min, details.runtime.memory_max);
+    if !details.reviewed_by.is_empty() {
+        println!("Reviewed by:  {}", details.reviewed_by.join(", "));
+    }
+
+    let mut services: Vec<_> = details.services.iter().collect();
+    services.sort_by_key(|(id, _)| *id);
+    for (service_id, service) in services {
+        println!("\nService {}", service_id);
+        if let Some(image) = &service.image {
+            println!("  Image:       {}", image);
+        }
+        if let Some(ports) = &service.ports {
+            println!("  Ports:       {}", ports.join(", "));
+        }
+        if let Some(permissions) = &service.permissions {
+            println!("  Permissions: {} (optional: {})", permissions.mandatory.join(", "), permissions.optional.join(", "));
+        }
+        if let Some(devices) = &service.devices {
+            println!("  Devices:     {}", devices.join(", "));
+        }
+        if let Some(depends_on) = &service.depends_on {
+            println!("  Depends on:  {}", depends_on.join(", "));
+        }
+    }
+    true
+}
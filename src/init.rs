@@ -0,0 +1,167 @@
+//! The `init` subcommand: Interactively creates an addons.yml file and Dockerfiles for the chosen architectures.
+use crate::error_codes;
+use crate::dto::addons::{self, AddonEntryCommon, AddonFileEntry, AddonRuntimeRequirements, AddonService, BuildContext, Permissions};
+use crate::dto::{metadata, migrate};
+use crate::registry;
+use crate::ALLOWED_ARCHITECTURES;
+use console::{style, Term};
+use log::error;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+/// Ask a question on the terminal. An empty answer selects the default.
+pub(crate) fn prompt(term: &Term, question: &str, default: &str) -> String {
+    let _ = term.write_str(&format!("{} [{}]: ", style(question).bold(), default));
+    match term.read_line() {
+        Ok(ref answer) if !answer.trim().is_empty() => answer.trim().to_owned(),
+        _ => default.to_owned(),
+    }
+}
+
+/// Ask until the answer is one of `allowed`
+pub(crate) fn prompt_choice(term: &Term, question: &str, default: &str, allowed: &[&str]) -> String {
+    loop {
+        let answer = prompt(term, &format!("{} ({})", question, allowed.join(", ")), default);
+        if allowed.contains(&answer.as_str()) {
+            return answer;
+        }
+        let _ = term.write_line(&format!("'{}' is not one of: {}", answer, allowed.join(", ")));
+    }
+}
+
+/// "Name <email>" of the git user, if configured
+fn git_author() -> Option<String> {
+    let get = |key: &str| {
+        Command::new("git").args(["config", "--get", key]).output().ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_owned())
+    };
+    match (get("user.name"), get("user.email")) {
+        (Some(name), Some(email)) => Some(format!("{} <{}>", name, email)),
+        (Some(name), None) => Some(name),
+        _ => None,
+    }
+}
+
+/// The Dockerfile name and the base image for an architecture
+fn dockerfile_for_arch(arch: &str) -> (String, &'static str) {
+    match arch {
+        "amd64" => ("Dockerfile".to_owned(), "alpine:3.10"),
+        "aarch64" => ("Dockerfile.aarch64".to_owned(), "arm64v8/alpine:3.10"),
+        "armhf" => ("Dockerfile.armhf".to_owned(), "arm32v7/alpine:3.10"),
+        "armv6" => ("Dockerfile.armv6".to_owned(), "arm32v6/alpine:3.10"),
+        "i386" => ("Dockerfile.i386".to_owned(), "i386/alpine:3.10"),
+        _ => (format!("Dockerfile.{}", arch), "alpine:3.10"),
+    }
+}
+
+fn dockerfile_template(base_image: &str, addon_id: &str) -> String {
+    format!(r#"FROM {}
+
+# Copy your addon executable or sources into the image
+# COPY target/{id} /usr/bin/{id}
+
+# Do not run as root
+USER nobody
+
+CMD ["/usr/bin/{id}"]
+"#, base_image, id = addon_id)
+}
+
+pub(crate) fn init(directory: &Path, force: bool) -> bool {
+    let term = Term::stdout();
+    let input_file = directory.join("addons.yml");
+    if input_file.exists() && !force {
+        error!("{} {} already exists. Use --force to overwrite it.", error_codes::FILE_EXISTS, input_file.display());
+        return false;
+    }
+
+    let default_id = directory.canonicalize().ok()
+        .and_then(|d| d.file_name().map(|f| f.to_string_lossy().to_lowercase()))
+        .unwrap_or("my-addon".to_owned());
+    let id = prompt(&term, "Addon id", &default_id);
+    let title = prompt(&term, "Title", &id);
+    let description = loop {
+        let description = prompt(&term, "Description", "");
+        match description.trim().chars().count() < metadata::REQUIRED_DESCRIPTION_LENGTH {
+            true => { let _ = term.write_line(&format!("Describe the addon in at least {} characters", metadata::REQUIRED_DESCRIPTION_LENGTH)); }
+            false => break description,
+        }
+    };
+    let profiles = registry::type_profiles();
+    let types: Vec<&str> = profiles.keys().map(|k| k.as_str()).collect();
+    let type_field = prompt_choice(&term, "Type", types.first().cloned().unwrap_or("binding"), &types);
+    let profile = profiles.get(&type_field).cloned().unwrap_or_default();
+    let license = prompt(&term, "License", "MIT");
+    let author = loop {
+        let author = prompt(&term, "Author", &git_author().unwrap_or_default());
+        match author.is_empty() || metadata::is_author(&author) {
+            true => break author,
+            false => { let _ = term.write_line("An author is like \"Name <name@example.com>\" or a https profile URL"); }
+        }
+    };
+    let archs: Vec<String> = loop {
+        let archs = prompt(&term, &format!("Architectures ({})", ALLOWED_ARCHITECTURES.join(", ")), "amd64,aarch64,armhf");
+        let archs: Vec<String> = archs.split(',').map(|a| a.trim().to_owned()).filter(|a| !a.is_empty()).collect();
+        match archs.iter().find(|a| !ALLOWED_ARCHITECTURES.contains(&a.as_str())) {
+            Some(unknown) => { let _ = term.write_line(&format!("Unknown architecture: {}", unknown)); }
+            None if archs.is_empty() => { let _ = term.write_line("At least one architecture is required"); }
+            None => break archs,
+        }
+    };
+
+    let mut services = HashMap::new();
+    // The template service fulfills the profile of the addon type
+    services.insert(id.clone(), AddonService {
+        build: Some(BuildContext { context: ".".to_owned(), ..Default::default() }),
+        ports: Some(profile.template_ports.clone()).filter(|p| !p.is_empty()),
+        permissions: Some(Permissions { mandatory: profile.template_permissions.clone(), optional: Vec::new() })
+            .filter(|p| !p.mandatory.is_empty()),
+        ..Default::default()
+    });
+    let descriptor = AddonFileEntry {
+        schema_version: migrate::CURRENT_SCHEMA_VERSION,
+        services,
+        x_ohx_registry: AddonEntryCommon {
+            title,
+            description,
+            authors: if author.is_empty() { Vec::new() } else { vec![author] },
+            license,
+            type_field,
+            id: id.clone(),
+            version: "0.1.0".to_owned(),
+            ..Default::default()
+        },
+        x_runtime: AddonRuntimeRequirements { memory_min: 16, memory_max: 64 },
+        ..Default::default()
+    };
+
+    let mut files = vec![(input_file.clone(), serde_yaml::to_string(&descriptor).expect("serializable addon description"))];
+    for arch in &archs {
+        let (filename, base_image) = dockerfile_for_arch(arch);
+        files.push((directory.join(filename), dockerfile_template(base_image, &id)));
+    }
+
+    for (file, _) in &files {
+        if file.exists() && !force {
+            error!("{} {} already exists. Use --force to overwrite it.", error_codes::FILE_EXISTS, file.display());
+            return false;
+        }
+    }
+    for (file, content) in &files {
+        if let Err(e) = std::fs::write(file, content) {
+            error!("{} Failed to write {}: {:?}", error_codes::FILE_ACCESS, file.display(), e);
+            return false;
+        }
+        println!("Created {}", file.display());
+    }
+
+    // The generated file must pass the validation
+    if let Err(e) = addons::validate_addons_file_with(input_file.to_str().unwrap(), &addons::ValidationOptions {
+        type_profiles: profiles, permissions: registry::permissions(), ..Default::default() }) {
+        error!("{} The generated addons.yml is invalid: {}", error_codes::INTERNAL, e);
+        return false;
+    }
+    true
+}
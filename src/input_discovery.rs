@@ -0,0 +1,121 @@
+//! Finds the addon description file. Without `--input-file`, the first of the [`FILE_NAMES`] that exists is used.
+//! `--input-file` also accepts a glob like "addons/*/addons.yml". It must match exactly one file.
+use crate::error_codes;
+use log::{error, info};
+use regex::Regex;
+use std::path::{Component, Path, PathBuf};
+
+/// The default value of `--input-file`
+pub(crate) const DEFAULT_FILE: &str = "addons.yml";
+
+/// The accepted names of an addon description file, in order of preference
+pub(crate) const FILE_NAMES: [&str; 4] = ["addons.yml", "addons.yaml", "ohx-addon.yml", "ohx-addon.yaml"];
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// `*` and `?` match within one path component, `[abc]` is a character class
+fn glob_regex(pattern: &str) -> Regex {
+    let mut expression = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => expression.push_str("[^/]*"),
+            '?' => expression.push_str("[^/]"),
+            '[' | ']' => expression.push(c),
+            _ => expression.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expression.push('$');
+    Regex::new(&expression).unwrap_or_else(|_| Regex::new("^$").unwrap())
+}
+
+/// All files that match the pattern, sorted
+fn expand(pattern: &Path) -> Vec<PathBuf> {
+    let mut matches = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let part = match component {
+            Component::Normal(v) => v.to_string_lossy().into_owned(),
+            _ => {
+                matches = matches.into_iter().map(|m| m.join(component.as_os_str())).collect();
+                continue;
+            }
+        };
+        if !is_glob(&part) {
+            matches = matches.into_iter().map(|m| m.join(&part)).collect();
+            continue;
+        }
+        let regex = glob_regex(&part);
+        matches = matches.into_iter().flat_map(|m| {
+            let directory = if m.as_os_str().is_empty() { PathBuf::from(".") } else { m.clone() };
+            directory.read_dir().into_iter().flatten().filter_map(|e| e.ok())
+                .filter(|e| regex.is_match(&e.file_name().to_string_lossy()))
+                .map(move |e| m.join(e.file_name()))
+                .collect::<Vec<_>>()
+        }).collect();
+    }
+    let mut files: Vec<PathBuf> = matches.into_iter().filter(|m| m.is_file()).collect();
+    files.sort();
+    files
+}
+
+/// The existing addon description files of the directory
+pub(crate) fn files_in(directory: &Path) -> Vec<PathBuf> {
+    FILE_NAMES.iter().map(|name| directory.join(name)).filter(|path| path.is_file()).collect()
+}
+
+/// The existing addon description files next to the default file
+fn candidates(input_file: &Path) -> Vec<PathBuf> {
+    files_in(input_file.parent().unwrap_or_else(|| Path::new("")))
+}
+
+/// The addon description file for `--input-file`. A missing explicit file is returned as it is,
+/// validation reports it. None and an error with a listing of the files if the choice is ambiguous.
+pub(crate) fn resolve(input_file: &Path) -> Option<PathBuf> {
+    let pattern = input_file.to_string_lossy();
+    let files = if is_glob(&pattern) {
+        expand(input_file)
+    } else if input_file.exists() || input_file != Path::new(DEFAULT_FILE) {
+        return Some(input_file.to_path_buf());
+    } else {
+        candidates(input_file)
+    };
+    match files.len() {
+        0 if is_glob(&pattern) => {
+            error!("{} No addon description file matches {}", error_codes::INPUT_FILE_NOT_FOUND, pattern);
+            None
+        }
+        0 => Some(input_file.to_path_buf()),
+        1 => {
+            info!("Using the addon description file {}", files[0].display());
+            Some(files[0].clone())
+        }
+        _ => {
+            let listing: Vec<String> = files.iter().map(|f| format!("  {}", f.display())).collect();
+            error!("{} Several addon description files found. Choose one with --input-file:\n{}",
+                   error_codes::INPUT_FILE_AMBIGUOUS, listing.join("\n"));
+            None
+        }
+    }
+}
+
+#[test]
+fn input_discovery_test() {
+    assert!(glob_regex("*.yml").is_match("addons.yml"));
+    assert!(!glob_regex("*.yml").is_match("addons.yaml"));
+    assert!(glob_regex("addons.y?ml").is_match("addons.yaml"));
+    assert!(!glob_regex("addons.y?ml").is_match("addons.yml"));
+    assert!(glob_regex("[ao]*.yml").is_match("ohx-addon.yml"));
+
+    let directory = crate::namespace::user_directory().join("input-discovery-test");
+    let _ = std::fs::remove_dir_all(&directory);
+    for addon in &["a", "b"] {
+        std::fs::create_dir_all(directory.join(addon)).unwrap();
+        std::fs::write(directory.join(addon).join("addons.yaml"), "").unwrap();
+    }
+    assert_eq!(expand(&directory.join("*").join("addons.y*ml")).len(), 2);
+    assert_eq!(resolve(&directory.join("a").join("*.yaml")), Some(directory.join("a").join("addons.yaml")));
+    assert_eq!(resolve(&directory.join("*").join("addons.yaml")), None);
+    assert_eq!(candidates(&directory.join("a").join(DEFAULT_FILE)), vec![directory.join("a").join("addons.yaml")]);
+    let _ = std::fs::remove_dir_all(&directory);
+}
@@ -0,0 +1,167 @@
+//! The secret store of the platform, for the login session: The Secret Service (GNOME Keyring, KWallet) via
+//! `secret-tool` of libsecret, the macOS Keychain via `security` and the Windows Credential Manager via PowerShell.
+//! Secrets are passed on stdin and never as arguments, which other users could see in the process list.
+//! The `keyring` crate is not used, see the note on the dependencies in Cargo.toml.
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// The service name of the entries. The account is the profile.
+const SERVICE: &str = "ohx-addon-publish";
+
+/// The exit code of `security` and of the PowerShell scripts for a missing entry
+#[cfg(any(target_os = "macos", windows))]
+const NOT_FOUND: i32 = 44;
+
+fn run(program: &str, args: &[&str], input: Option<&str>) -> Result<Output, String> {
+    let mut child = Command::new(program).args(args)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{} is not available: {}", program, e))?;
+    // Without input the closed stdin ends any prompt
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Some(input) = input {
+            stdin.write_all(input.as_bytes()).map_err(|e| e.to_string())?;
+        }
+    }
+    child.wait_with_output().map_err(|e| e.to_string())
+}
+
+fn failure(program: &str, output: &Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+    match stderr.is_empty() {
+        true => format!("{} failed with {}", program, output.status),
+        false => format!("{} failed: {}", program, stderr),
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+mod platform {
+    use super::{failure, run, SERVICE};
+
+    pub(super) fn store(account: &str, secret: &str) -> Result<(), String> {
+        let label = format!("OHX addon registry session ({})", account);
+        let output = run("secret-tool", &["store", "--label", &label, "service", SERVICE, "account", account], Some(secret))?;
+        match output.status.success() {
+            true => Ok(()),
+            false => Err(failure("secret-tool", &output)),
+        }
+    }
+
+    pub(super) fn load(account: &str) -> Result<Option<String>, String> {
+        let output = run("secret-tool", &["lookup", "service", SERVICE, "account", account], None)?;
+        // A missing entry fails without a message
+        match (output.status.success(), output.stderr.is_empty()) {
+            (true, _) => Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned())),
+            (false, true) => Ok(None),
+            (false, false) => Err(failure("secret-tool", &output)),
+        }
+    }
+
+    pub(super) fn delete(account: &str) -> Result<(), String> {
+        let output = run("secret-tool", &["clear", "service", SERVICE, "account", account], None)?;
+        match output.status.success() {
+            true => Ok(()),
+            false => Err(failure("secret-tool", &output)),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::{failure, run, NOT_FOUND, SERVICE};
+
+    pub(super) fn store(account: &str, secret: &str) -> Result<(), String> {
+        // The prompt ends at a newline. A serialized session has none.
+        if secret.contains('\n') {
+            return Err("security cannot store a secret with a newline".to_owned());
+        }
+        // -U updates an existing entry. -w as last option without a value prompts for the secret and
+        // reads it from stdin twice, the second time to confirm it.
+        let input = format!("{}\n{}\n", secret, secret);
+        let output = run("security", &["add-generic-password", "-U", "-s", SERVICE, "-a", account, "-w"], Some(&input))?;
+        match output.status.success() {
+            true => Ok(()),
+            false => Err(failure("security", &output)),
+        }
+    }
+
+    pub(super) fn load(account: &str) -> Result<Option<String>, String> {
+        let output = run("security", &["find-generic-password", "-s", SERVICE, "-a", account, "-w"], None)?;
+        match output.status.code() {
+            Some(0) => Ok(Some(String::from_utf8_lossy(&output.stdout).trim_end_matches('\n').to_owned())),
+            Some(NOT_FOUND) => Ok(None),
+            _ => Err(failure("security", &output)),
+        }
+    }
+
+    pub(super) fn delete(account: &str) -> Result<(), String> {
+        let output = run("security", &["delete-generic-password", "-s", SERVICE, "-a", account], None)?;
+        match output.status.code() {
+            Some(0) | Some(NOT_FOUND) => Ok(()),
+            _ => Err(failure("security", &output)),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::{failure, run, NOT_FOUND, SERVICE};
+
+    const VAULT: &'static str = "[void][Windows.Security.Credentials.PasswordVault,Windows.Security.Credentials,ContentType=WindowsRuntime]; \
+                                 $vault = New-Object Windows.Security.Credentials.PasswordVault;";
+
+    /// A single quoted PowerShell string
+    fn quote(text: &str) -> String {
+        format!("'{}'", text.replace('\'', "''"))
+    }
+
+    fn powershell(script: &str, input: Option<&str>) -> Result<std::process::Output, String> {
+        run("powershell", &["-NoProfile", "-NonInteractive", "-Command", &format!("{} {}", VAULT, script)], input)
+    }
+
+    pub(super) fn store(account: &str, secret: &str) -> Result<(), String> {
+        let script = format!("$secret = [Console]::In.ReadToEnd(); \
+                              $vault.Add((New-Object Windows.Security.Credentials.PasswordCredential -ArgumentList {}, {}, $secret))",
+                             quote(SERVICE), quote(account));
+        let output = powershell(&script, Some(secret))?;
+        match output.status.success() {
+            true => Ok(()),
+            false => Err(failure("powershell", &output)),
+        }
+    }
+
+    pub(super) fn load(account: &str) -> Result<Option<String>, String> {
+        let script = format!("try {{ $entry = $vault.Retrieve({}, {}) }} catch {{ exit {} }}; $entry.RetrievePassword(); \
+                              [Console]::Out.Write($entry.Password)", quote(SERVICE), quote(account), NOT_FOUND);
+        let output = powershell(&script, None)?;
+        match output.status.code() {
+            Some(0) => Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned())),
+            Some(NOT_FOUND) => Ok(None),
+            _ => Err(failure("powershell", &output)),
+        }
+    }
+
+    pub(super) fn delete(account: &str) -> Result<(), String> {
+        let script = format!("try {{ $vault.Remove($vault.Retrieve({}, {})) }} catch {{ exit {} }}", quote(SERVICE), quote(account), NOT_FOUND);
+        let output = powershell(&script, None)?;
+        match output.status.code() {
+            Some(0) | Some(NOT_FOUND) => Ok(()),
+            _ => Err(failure("powershell", &output)),
+        }
+    }
+}
+
+/// Stores or replaces the secret of the account
+pub(crate) fn store(account: &str, secret: &str) -> Result<(), String> {
+    platform::store(account, secret)
+}
+
+/// The secret of the account, None if there is none
+pub(crate) fn load(account: &str) -> Result<Option<String>, String> {
+    platform::load(account)
+}
+
+/// Removes the secret of the account. Missing entries are no error.
+pub(crate) fn delete(account: &str) -> Result<(), String> {
+    platform::delete(account)
+}
@@ -1,3 +1,8 @@
 mod dto;
 
-pub use dto::addons;
+pub use dto::{addons, capabilities, docs, extends, fix, interpolate, lint, metadata, migrate, overrides, schema, search, translations, type_profiles};
+
+pub mod pipeline;
+
+#[cfg(feature = "reqwest")]
+pub mod ohx_oauth;
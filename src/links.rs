@@ -0,0 +1,78 @@
+//! `--online-checks`: The homepage, github and changelog_url links of x-ohx-registry must be reachable.
+//! Each link gets a HEAD request, or a GET if the server does not allow HEAD. 404 and 410 fail the validation,
+//! other failures are only warnings because the server might be down for a moment. Links that are not https URLs
+//! are already rejected by the validation.
+use crate::dto::addons::AddonEntryCommon;
+use crate::dto::metadata;
+use crate::error_codes;
+use crate::http;
+use log::{error, info, warn};
+use reqwest::StatusCode;
+use std::cell::RefCell;
+
+thread_local! {
+    static CLIENT: RefCell<Option<reqwest::Client>> = const { RefCell::new(None) };
+}
+
+/// The http client for the link checks. Without one, the links are not checked.
+pub(crate) fn set_client(client: reqwest::Client) {
+    CLIENT.with(|c| *c.borrow_mut() = Some(client));
+}
+
+/// The field name and https URL of each given link
+fn links(common: &AddonEntryCommon) -> Vec<(&'static str, &str)> {
+    [("homepage", &common.homepage), ("github", &common.github), ("changelog_url", &common.changelog_url)].iter()
+        .filter_map(|(field, url)| url.as_ref().map(|u| (*field, u.trim())))
+        .filter(|(_, url)| metadata::is_https_url(url))
+        .collect()
+}
+
+fn is_broken(status: StatusCode) -> bool {
+    status == StatusCode::NOT_FOUND || status == StatusCode::GONE
+}
+
+fn status(client: &reqwest::Client, url: &str) -> Result<StatusCode, reqwest::Error> {
+    let status = client.head(url).send()?.status();
+    match status == StatusCode::METHOD_NOT_ALLOWED || status == StatusCode::NOT_IMPLEMENTED {
+        true => Ok(client.get(url).send()?.status()),
+        false => Ok(status),
+    }
+}
+
+/// Requests every link. Returns false and prints an error for each broken one.
+pub(crate) fn check(common: &AddonEntryCommon) -> bool {
+    if http::is_offline() {
+        warn!("Offline: The links are not checked");
+        return true;
+    }
+    let client = match CLIENT.with(|c| c.borrow().clone()) {
+        Some(v) => v,
+        None => return true,
+    };
+    let mut success = true;
+    for (field, url) in links(common) {
+        match status(&client, url) {
+            Ok(status) if is_broken(status) => {
+                error!("{} The {} {} answers with {}", error_codes::BROKEN_LINK, field, url, status);
+                success = false;
+            }
+            Ok(status) if !status.is_success() => warn!("The {} {} answers with {}", field, url, status),
+            Ok(_) => info!("The {} {} is reachable", field, url),
+            Err(e) => warn!("Failed to check the {} {}: {}", field, url, e),
+        }
+    }
+    success
+}
+
+#[test]
+fn links_test() {
+    let common = AddonEntryCommon {
+        homepage: Some("https://example.com".to_owned()),
+        github: Some("http://github.com/owner/addon".to_owned()),
+        changelog_url: Some(" https://example.com/changes ".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(links(&common), vec![("homepage", "https://example.com"), ("changelog_url", "https://example.com/changes")]);
+    assert!(is_broken(StatusCode::GONE));
+    assert!(!is_broken(StatusCode::SERVICE_UNAVAILABLE));
+}
@@ -0,0 +1,25 @@
+//! The `list` subcommand: The addons published by the logged in user.
+use crate::info::format_timestamp;
+use crate::login::UserSession;
+use crate::registry;
+use prettytable::{cell, row, Table};
+
+pub(crate) fn list(client: &reqwest::Client, session: &UserSession) -> bool {
+    let registry = match registry::addon_registry(client) {
+        Some(v) => v,
+        None => return false,
+    };
+
+    let mut table = Table::new();
+    table.add_row(row!["ID", "Version", "Status", "Last updated"]);
+    for entry in registry.values().filter(|e| e.owner == session.user_id) {
+        let e = &entry.entry;
+        table.add_row(row![e.id, e.version, format!("{:?}", e.status.code), format_timestamp(entry.last_updated)]);
+    }
+    if table.len() == 1 {
+        println!("You have not published any addons yet");
+        return true;
+    }
+    table.printstd();
+    true
+}
@@ -1,9 +1,17 @@
-
-const OAUTH_CLIENT_ID: &'static str = "addoncli";
+const OAUTH_CLIENT_ID: &str = "addoncli";
+/// The scopes of a login. offline_access grants the refresh token.
+const SCOPE: &str = "offline_access addons profile";
+use crate::docker_registry;
+use crate::endpoints;
+use crate::error_codes;
+use crate::http;
+use crate::keyring;
+use crate::output;
 use serde::{Deserialize, Serialize};
 use log::{info, warn, error};
+use ohx_addon_publish::ohx_oauth::{self, OAuthClient, OAuthTokenResponse};
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct UserSession {
     pub refresh_token: Option<String>,
     pub access_token: String,
@@ -14,105 +22,269 @@ pub struct UserSession {
     pub user_display_name: String,
 }
 
-#[derive(Serialize)]
-pub struct TokenRequestForRefreshToken {
-    refresh_token: String,
-    client_id: String,
-    grant_type: String,
+impl UserSession {
+    /// Adds the access token to the request. Sessions of client certificate logins have none.
+    pub(crate) fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.access_token.is_empty() {
+            true => request,
+            false => request.bearer_auth(&self.access_token),
+        }
+    }
+}
+
+use std::fs::File;
+use indicatif::ProgressStyle;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use console::{style};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+static INSECURE_SESSION_FILE: AtomicBool = AtomicBool::new(false);
+
+/// Store the session in a plain file instead of the secret store of the platform, see [`keyring`]
+pub(crate) fn set_insecure_session_file(insecure: bool) {
+    INSECURE_SESSION_FILE.store(insecure, Ordering::Relaxed);
 }
 
-#[derive(Serialize)]
-pub struct TokenRequestForDevice {
-    device_code: String,
-    client_id: String,
-    grant_type: String,
+pub(crate) fn uses_session_file() -> bool {
+    INSECURE_SESSION_FILE.load(Ordering::Relaxed)
 }
 
-#[derive(Deserialize)]
-pub struct OAuthTokenResponse {
-    pub access_token: String,
-    pub token_type: String,
-    // "bearer"
-    pub expires_in: i64,
-    pub refresh_token: Option<String>,
-    pub scope: String, // Space delimiter
+/// The OAuth client of the current profile
+fn oauth_client(client: &reqwest::Client) -> OAuthClient<'_> {
+    OAuthClient::new(client, OAUTH_CLIENT_ID).with_base_url(&endpoints::oauth_url()).with_retry(http::retry_policy())
 }
 
-#[derive(Deserialize)]
-pub struct ErrorResult {
-    pub error: String,
+/// The secret store entry of the session
+fn keyring_account() -> String {
+    endpoints::current().profile.unwrap_or_else(|| "default".to_owned())
 }
 
-impl From<String> for ErrorResult {
-    fn from(message: String) -> Self {
-        serde_json::from_str(&message).expect("extracting json from a 400 error")
-    }
+/// Sessions are stored per profile
+pub(crate) fn user_session_file() -> PathBuf {
+    let file = match endpoints::current().profile {
+        Some(profile) => format!(".ohx_login_{}", profile),
+        None => ".ohx_login".to_owned(),
+    };
+    dirs::config_dir().expect("config_dir to exist").join(file)
 }
 
+/// Revokes the refresh token, removes the session from the secret store and the session file and removes
+/// the docker hub login of `publish`. Prints what was removed. Returns false if there was no session.
+pub fn logout(client: &reqwest::Client) -> bool {
+    let account = keyring_account();
+    let stored = match uses_session_file() {
+        true => None,
+        false => match keyring::load(&account) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{} Failed to read the session from the secret store: {}", error_codes::SECRET_STORE, e);
+                return false;
+            }
+        },
+    };
+    let user_session_file = user_session_file();
+    if stored.is_none() && !user_session_file.exists() {
+        println!("You are not logged in");
+        return false;
+    }
+
+    // The session is removed even if the token cannot be revoked
+    let session = stored.as_ref().and_then(|v| serde_json::from_str::<UserSession>(v).ok()).or_else(read_session_file);
+    if let Some(refresh_token) = session.as_ref().and_then(|s| s.refresh_token.as_ref()) {
+        let oauth = oauth_client(client);
+        match http::is_offline() {
+            true => warn!("Offline: The refresh token is not revoked"),
+            false => match oauth.revoke(refresh_token, "refresh_token") {
+                Ok(_) => println!("Revoked the refresh token at {}", endpoints::oauth_url()),
+                Err(e) => warn!("Failed to revoke the refresh token: {}", e),
+            },
+        }
+    }
 
-/// Users id, email, display name and a few more information
-#[allow(non_snake_case)]
-#[derive(Debug, Default, Deserialize, Serialize)]
-pub struct FirebaseAuthUser {
-    pub localId: Option<String>,
-    pub email: Option<String>,
-    pub displayName: Option<String>,
+    let mut success = true;
+    if stored.is_some() {
+        match keyring::delete(&account) {
+            Ok(_) => println!("Removed the session from the secret store"),
+            Err(e) => {
+                error!("{} Failed to remove the session from the secret store: {}", error_codes::SECRET_STORE, e);
+                success = false;
+            }
+        }
+    }
+    match std::fs::remove_file(&user_session_file) {
+        Ok(_) => println!("Removed the session {}", user_session_file.display()),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            error!("{} Failed to remove the session at {}: {:?}", error_codes::FILE_ACCESS, user_session_file.display(), e);
+            success = false;
+        }
+    }
+    match docker_registry::docker_logout() {
+        Ok(true) => println!("Removed the docker.io login of docker"),
+        Ok(false) => {}
+        Err(e) => warn!("Failed to remove the docker.io login of docker: {}", e),
+    }
+    success
 }
 
+/// Print the verification uri as QR code to the terminal, so that the authorization can be done on a phone.
+/// The code is also stored as PNG in `qr_code_dir`, if given.
+fn show_qr_code(verification_uri: &str, qr_code_dir: Option<&Path>) {
+    let code = match qrcode::QrCode::new(verification_uri.as_bytes()) {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("Failed to create a QR code for the verification uri: {:?}", e);
+            return;
+        }
+    };
+    let text = code.render::<char>().dark_color('█').light_color(' ').module_dimensions(2, 1).build();
+    output::line(&text);
 
-use std::fs::File;
-use indicatif::{ProgressBar, ProgressStyle};
-use std::io::{Read, Write};
-use std::time::Duration;
-use console::{style};
-use std::thread;
+    if let Some(qr_code_dir) = qr_code_dir {
+        let file = qr_code_dir.join("login_qr_code.png");
+        let result = std::fs::create_dir_all(qr_code_dir)
+            .map_err(|e| e.to_string())
+            .and_then(|_| code.render::<image::Luma<u8>>().build().save(&file).map_err(|e| e.to_string()));
+        match result {
+            Ok(_) => info!("QR code stored in {}", file.display()),
+            Err(e) => warn!("Failed to store the QR code in {}: {}", file.display(), e),
+        }
+    }
+}
 
-pub fn perform_login(client: &reqwest::Client) -> Option<UserSession> {
-    let spinner_style = ProgressStyle::default_spinner()
-        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
-        .template("{prefix:.bold.dim} {spinner} {wide_msg}");
+/// The stored session, if any. A session file of an earlier version is moved into the secret store.
+fn read_session() -> Option<UserSession> {
+    if uses_session_file() {
+        return read_session_file();
+    }
+    match keyring::load(&keyring_account()) {
+        Ok(Some(v)) => return serde_json::from_str(&v).ok(),
+        Ok(None) => {}
+        Err(e) => {
+            warn!("The session cannot be read from the secret store: {}", e);
+            return None;
+        }
+    }
+    let session = read_session_file()?;
+    if write_session(&session) {
+        let user_session_file = user_session_file();
+        match std::fs::remove_file(&user_session_file) {
+            Ok(_) => info!("Moved the session of {} into the secret store", user_session_file.display()),
+            Err(e) => warn!("Failed to remove the session file {}: {:?}", user_session_file.display(), e),
+        }
+    }
+    Some(session)
+}
 
-    let user_session_file = dirs::config_dir().expect("config_dir to exist").join(".ohx_login");
+/// Stores the session in the secret store, or the session file with `--insecure-session-file`
+pub(crate) fn write_session(session: &UserSession) -> bool {
+    let data = serde_json::to_string(session).expect("serializable user session");
+    if !uses_session_file() {
+        return match keyring::store(&keyring_account(), &data) {
+            Ok(_) => true,
+            Err(e) => {
+                error!("{} Failed to store the session in the secret store: {}", error_codes::SECRET_STORE, e);
+                false
+            }
+        };
+    }
+    let user_session_file = user_session_file();
+    match File::create(&user_session_file).and_then(|mut f| f.write_all(data.as_bytes())) {
+        Ok(_) => true,
+        Err(_) => {
+            error!("{} Failed to write user session file at {}", error_codes::FILE_ACCESS, user_session_file.display());
+            false
+        }
+    }
+}
 
-    // Read OHX session
+fn read_session_file() -> Option<UserSession> {
     let mut buffer = Vec::new();
-    let session: Option<UserSession> = match File::open(&user_session_file) {
+    match File::open(user_session_file()) {
         Ok(mut f) => {
             match f.read_to_end(&mut buffer) {
                 Ok(_) => {
-                    match serde_json::from_slice(&buffer) {
-                        Ok(v) => Some(v),
-                        Err(_) => None
-                    }
+                    serde_json::from_slice(&buffer).ok()
                 }
                 _ => None
             }
         }
         Err(_) => None
+    }
+}
+
+/// Verifies credentials without storing anything. The given access token is introspected.
+/// Without a token the refresh token of the stored session is exchanged for a new access token.
+pub fn check(client: &reqwest::Client, token: Option<&str>) -> bool {
+    let oauth = oauth_client(client);
+    if let Some(token) = token {
+        let introspection = match oauth.introspect(token) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{} Token introspection failed: {}", error_codes::SESSION_INVALID, e);
+                return false;
+            }
+        };
+        if !introspection.active {
+            error!("{} The token is not active. It is expired or revoked.", error_codes::SESSION_INVALID);
+            return false;
+        }
+        println!("Token is active");
+        if let Some(username) = &introspection.username {
+            println!("User:    {}", username);
+        }
+        println!("Scopes:  {}", introspection.scope.as_deref().unwrap_or("-"));
+        println!("Expires: {}", introspection.exp.map(crate::info::format_timestamp).unwrap_or("-".to_owned()));
+        return true;
+    }
+
+    let session = match read_session() {
+        Some(v) => v,
+        None => {
+            error!("{} You are not logged in. Login or provide a token with --token.", error_codes::NOT_LOGGED_IN);
+            return false;
+        }
     };
+    let refresh_token = match &session.refresh_token {
+        Some(v) => v,
+        None => {
+            error!("{} The stored session has no refresh token. Login again.", error_codes::SESSION_INVALID);
+            return false;
+        }
+    };
+    match oauth.refresh(refresh_token) {
+        Ok(r) => {
+            println!("Session of {} ({}) is valid", session.user_email, session.user_id);
+            println!("Scopes:  {}", r.scope);
+            println!("Expires: {}", crate::info::format_timestamp(chrono::Utc::now().timestamp() + r.expires_in));
+            true
+        }
+        Err(e) => {
+            error!("{} The access token could not be refreshed: {}", error_codes::SESSION_INVALID, e);
+            false
+        }
+    }
+}
+
+/// Refreshes the stored session or starts the device authorization flow.
+/// Without `interactive` only a stored session is accepted, for example in CI pipelines.
+pub fn perform_login(client: &reqwest::Client, qr_code_dir: Option<&Path>, interactive: bool) -> Option<UserSession> {
+    let spinner_style = ProgressStyle::default_spinner()
+        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
+        .template("{prefix:.bold.dim} {spinner} {wide_msg}");
+
+    let oauth = oauth_client(client);
+
+    // Read OHX session
+    let session = read_session();
 
     let session: Option<UserSession> = if let Some(session) = &session {
         if let Some(refresh_token) = &session.refresh_token {
-            println!("{} Getting access token", style("[2/6]").bold().dim());
-            let token_request = TokenRequestForRefreshToken {
-                refresh_token: refresh_token.clone(),
-                client_id: OAUTH_CLIENT_ID.to_string(),
-                grant_type: "refresh_token".to_string(),
-            };
-
-            let r = client.post("https://oauth.openhabx.com/token").form(&token_request).send();
-            if r.is_err() {
-                error!("Failed to contact https://oauth.openhabx.com/token!\n{:?}", r.err().unwrap());
-                return None;
-            }
-            let mut r = r.unwrap();
-            match r.status().as_u16() {
-                400 => {
-                    warn!("Access token could not be refreshed. {}. Login required", &serde_json::from_str::<ErrorResult>(r.text().as_ref().unwrap()).unwrap().error);
-                    None
-                }
-                200 => {
-                    let r: OAuthTokenResponse = r.json().expect("an oauth token response from the /token endpoint");
+            output::line(&format!("{} Getting access token", style("[2/6]").bold().dim()));
+            match oauth.refresh(refresh_token) {
+                Ok(r) => {
                     Some(UserSession {
                         refresh_token: session.refresh_token.clone(),
                         access_token: r.access_token,
@@ -122,9 +294,12 @@ pub fn perform_login(client: &reqwest::Client) -> Option<UserSession> {
                         user_display_name: session.user_display_name.clone(),
                     })
                 }
-                v => {
-                    error!("Unexpected response {} while refreshing access token: {}\nhttps://oauth.openhabx.com/token?auth={}",
-                           v, &r.text().unwrap(), refresh_token);
+                Err(ohx_oauth::OAuthError::Server { status: 400, error }) => {
+                    warn!("Access token could not be refreshed. {}. Login required", &error.error);
+                    None
+                }
+                Err(e) => {
+                    error!("{} Unexpected response while refreshing access token: {}", error_codes::SESSION_INVALID, e);
                     return None;
                 }
             }
@@ -136,147 +311,98 @@ pub fn perform_login(client: &reqwest::Client) -> Option<UserSession> {
         None
     };
 
-    let session: UserSession = if session.is_some() {
-        session.unwrap()
+    let session: UserSession = if let Some(session) = session {
+        session
+    } else if !interactive {
+        match uses_session_file() {
+            true => error!("{} No valid session found at {}. Login on a workstation with --insecure-session-file \
+                            and provide the session file.", error_codes::NOT_LOGGED_IN, user_session_file().display()),
+            false => error!("{} No valid session found in the secret store. Login first, or provide a session file \
+                             with --insecure-session-file.", error_codes::NOT_LOGGED_IN),
+        }
+        return None;
     } else {
-        #[derive(Serialize)]
-        struct AuthRequest {
-            client_id: String,
-            client_name: String,
-            response_type: String,
-            scope: String,
-        };
-        let token_request = AuthRequest {
-            client_id: OAUTH_CLIENT_ID.to_string(),
-            client_name: "OHX Addon Registry CLI".to_string(),
-            response_type: "device".to_string(),
-            scope: "offline_access addons profile".to_string(),
+        let device_flow_response = match oauth.start_device_flow("OHX Addon Registry CLI", SCOPE) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{} Could not start authorisation process: {}", error_codes::LOGIN_FAILED, e);
+                return None;
+            }
         };
-        #[derive(Deserialize)]
-        pub struct DeviceFlowResponse {
-            pub device_code: String,
-            pub user_code: String,
-            pub verification_uri: String,
-            pub interval: u32,
-            pub expires_in: i64,
-        }
 
-        let r = client.post("https://oauth.openhabx.com/authorize").form(&token_request).send();
-        if r.is_err() {
-            error!("Failed to contact https://oauth.openhabx.com/authorize!\n{:?}", r.err().unwrap());
-            return None;
-        }
-        let mut r = r.unwrap();
-        if r.status() != 200 {
-            let message = match r.status().as_u16() {
-                400 => serde_json::from_str::<ErrorResult>(r.text().as_ref().unwrap()).unwrap().error,
-                _ => r.text().expect("a helping error message from /authorize")
-            };
-            error!("Could not start authorisation process: {}", &message);
-            return None;
-        }
-
-        let device_flow_response: DeviceFlowResponse = r.json().expect("a device flow response from /authorize");
-        warn!("Please authorize the CLI to publish Addons on your behalf.\n\tURL: {}", &device_flow_response.verification_uri);
+        warn!("Please authorize the CLI to publish Addons on your behalf.\n\tURL: {}\n\tCode: {}",
+              &device_flow_response.verification_uri, &device_flow_response.user_code);
+        show_qr_code(&device_flow_response.verification_uri, qr_code_dir);
         let _ = webbrowser::open(&device_flow_response.verification_uri);
 
-        let expires_in = chrono::Utc::now().timestamp() + device_flow_response.expires_in;
-
-        let diff = expires_in - chrono::Utc::now().timestamp();
-        info!("Request expires in {} s.", diff);
+        info!("Request expires in {} s.", device_flow_response.expires_in);
 
-        let pb = ProgressBar::new(device_flow_response.expires_in as u64);
+        let pb = output::progress_bar(device_flow_response.expires_in as u64);
         pb.set_style(spinner_style.clone());
         pb.set_prefix("[2/6]");
 
-        let token_response: Option<OAuthTokenResponse> = loop {
-            let diff = expires_in - chrono::Utc::now().timestamp();
-            pb.set_message(&format!("Waiting for authorization! Request expires in {} s.", diff));
-            pb.inc(2);
+        let token_response = oauth.wait_for_device_token(&device_flow_response, &mut |interval, remaining| {
+            pb.set_message(&format!("Waiting for authorization! Request expires in {} s.", remaining));
+            pb.inc(interval.as_secs());
             pb.finish();
-            thread::sleep(Duration::from_secs(2));
-
-            let token_request = TokenRequestForDevice {
-                device_code: device_flow_response.device_code.clone(),
-                client_id: OAUTH_CLIENT_ID.to_string(),
-                grant_type: "urn:ietf:params:oauth:grant-type:device_code".to_string(),
-            };
-            let response = client.post("https://oauth.openhabx.com/token").form(&token_request).send();
-            if response.is_err() {
-                error!("Failed to contact https://oauth.openhabx.com/token!\n{:?}", response.err().unwrap());
-                return None;
-            }
-            let mut response = response.unwrap();
-            match response.status().as_u16() {
-                200 => {
-                    let r = response.text().unwrap();
-                    let r: OAuthTokenResponse = serde_json::from_str(&r).unwrap();
-                    break (Some(r));
-                }
-                400 => {
-                    let response = ErrorResult::from(response.text().unwrap());
-                    if &response.error != "authorization_pending" {
-                        error!("Server response: {}", &response.error);
-                        break (None);
-                    }
-                }
-                _ => {
-                    error!("Server response: {}", &response.text().unwrap());
-                    break (None);
-                }
-            };
-            if diff < 0 {
-                error!("Request expired");
-                break (None);
-            }
-        };
+            thread::sleep(interval);
+        });
 
         pb.finish_with_message("done!");
 
-        if token_response.is_none() {
-            return None;
-        }
-        let token_response = token_response.unwrap();
-
-        // get user information if possible
-        let user_data: FirebaseAuthUser = match client.get("https://oauth.openhabx.com/userinfo").bearer_auth(&token_response.access_token).send() {
-            Ok(mut response) => {
-                let response_text = response.text().unwrap();
-                let response: Result<FirebaseAuthUser, _> = serde_json::from_str(&response_text);
-                if let Ok(response) = response {
-                    response
-                } else {
-                    error!("Unexpected response userinfo response!\n{:?}\nRaw: {}", response.err().unwrap(), response_text);
-                    return None;
-                }
-            }
-            Err(err) => {
-                error!("Failed to contact https://oauth.openhabx.com/userinfo!\n{:?}", err);
-                return None;
-            }
-        };
-
-        let user_session = UserSession {
-            refresh_token: token_response.refresh_token.clone(),
-            access_token: token_response.access_token,
-            access_token_expires: chrono::Utc::now().timestamp() + token_response.expires_in - 10,
-            user_id: user_data.localId.unwrap_or_default(),
-            user_email: user_data.email.unwrap_or_default(),
-            user_display_name: user_data.displayName.unwrap_or_default(),
-        };
-
-        match File::create(&user_session_file) {
-            Ok(mut f) => {
-                let _ = f.write_all(&serde_json::to_vec(&user_session).expect("write user session to disk"));
-            }
-            Err(_) => {
-                error!("Failed to write user session file at {}", user_session_file.to_str().unwrap());
+        let token_response = match token_response {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{} Server response: {}", error_codes::LOGIN_FAILED, e);
                 return None;
             }
         };
+        let user_session = user_session(&oauth, token_response)?;
+        if !write_session(&user_session) {
+            return None;
+        }
 
         user_session
     };
 
     Some(session)
-}
\ No newline at end of file
+}
+
+/// The session of a token response, with the user information
+fn user_session(oauth: &OAuthClient, token_response: OAuthTokenResponse) -> Option<UserSession> {
+    let user_data = match oauth.userinfo(&token_response.access_token) {
+        Ok(v) => v,
+        Err(err) => {
+            error!("{} Failed to get the user information from {}!\n{}", error_codes::LOGIN_FAILED, oauth.url("userinfo"), err);
+            return None;
+        }
+    };
+    Some(UserSession {
+        refresh_token: token_response.refresh_token,
+        access_token: token_response.access_token,
+        access_token_expires: chrono::Utc::now().timestamp() + token_response.expires_in - 10,
+        user_id: user_data.localId.unwrap_or_default(),
+        user_email: user_data.email.unwrap_or_default(),
+        user_display_name: user_data.displayName.unwrap_or_default(),
+    })
+}
+
+/// Logs in with username and password via the password grant, for CI systems without a browser.
+/// The session is not stored, see [`write_session`].
+pub fn password_login(client: &reqwest::Client, username: &str, password: &str) -> Option<UserSession> {
+    let oauth = oauth_client(client);
+    output::line(&format!("{} Getting access token for {}", style("[2/6]").bold().dim(), username));
+    let token_response = match oauth.password_token(username, password, SCOPE) {
+        Ok(v) => v,
+        Err(ohx_oauth::OAuthError::Server { error, .. }) => {
+            error!("{} The login of {} was rejected: {}", error_codes::LOGIN_FAILED, username,
+                   error.error_description.as_ref().unwrap_or(&error.error));
+            return None;
+        }
+        Err(e) => {
+            error!("{} Unexpected response of the password login: {}", error_codes::LOGIN_FAILED, e);
+            return None;
+        }
+    };
+    user_session(&oauth, token_response)
+}
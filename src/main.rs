@@ -4,94 +4,678 @@ pub mod dto;
 mod login;
 mod registry;
 mod docker_registry;
+mod buildah;
+mod http;
+mod podman;
+mod wasm;
+mod oras;
+mod search;
+mod info;
+mod provenance;
+mod init;
+mod image_checks;
+mod image_fs;
+mod elf;
+mod stats;
+mod list;
+mod output;
+mod config;
+mod state;
+mod hook;
+mod build_cache;
+mod watch;
+mod cleanup;
+mod error_codes;
+mod report;
+mod bundle;
+mod auth;
+mod endpoints;
+mod security;
+mod warnings;
+mod cache;
+mod policy;
+mod progress;
+mod snapshot;
+mod namespace;
+mod external_images;
+mod validation_report;
+mod storage;
+mod input_discovery;
+mod workspace;
+mod convert;
+mod permissions;
+mod links;
+mod keyring;
+mod dashboard;
 
 use structopt::StructOpt;
 use std::path::PathBuf;
+use std::collections::BTreeMap;
 
-use serde::{Deserialize, Serialize};
 use dto::{addons,BuildInstruction};
+use ohx_addon_publish::pipeline::{Outcome, Pipeline};
+use docker_registry::ContainerEngine;
+use error_codes::Exit;
 
 use log::{info, debug, warn, error};
 use env_logger::Env;
 
 use console::{style, Emoji};
-use std::str::FromStr;
 
 pub static LOOKING_GLASS: Emoji<'_, '_> = Emoji("🔍  ", "");
 pub static PAPER: Emoji<'_, '_> = Emoji("📃  ", "");
 pub static SPARKLE: Emoji<'_, '_> = Emoji("✨ ", ":-)");
 
 // as of https://github.com/containerd/containerd/blob/master/platforms/platforms.go#L88
-const ALLOWED_ARCHITECTURES: [&str; 4] = ["aarch64", "armhf", "i386", "amd64"];
+const ALLOWED_ARCHITECTURES: [&str; 5] = ["aarch64", "armhf", "armv6", "i386", "amd64"];
 
 #[derive(Debug, StructOpt)]
 #[structopt(author, about)]
 struct Opt {
     /// Verbose mode (-v, -vv, -vvv, etc.)
-    #[structopt(short, long, parse(from_occurrences))]
+    #[structopt(short, long, parse(from_occurrences), global = true)]
     verbose: u8,
 
+    /// The TLS backend: "rustls" or "native". Only backends that are compiled in are available.
+    #[structopt(long, global = true)]
+    tls_backend: Option<http::TlsBackend>,
+
+    /// Enforce FIPS compliant crypto. Requires the native TLS backend and a system in FIPS mode.
+    #[structopt(long, global = true)]
+    fips: bool,
+
+    /// Settings for CI pipelines: No interactive login, plain progress output without colors, JSON output
+    /// unless --output is given, all image checks fail the build, http requests time out after 60 seconds
+    /// and OAuth requests and registry mutations are retried 5 times.
+    #[structopt(long, global = true)]
+    ci: bool,
+
+    /// "human" or "json". With json, validate, build and publish print a final JSON document with the validation
+    /// result, the per-architecture build and upload state, image digests and the registry response to stdout.
+    /// Progress messages go to stderr. Defaults to json with --ci and to human otherwise.
+    #[structopt(long, global = true, possible_values = &output::OUTPUT_FORMATS)]
+    output: Option<output::OutputFormat>,
+
+    /// A profile of the configuration file, like "staging", with its own OAuth, registry and vault urls
+    /// and docker namespace. A separate session is stored per profile.
+    #[structopt(long, global = true, env = "OHX_PROFILE")]
+    profile: Option<String>,
+
+    /// The registry server. Overrides the configuration file and the profile, like the other endpoint flags.
+    #[structopt(long, global = true, env = "OHX_REGISTRY_URL")]
+    registry_url: Option<String>,
+
+    /// The OAuth server for login
+    #[structopt(long, global = true, env = "OHX_OAUTH_URL")]
+    oauth_url: Option<String>,
+
+    /// The vault with the docker credentials of the addons
+    #[structopt(long, global = true, env = "OHX_VAULT_URL")]
+    vault_url: Option<String>,
+
+    /// The registry index file with the latest version of every addon
+    #[structopt(long, global = true, env = "OHX_INDEX_URL")]
+    index_url: Option<String>,
+
+    /// The registry stats file with downloads and ratings
+    #[structopt(long, global = true, env = "OHX_STATS_URL")]
+    stats_url: Option<String>,
+
+    /// The proxy for all http requests and the container engine, like "http://proxy.example.com:3128".
+    /// Replaces HTTP_PROXY and HTTPS_PROXY. Hosts in NO_PROXY are reached directly.
+    #[structopt(long, global = true)]
+    proxy: Option<String>,
+
+    /// No login, registry refresh or upload. Validates against the last cached registry index and builds locally.
+    /// `publish` stops after the build and prints what would be uploaded.
+    #[structopt(long, global = true)]
+    offline: bool,
+
+    /// Store the login session in a plain file of the configuration directory instead of the secret store
+    /// of the platform. For build servers without a secret store.
+    #[structopt(long, global = true)]
+    insecure_session_file: bool,
+
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Validate the addons.yml file and the Dockerfiles next to it
+    Validate(ValidateOpt),
+    /// Migrate an addons.yml file of an older schema version to the current one. Prints the changes.
+    Migrate {
+        /// The input addon description file, or a glob that matches one file
+        #[structopt(short, long, parse(from_os_str), default_value = input_discovery::DEFAULT_FILE)]
+        input_file: PathBuf,
+        /// Rewrite the file. Comments and formatting are not preserved.
+        #[structopt(long)]
+        write: bool,
+    },
+    /// Validate and build the software containers locally
+    Build(BuildOpt),
+    /// Validate and build locally whenever a file of the addon directory changes
+    Watch {
+        #[structopt(flatten)]
+        build: BuildOpt,
+        /// Only validate, do not build the software containers
+        #[structopt(long)]
+        no_build: bool,
+    },
+    /// Validate, build, upload the software containers and publish the addon to the registry
+    Publish(PublishOpt),
+    /// Rebuild the published version from the committed sources with fresh base images, push the images
+    /// and update the registry entry. The version gets a build metadata suffix like "1.2.0+rebuild.20200101120000".
+    /// Meant to run from cron.
+    Rebuild(RebuildOpt),
+    /// Validate and build, or publish, all addons of a repository. Every directory below the root
+    /// with an addons.yml file is an addon.
+    Workspace(WorkspaceCommand),
+    /// Print the registry payload in its canonical JSON form, as it would be published.
+    /// Sizes are taken from already build local images.
+    Payload(PayloadOpt),
+    /// Login, store the session token and exit
+    Login {
+        #[structopt(flatten)]
+        login: LoginOpt,
+        /// Only verify the credentials and print scopes and expiry. Nothing is stored.
+        #[structopt(long)]
+        check: bool,
+        /// An access token to verify with --check, for example a CI secret
+        #[structopt(long, env = "OHX_TOKEN", hide_env_values = true, requires = "check")]
+        token: Option<String>,
+    },
+    /// Revoke the refresh token, remove the stored session and the docker hub login that publish stored for docker
+    Logout,
+    /// Search the addon registry. Check this before picking an addon id to avoid collisions.
+    Search(SearchOpt),
+    /// Create an addons.yml file and Dockerfiles for the chosen architectures in the current directory
+    Init {
+        /// Overwrite existing files
+        #[structopt(long)]
+        force: bool,
+    },
+    /// Create a starting addons.yml from a docker-compose file. Lists what is not converted.
+    Convert {
+        /// The compose file
+        #[structopt(long, parse(from_os_str), default_value = "docker-compose.yml")]
+        from: PathBuf,
+        /// The addons.yml file to write. Next to the compose file by default.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Overwrite an existing file
+        #[structopt(long)]
+        force: bool,
+    },
+    /// Browse the permission catalogue and pick the permissions of a service.
+    /// The selection is written into the permissions section of the service.
+    Permissions {
+        /// The input addon description file, or a glob that matches one file
+        #[structopt(short, long, parse(from_os_str), default_value = input_discovery::DEFAULT_FILE)]
+        input_file: PathBuf,
+        /// The service to pick the permissions for. Asked for if the file has several.
+        #[structopt(long)]
+        service: Option<String>,
+        /// Only show permissions with this text in their id, label or description
+        #[structopt(long, default_value = "")]
+        filter: String,
+        /// Only print the catalogue, nothing is picked or written
+        #[structopt(long)]
+        list: bool,
+    },
+    /// Remove a published version of one of your addons from the registry
+    Unpublish {
+        addon_id: String,
+        version: String,
+        /// Do not ask for confirmation
+        #[structopt(long)]
+        yes: bool,
+        #[structopt(flatten)]
+        login: LoginOpt,
+    },
+    /// Show the complete registry information of an addon
+    Info {
+        addon_id: String,
+        /// Print the information as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Inspect the configuration
+    Config(ConfigCommand),
+    /// Manage git hooks that validate changed addons.yml files
+    Hook(HookCommand),
+    /// Inspect or clear the cached http responses, validation results and registry index
+    Cache(CacheCommand),
+    /// Dated snapshots of the registry index and the changes between them
+    Index(IndexCommand),
+    /// Pin the validation policy data of the registry, like the addon type profiles
+    Policy(PolicyCommand),
+    /// List your published addons
+    List(LoginOpt),
+    /// Explain an error code like OHX-E012: Causes and remedies. Lists all codes if no code is given.
+    Explain {
+        code: Option<String>,
+    },
+    /// Delete the images and sandbox entries that sandbox and failed publishes left behind
+    CleanupRemote {
+        /// The build directory with the state file of the publishes
+        #[structopt(long, default_value = "out")]
+        build_directory: PathBuf,
+        /// Only print what would be deleted
+        #[structopt(long)]
+        dry_run: bool,
+        #[structopt(flatten)]
+        login: LoginOpt,
+    },
+    /// Check a downloaded addon bundle against the registry before sideloading it: Image digests, signatures,
+    /// SBOM and the addon description
+    VerifyBundle {
+        file: PathBuf,
+    },
+    /// Vulnerability checks of published addons
+    Security(SecurityCommand),
+    /// Show downloads, ratings and issues of your addons
+    Stats {
+        /// Sort by: id, downloads, votes, rating, issues
+        #[structopt(long, default_value = "downloads")]
+        sort: stats::StatsSort,
+        /// Print the statistics as JSON
+        #[structopt(long)]
+        json: bool,
+        #[structopt(flatten)]
+        login: LoginOpt,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum SecurityCommand {
+    /// Look up the packages of the SBOM of the published version in the OSV vulnerability database.
+    /// Fails if vulnerabilities that became known after the publish reach the --fail-on severity.
+    Check {
+        addon_id: String,
+        /// low, medium, high or critical. Vulnerabilities without severity are only reported.
+        #[structopt(long, default_value = "high")]
+        fail_on: security::Severity,
+        /// Also fail for vulnerabilities that were known when the version was published
+        #[structopt(long)]
+        all: bool,
+        /// The OSV API, for example a mirror
+        #[structopt(long, default_value = security::OSV_URL)]
+        osv_url: String,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum WorkspaceCommand {
+    /// Validate and build all addons. Each addon has its own build directory below the build directory.
+    /// --input-file is not used.
+    Build {
+        /// The repository directory
+        #[structopt(long, parse(from_os_str), default_value = ".")]
+        root: PathBuf,
+        #[structopt(flatten)]
+        build: BuildOpt,
+    },
+    /// Build and publish the addons whose version is not published yet
+    Publish {
+        /// The repository directory
+        #[structopt(long, parse(from_os_str), default_value = ".")]
+        root: PathBuf,
+        #[structopt(flatten)]
+        publish: PublishOpt,
+        /// Show a live grid of the addons and their publish stages instead of the output of each addon.
+        /// The log of an addon can be selected after the run.
+        #[structopt(long)]
+        dashboard: bool,
+        /// Write the stages, result and log of each addon as JSON to this file after a --dashboard run
+        #[structopt(long, parse(from_os_str), requires = "dashboard")]
+        dashboard_export: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum HookCommand {
+    /// Install the hook. An existing hook script is kept and extended.
+    Install {
+        /// The git hook
+        #[structopt(long, default_value = "pre-commit", possible_values = &hook::HOOK_NAMES)]
+        hook: String,
+    },
+    /// Remove the validation from the hook
+    Uninstall {
+        /// The git hook
+        #[structopt(long, default_value = "pre-commit", possible_values = &hook::HOOK_NAMES)]
+        hook: String,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum CacheCommand {
+    /// Print the number of entries, the size and the oldest entry of each cache
+    Stats,
+    /// Remove all cached entries
+    Clear,
+}
+
+#[derive(Debug, StructOpt)]
+enum PolicyCommand {
+    /// Verify the signed policy manifest of the registry and pin the digests of the policy files in .ohx-policy.lock
+    Update {
+        /// Accept a changed registry key
+        #[structopt(long)]
+        trust_new_key: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum IndexCommand {
+    /// Store the current registry index as the snapshot of today, for example nightly from cron
+    Snapshot,
+    /// Show the addons that were added, updated, removed or changed their status since the snapshot of a date
+    Diff {
+        /// Like 2020-01-31. Without a snapshot of that day, the snapshot before is used.
+        #[structopt(parse(try_from_str = snapshot::parse_date))]
+        date: chrono::NaiveDate,
+        /// Compare with the snapshot of this date instead of the newest snapshot
+        #[structopt(long, parse(try_from_str = snapshot::parse_date))]
+        to: Option<chrono::NaiveDate>,
+        /// Print the changes as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum ConfigCommand {
+    /// Print the configuration values that are not defaults
+    Show {
+        /// Print all values, including defaults, and the source of each value
+        #[structopt(long)]
+        resolved: bool,
+    },
+    /// Print the effective value of a key
+    Get {
+        key: String,
+    },
+    /// Set a key in ~/.config/ohx/cli.toml. Without a value the key is removed.
+    /// Keys: build-directory, registry-url, archs (comma separated), engine, verbose
+    Set {
+        key: String,
+        value: Option<String>,
+        /// Write to .ohxcli.toml in the current directory instead. Its values win over the user file.
+        #[structopt(long)]
+        project: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct ValidateOpt {
+    #[structopt(flatten)]
+    input: InputOpt,
+
+    /// Correct recoverable mistakes in the input file in place and report each fix, then validate
+    #[structopt(long)]
+    fix: bool,
+
+    /// Print the validated addon description, with all files of `extends` merged and defaults filled in
+    #[structopt(long)]
+    emit_normalized: bool,
+
+    /// "human", or a report of all issues with rule, severity and position on stdout: "json" or "sarif"
+    #[structopt(long, default_value = "human", possible_values = &validation_report::FORMATS, conflicts_with = "emit_normalized")]
+    format: validation_report::Format,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct InputOpt {
+    /// The input addon description file, or a glob that matches one file. addons.yaml, ohx-addon.yml and ohx-addon.yaml
+    /// are found without this flag.
+    #[structopt(short, long, parse(from_os_str), default_value = input_discovery::DEFAULT_FILE)]
+    input_file: PathBuf,
+
+    /// Only build and publish the given architectures. Can be given multiple times. All architectures by default.
+    #[structopt(long = "arch", possible_values = &ALLOWED_ARCHITECTURES)]
+    archs: Vec<String>,
+
+    /// Fail if more warnings are logged. Warnings covered by an `# ohx-allow:` annotation in addons.yml do not count.
+    #[structopt(long)]
+    max_warnings: Option<usize>,
+
+    /// Turn lint warnings into errors, like missing translations or unpinned base images. Implied by --ci.
+    #[structopt(long)]
+    strict: bool,
+
+    /// Languages that need a translated title and description, like "de,fr". BCP-47 language tags.
+    #[structopt(long = "required-langs", use_delimiter = true)]
+    required_langs: Vec<String>,
+
+    /// Warn if `memory_max` of x-runtime is above this many MiB
+    #[structopt(long, default_value = "2048")]
+    memory_warning: i64,
+
+    /// Override a value of the addon description before validation, like `--set services.app.ports[0]=8080:8080`.
+    /// Can be given multiple times.
+    #[structopt(long = "set", parse(try_from_str = dto::overrides::parse))]
+    overrides: Vec<(String, String)>,
+
+    /// A dotenv file with values for `${VAR}` references in the addon description.
+    /// Variables of the environment take precedence.
+    #[structopt(long, parse(from_os_str))]
+    env_file: Option<PathBuf>,
+
+    /// Fail if a `${VAR}` reference has no value and no default, instead of replacing it by an empty string
+    #[structopt(long)]
+    require_variables: bool,
+
+    /// Request the homepage, github and changelog_url links and fail on 404 Not Found or 410 Gone
+    #[structopt(long)]
+    online_checks: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct BuildOpt {
+    #[structopt(flatten)]
+    input: InputOpt,
+
     /// Build directory. All intermediate build artifacts including the generated software containers
     /// are stored in here. Just delete this directory to perform a clean build.
     #[structopt(short, long, parse(from_os_str), default_value = "out")]
     build_directory: PathBuf,
 
-    /// The input addon description file.
-    #[structopt(short, long, parse(from_os_str), default_value = "addons.yml")]
-    input_file: PathBuf,
+    /// Skip images that the last run already build or pushed, according to the state in the build directory
+    #[structopt(long)]
+    resume: bool,
+
+    /// Fail instead of warn for the given image check. Can be given multiple times. Checks: root-user
+    #[structopt(long)]
+    deny: Vec<image_checks::Deny>,
+
+    /// Fail if an image is larger than this many MiB. Images above 500 MiB are a warning without this flag.
+    #[structopt(long)]
+    max_image_size: Option<u64>,
 
-    /// Only validate the addons.yml file and exit
+    /// The container engine. Detected if not given: podman is preferred, then docker and buildah.
+    #[structopt(long, possible_values = &docker_registry::ENGINES)]
+    engine: Option<String>,
+
+    /// Build on a remote machine, for example with a real ARM CPU. The build context is transferred by the engine.
+    /// podman: ssh://builder@host/run/user/1000/podman/podman.sock, docker: ssh://builder@host
     #[structopt(long)]
-    validate_only: bool,
+    remote_host: Option<String>,
+
+    /// Pull base images through this registry mirror or pull-through cache, like harbor.local/dockerhub-proxy.
+    /// Can be given multiple times, mirrors are tried in order. podman and buildah only.
+    #[structopt(long = "mirror")]
+    mirrors: Vec<String>,
 
-    /// Only login, store the session token and exit
-    #[structopt(long, short)]
-    login_only: bool,
+    /// Restore the build directory from this storage and store it afterwards, for ephemeral CI runners.
+    /// A directory, s3://bucket/prefix or webdav+https://host/path
+    #[structopt(long, env = "OHX_BUILD_STORAGE")]
+    build_storage: Option<String>,
 
-    /// Logout, remove the session token and exit
+    /// Export the build images as OCI archives to images/<arch>.tar in the build directory, the image layout
+    /// of an addon bundle. podman and buildah only.
     #[structopt(long)]
-    logout: bool,
+    oci_archive: bool,
+}
 
-    /// Your https://openhabx.com username / email address. This is only used if you are not logged in yet.
+#[derive(Debug, Clone, StructOpt)]
+struct LoginOpt {
+    /// Your https://openhabx.com username / email address. Logs in with the password instead of the stored
+    /// session or the device authorization.
     #[structopt(long, short, env = "OHX_USERNAME")]
     username: Option<String>,
 
-    /// Your https://openhabx.com password. This is only used if you are not logged in yet.
-    /// Pass this via stdin or environment variable.
-    #[structopt(env = "OHX_PASSWORD")]
+    /// Your https://openhabx.com password, for a login without browser via the OAuth password grant.
+    /// Pass this via environment variable, or "-" to read it from stdin. Asked for if only the username is given.
+    #[structopt(long, env = "OHX_PASSWORD", hide_env_values = true)]
     password: Option<String>,
 }
 
-fn main() {
-    let client = reqwest::Client::new();
+#[derive(Debug, Clone, StructOpt)]
+struct PublishOpt {
+    #[structopt(flatten)]
+    build: BuildOpt,
 
-    // Parse command line and setup logger
-    let opt = Opt::from_args();
-    let level = match opt.verbose {
-        0 => "warn",
-        1 => "info",
-        _ => "debug"
-    };
-    env_logger::from_env(Env::default().default_filter_or(level)).default_format_timestamp(false).init();
-    debug!("{:?}", opt);
+    #[structopt(flatten)]
+    login: LoginOpt,
 
+    /// Do not record the git commit, tag, remote url and dirty state of the addon directory in the registry
+    #[structopt(long)]
+    no_provenance: bool,
 
-    // Read in yaml file and validate
-    let input_file_name: PathBuf = opt.input_file;
-    let input_file_name_str = input_file_name.to_str().unwrap();
-    println!("{} Validating input file {}", style("[1/6]").bold().dim(), input_file_name_str);
-    let input_file = match addons::open_validate_addons_file(input_file_name_str) {
-        Ok(v) => v,
-        Err(e) => {
-            match e.downcast::<std::io::Error>() {
-                Ok(_) => error!("{} Did not find the addon description file: {}!", LOOKING_GLASS, input_file_name_str),
-                Err(e) => error!("Input file validation failed!\n{:?}", e)
-            };
-            return;
+    /// Skip the checks against the registry index before building. The index is not downloaded.
+    #[structopt(long)]
+    skip_publish_checks: bool,
+
+    /// Check that the `image:` of each service without a build exists in its registry for all published architectures
+    #[structopt(long)]
+    check_images: bool,
+
+    /// Validate and build locally, then print what would be uploaded instead of pushing and publishing
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Build and push all images, even if the build inputs did not change since the last push of this version
+    #[structopt(long)]
+    no_build_cache: bool,
+
+    /// Do not build and upload. The images must already be pushed, for example by a CI pipeline.
+    /// Only the registry entry is updated.
+    #[structopt(long, conflicts_with = "dry_run")]
+    skip_build: bool,
+
+    /// Trial publish: Push the images under a throwaway sandbox tag and post to the sandbox registry,
+    /// where entries expire automatically. The public listing is not affected.
+    #[structopt(long, conflicts_with_all = &["dry_run", "skip_build"])]
+    sandbox: bool,
+
+    /// Publish even if the registry already has the same or a higher version of the addon
+    #[structopt(long)]
+    allow_republish: bool,
+
+    /// Post each stage and the result to the registry, so that the website shows the publish status live.
+    /// Registry servers without publish jobs are skipped.
+    #[structopt(long)]
+    report_progress: bool,
+}
+
+/// The reasons for `rebuild`
+const REBUILD_REASONS: [&str; 1] = ["base-image-update"];
+
+#[derive(Debug, StructOpt)]
+struct RebuildOpt {
+    #[structopt(flatten)]
+    publish: PublishOpt,
+
+    /// Why the version is rebuild. base-image-update: The base images received (security) fixes.
+    #[structopt(long, possible_values = &REBUILD_REASONS)]
+    reason: String,
+}
+
+#[derive(Debug, StructOpt)]
+struct PayloadOpt {
+    #[structopt(flatten)]
+    input: InputOpt,
+
+    /// Only print the sha256 digest of the canonical payload
+    #[structopt(long)]
+    hash: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct SearchOpt {
+    /// Search term. Matches the addon id, title, description and products.
+    pub(crate) term: Option<String>,
+
+    /// A "key=value" filter. Can be given multiple times, all filters must match.
+    /// Keys: id, license, type, status, arch, author, owner, manufacturer
+    #[structopt(long = "filter")]
+    pub(crate) filters: Vec<dto::search::AddonFilter>,
+
+    /// Sort order: id, title, updated, downloads, rating
+    #[structopt(long, default_value = "id")]
+    pub(crate) sort: dto::search::AddonSort,
+}
+
+/// A validated addon description and the images to build
+struct Addon {
+    input_file_name: PathBuf,
+    input_file: addons::AddonFileEntry,
+    build_instructions: Vec<BuildInstruction>,
+    /// Published with the registry payload
+    suppressions: Vec<dto::lint::Suppression>,
+}
+
+impl Addon {
+    /// The architectures of all images that are build, of all variants
+    fn published_archs(&self) -> Vec<String> {
+        let mut archs: Vec<String> = self.build_instructions.iter().filter(|b| !b.skipped).map(|b| b.arch.clone()).collect();
+        archs.sort();
+        archs.dedup();
+        archs
+    }
+
+    fn directory(&self) -> &std::path::Path {
+        self.input_file_name.parent().unwrap()
+    }
+}
+
+/// Prints the canonical registry payload or its digest
+fn print_payload(addon: &mut Addon, hash: bool) {
+    let cli = docker_registry::installed_binary().map(docker_registry::EngineCli::local);
+    for build_instruction in addon.build_instructions.iter_mut() {
+        if let Some(size) = cli.as_ref().and_then(|cli| docker_registry::inspect_image_size(cli, &build_instruction.image_name)) {
+            build_instruction.image_size = size;
         }
-    };
+    }
+    let mut payload = registry::create_payload(&addon.build_instructions, &addon.input_file,
+                                               &wasm::artifact_references(&addon.input_file), Vec::new());
+    payload.provenance = provenance::detect(addon.directory());
+    payload.suppressions = addon.suppressions.clone();
+    if hash {
+        match registry::payload_digest(&payload) {
+            Ok(digest) => println!("{}", digest),
+            Err(e) => error!("{} Failed to serialize the payload: {:?}", error_codes::INTERNAL, e),
+        }
+    } else {
+        match addons::to_canonical_json(&payload) {
+            Ok(json) => println!("{}", String::from_utf8_lossy(&json)),
+            Err(e) => error!("{} Failed to serialize the payload: {:?}", error_codes::INTERNAL, e),
+        }
+    }
+}
 
+/// Determine the images to build from the Dockerfiles next to the addons.yml file and the build recipes
+/// Architectures that are not in `archs` are marked as skipped. An empty filter includes all architectures.
+fn discover_build_instructions(input_file_name: &std::path::Path, input_file: &addons::AddonFileEntry, archs: &[String]) -> Vec<BuildInstruction> {
     let mut build_instructions: Vec<BuildInstruction> = Vec::new();
+    // The Dockerfiles of all services are build with the secrets of all services
+    let secrets: BTreeMap<String, String> = input_file.services.values()
+        .filter_map(|s| s.build.as_ref())
+        .flat_map(|b| b.secrets.clone())
+        .collect();
 
     for entry in input_file_name.parent().unwrap().read_dir().unwrap() {
         let entry = entry.unwrap();
@@ -107,104 +691,967 @@ fn main() {
                 if !ALLOWED_ARCHITECTURES.contains(&arch) {
                     warn!("A Dockerfile architecture is not supported: {}", arch);
                 } else {
+                    let skipped = !archs.is_empty() && !archs.iter().any(|a| a == arch);
                     build_instructions.push(BuildInstruction {
                         arch: arch.to_owned(),
-                        image_name: format!("docker.io/openhabx/{}_{}:{}", &input_file.x_ohx_registry.id, arch, &input_file.x_ohx_registry.version),
+                        image_name: endpoints::image_name(&format!("{}_{}", &input_file.x_ohx_registry.id, arch), &input_file.x_ohx_registry.version),
                         filename,
                         build: false,
                         uploaded: false,
                         image_size: 0,
+                        digest: None,
+                        recipe: None,
+                        skipped,
+                        content_hash: None,
+                        variant: None,
+                        build_args: BTreeMap::new(),
+                        pull: false,
+                        secrets: secrets.clone(),
                     });
                 }
             }
         }
     }
 
-    if build_instructions.len() == 0 {
-        error!("No Dockerfiles found in {}. Cannot build Addon.\nPlease check the documentation or clone one the scaffolding repositories for working examples.",
-               input_file_name.parent().unwrap().to_str().unwrap());
-        return;
+    // Every Dockerfile is build once per variant, with the variant tag suffix
+    if !input_file.x_variants.is_empty() {
+        build_instructions = build_instructions.into_iter().flat_map(|b| {
+            input_file.x_variants.iter().map(move |(name, variant)| BuildInstruction {
+                image_name: endpoints::image_name(&format!("{}_{}", &input_file.x_ohx_registry.id, b.arch),
+                                                  &format!("{}{}", &input_file.x_ohx_registry.version, variant.tag_suffix(name))),
+                filename: b.filename.clone(),
+                arch: b.arch.clone(),
+                build: false,
+                uploaded: false,
+                image_size: 0,
+                digest: None,
+                recipe: None,
+                skipped: b.skipped,
+                content_hash: None,
+                variant: Some(name.clone()),
+                build_args: variant.build_args.clone(),
+                pull: b.pull,
+                secrets: b.secrets.clone(),
+            }).collect::<Vec<_>>()
+        }).collect();
     }
 
-    if opt.validate_only {
-        return;
+    // Services that are build from a recipe in addons.yml
+    for (service_id, service) in &input_file.services {
+        if let Some(recipe) = service.build.as_ref().and_then(|b| b.recipe.as_ref()) {
+            if !input_file.x_variants.is_empty() {
+                warn!("Recipe of service {} is only build for the default variant. Recipes have no build arguments.", service_id);
+            }
+            for arch in &recipe.archs {
+                if !ALLOWED_ARCHITECTURES.contains(&arch.as_str()) {
+                    warn!("A recipe architecture is not supported: {}", arch);
+                } else if build_instructions.iter().any(|b| &b.arch == arch) {
+                    warn!("Recipe of service {} ignored for {}. A Dockerfile exists for this architecture.", service_id, arch);
+                } else {
+                    build_instructions.push(BuildInstruction {
+                        arch: arch.to_owned(),
+                        image_name: endpoints::image_name(&format!("{}_{}", &input_file.x_ohx_registry.id, arch), &input_file.x_ohx_registry.version),
+                        filename: format!("recipe of {}", service_id),
+                        build: false,
+                        uploaded: false,
+                        image_size: 0,
+                        digest: None,
+                        recipe: Some(recipe.clone()),
+                        skipped: !archs.is_empty() && !archs.contains(arch),
+                        content_hash: None,
+                        variant: None,
+                        build_args: BTreeMap::new(),
+                        pull: false,
+                        secrets: BTreeMap::new(),
+                    });
+                }
+            }
+        }
     }
 
-    let session = login::perform_login(&client);
-    if session.is_none() {
-        return;
+    build_instructions
+}
+
+/// Step 1: Read in the yaml file, validate it and determine the images to build
+/// Applies `dto::fix` to the input file and writes it back, if anything was fixed.
+/// Comments and formatting of the file are not preserved.
+fn fix_input_file(input_file: &std::path::Path) -> bool {
+    let mut data: serde_yaml::Value = match std::fs::read(input_file).map_err(failure::Error::from)
+        .and_then(|v| serde_yaml::from_slice(&v).map_err(failure::Error::from)) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} Failed to read {}: {}", error_codes::FILE_ACCESS, input_file.display(), e);
+            return false;
+        }
+    };
+    let fixes = dto::fix::fix_addons_file(&mut data);
+    if fixes.is_empty() {
+        output::line(&format!("Nothing to fix in {}", input_file.display()));
+        return true;
     }
-    let session = session.unwrap();
-    info!("You are logged in as {} ({})", session.user_email, &session.user_id);
+    let written = serde_yaml::to_string(&data).map_err(failure::Error::from)
+        .and_then(|v| std::fs::write(input_file, v).map_err(failure::Error::from));
+    if let Err(e) = written {
+        error!("{} Failed to write {}: {}", error_codes::FILE_ACCESS, input_file.display(), e);
+        return false;
+    }
+    for fix in &fixes {
+        output::line(&format!("Fixed {}", fix));
+    }
+    warn!("{} was rewritten. Comments and formatting are not preserved.", input_file.display());
+    true
+}
 
-    if opt.login_only {
-        return;
+/// Migrates the file to the current schema version and prints the changes. The file is only rewritten with `write`.
+fn migrate_input_file(input_file: &std::path::Path, write: bool) -> bool {
+    let mut data: serde_yaml::Value = match std::fs::read(input_file).map_err(failure::Error::from)
+        .and_then(|v| serde_yaml::from_slice(&v).map_err(failure::Error::from)) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} Failed to read {}: {}", error_codes::FILE_ACCESS, input_file.display(), e);
+            return false;
+        }
+    };
+    let from = dto::migrate::version(&data);
+    let changes = match dto::migrate::migrate(&mut data) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} {}:schema_version: {}", error_codes::INPUT_FILE_INVALID, input_file.display(), e);
+            return false;
+        }
+    };
+    if from == Ok(dto::migrate::CURRENT_SCHEMA_VERSION) {
+        output::line(&format!("{} is already at schema version {}", input_file.display(), dto::migrate::CURRENT_SCHEMA_VERSION));
+        return true;
+    }
+    for change in &changes {
+        output::line(&format!("Migrated {}", change));
+    }
+    if !write {
+        output::line(&format!("Run with --write to update {} to schema version {}", input_file.display(), dto::migrate::CURRENT_SCHEMA_VERSION));
+        return true;
     }
+    let written = serde_yaml::to_string(&data).map_err(failure::Error::from)
+        .and_then(|v| std::fs::write(input_file, v).map_err(failure::Error::from));
+    if let Err(e) = written {
+        error!("{} Failed to write {}: {}", error_codes::FILE_ACCESS, input_file.display(), e);
+        return false;
+    }
+    warn!("{} was rewritten. Comments and formatting are not preserved.", input_file.display());
+    true
+}
 
-    println!("{} {} Updating registry index", style("[3/6]").bold().dim(), PAPER);
-    let registry = registry::addon_registry(&client);
-    if registry.is_none() {
-        return;
+fn validate(opt: &InputOpt) -> Option<Addon> {
+    let input_file_name: PathBuf = opt.input_file.clone();
+    let input_file_name_str = input_file_name.to_str().unwrap();
+    output::line(&format!("{} Validating input file {}", style("[1/6]").bold().dim(), input_file_name_str));
+    let mut variables = BTreeMap::new();
+    if let Some(env_file) = &opt.env_file {
+        let parsed = std::fs::read_to_string(env_file).map_err(|e| e.to_string())
+            .and_then(|text| dto::interpolate::parse_env_file(&text));
+        match parsed {
+            Ok(v) => variables = v,
+            Err(e) => {
+                error!("{} Failed to read the env file {}: {}", error_codes::FILE_ACCESS, env_file.display(), e);
+                return None;
+            }
+        }
     }
-    let _registry = registry.unwrap();
+    variables.extend(std::env::vars());
+    // Unchanged files are not validated again
+    let options = addons::ValidationOptions { type_profiles: registry::type_profiles(), permissions: registry::permissions(), required_languages: opt.required_langs.clone(),
+        memory_warning: opt.memory_warning, overrides: opt.overrides.clone(), variables, require_variables: opt.require_variables };
+    let cache_key = cache::validation_key(input_file_name_str, &options);
+    let cached = cache_key.as_ref().and_then(|key| cache::validated_addons_file(key));
+    let validated = match cached.map(Ok).unwrap_or_else(|| addons::validate_addons_file_with(input_file_name_str, &options)) {
+        Ok(v) => {
+            if let Some(key) = &cache_key {
+                cache::put(cache::VALIDATION, key, &v);
+            }
+            v
+        }
+        Err(e) => {
+            report::update(|r| r.valid = Some(false));
+            let e = match e.downcast::<dto::schema::ValidationIssues>() {
+                Ok(validation) => {
+                    report::update(|r| r.issues = validation.issues.clone());
+                    warnings::reset(Vec::new());
+                    for issue in &validation.issues {
+                        match issue.is_error() {
+                            true => error!("{} {}:{}", error_codes::INPUT_FILE_INVALID, input_file_name_str, issue),
+                            false => warnings::report_issue(input_file_name_str, issue),
+                        }
+                    }
+                    let errors = validation.issues.iter().filter(|i| i.is_error()).count();
+                    output::line(&format!("{} errors in {}", errors, input_file_name_str));
+                    return None;
+                }
+                Err(e) => e,
+            };
+            match e.downcast::<std::io::Error>() {
+                Ok(_) => error!("{} {} Did not find the addon description file: {}!", error_codes::INPUT_FILE_NOT_FOUND, LOOKING_GLASS, input_file_name_str),
+                Err(e) => error!("{} Input file validation failed!\n{}", error_codes::INPUT_FILE_INVALID, e)
+            };
+            return None;
+        }
+    };
 
-    // Check for docker file
-    // Check for podman executable
-    println!("{} Checking podman", style("[3/6]").bold().dim());
+    report::update(|r| r.issues = validated.warnings.clone());
+    warnings::reset(validated.suppressions.clone());
+    for warning in &validated.warnings {
+        warnings::report_issue(input_file_name_str, warning);
+    }
+    if !warnings::within_budget(opt.max_warnings, opt.strict) {
+        report::update(|r| r.valid = Some(false));
+        return None;
+    }
+    let input_file = validated.data;
+    if opt.online_checks && !links::check(&input_file.x_ohx_registry) {
+        report::update(|r| r.valid = Some(false));
+        return None;
+    }
+    if wasm::validate_modules(&input_file, input_file_name.parent().unwrap()).is_none() {
+        report::update(|r| r.valid = Some(false));
+        return None;
+    }
+    report::update(|r| {
+        r.valid = Some(true);
+        r.addon_id = Some(input_file.x_ohx_registry.id.clone());
+        r.version = Some(input_file.x_ohx_registry.version.clone());
+    });
+    let build_instructions = discover_build_instructions(&input_file_name, &input_file, &opt.archs);
 
-    #[derive(Serialize, Deserialize)]
-    struct PodmanVersionResult {
-        #[serde(rename = "Version")]
-        version: String
+    if build_instructions.is_empty() && !wasm::has_wasm_services(&input_file) {
+        error!("{} No Dockerfiles or build recipes found in {}. Cannot build Addon.\nPlease check the documentation or clone one the scaffolding repositories for working examples.",
+               error_codes::NO_BUILD_INSTRUCTIONS, input_file_name.parent().unwrap().to_str().unwrap());
+        return None;
+    }
+    if !build_instructions.is_empty() && build_instructions.iter().all(|b| b.skipped) {
+        error!("{} None of the architectures {} is provided by a Dockerfile or build recipe", error_codes::NO_BUILD_INSTRUCTIONS, opt.archs.join(", "));
+        return None;
     }
 
-    let version: Result<PodmanVersionResult, _> = std::process::Command::new("podman")
-        .arg("version")
-        .arg("--format")
-        .arg("json")
-        .output()
-        .and_then(|f| serde_json::from_slice(&f.stdout).map_err(|o| std::io::Error::from(o)));
+    Some(Addon { input_file_name, input_file, build_instructions, suppressions: validated.suppressions })
+}
 
-    if let Err(version) = version {
-        error!("'podman' is required to build software containers. Please check https://podman.io/getting-started/installation. {:?}", version);
-        return;
+/// Check for a container engine (podman, docker or buildah) and its features
+fn check_engine(addon: &Addon, opt: &BuildOpt) -> Option<Box<dyn ContainerEngine>> {
+    output::line(&format!("{} Checking container engine", style("[3/6]").bold().dim()));
+    let remote_host = opt.remote_host.as_deref();
+    let engine = docker_registry::detect_engine(opt.engine.as_deref(), remote_host)?;
+    if !opt.mirrors.is_empty() && !docker_registry::use_mirrors(engine.as_ref(), &opt.mirrors, &opt.build_directory, remote_host.is_some()) {
+        return None;
+    }
+    let recipes = addon.build_instructions.iter().any(|b| b.recipe.is_some() && !b.skipped);
+    if recipes && remote_host.is_some() {
+        error!("{} Build recipes are build with the local buildah. Build them without --remote-host or select the other architectures with --arch.",
+               error_codes::REMOTE_HOST);
+        return None;
+    }
+    if recipes && !buildah::available() {
+        error!("{} 'buildah' is required to build images from a recipe. Please check https://github.com/containers/buildah/blob/master/install.md.", error_codes::ENGINE_MISSING);
+        return None;
     }
+    if !engine.build_secrets() && addon.build_instructions.iter().any(|b| !b.secrets.is_empty() && b.recipe.is_none() && !b.skipped) {
+        error!("{} {} does not support the build.secrets of addons.yml. Update it, or use docker with buildx.",
+               error_codes::ENGINE_FEATURE, engine.cli().binary);
+        return None;
+    }
+    if opt.oci_archive && !engine.oci_archive() {
+        error!("{} {} can not export OCI archives for --oci-archive. Use podman or buildah.", error_codes::ENGINE_FEATURE, engine.cli().binary);
+        return None;
+    }
+    Some(engine)
+}
 
-    let podman_version = semver::Version::from_str(&version.unwrap().version).unwrap();
+/// The password of `--password`, read from stdin for "-" or asked for on the terminal
+fn login_password(username: &str, password: Option<&String>) -> Option<String> {
+    match password.map(|v| v.as_str()) {
+        Some("-") => {
+            let mut line = String::new();
+            match std::io::stdin().read_line(&mut line) {
+                Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_owned()),
+                Err(e) => {
+                    error!("{} Failed to read the password from stdin: {}", error_codes::LOGIN_FAILED, e);
+                    None
+                }
+            }
+        }
+        Some(v) => Some(v.to_owned()),
+        None if output::is_interactive() => {
+            let term = console::Term::stderr();
+            let _ = term.write_str(&format!("Password of {}: ", username));
+            term.read_secure_line().map_err(|e| error!("{} Failed to read the password: {}", error_codes::LOGIN_FAILED, e)).ok()
+        }
+        None => {
+            error!("{} The login of {} requires --password or OHX_PASSWORD", error_codes::NOT_INTERACTIVE, username);
+            None
+        }
+    }
+}
 
-    if podman_version < semver::Version::new(1, 5, 0) {
-        error!("'podman' 1.5.0 or better is required. Please check https://podman.io/getting-started/installation.");
-    } else {
-        info!("Found Podman version {}", podman_version);
+/// Login. The QR code of the device authorization is stored in `build_directory`, if given.
+/// With `--username` the OAuth password grant is used instead. Its session is not stored.
+fn login(client: &reqwest::Client, auth: &dyn auth::Authenticator, opt: &LoginOpt, build_directory: Option<&std::path::Path>) -> Option<login::UserSession> {
+    let session = match &opt.username {
+        Some(username) if auth.name() == "oauth-device" => {
+            let password = login_password(username, opt.password.as_ref())?;
+            login::password_login(client, username, &password)?
+        }
+        None if opt.password.is_some() => {
+            error!("{} --password requires --username", error_codes::LOGIN_FAILED);
+            return None;
+        }
+        Some(_) => {
+            warn!("A username / password login is only supported by oauth-device. Using {}.", auth.name());
+            auth.login(client, build_directory, output::is_interactive())?
+        }
+        None => auth.login(client, build_directory, output::is_interactive())?,
+    };
+    match session.user_id.is_empty() {
+        true => info!("Authenticated with {}", auth.name()),
+        false => info!("You are logged in as {} ({})", session.user_email, &session.user_id),
     }
+    Some(session)
+}
 
-    // Get docker access credentials
-    let docker_creds = docker_registry::get_access_credentials(&client,&session);
-    if docker_creds.is_none() {
-        return;
+/// The state that the stages of `build` and `publish` share
+struct Run {
+    /// Set by the validate stage
+    addon: Option<Addon>,
+    engine: Option<Box<dyn ContainerEngine>>,
+    runtime: Option<tokio::runtime::Runtime>,
+    /// The build directory of this run. Sandbox publishes have their own.
+    build_directory: PathBuf,
+    registry_index: Option<registry::LazyRegistry>,
+    session: Option<login::UserSession>,
+    credentials: Option<std::thread::JoinHandle<Option<String>>>,
+    docker_credentials: Option<String>,
+    sandbox: Option<String>,
+    wasm_artifacts: BTreeMap<String, String>,
+    referrers: Vec<addons::AttachmentReferrer>,
+    /// Pushed images that are remnants until the registry entry is published
+    remote: Vec<state::RemoteArtifact>,
+}
+
+impl Run {
+    fn new(build_directory: &std::path::Path) -> Self {
+        Run { addon: None, engine: None, runtime: None, build_directory: build_directory.to_path_buf(), registry_index: None,
+            session: None, credentials: None, docker_credentials: None, sandbox: None, wasm_artifacts: BTreeMap::new(),
+            referrers: Vec::new(), remote: Vec::new() }
     }
-    let docker_creds = docker_creds.unwrap();
+}
+
+const VALIDATED: &str = "validated addon";
+
+/// The exit category of a pipeline run: The category of the stage that failed or did not run anymore
+fn stage_exit(outcome: &Outcome) -> Result<(), Exit> {
+    let stage = match outcome {
+        Outcome::Completed => return Ok(()),
+        Outcome::Failed(stage) | Outcome::Cancelled(stage) => *stage,
+    };
+    Err(match stage {
+        "login" | "docker-credentials" => Exit::Auth,
+        "engine" | "prepare" | "build" | "image-checks" => Exit::Build,
+        "pushed-images" | "upload" | "wasm" | "attachments" => Exit::Upload,
+        "publish-checks" | "registry" => Exit::Registry,
+        _ => Exit::Validation,
+    })
+}
+
+/// An addon that the caller already validated, like `workspace publish` does, is not validated again
+fn validate_stage(run: &mut Run, opt: &InputOpt) -> Option<()> {
+    if run.addon.is_none() {
+        run.addon = Some(validate(opt)?);
+    }
+    Some(())
+}
+
+/// A wasm-only addon has no images and does not need a container engine. The image stages skip it.
+fn engine_stage(run: &mut Run, opt: &BuildOpt) -> Option<()> {
+    let addon = run.addon.as_ref().expect(VALIDATED);
+    if addon.build_instructions.is_empty() {
+        info!("No container images to build");
+        return Some(());
+    }
+    run.engine = Some(check_engine(addon, opt)?);
+    Some(())
+}
+
+/// Builds the wasm modules and the images. The docker credentials of a publish are used to pull base images of the
+/// addon namespace. With `--oci-archive` the build images are exported.
+fn build_stage(run: &mut Run, opt: &BuildOpt) -> Option<()> {
+    let addon = run.addon.as_mut().expect(VALIDATED);
+    wasm::build_modules(&addon.input_file, addon.directory())?;
+    let engine = match &run.engine {
+        Some(engine) => engine.as_ref(),
+        None => return Some(()),
+    };
     let runtime = tokio::runtime::Runtime::new().expect("Unable to start the runtime");
+    docker_registry::build_images(&runtime, engine, run.docker_credentials.as_deref(),
+                                  &mut addon.build_instructions, &addon.input_file_name);
+    run.runtime = Some(runtime);
+    if opt.oci_archive {
+        let images = run.build_directory.join("images");
+        if let Err(e) = std::fs::create_dir_all(&images) {
+            error!("{} Failed to create {}: {}", error_codes::FILE_ACCESS, images.display(), e);
+            return None;
+        }
+        for build_instruction in addon.build_instructions.iter().filter(|b| b.build && !b.skipped) {
+            let file = images.join(format!("{}.tar", build_instruction.key()));
+            if !docker_registry::save_oci_archive(engine, &build_instruction.image_name, &file) {
+                return None;
+            }
+        }
+    }
+    Some(())
+}
+
+fn image_checks_stage(run: &mut Run, opt: &BuildOpt) -> Option<()> {
+    let addon = run.addon.as_mut().expect(VALIDATED);
+    let cli = match &run.engine {
+        Some(engine) => engine.cli(),
+        None => return Some(()),
+    };
+    let images_valid = image_checks::check_images(cli, &addon.input_file, &mut addon.build_instructions, &opt.deny)
+        & image_checks::check_sizes(&addon.build_instructions, opt.max_image_size);
+    state::save(&run.build_directory, &addon.input_file, &addon.build_instructions);
+    report::archs(&addon.build_instructions);
+    if !images_valid || !warnings::within_budget(opt.input.max_warnings, opt.input.strict) {
+        return None;
+    }
+    Some(())
+}
 
-    docker_registry::build_images(&runtime,&docker_creds, &mut build_instructions,&input_file_name);
-    docker_registry::upload_images(&runtime,&docker_creds, &mut build_instructions,
-                                   &input_file_name);
+/// Build all images locally. Nothing is uploaded. `validated` is the addon of `opt`, if the caller validated it.
+fn build(opt: &BuildOpt, validated: Option<Addon>) -> Result<Addon, Exit> {
+    let mut run = Run::new(&opt.build_directory);
+    run.addon = validated;
+    let outcome = Pipeline::new()
+        .stage("validate", |run: &mut Run| validate_stage(run, &opt.input))
+        .stage("engine", |run: &mut Run| {
+            engine_stage(run, opt)?;
+            if let (true, Some(engine)) = (opt.resume, &run.engine) {
+                let addon = run.addon.as_mut().expect(VALIDATED);
+                state::resume(engine.cli(), &opt.build_directory, &addon.input_file, &mut addon.build_instructions);
+            }
+            Some(())
+        })
+        .stage("build", |run: &mut Run| build_stage(run, opt))
+        .stage("image-checks", |run: &mut Run| image_checks_stage(run, opt))
+        .run(&mut run);
+    stage_exit(&outcome)?;
+    let addon = run.addon.expect(VALIDATED);
+    print_summary(&addon);
+    Ok(addon)
+}
 
-    println!("{} Upload to registry", style("[6/6]").bold().dim());
-    if !registry::post_to_registry(&client, &mut build_instructions,&input_file,&session) {
-        return;
+/// Fails if the addon id is registered by another account, so that the registry does not reject
+/// the addon after the build. Sessions without user, like those of a static token, are not checked.
+fn ownership_check(registry_index: &mut registry::LazyRegistry, addon: &Addon, session: &login::UserSession) -> Option<()> {
+    let index = registry_index.get()?;
+    let id = &addon.input_file.x_ohx_registry.id;
+    match index.get(id) {
+        Some(entry) if !session.user_id.is_empty() && entry.owner != session.user_id => {
+            error!("{} The addon id {} is already taken by {}. Pick another id, `search` shows the taken ones.",
+                   error_codes::NOT_OWNER, id, entry.owner);
+            None
+        }
+        _ => Some(()),
+    }
+}
+
+/// Checks the addon against the registry index before anything is build.
+/// Only a version higher than the published one can be published, unless `allow_republish` is set.
+fn publish_checks(registry_index: &mut registry::LazyRegistry, addon: &Addon, allow_republish: bool) -> Option<()> {
+    let index = registry_index.get()?;
+    let common = &addon.input_file.x_ohx_registry;
+    let entry = match index.get(&common.id) {
+        Some(v) => v,
+        None => {
+            info!("{} is a new addon", common.id);
+            return Some(());
+        }
+    };
+    info!("{} is published in version {} by {}", common.id, entry.entry.version, entry.owner);
+    // Build metadata, like the suffix of rebuilds, is ignored
+    let version = match semver::Version::parse(&common.version) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} The version {} is not a semantic version: {}", error_codes::INPUT_FILE_INVALID, common.version, e);
+            return None;
+        }
+    };
+    match semver::Version::parse(&entry.entry.version) {
+        Ok(published) if version <= published && !allow_republish => {
+            error!("{} {} is published in version {}. Increase the version of addons.yml {} or use --allow-republish.",
+                   error_codes::VERSION_NOT_NEWER, common.id, entry.entry.version, common.version);
+            return None;
+        }
+        Ok(published) if version <= published => warn!("Republishing {} {} over version {}", common.id, common.version, entry.entry.version),
+        Ok(_) => {}
+        Err(e) => warn!("The published version {} of {} is not a semantic version: {}", entry.entry.version, common.id, e),
+    }
+    Some(())
+}
+
+/// Build like `publish` does, but only print the images and the registry payload that would be uploaded.
+/// No login is required.
+fn publish_dry_run(client: &reqwest::Client, opt: &PublishOpt, validated: Option<Addon>) -> Result<(), Exit> {
+    let addon = build(&opt.build, validated)?;
+    match (opt.check_images, http::is_offline()) {
+        (true, true) => warn!("Offline: The service images are not checked"),
+        (true, false) if !external_images::check(client, &addon.input_file, &addon.published_archs()) => return Err(Exit::Validation),
+        _ => {}
+    }
+
+    output::line("\nImages that would be pushed:");
+    for build_instruction in addon.build_instructions.iter().filter(|b| b.build) {
+        output::line(&format!("  {} ({})", build_instruction.image_name, info::format_size(build_instruction.image_size)));
+    }
+    let wasm_artifacts = wasm::artifact_references(&addon.input_file);
+    for (service_id, reference) in &wasm_artifacts {
+        output::line(&format!("  {} (wasm module of {})", reference, service_id));
+    }
+
+    let mut payload = registry::create_payload(&addon.build_instructions, &addon.input_file, &wasm_artifacts, Vec::new());
+    payload.suppressions = addon.suppressions.clone();
+    if !opt.no_provenance {
+        payload.provenance = provenance::detect(addon.directory());
+    }
+    output::line("\nRegistry payload that would be published:");
+    match serde_json::to_string_pretty(&payload) {
+        Ok(json) => output::line(&json),
+        Err(e) => {
+            error!("{} Failed to serialize the payload: {:?}", error_codes::INTERNAL, e);
+            return Err(error_codes::INTERNAL.exit);
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the version by the published version with a new build metadata suffix, for `rebuild`.
+/// Base images are pulled again.
+fn rebuild_version(registry_index: &mut registry::LazyRegistry, addon: &mut Addon, archs: &[String], reason: &str) -> Option<()> {
+    let common = &addon.input_file.x_ohx_registry;
+    let published = match registry_index.get()?.get(&common.id) {
+        Some(entry) => entry.entry.version.clone(),
+        None => {
+            error!("{} {} is not published yet. Use publish.", error_codes::REBUILD_NOT_POSSIBLE, common.id);
+            return None;
+        }
+    };
+    // Earlier rebuilds already have a build metadata suffix
+    if published.split('+').next() != Some(common.version.as_str()) {
+        error!("{} {} is published in version {}, but addons.yml has version {}. Only the published version can be rebuild.",
+               error_codes::REBUILD_NOT_POSSIBLE, common.id, published, common.version);
+        return None;
+    }
+    match provenance::detect(addon.directory()) {
+        Some(provenance) if provenance.dirty => {
+            error!("{} The addon directory has uncommitted changes. Rebuilds use the committed sources of the published version.",
+                   error_codes::REBUILD_NOT_POSSIBLE);
+            return None;
+        }
+        Some(_) => {}
+        None => warn!("The addon directory is not within a git repository. The sources of the rebuild cannot be verified."),
+    }
+
+    let version = format!("{}+rebuild.{}", common.version, chrono::Utc::now().format("%Y%m%d%H%M%S"));
+    output::line(&format!("Rebuilding {} {} as {} ({})", common.id, published, version, reason));
+    addon.input_file.x_ohx_registry.version = version.clone();
+    addon.build_instructions = discover_build_instructions(&addon.input_file_name, &addon.input_file, archs);
+    for build_instruction in addon.build_instructions.iter_mut() {
+        build_instruction.pull = true;
+    }
+    report::update(|r| r.version = Some(version));
+    Some(())
+}
+
+/// Validate, build, push and publish. `rebuild` is the reason of a rebuild of the published version.
+fn publish(client: &reqwest::Client, auth: &dyn auth::Authenticator, opt: &PublishOpt, rebuild: Option<&str>,
+           mut validated: Option<Addon>) -> Result<(), Exit> {
+    if rebuild.is_some() && (opt.dry_run || opt.skip_build) {
+        error!("{} A rebuild always builds and pushes. --dry-run and --skip-build are not supported.", error_codes::REBUILD_NOT_POSSIBLE);
+        return Err(error_codes::REBUILD_NOT_POSSIBLE.exit);
+    }
+    if http::is_offline() {
+        if opt.skip_build {
+            error!("{} --skip-build checks the pushed images in the docker registry", error_codes::OFFLINE);
+            return Err(error_codes::OFFLINE.exit);
+        }
+        if !opt.skip_publish_checks {
+            let addon = match validated.take() {
+                Some(v) => v,
+                None => validate(&opt.build.input).ok_or(Exit::Validation)?,
+            };
+            publish_checks(&mut registry::LazyRegistry::new(client), &addon, opt.allow_republish).ok_or(Exit::Registry)?;
+            validated = Some(addon);
+        }
+        warn!("Offline: Nothing is uploaded");
+        return publish_dry_run(client, opt, validated);
+    }
+    if opt.dry_run {
+        return publish_dry_run(client, opt, validated);
+    }
+    let mut run = Run::new(&opt.build.build_directory);
+    run.addon = validated;
+    let mut pipeline = Pipeline::new()
+        .reporter(progress::JobReporter)
+        .reporter(dashboard::StageReporter)
+        .stage("validate", |run: &mut Run| validate_stage(run, &opt.build.input))
+        .stage("login", |run: &mut Run| {
+            // The registry index does not depend on the login and is fetched in the background
+            let mut registry_index = registry::LazyRegistry::new(client);
+            if !opt.skip_publish_checks || rebuild.is_some() {
+                output::line(&format!("{} {} Updating registry index", style("[3/6]").bold().dim(), PAPER));
+                registry_index.prefetch();
+            }
+            run.registry_index = Some(registry_index);
+
+            let session = login(client, auth, &opt.login, Some(&opt.build.build_directory))?;
+            let common = &run.addon.as_ref().expect(VALIDATED).input_file.x_ohx_registry;
+            if opt.report_progress {
+                progress::start(client, &session, &common.id, &common.version, opt.sandbox);
+            }
+            // Get docker access credentials while checking the local container engine
+            let (client, session_clone, addon_id, vault_url) = (client.clone(), session.clone(), common.id.clone(), endpoints::vault_url());
+            run.credentials = Some(std::thread::spawn(move || docker_registry::get_access_credentials(&client, &vault_url, &session_clone, &addon_id)));
+            run.session = Some(session);
+            Some(())
+        });
+    if !opt.skip_build {
+        pipeline = pipeline.stage("engine", |run: &mut Run| engine_stage(run, &opt.build));
+    }
+    pipeline = pipeline.stage("publish-checks", |run: &mut Run| {
+        let addon = run.addon.as_mut().expect(VALIDATED);
+        let registry_index = run.registry_index.as_mut().expect("registry index");
+        if !opt.skip_publish_checks {
+            ownership_check(registry_index, addon, run.session.as_ref().expect("session"))?;
+            // A rebuild republishes the published version
+            publish_checks(registry_index, addon, opt.allow_republish || rebuild.is_some())?;
+        }
+        if let Some(reason) = rebuild {
+            rebuild_version(registry_index, addon, &opt.build.input.archs, reason)?;
+        }
+        Some(())
+    });
+    if opt.check_images {
+        pipeline = pipeline.stage("image-references", |run: &mut Run| {
+            let addon = run.addon.as_ref().expect(VALIDATED);
+            if !external_images::check(client, &addon.input_file, &addon.published_archs()) {
+                return None;
+            }
+            Some(())
+        });
+    }
+    pipeline = pipeline.stage("docker-credentials", |run: &mut Run| {
+        let credentials = run.credentials.take().expect("vault credentials thread");
+        run.docker_credentials = Some(credentials.join().expect("vault credentials thread")?);
+        Some(())
+    });
+    pipeline = match opt.skip_build {
+        true => pipeline.stage("pushed-images", |run: &mut Run| check_pushed_images(client, run)),
+        false => pipeline
+            .stage("prepare", |run: &mut Run| prepare_stage(run, opt, rebuild))
+            .stage("build", |run: &mut Run| build_stage(run, &opt.build))
+            .stage("image-checks", |run: &mut Run| image_checks_stage(run, &opt.build))
+            .stage("upload", |run: &mut Run| upload_stage(client, run, opt))
+            .stage("wasm", |run: &mut Run| wasm_stage(run, opt))
+            .stage("attachments", |run: &mut Run| {
+                let addon = run.addon.as_ref().expect(VALIDATED);
+                run.referrers = oras::attach_files(&addon.input_file, &addon.build_instructions, addon.directory(),
+                                                   &run.build_directory, run.docker_credentials.as_ref().expect("docker credentials"))?;
+                Some(())
+            }),
+    };
+    let outcome = pipeline
+        .stage("registry", |run: &mut Run| {
+            publish_payload(client, opt, run.addon.as_ref().expect(VALIDATED), &run.wasm_artifacts, run.referrers.clone(),
+                            run.session.as_ref().expect("session"), rebuild)
+        })
+        .run(&mut run);
+    let published = outcome.is_completed();
+
+    let Run { addon, mut remote, .. } = run;
+    match (published, opt.sandbox) {
+        (true, true) => {
+            let addon = addon.expect(VALIDATED);
+            let common = &addon.input_file.x_ohx_registry;
+            remote.push(state::RemoteArtifact::SandboxEntry { addon_id: common.id.clone(), version: common.version.clone() });
+            state::track_remote(&opt.build.build_directory, &remote);
+        }
+        (true, false) => state::untrack_remote(&opt.build.build_directory, &remote, false),
+        (false, _) if !remote.is_empty() => {
+            warn!("The pushed images are not published. Run 'cleanup-remote' to delete them.");
+        }
+        _ => {}
+    }
+    stage_exit(&outcome)
+}
+
+/// Sandbox runs keep their own build state, so that a later publish does not take over sandbox images.
+/// Every run has a new sandbox id, so nothing is taken over from earlier sandbox runs either.
+fn prepare_stage(run: &mut Run, opt: &PublishOpt, rebuild: Option<&str>) -> Option<()> {
+    let addon = run.addon.as_mut().expect(VALIDATED);
+    if opt.sandbox {
+        let sandbox = docker_registry::sandbox_id();
+        for build_instruction in addon.build_instructions.iter_mut() {
+            build_instruction.image_name = docker_registry::sandbox_reference(&build_instruction.image_name, &sandbox);
+        }
+        info!("Sandbox publish {}. Images are tagged with the sandbox id.", sandbox);
+        run.build_directory = opt.build.build_directory.join("sandbox");
+        run.sandbox = Some(sandbox);
+    }
+    // A rebuild has the same build inputs, but new base images
+    if !opt.no_build_cache && run.sandbox.is_none() && rebuild.is_none() {
+        let addon_dir = addon.directory().to_path_buf();
+        state::skip_unchanged(&run.build_directory, &addon.input_file, &addon_dir, &mut addon.build_instructions);
+    }
+    if let (true, None, Some(engine)) = (opt.build.resume, &run.sandbox, &run.engine) {
+        state::resume(engine.cli(), &run.build_directory, &addon.input_file, &mut addon.build_instructions);
+    }
+    Some(())
+}
+
+/// Records pushed images or wasm artifacts for `cleanup-remote`. They are remnants until the registry entry
+/// is published, also if a later stage fails.
+fn track_pushed(remote: &mut Vec<state::RemoteArtifact>, build_directory: &std::path::Path, addon_id: &str, references: Vec<String>) {
+    let artifacts: Vec<state::RemoteArtifact> = references.into_iter()
+        .map(|reference| state::RemoteArtifact::Image { addon_id: addon_id.to_owned(), reference })
+        .collect();
+    state::track_remote(build_directory, &artifacts);
+    remote.extend(artifacts);
+}
+
+/// Fails if an image was not pushed. The pushed ones are tracked, and `--resume` only retries the others.
+fn upload_stage(client: &reqwest::Client, run: &mut Run, opt: &PublishOpt) -> Option<()> {
+    if run.engine.is_none() {
+        return Some(());
+    }
+    let addon = run.addon.as_mut().expect(VALIDATED);
+    let session = run.session.as_ref().expect("session");
+    let addon_id = addon.input_file.x_ohx_registry.id.clone();
+    docker_registry::upload_images(run.runtime.as_ref().expect("runtime"), run.engine.as_ref().expect("container engine").as_ref(),
+                                   run.docker_credentials.as_mut().expect("docker credentials"), &mut addon.build_instructions,
+                                   &addon.input_file_name, &run.build_directory,
+                                   &|| docker_registry::get_access_credentials(client, &endpoints::vault_url(), session, &addon_id));
+    state::save(&run.build_directory, &addon.input_file, &addon.build_instructions);
+    report::archs(&addon.build_instructions);
+    let pushed = addon.build_instructions.iter().filter(|b| b.uploaded).map(|b| b.image_name.clone()).collect();
+    track_pushed(&mut run.remote, &opt.build.build_directory, &addon_id, pushed);
+    if addon.build_instructions.iter().any(|b| b.build && !b.skipped && !b.uploaded) {
+        return None;
+    }
+    let lists = docker_registry::push_manifest_lists(run.engine.as_ref().expect("container engine").as_ref(),
+                                                     run.docker_credentials.as_ref().expect("docker credentials"),
+                                                     &addon.build_instructions)?;
+    track_pushed(&mut run.remote, &opt.build.build_directory, &addon_id, lists);
+    Some(())
+}
+
+fn wasm_stage(run: &mut Run, opt: &PublishOpt) -> Option<()> {
+    let addon = run.addon.as_ref().expect(VALIDATED);
+    run.wasm_artifacts = wasm::push_modules(&addon.input_file, addon.directory(), &run.build_directory,
+                                            run.docker_credentials.as_ref().expect("docker credentials"),
+                                            run.sandbox.as_deref())?;
+    let pushed = run.wasm_artifacts.values().cloned().collect();
+    track_pushed(&mut run.remote, &opt.build.build_directory, &addon.input_file.x_ohx_registry.id, pushed);
+    Some(())
+}
+
+/// For images that are already pushed. The images are required to exist in the docker registry.
+fn check_pushed_images(client: &reqwest::Client, run: &mut Run) -> Option<()> {
+    output::line(&format!("{} {} Checking pushed images", style("[5/6]").bold().dim(), LOOKING_GLASS));
+    let addon = run.addon.as_mut().expect(VALIDATED);
+    let docker_creds = run.docker_credentials.as_ref().expect("docker credentials");
+    let cli = docker_registry::installed_binary().map(docker_registry::EngineCli::local);
+    for build_instruction in addon.build_instructions.iter_mut().filter(|b| !b.skipped) {
+        match docker_registry::remote_manifest_digest(client, &build_instruction.image_name, docker_creds) {
+            Ok(Some(digest)) => {
+                build_instruction.uploaded = true;
+                build_instruction.digest = Some(digest).filter(|d| !d.is_empty());
+            }
+            Ok(None) => {
+                error!("{} Image {} not found in the docker registry. Push it or publish without --skip-build.", error_codes::NOT_FOUND, build_instruction.image_name);
+                return None;
+            }
+            Err(e) => {
+                error!("{} Failed to check image {}: {:?}", error_codes::REMOTE_IMAGE_CHECK, build_instruction.image_name, e);
+                return None;
+            }
+        }
+        // Sizes are only known if the image is also available locally
+        if let Some(size) = cli.as_ref().and_then(|cli| docker_registry::inspect_image_size(cli, &build_instruction.image_name)) {
+            build_instruction.image_size = size;
+        }
+    }
+    run.wasm_artifacts = wasm::artifact_references(&addon.input_file);
+    Some(())
+}
+
+fn publish_payload(client: &reqwest::Client, opt: &PublishOpt, addon: &Addon, wasm_artifacts: &BTreeMap<String, String>,
+                   referrers: Vec<addons::AttachmentReferrer>, session: &login::UserSession, rebuild: Option<&str>) -> Option<()> {
+    output::line(&format!("{} Upload to registry", style("[6/6]").bold().dim()));
+    let mut payload = registry::create_payload(&addon.build_instructions, &addon.input_file, wasm_artifacts, referrers);
+    payload.rebuild_reason = rebuild.map(|v| v.to_owned());
+    payload.suppressions = addon.suppressions.clone();
+    if !opt.no_provenance {
+        payload.provenance = provenance::detect(addon.directory());
+    }
+    if !registry::post_to_registry(client, payload, session, opt.sandbox, &opt.build.build_directory) {
+        return None;
+    }
+
+    print_summary(addon);
+    if opt.sandbox {
+        output::line("Published to the sandbox registry. The entry and the sandbox images expire automatically.");
+    }
+    Some(())
+}
+
+/// Ask the user to confirm with "yes"
+fn confirm(question: &str) -> bool {
+    if !output::is_interactive() {
+        error!("{} {} Confirmation required, but the terminal is not interactive. Use --yes.", error_codes::NOT_INTERACTIVE, question);
+        return false;
+    }
+    let term = console::Term::stdout();
+    let _ = term.write_str(&format!("{} Type 'yes' to confirm: ", question));
+    term.read_line().map(|answer| answer.trim() == "yes").unwrap_or(false)
+}
+
+fn unpublish(client: &reqwest::Client, auth: &dyn auth::Authenticator, addon_id: &str, version: &str, yes: bool,
+             login_opt: &LoginOpt) -> Result<(), Exit> {
+    if !yes && !confirm(&format!("Unpublish version {} of {}? Users will not be able to install it anymore.", version, addon_id)) {
+        println!("Aborted");
+        return Err(Exit::Validation);
+    }
+    let session = login(client, auth, login_opt, None).ok_or(Exit::Auth)?;
+    // Unanswered requests are tracked in the default build directory
+    if !registry::unpublish(client, &session, addon_id, version, std::path::Path::new("out")) {
+        return Err(Exit::Registry);
+    }
+    println!("{} Unpublished version {} of {}", SPARKLE, version, addon_id);
+    Ok(())
+}
+
+/// The options for one addon of a workspace: Its description file and own build directory
+fn workspace_build_opt(opt: &BuildOpt, root: &std::path::Path, input_file: &std::path::Path) -> BuildOpt {
+    let mut opt = opt.clone();
+    opt.build_directory = workspace::build_directory(&opt.build_directory, root, input_file);
+    opt.input.input_file = input_file.to_path_buf();
+    opt
+}
+
+fn workspace_outcome(input_file: &std::path::Path, addon: Option<&Addon>, status: workspace::Status) -> workspace::Outcome {
+    workspace::Outcome {
+        input_file: input_file.display().to_string(),
+        id: addon.map(|a| a.input_file.x_ohx_registry.id.clone()),
+        version: addon.map(|a| a.input_file.x_ohx_registry.version.clone()),
+        status,
+    }
+}
+
+/// Builds all addons of the workspace and prints a summary of all of them
+/// Fails with the category of the first failed addon.
+fn workspace_build(root: &std::path::Path, opt: &BuildOpt) -> Result<(), Exit> {
+    let files = workspace::discover(root).ok_or(Exit::Validation)?;
+    let mut first_failure = None;
+    let outcomes: Vec<workspace::Outcome> = files.iter().map(|input_file| {
+        output::line(&format!("\n{} {}", style("Addon").bold(), input_file.display()));
+        match build(&workspace_build_opt(opt, root, input_file), None) {
+            Ok(addon) => workspace_outcome(input_file, Some(&addon), workspace::Status::Built),
+            Err(exit) => {
+                first_failure = first_failure.or(Some(exit));
+                workspace_outcome(input_file, None, workspace::Status::Failed)
+            }
+        }
+    }).collect();
+    workspace::print_summary(&outcomes);
+    first_failure.map_or(Ok(()), Err)
+}
+
+/// Publishes the addons of the workspace whose version is not in the registry, and prints a summary of all of them.
+/// Fails with the category of the first failed addon. With `dashboard` the addons are shown as a live grid.
+fn workspace_publish(client: &reqwest::Client, auth: &dyn auth::Authenticator, root: &std::path::Path, opt: &PublishOpt,
+                     dashboard: bool, dashboard_export: Option<&std::path::Path>) -> Result<(), Exit> {
+    let files = workspace::discover(root).ok_or(Exit::Validation)?;
+    let dashboard = dashboard && dashboard::start(root, &files);
+    let mut registry_index = registry::LazyRegistry::new(client);
+    let mut first_failure = None;
+    let outcomes: Vec<workspace::Outcome> = files.iter().enumerate().map(|(index, input_file)| {
+        dashboard::select(index);
+        output::line(&format!("\n{} {}", style("Addon").bold(), input_file.display()));
+        let mut addon_opt = opt.clone();
+        addon_opt.build = workspace_build_opt(&opt.build, root, input_file);
+        let addon = match validate(&addon_opt.build.input) {
+            Some(v) => v,
+            None => {
+                first_failure = first_failure.or(Some(Exit::Validation));
+                dashboard::finish_addon(workspace::Status::Failed);
+                return workspace_outcome(input_file, None, workspace::Status::Failed);
+            }
+        };
+        let mut outcome = workspace_outcome(input_file, Some(&addon), workspace::Status::Failed);
+        let common = &addon.input_file.x_ohx_registry;
+        let unpublished = match opt.skip_publish_checks || opt.allow_republish {
+            true => Some(true),
+            false => registry_index.get().map(|index| workspace::is_unpublished(index, &common.id, &common.version)),
+        };
+        // The addon is validated already, its warnings are not printed twice
+        outcome.status = match unpublished {
+            Some(true) => match publish(client, auth, &addon_opt, None, Some(addon)) {
+                Ok(()) => workspace::Status::Published,
+                Err(exit) => {
+                    first_failure = first_failure.or(Some(exit));
+                    workspace::Status::Failed
+                }
+            },
+            Some(false) => {
+                output::line(&format!("{} {} is already published", common.id, common.version));
+                workspace::Status::Unchanged
+            }
+            None => {
+                first_failure = first_failure.or(Some(Exit::Registry));
+                workspace::Status::Failed
+            }
+        };
+        dashboard::finish_addon(outcome.status);
+        outcome
+    }).collect();
+    if dashboard && !dashboard::stop(&outcomes, dashboard_export) {
+        first_failure = first_failure.or(Some(error_codes::FILE_ACCESS.exit));
     }
+    workspace::print_summary(&outcomes);
+    if dashboard {
+        dashboard::browse_logs();
+    }
+    first_failure.map_or(Ok(()), Err)
+}
 
-    // Print summary
-    println!("\nSummary for {} - Version {}\n", &input_file.x_ohx_registry.title, &input_file.x_ohx_registry.version);
+fn print_summary(addon: &Addon) {
+    report::archs(&addon.build_instructions);
+    if output::is_json() {
+        return;
+    }
+    output::line(&format!("\nSummary for {} - Version {}\n", &addon.input_file.x_ohx_registry.title, &addon.input_file.x_ohx_registry.version));
     use prettytable::{Table, Row, Cell, cell};
     let mut table = Table::new();
 
     // Add a row per time
-    table.add_row(prettytable::row!["Architecture", "Build", "Upload"]);
-    for build_instruction in &build_instructions {
+    table.add_row(prettytable::row!["Architecture", "Build", "Upload", "Digest"]);
+    for build_instruction in &addon.build_instructions {
+        if build_instruction.skipped {
+            table.add_row(Row::new(vec![
+                Cell::new(&build_instruction.key()),
+                Cell::new("skipped").style_spec("Fy"),
+                Cell::new("skipped").style_spec("Fy"),
+                Cell::new("-")]));
+            continue;
+        }
         table.add_row(Row::new(vec![
-            Cell::new(&build_instruction.arch),
+            Cell::new(&build_instruction.key()),
             match build_instruction.build {
                 true => Cell::new("true").style_spec("bFg"),
                 false => Cell::new("false").style_spec("BriH2")
@@ -212,7 +1659,409 @@ fn main() {
             match build_instruction.uploaded {
                 true => Cell::new("true").style_spec("bFg"),
                 false => Cell::new("false").style_spec("BriH2")
-            }]));
+            },
+            Cell::new(build_instruction.digest.as_deref().unwrap_or("-"))]));
+    }
+    // The dashboard shows the tables with the log of the addon
+    if dashboard::is_active() {
+        output::line(&table.to_string());
+    } else {
+        table.printstd();
+    }
+}
+
+/// An endpoint flag wins over the configuration files
+fn override_url(flag: &Option<String>, url: &mut String) -> Result<(), String> {
+    if let Some(flag) = flag {
+        if !flag.starts_with("https://") && !flag.starts_with("http://") {
+            return Err(format!("{} is no http(s) url", flag));
+        }
+        *url = flag.clone();
+    }
+    Ok(())
+}
+
+/// Defaults of the configuration files for flags that are not given on the command line, and the endpoints
+fn apply_config_files(opt: &mut Opt, files: &config::ConfigFiles) -> Result<(), String> {
+    let mut current = files.endpoints(opt.profile.as_deref())?;
+    override_url(&opt.registry_url, &mut current.registry_url)?;
+    override_url(&opt.oauth_url, &mut current.oauth_url)?;
+    override_url(&opt.vault_url, &mut current.vault_url)?;
+    override_url(&opt.index_url, &mut current.index_url)?;
+    override_url(&opt.stats_url, &mut current.stats_url)?;
+    endpoints::set(current);
+    let config = files.merged();
+    if opt.verbose == 0 {
+        opt.verbose = config.verbose.unwrap_or(0);
+    }
+    let input_opt = match &mut opt.cmd {
+        Command::Validate(validate_opt) => &mut validate_opt.input,
+        Command::Payload(payload_opt) => &mut payload_opt.input,
+        Command::Build(build_opt) | Command::Watch { build: build_opt, .. }
+        | Command::Workspace(WorkspaceCommand::Build { build: build_opt, .. }) => &mut build_opt.input,
+        Command::Publish(publish_opt) | Command::Rebuild(RebuildOpt { publish: publish_opt, .. })
+        | Command::Workspace(WorkspaceCommand::Publish { publish: publish_opt, .. }) => &mut publish_opt.build.input,
+        _ => return Ok(()),
+    };
+    if input_opt.archs.is_empty() {
+        input_opt.archs = config.archs.unwrap_or_default();
+    }
+    // "out" is the default value of the flag
+    let build_opt = match &mut opt.cmd {
+        Command::Build(build_opt) | Command::Watch { build: build_opt, .. }
+        | Command::Workspace(WorkspaceCommand::Build { build: build_opt, .. }) => build_opt,
+        Command::Publish(publish_opt) | Command::Rebuild(RebuildOpt { publish: publish_opt, .. })
+        | Command::Workspace(WorkspaceCommand::Publish { publish: publish_opt, .. }) => &mut publish_opt.build,
+        _ => return Ok(()),
+    };
+    if let (Some(build_directory), true) = (config.build_directory, build_opt.build_directory.as_path() == std::path::Path::new("out")) {
+        build_opt.build_directory = build_directory;
+    }
+    if build_opt.engine.is_none() {
+        build_opt.engine = config.engine;
+    }
+    if build_opt.mirrors.is_empty() {
+        build_opt.mirrors = config.mirrors.unwrap_or_default();
+    }
+    if build_opt.build_storage.is_none() {
+        build_opt.build_storage = config.build_storage;
+    }
+    Ok(())
+}
+
+/// The name of a subcommand that cannot work with --offline
+fn requires_network(cmd: &Command) -> Option<&'static str> {
+    Some(match cmd {
+        Command::Rebuild(_) => "rebuild",
+        Command::Login { .. } => "login",
+        Command::Unpublish { .. } => "unpublish",
+        Command::List(_) => "list",
+        Command::CleanupRemote { .. } => "cleanup-remote",
+        Command::VerifyBundle { .. } => "verify-bundle",
+        Command::Security(_) => "security",
+        Command::Stats { .. } => "stats",
+        Command::Index(IndexCommand::Snapshot) => "index snapshot",
+        Command::Policy(_) => "policy update",
+        _ => return None,
+    })
+}
+
+/// Commands that validate an addon description, or create or edit one like `init`
+fn validates(cmd: &Command) -> bool {
+    matches!(cmd, Command::Validate(_) | Command::Payload(_) | Command::Build(_) | Command::Watch { .. } | Command::Publish(_)
+        | Command::Rebuild(_) | Command::Workspace(_) | Command::Init { .. }
+        | Command::Convert { .. } | Command::Permissions { .. })
+}
+
+/// Request timeout in seconds for `--ci`
+pub(crate) const CI_HTTP_TIMEOUT: u64 = 60;
+
+/// The `--ci` preset. Output and login are non interactive, requests are retried more often and all image checks
+/// fail the build. The JSON output is selected before the logger is set up.
+fn apply_ci_preset(opt: &mut Opt) {
+    output::set_plain(true);
+    output::set_interactive(false);
+    http::set_patient_retries(true);
+    match &mut opt.cmd {
+        Command::Validate(ValidateOpt { input, .. }) | Command::Payload(PayloadOpt { input, .. }) => input.strict = true,
+        Command::Build(build_opt) | Command::Watch { build: build_opt, .. }
+        | Command::Workspace(WorkspaceCommand::Build { build: build_opt, .. }) => build_opt.input.strict = true,
+        Command::Publish(publish_opt) | Command::Rebuild(RebuildOpt { publish: publish_opt, .. })
+        | Command::Workspace(WorkspaceCommand::Publish { publish: publish_opt, .. }) => publish_opt.build.input.strict = true,
+        _ => {}
+    }
+    let build_opt = match &mut opt.cmd {
+        Command::Build(build_opt) | Command::Workspace(WorkspaceCommand::Build { build: build_opt, .. }) => build_opt,
+        Command::Publish(publish_opt) | Command::Rebuild(RebuildOpt { publish: publish_opt, .. })
+        | Command::Workspace(WorkspaceCommand::Publish { publish: publish_opt, .. }) => &mut publish_opt.build,
+        _ => return,
+    };
+    for deny in image_checks::Deny::ALL.iter() {
+        if !build_opt.deny.contains(deny) {
+            build_opt.deny.push(*deny);
+        }
+    }
+}
+
+/// Runs `f` between restoring and storing the build directory, if a `--build-storage` is given.
+/// The build directory is also stored after a failed run, so that `--resume` continues there.
+fn with_build_storage<F: FnOnce() -> Result<(), Exit>>(client: &reqwest::Client, build_opt: &BuildOpt, f: F) -> Result<(), Exit> {
+    let url = match &build_opt.build_storage {
+        Some(v) => v,
+        None => return f(),
+    };
+    if http::is_offline() && storage::is_remote(url) {
+        warn!("Offline: The build directory is not restored from {}", provenance::strip_credentials(url));
+        return f();
+    }
+    error_codes::check(storage::restore(client, url, &build_opt.build_directory), error_codes::BUILD_STORAGE.exit)?;
+    let result = f();
+    let stored = error_codes::check(storage::store(client, url, &build_opt.build_directory), error_codes::BUILD_STORAGE.exit);
+    result.and(stored)
+}
+
+/// A build directory of another user, like `out` of a shared checkout, is replaced by one of the user and project
+fn use_own_build_directory(opt: &mut Opt) {
+    let build_opt = match &mut opt.cmd {
+        Command::Build(build_opt) | Command::Watch { build: build_opt, .. }
+        | Command::Workspace(WorkspaceCommand::Build { build: build_opt, .. }) => build_opt,
+        Command::Publish(publish_opt) | Command::Rebuild(RebuildOpt { publish: publish_opt, .. })
+        | Command::Workspace(WorkspaceCommand::Publish { publish: publish_opt, .. }) => &mut publish_opt.build,
+        _ => return,
+    };
+    build_opt.build_directory = namespace::build_directory(&build_opt.build_directory, &build_opt.input.input_file);
+}
+
+/// Finds the addon description file of the commands with an `--input-file`, see [`input_discovery`]
+fn discover_input_file(opt: &mut Opt) -> bool {
+    let input_file = match &mut opt.cmd {
+        Command::Validate(ValidateOpt { input, .. }) | Command::Payload(PayloadOpt { input, .. }) => &mut input.input_file,
+        Command::Build(build_opt) | Command::Watch { build: build_opt, .. } => &mut build_opt.input.input_file,
+        Command::Publish(publish_opt) | Command::Rebuild(RebuildOpt { publish: publish_opt, .. }) => &mut publish_opt.build.input.input_file,
+        Command::Migrate { input_file, .. } => input_file,
+        Command::Permissions { input_file, list: false, .. } => input_file,
+        _ => return true,
+    };
+    match input_discovery::resolve(input_file) {
+        Some(v) => {
+            *input_file = v;
+            true
+        }
+        None => false,
     }
-    table.printstd();
-}
\ No newline at end of file
+}
+
+fn main() {
+    // Parse command line, merge the configuration files and setup logger
+    let mut opt = Opt::from_args();
+    let config_files = config::load().and_then(|files| apply_config_files(&mut opt, &files).map(|_| files));
+    let level = match opt.verbose {
+        0 => "warn",
+        1 => "info",
+        _ => "debug"
+    };
+    // The JSON document is the machine readable result for CI pipelines
+    if opt.ci && opt.output.is_none() {
+        opt.output = Some(output::OutputFormat::Json);
+    }
+    let json = opt.output == Some(output::OutputFormat::Json);
+    let write_style = if opt.ci || json { env_logger::WriteStyle::Never } else { env_logger::WriteStyle::Auto };
+    let mut logger = env_logger::from_env(Env::default().default_filter_or(level));
+    logger.default_format_timestamp(false).write_style(write_style);
+    if json {
+        // Errors and warnings are also part of the JSON document
+        logger.format(|buf, record| {
+            let message = record.args().to_string();
+            match record.level() {
+                log::Level::Error => report::update(|r| r.errors.push(message.clone())),
+                log::Level::Warn => report::update(|r| r.warnings.push(message.clone())),
+                _ => {}
+            }
+            use std::io::Write;
+            writeln!(buf, "[{}] {}", record.level(), message)
+        });
+        output::set_json(true);
+    }
+    error_codes::init_logger(logger);
+    let config_files = match config_files {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} {}", error_codes::CONFIG_INVALID, e);
+            std::process::exit(error_codes::CONFIG_INVALID.exit.code());
+        }
+    };
+    if opt.ci {
+        apply_ci_preset(&mut opt);
+    }
+    if !discover_input_file(&mut opt) {
+        std::process::exit(Exit::Validation.code());
+    }
+    use_own_build_directory(&mut opt);
+    debug!("{:?}", opt);
+
+    // Works offline, even if the http client cannot be created
+    if let Command::Explain { code } = &opt.cmd {
+        if !error_codes::explain(code.as_ref().map(|v| v.as_str())) {
+            std::process::exit(Exit::Validation.code());
+        }
+        return;
+    }
+
+    if opt.offline {
+        if let Some(name) = requires_network(&opt.cmd) {
+            error!("{} {} requires network access", error_codes::OFFLINE, name);
+            std::process::exit(error_codes::OFFLINE.exit.code());
+        }
+        http::set_offline(true);
+    }
+
+    login::set_insecure_session_file(opt.insecure_session_file);
+    if let Some(proxy) = &opt.proxy {
+        if !http::set_proxy(proxy) {
+            std::process::exit(error_codes::HTTP_CLIENT.exit.code());
+        }
+    }
+    let timeout = if opt.ci { Some(std::time::Duration::from_secs(CI_HTTP_TIMEOUT)) } else { None };
+    let auth = match auth::authenticator(&config_files.merged()) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} {}", error_codes::CONFIG_INVALID, e);
+            std::process::exit(error_codes::CONFIG_INVALID.exit.code());
+        }
+    };
+    let client = match http::create_client(opt.tls_backend, opt.fips, timeout, auth.as_ref()) {
+        Some(v) => v,
+        None => std::process::exit(Exit::Validation.code()),
+    };
+    if validates(&opt.cmd) && !(registry::load_type_profiles(&client) && registry::load_permissions(&client)) {
+        std::process::exit(Exit::Registry.code());
+    }
+    links::set_client(client.clone());
+
+    let result = match &opt.cmd {
+        Command::Validate(validate_opt) => {
+            // stdout is reserved for the report
+            if validate_opt.format != validation_report::Format::Human {
+                output::set_json(true);
+            }
+            let mut success = false;
+            if !validate_opt.fix || fix_input_file(&validate_opt.input.input_file) {
+                let addon = validate(&validate_opt.input);
+                success = addon.is_some();
+                if let (Some(addon), true) = (addon, validate_opt.emit_normalized) {
+                    match serde_yaml::to_string(&addon.input_file) {
+                        Ok(v) => println!("{}", v),
+                        Err(e) => error!("{} Failed to serialize the addon description: {:?}", error_codes::INTERNAL, e),
+                    }
+                }
+            }
+            match validate_opt.format {
+                validation_report::Format::Human if json => report::print("validate", success),
+                validation_report::Format::Human => {}
+                format => validation_report::print(format, &validate_opt.input.input_file.display().to_string(), success, &report::issues()),
+            }
+            error_codes::check(success, Exit::Validation)
+        }
+        Command::Migrate { input_file, write } => error_codes::check(migrate_input_file(input_file, *write), Exit::Validation),
+        Command::Build(build_opt) => {
+            let result = with_build_storage(&client, build_opt, || build(build_opt, None).map(|_| ()));
+            if json {
+                report::print("build", result.is_ok());
+            }
+            result
+        }
+        Command::Watch { build: build_opt, no_build } => {
+            let dir = build_opt.input.input_file.parent().filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or(std::path::Path::new(".")).to_path_buf();
+            let watched = watch::watch(&dir, std::slice::from_ref(&build_opt.build_directory), || {
+                if *no_build {
+                    validate(&build_opt.input);
+                } else {
+                    let _ = build(build_opt, None);
+                }
+            });
+            error_codes::check(watched, Exit::Validation)
+        }
+        Command::Publish(publish_opt) => {
+            let result = with_build_storage(&client, &publish_opt.build,
+                                            || publish(&client, auth.as_ref(), publish_opt, None, None));
+            progress::finish(result.is_ok());
+            if json {
+                report::print("publish", result.is_ok());
+            }
+            result
+        }
+        Command::Workspace(WorkspaceCommand::Build { root, build: build_opt }) =>
+            with_build_storage(&client, build_opt, || workspace_build(root, build_opt)),
+        Command::Workspace(WorkspaceCommand::Publish { root, publish: publish_opt, dashboard, dashboard_export }) => {
+            let result = with_build_storage(&client, &publish_opt.build, || {
+                workspace_publish(&client, auth.as_ref(), root, publish_opt, *dashboard, dashboard_export.as_ref().map(|v| v.as_path()))
+            });
+            progress::finish(result.is_ok());
+            result
+        }
+        Command::Rebuild(rebuild_opt) => {
+            let result = with_build_storage(&client, &rebuild_opt.publish.build,
+                                            || publish(&client, auth.as_ref(), &rebuild_opt.publish, Some(&rebuild_opt.reason), None));
+            progress::finish(result.is_ok());
+            if json {
+                report::print("rebuild", result.is_ok());
+            }
+            result
+        }
+        Command::Payload(payload_opt) => {
+            validate(&payload_opt.input).map(|mut addon| print_payload(&mut addon, payload_opt.hash)).ok_or(Exit::Validation)
+        }
+        Command::Login { login: login_opt, check, token } => {
+            if *check {
+                error_codes::check(login::check(&client, token.as_ref().map(|v| v.as_str())), Exit::Auth)
+            } else {
+                // The session of a password login is only stored by this command
+                let stored = login(&client, auth.as_ref(), login_opt, None)
+                    .map(|session| login_opt.username.is_none() || login::write_session(&session))
+                    .unwrap_or(false);
+                error_codes::check(stored, Exit::Auth)
+            }
+        }
+        Command::Logout => error_codes::check(login::logout(&client), Exit::Auth),
+        Command::Search(search_opt) => error_codes::check(search::search(&client, search_opt), Exit::Registry),
+        Command::Init { force } => error_codes::check(init::init(std::path::Path::new("."), *force), Exit::Validation),
+        Command::Convert { from, output, force } => {
+            let output = output.clone().unwrap_or_else(|| from.with_file_name(input_discovery::DEFAULT_FILE));
+            error_codes::check(convert::convert(from, &output, *force), Exit::Validation)
+        }
+        Command::Permissions { list: true, filter, .. } => error_codes::check(permissions::list(filter), Exit::Validation),
+        Command::Permissions { input_file, service, filter, .. } =>
+            error_codes::check(permissions::pick(input_file, service.as_ref(), filter), Exit::Validation),
+        Command::Unpublish { addon_id, version, yes, login } => unpublish(&client, auth.as_ref(), addon_id, version, *yes, login),
+        Command::Info { addon_id, json } => error_codes::check(info::info(&client, addon_id, *json), Exit::Registry),
+        Command::Hook(HookCommand::Install { hook: hook_name }) => error_codes::check(hook::install(hook_name), Exit::Validation),
+        Command::Hook(HookCommand::Uninstall { hook: hook_name }) => error_codes::check(hook::uninstall(hook_name), Exit::Validation),
+        Command::Cache(CacheCommand::Stats) => error_codes::check(cache::stats(), Exit::Validation),
+        Command::Cache(CacheCommand::Clear) => error_codes::check(cache::clear(), Exit::Validation),
+        Command::Index(IndexCommand::Snapshot) => error_codes::check(snapshot::snapshot(&client), Exit::Registry),
+        Command::Policy(PolicyCommand::Update { trust_new_key }) => error_codes::check(policy::update(&client, *trust_new_key), Exit::Registry),
+        Command::Index(IndexCommand::Diff { date, to, json }) => error_codes::check(snapshot::diff(*date, *to, *json), Exit::Validation),
+        Command::Config(ConfigCommand::Show { resolved }) => error_codes::check(config::show(&opt, &config_files, *resolved), Exit::Validation),
+        Command::Config(ConfigCommand::Get { key }) => error_codes::check(config::get(&opt, &config_files, key), Exit::Validation),
+        Command::Config(ConfigCommand::Set { key, value, project }) =>
+            error_codes::check(config::set(key, value.as_ref().map(|v| v.as_str()), *project), Exit::Validation),
+        Command::List(login_opt) => {
+            login(&client, auth.as_ref(), login_opt, None).ok_or(Exit::Auth)
+                .and_then(|session| error_codes::check(list::list(&client, &session), Exit::Registry))
+        }
+        Command::Explain { .. } => Ok(()),
+        Command::CleanupRemote { build_directory, dry_run, login: login_opt } => {
+            login(&client, auth.as_ref(), login_opt, None).ok_or(Exit::Auth)
+                .and_then(|session| error_codes::check(cleanup::cleanup_remote(&client, &session, build_directory, *dry_run), Exit::Registry))
+        }
+        Command::VerifyBundle { file } => bundle::verify_bundle(&client, file),
+        Command::Security(SecurityCommand::Check { addon_id, fail_on, all, osv_url }) =>
+            error_codes::check(security::check(&client, addon_id, *fail_on, *all, osv_url.trim_end_matches('/')), Exit::Validation),
+        Command::Stats { sort, json, login: login_opt } => {
+            login(&client, auth.as_ref(), login_opt, None).ok_or(Exit::Auth)
+                .and_then(|session| error_codes::check(stats::stats(&client, &session, *sort, *json), Exit::Registry))
+        }
+    };
+    if let Err(exit) = result {
+        std::process::exit(exit.code());
+    }
+}
+
+#[test]
+fn failed_publish_tracks_remnants_test() {
+    let build_directory = namespace::user_directory().join("remnants-test");
+    let _ = std::fs::remove_dir_all(&build_directory);
+    let mut run = Run::new(&build_directory);
+    let outcome = Pipeline::new()
+        .stage("upload", |run: &mut Run| {
+            track_pushed(&mut run.remote, &build_directory, "mqtt", vec!["docker.io/openhabx/mqtt_amd64:1.0".to_owned()]);
+            Some(())
+        })
+        .stage("registry", |_: &mut Run| None)
+        .run(&mut run);
+    assert_eq!(stage_exit(&outcome), Err(Exit::Registry));
+    assert_eq!(run.remote.len(), 1);
+    assert_eq!(state::remote_artifacts(&build_directory), run.remote);
+    let _ = std::fs::remove_dir_all(&build_directory);
+}
@@ -0,0 +1,96 @@
+//! Per user state for shared build servers. Several users run the CLI on the same host, sometimes with a shared HOME,
+//! with `sudo` or in the same checkout. State of another user is never reused: It is not writable for this user,
+//! and a cache of another user could be poisoned. Temporary state lives in the private `<tmp>/ohx-<uid>`,
+//! with a directory per project hash for builds.
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// The real user id of the process. None on platforms without user ids.
+#[cfg(unix)]
+pub(crate) fn uid() -> Option<u32> {
+    Some(unsafe { libc::getuid() })
+}
+
+#[cfg(not(unix))]
+pub(crate) fn uid() -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn owner(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    path.metadata().ok().map(|m| m.uid())
+}
+
+#[cfg(not(unix))]
+fn owner(_path: &Path) -> Option<u32> {
+    None
+}
+
+/// True if the path exists and belongs to another user. Also for root: Files that root creates in the state of a user
+/// cause permission errors for that user later.
+pub(crate) fn is_foreign(path: &Path) -> bool {
+    match (uid(), owner(path)) {
+        (Some(uid), Some(owner)) => uid != owner,
+        _ => false,
+    }
+}
+
+#[cfg(unix)]
+fn create_private(directory: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    std::fs::DirBuilder::new().recursive(true).mode(0o700).create(directory)
+}
+
+#[cfg(not(unix))]
+fn create_private(directory: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(directory)
+}
+
+/// The private temporary directory of the user, like `/tmp/ohx-1000`. If another user created it first,
+/// a directory of this process is used.
+pub(crate) fn user_directory() -> PathBuf {
+    let name = match uid() {
+        Some(uid) => format!("ohx-{}", uid),
+        None => "ohx".to_owned(),
+    };
+    let mut directory = std::env::temp_dir().join(&name);
+    if is_foreign(&directory) {
+        warn!("{} belongs to another user. Using a directory of this process.", directory.display());
+        directory = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    }
+    if let Err(e) = create_private(&directory) {
+        warn!("Failed to create {}: {}", directory.display(), e);
+    }
+    directory
+}
+
+/// A short hash of the absolute addon directory, so that builds of different projects do not share state
+pub(crate) fn project_hash(input_file: &Path) -> String {
+    let input_file = input_file.canonicalize().unwrap_or_else(|_| input_file.to_path_buf());
+    let directory = input_file.parent().unwrap_or(&input_file);
+    let hash = format!("{:x}", Sha256::digest(directory.to_string_lossy().as_bytes()));
+    hash[..16].to_owned()
+}
+
+/// The default build directory, or a directory of the user and project if it belongs to another user,
+/// like `out` of a checkout that another user built before
+pub(crate) fn build_directory(directory: &Path, input_file: &Path) -> PathBuf {
+    if !is_foreign(directory) {
+        return directory.to_path_buf();
+    }
+    let namespaced = user_directory().join("builds").join(project_hash(input_file));
+    warn!("The build directory {} belongs to another user. Using {}.", directory.display(), namespaced.display());
+    namespaced
+}
+
+#[test]
+fn namespace_test() {
+    let own = user_directory();
+    assert!(!is_foreign(&own));
+    assert!(!is_foreign(Path::new("/does/not/exist")));
+    assert_eq!(build_directory(&own, Path::new("addons.yml")), own);
+    assert_eq!(project_hash(Path::new("a/addons.yml")), project_hash(Path::new("a/other.yml")));
+    assert_ne!(project_hash(Path::new("a/addons.yml")), project_hash(Path::new("b/addons.yml")));
+}
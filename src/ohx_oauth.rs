@@ -0,0 +1,355 @@
+//! A typed client for the openhabx.com OAuth server.
+//!
+//! Covers the device authorization flow, token refresh, userinfo, token revocation and introspection.
+//! Transport errors, 5xx and 429 responses are retried with an exponential backoff.
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub const OAUTH_URL: &str = "https://oauth.openhabx.com";
+
+pub const GRANT_TYPE_REFRESH_TOKEN: &str = "refresh_token";
+pub const GRANT_TYPE_DEVICE_CODE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+pub const GRANT_TYPE_PASSWORD: &str = "password";
+
+/// The error code of the token endpoint while the user has not yet authorized the device
+pub const ERROR_AUTHORIZATION_PENDING: &str = "authorization_pending";
+pub const ERROR_SLOW_DOWN: &str = "slow_down";
+pub const ERROR_EXPIRED_TOKEN: &str = "expired_token";
+
+/// The poll interval if the server does not send one, and the increase for every "slow_down", RFC 8628 section 3.5
+const DEFAULT_POLL_INTERVAL: u64 = 5;
+
+#[derive(Serialize)]
+pub struct DeviceAuthorizationRequest<'a> {
+    pub client_id: &'a str,
+    pub client_name: &'a str,
+    pub response_type: &'a str,
+    pub scope: &'a str,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceFlowResponse {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub interval: u32,
+    pub expires_in: i64,
+}
+
+#[derive(Serialize)]
+pub struct TokenRequestForRefreshToken<'a> {
+    pub refresh_token: &'a str,
+    pub client_id: &'a str,
+    pub grant_type: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct TokenRequestForDevice<'a> {
+    pub device_code: &'a str,
+    pub client_id: &'a str,
+    pub grant_type: &'a str,
+}
+
+/// Resource owner password credentials grant, RFC 6749 section 4.3
+#[derive(Serialize)]
+pub struct TokenRequestForPassword<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+    pub client_id: &'a str,
+    pub grant_type: &'a str,
+    pub scope: &'a str,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuthTokenResponse {
+    pub access_token: String,
+    // "bearer"
+    pub token_type: String,
+    pub expires_in: i64,
+    pub refresh_token: Option<String>,
+    // Space delimiter
+    pub scope: String,
+}
+
+/// Users id, email, display name and a few more information
+#[allow(non_snake_case)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct UserInfo {
+    pub localId: Option<String>,
+    pub email: Option<String>,
+    pub displayName: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RevocationRequest<'a> {
+    pub token: &'a str,
+    pub token_type_hint: &'a str,
+    pub client_id: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct IntrospectionRequest<'a> {
+    pub token: &'a str,
+    pub client_id: &'a str,
+}
+
+/// RFC 7662 token introspection response
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct TokenIntrospection {
+    pub active: bool,
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Expiry as unix timestamp in seconds
+    #[serde(default)]
+    pub exp: Option<i64>,
+}
+
+/// An OAuth error response, RFC 6749 section 5.2
+#[derive(Debug, Clone, Deserialize)]
+pub struct ErrorResult {
+    pub error: String,
+    #[serde(default)]
+    pub error_description: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum OAuthError {
+    /// The server could not be contacted
+    Http(reqwest::Error),
+    /// The server responded with an OAuth error, for example "authorization_pending"
+    Server { status: u16, error: ErrorResult },
+    /// Any other unexpected response
+    Unexpected { status: u16, body: String },
+}
+
+impl OAuthError {
+    /// The OAuth error code, if the server responded with an OAuth error
+    pub fn error_code(&self) -> Option<&str> {
+        match self {
+            OAuthError::Server { error, .. } => Some(&error.error),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for OAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OAuthError::Http(e) => write!(f, "Failed to contact the OAuth server: {}", e),
+            OAuthError::Server { status, error } => match &error.error_description {
+                Some(description) => write!(f, "{} ({}): {}", error.error, status, description),
+                None => write!(f, "{} ({})", error.error, status),
+            },
+            OAuthError::Unexpected { status, body } => write!(f, "Unexpected response {}: {}", status, body),
+        }
+    }
+}
+
+impl std::error::Error for OAuthError {}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub attempts: u32,
+    /// Backoff before the first retry. Doubled for each further retry.
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { attempts: 3, initial_backoff: Duration::from_millis(500) }
+    }
+}
+
+pub struct OAuthClient<'a> {
+    client: &'a reqwest::Client,
+    base_url: String,
+    client_id: String,
+    retry: RetryPolicy,
+}
+
+impl<'a> OAuthClient<'a> {
+    pub fn new(client: &'a reqwest::Client, client_id: &str) -> Self {
+        OAuthClient { client, base_url: OAUTH_URL.to_owned(), client_id: client_id.to_owned(), retry: RetryPolicy::default() }
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_owned();
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn url(&self, endpoint: &str) -> String {
+        format!("{}/{}", self.base_url, endpoint)
+    }
+
+    /// Start the device authorization flow
+    pub fn start_device_flow(&self, client_name: &str, scope: &str) -> Result<DeviceFlowResponse, OAuthError> {
+        let request = DeviceAuthorizationRequest { client_id: &self.client_id, client_name, response_type: "device", scope };
+        self.send(|| self.client.post(&self.url("authorize")).form(&request))
+    }
+
+    /// Poll the token endpoint once. Returns an [`OAuthError::Server`] with [`ERROR_AUTHORIZATION_PENDING`]
+    /// while the user has not yet authorized the device.
+    pub fn poll_device_token(&self, device_code: &str) -> Result<OAuthTokenResponse, OAuthError> {
+        let request = TokenRequestForDevice { device_code, client_id: &self.client_id, grant_type: GRANT_TYPE_DEVICE_CODE };
+        self.send(|| self.client.post(&self.url("token")).form(&request))
+    }
+
+    /// Poll the token endpoint until the user authorized the device. Polls in the interval of the server and
+    /// slows down by 5 s for every [`ERROR_SLOW_DOWN`]. `wait` is called before every poll with the interval and
+    /// the seconds until the device code expires. It sleeps for the interval.
+    pub fn wait_for_device_token(&self, device_flow: &DeviceFlowResponse, wait: &mut dyn FnMut(Duration, i64))
+                                 -> Result<OAuthTokenResponse, OAuthError> {
+        let expires = std::time::Instant::now() + Duration::from_secs(device_flow.expires_in.max(0) as u64);
+        let mut interval = match device_flow.interval {
+            0 => DEFAULT_POLL_INTERVAL,
+            v => u64::from(v),
+        };
+        loop {
+            let remaining = expires.saturating_duration_since(std::time::Instant::now()).as_secs() as i64;
+            if remaining == 0 {
+                let error = ErrorResult { error: ERROR_EXPIRED_TOKEN.to_owned(), error_description: Some("Request expired".to_owned()) };
+                return Err(OAuthError::Server { status: 400, error });
+            }
+            wait(Duration::from_secs(interval), remaining);
+            match self.poll_device_token(&device_flow.device_code) {
+                Err(ref e) if e.error_code() == Some(ERROR_AUTHORIZATION_PENDING) => {}
+                Err(ref e) if e.error_code() == Some(ERROR_SLOW_DOWN) => interval += DEFAULT_POLL_INTERVAL,
+                result => return result,
+            }
+        }
+    }
+
+    pub fn refresh(&self, refresh_token: &str) -> Result<OAuthTokenResponse, OAuthError> {
+        let request = TokenRequestForRefreshToken { refresh_token, client_id: &self.client_id, grant_type: GRANT_TYPE_REFRESH_TOKEN };
+        self.send(|| self.client.post(&self.url("token")).form(&request))
+    }
+
+    /// Exchange a username and password for tokens, without device authorization
+    pub fn password_token(&self, username: &str, password: &str, scope: &str) -> Result<OAuthTokenResponse, OAuthError> {
+        let request = TokenRequestForPassword { username, password, client_id: &self.client_id, grant_type: GRANT_TYPE_PASSWORD, scope };
+        self.send(|| self.client.post(&self.url("token")).form(&request))
+    }
+
+    pub fn userinfo(&self, access_token: &str) -> Result<UserInfo, OAuthError> {
+        self.send(|| self.client.get(&self.url("userinfo")).bearer_auth(access_token))
+    }
+
+    /// Revoke a token (RFC 7009). `token_type_hint` is "refresh_token" or "access_token".
+    pub fn revoke(&self, token: &str, token_type_hint: &str) -> Result<(), OAuthError> {
+        let request = RevocationRequest { token, token_type_hint, client_id: &self.client_id };
+        self.execute(|| self.client.post(&self.url("revoke")).form(&request)).map(|_| ())
+    }
+
+    /// Introspect a token (RFC 7662)
+    pub fn introspect(&self, token: &str) -> Result<TokenIntrospection, OAuthError> {
+        let request = IntrospectionRequest { token, client_id: &self.client_id };
+        self.send(|| self.client.post(&self.url("introspect")).form(&request))
+    }
+
+    fn send<T: DeserializeOwned>(&self, request: impl Fn() -> reqwest::RequestBuilder) -> Result<T, OAuthError> {
+        let body = self.execute(request)?;
+        serde_json::from_str(&body).map_err(|_| OAuthError::Unexpected { status: 200, body })
+    }
+
+    /// Execute the request with retries. Returns the response body of a successful response.
+    fn execute(&self, request: impl Fn() -> reqwest::RequestBuilder) -> Result<String, OAuthError> {
+        let mut backoff = self.retry.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            let result = request().send().map_err(OAuthError::Http).and_then(|mut response| {
+                let status = response.status().as_u16();
+                let body = response.text().map_err(OAuthError::Http)?;
+                Ok((status, body))
+            });
+            let retry = match &result {
+                Err(_) => true,
+                Ok((status, _)) => *status == 429 || *status >= 500,
+            };
+            if retry && attempt < self.retry.attempts {
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+                continue;
+            }
+            let (status, body) = result?;
+            return match status {
+                200..=299 => Ok(body),
+                _ => match serde_json::from_str::<ErrorResult>(&body) {
+                    Ok(error) => Err(OAuthError::Server { status, error }),
+                    Err(_) => Err(OAuthError::Unexpected { status, body }),
+                },
+            };
+        }
+    }
+}
+
+#[test]
+fn oauth_fixtures_test() {
+    let device: DeviceFlowResponse = serde_json::from_str(include_str!("../tests/fixtures/oauth/authorize.json")).unwrap();
+    assert_eq!(device.user_code, "WDJB-MJHT");
+    let token: OAuthTokenResponse = serde_json::from_str(include_str!("../tests/fixtures/oauth/token.json")).unwrap();
+    assert_eq!(token.refresh_token.as_deref(), Some("tGzv3JOkF0XG5Qx2TlKWIA"));
+    let pending: ErrorResult = serde_json::from_str(include_str!("../tests/fixtures/oauth/token_pending.json")).unwrap();
+    assert_eq!(pending.error, ERROR_AUTHORIZATION_PENDING);
+    let user: UserInfo = serde_json::from_str(include_str!("../tests/fixtures/oauth/userinfo.json")).unwrap();
+    assert_eq!(user.email.as_deref(), Some("dev@example.com"));
+    let introspection: TokenIntrospection = serde_json::from_str(include_str!("../tests/fixtures/oauth/introspect.json")).unwrap();
+    assert!(introspection.active);
+}
+
+#[test]
+fn wait_for_device_token_test() {
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    // Recorded responses of the token endpoint, one per connection
+    let responses = vec![
+        ("400 Bad Request", include_str!("../tests/fixtures/oauth/token_pending.json")),
+        ("400 Bad Request", include_str!("../tests/fixtures/oauth/token_slow_down.json")),
+        ("400 Bad Request", include_str!("../tests/fixtures/oauth/token_pending.json")),
+        ("200 OK", include_str!("../tests/fixtures/oauth/token.json")),
+    ];
+    let server = std::thread::spawn(move || {
+        let mut requests = Vec::new();
+        for (status, body) in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            // The form body is small and sent with the headers
+            while !String::from_utf8_lossy(&request).contains("grant_type") {
+                let read = stream.read(&mut buffer).unwrap();
+                assert!(read > 0);
+                request.extend_from_slice(&buffer[..read]);
+            }
+            requests.push(String::from_utf8(request).unwrap());
+            write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                   status, body.len(), body).unwrap();
+        }
+        requests
+    });
+
+    let client = reqwest::Client::new();
+    let oauth = OAuthClient::new(&client, "addoncli").with_base_url(&base_url);
+    let device_flow: DeviceFlowResponse = serde_json::from_str(include_str!("../tests/fixtures/oauth/authorize.json")).unwrap();
+    let mut intervals = Vec::new();
+    let token = oauth.wait_for_device_token(&device_flow, &mut |interval, _| intervals.push(interval.as_secs())).unwrap();
+    assert_eq!(token.access_token, "2YotnFZFEjr1zCsicMWpAA");
+    assert_eq!(intervals, vec![5, 5, 10, 10]);
+    let requests = server.join().unwrap();
+    assert!(requests.iter().all(|r| r.starts_with("POST /token ") && r.contains("device_code=GmRhmhcxhwAzkoEqiMEg_DnyEysNkuNhszIySk9eS")));
+}
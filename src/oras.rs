@@ -0,0 +1,156 @@
+//! Pushing OCI artifacts with the `oras` CLI (https://oras.land).
+//! The engines (podman, buildah) can only handle container images.
+use crate::error_codes;
+use crate::docker_registry::ManifestDescriptor;
+use crate::dto::addons::{AddonFileEntry, Attachment, AttachmentKind, AttachmentReferrer};
+use crate::dto::BuildInstruction;
+use log::{error, info};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Execute oras with the given arguments. The registry credentials ("username:secret") are passed via stdin.
+pub(crate) fn oras(args: &[String], docker_credentials: &str) -> bool {
+    let mut credentials = docker_credentials.splitn(2, ':');
+    let username = credentials.next().unwrap_or_default();
+    let secret = credentials.next().unwrap_or_default();
+
+    let child = Command::new("oras")
+        .arg(&args[0])
+        .arg("--username")
+        .arg(username)
+        .arg("--password-stdin")
+        .args(&args[1..])
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} 'oras' is required to push OCI artifacts. Please check https://oras.land. {:?}", error_codes::ORAS_MISSING, e);
+            return false;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(secret.as_bytes());
+    }
+    child.wait().map(|s| s.success()).unwrap_or(false)
+}
+
+/// Packs the `x-docs` directory into `docs.tar` of the build directory. The language directories are at the top level.
+fn docs_bundle(docs_dir: &Path, build_directory: &Path) -> Option<PathBuf> {
+    let bundle = build_directory.join("docs.tar");
+    let result = File::create(&bundle).and_then(|file| {
+        let mut builder = tar::Builder::new(file);
+        builder.append_dir_all(".", docs_dir)?;
+        builder.into_inner().map(|_| ())
+    });
+    match result {
+        Ok(()) => Some(bundle),
+        Err(e) => {
+            error!("{} Failed to pack the docs directory {}: {:?}", error_codes::FILE_ACCESS, docs_dir.display(), e);
+            None
+        }
+    }
+}
+
+/// Attach all `x-attachments` files and the `x-docs` bundle to every uploaded image via the OCI referrers API.
+/// Returns the referrer manifest digests.
+pub(crate) fn attach_files(input_file: &AddonFileEntry, build_instructions: &[BuildInstruction], addon_dir: &Path,
+                           build_directory: &Path, docker_credentials: &str) -> Option<Vec<AttachmentReferrer>> {
+    let mut referrers = Vec::new();
+    if input_file.x_attachments.is_empty() && input_file.x_docs.is_none() {
+        return Some(referrers);
+    }
+    if std::fs::create_dir_all(build_directory).is_err() {
+        error!("{} Failed to create the build directory {}", error_codes::FILE_ACCESS, build_directory.display());
+        return None;
+    }
+    let mut attachments: Vec<Attachment> = input_file.x_attachments.clone();
+    if let Some(docs) = &input_file.x_docs {
+        let bundle = docs_bundle(&addon_dir.join(&docs.directory), build_directory)?;
+        // Attachment paths are relative to the addon directory, the build directory may be elsewhere
+        let bundle = bundle.canonicalize().unwrap_or(bundle);
+        attachments.push(Attachment { kind: AttachmentKind::DocsBundle, path: bundle.display().to_string() });
+    }
+
+    for build_instruction in build_instructions.iter().filter(|b| b.uploaded) {
+        let subject = match &build_instruction.digest {
+            Some(digest) => format!("{}@{}", build_instruction.image_name.split(':').next().unwrap(), digest),
+            None => build_instruction.image_name.clone(),
+        };
+        for (index, attachment) in attachments.iter().enumerate() {
+            let file = addon_dir.join(&attachment.path);
+            if !file.is_file() {
+                error!("{} Attachment {} not found", error_codes::ATTACHMENT_NOT_FOUND, file.display());
+                return None;
+            }
+            let manifest = build_directory.join(format!("attachment_{}_{}.json", build_instruction.key(), index));
+            let args = vec![
+                "attach".to_owned(),
+                "--artifact-type".to_owned(),
+                attachment.kind.artifact_type().to_owned(),
+                "--export-manifest".to_owned(),
+                manifest.display().to_string(),
+                subject.clone(),
+                format!("{}:{}", file.display(), attachment.kind.artifact_type()),
+            ];
+            if !oras(&args, docker_credentials) {
+                error!("{} Failed to attach {} to {}", error_codes::PUSH_FAILED, file.display(), subject);
+                return None;
+            }
+            let digest = match std::fs::read(&manifest) {
+                Ok(data) => format!("sha256:{:x}", Sha256::digest(&data)),
+                Err(e) => {
+                    error!("{} Failed to read the referrer manifest {}: {:?}", error_codes::FILE_ACCESS, manifest.display(), e);
+                    return None;
+                }
+            };
+            info!("Attached {} to {}: {}", attachment.path, subject, digest);
+            referrers.push(AttachmentReferrer {
+                kind: attachment.kind,
+                arch: build_instruction.arch.clone(),
+                variant: build_instruction.variant.clone(),
+                digest,
+            });
+        }
+    }
+    Some(referrers)
+}
+
+/// The output of `oras discover`. Older oras versions call the manifests "referrers".
+#[derive(Deserialize)]
+struct Referrers {
+    #[serde(default, alias = "referrers")]
+    manifests: Vec<ManifestDescriptor>,
+}
+
+/// The digests of the referrer manifests of a public image with the given artifact type
+pub(crate) fn discover(image_name: &str, artifact_type: &str) -> Result<Vec<String>, String> {
+    let output = Command::new("oras")
+        .args(["discover", "--artifact-type", artifact_type, "-o", "json", image_name])
+        .output()
+        .map_err(|e| format!("'oras' is required to download OCI artifacts. Please check https://oras.land. {:?}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_owned());
+    }
+    let referrers: Referrers = serde_json::from_slice(&output.stdout).map_err(|e| e.to_string())?;
+    Ok(referrers.manifests.into_iter().map(|m| m.digest).collect())
+}
+
+/// Downloads the files of a public OCI artifact into the directory
+pub(crate) fn pull(reference: &str, directory: &Path) -> Result<(), String> {
+    let output = Command::new("oras")
+        .arg("pull")
+        .arg(reference)
+        .arg("-o")
+        .arg(directory)
+        .output()
+        .map_err(|e| format!("'oras' is required to download OCI artifacts. Please check https://oras.land. {:?}", e))?;
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(String::from_utf8_lossy(&output.stderr).trim().to_owned()),
+    }
+}
@@ -0,0 +1,99 @@
+//! Terminal output settings, shared by all subcommands.
+use crate::dashboard;
+use indicatif::ProgressBar;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static PLAIN: AtomicBool = AtomicBool::new(false);
+static INTERACTIVE: AtomicBool = AtomicBool::new(true);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub(crate) const OUTPUT_FORMATS: [&str; 2] = ["human", "json"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum OutputFormat {
+    Human,
+    /// Progress messages go to stderr and a final JSON document to stdout
+    Json,
+}
+
+impl OutputFormat {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Human => "human",
+            OutputFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(OutputFormat::Human),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Unknown output format {}. Allowed: {}", s, OUTPUT_FORMATS.join(", "))),
+        }
+    }
+}
+
+/// Plain output prints progress messages as lines instead of animated spinners. Useful for CI logs.
+pub(crate) fn set_plain(plain: bool) {
+    PLAIN.store(plain, Ordering::Relaxed);
+    if plain {
+        console::set_colors_enabled(false);
+    }
+}
+
+pub(crate) fn is_plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+/// Non interactive mode never waits for user input, like confirmations or the device authorization
+pub(crate) fn set_interactive(interactive: bool) {
+    INTERACTIVE.store(interactive, Ordering::Relaxed);
+}
+
+pub(crate) fn is_interactive() -> bool {
+    INTERACTIVE.load(Ordering::Relaxed)
+}
+
+/// JSON output implies plain output. Tables are not printed.
+pub(crate) fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+    if json {
+        set_plain(true);
+    }
+}
+
+pub(crate) fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Prints a progress or result line. For JSON output stdout is reserved for the JSON document.
+/// The dashboard collects the lines instead.
+pub(crate) fn line(message: &str) {
+    if dashboard::capture(message) {
+        return;
+    }
+    if is_json() {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// A spinner progress bar, or a hidden one for plain output and the dashboard
+pub(crate) fn progress_bar(len: u64) -> ProgressBar {
+    if is_plain() || dashboard::is_active() { ProgressBar::hidden() } else { ProgressBar::new(len) }
+}
+
+/// Shows a progress message. For plain output the message is printed as a line with the given step prefix.
+pub(crate) fn progress(pb: &ProgressBar, prefix: &str, message: &str) {
+    if is_plain() || dashboard::is_active() {
+        line(&format!("{} {}", prefix, message));
+    } else {
+        pb.set_message(message);
+    }
+}
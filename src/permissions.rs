@@ -0,0 +1,225 @@
+//! `permissions`: Browse the permission catalogue and pick the permissions of a service. The selection is written
+//! into `permissions.mandatory` and `permissions.optional` of the service in addons.yml.
+use crate::dto::addons::{AddonPermission, AddonPermissions};
+use crate::error_codes;
+use crate::init::{prompt, prompt_choice};
+use crate::output;
+use crate::registry;
+use console::Term;
+use log::{error, warn};
+use prettytable::{cell, row, Table};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Mandatory,
+    Optional,
+}
+
+impl Kind {
+    fn key(&self) -> &'static str {
+        match self {
+            Kind::Mandatory => "mandatory",
+            Kind::Optional => "optional",
+        }
+    }
+}
+
+/// The chosen permissions of a service by id
+type Selection = BTreeMap<String, Kind>;
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Filter(String),
+    Select(String, Option<Kind>),
+    Save,
+    Quit,
+}
+
+/// Permissions with the term in their id, label or description, ignoring case
+fn filter<'a>(permissions: &'a AddonPermissions, term: &str) -> Vec<&'a AddonPermission> {
+    let term = term.to_lowercase();
+    permissions.values()
+        .filter(|p| [&p.id, &p.label, &p.description].iter().any(|v| v.to_lowercase().contains(&term)))
+        .collect()
+}
+
+/// "/gpio" filters, "HW_GPIO m" or "2 o" selects by id or row number, "HW_GPIO -" removes, "" saves, "q" quits
+fn parse_action(answer: &str, shown: &[&AddonPermission]) -> Result<Action, String> {
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return Ok(Action::Save);
+    }
+    if answer == "q" {
+        return Ok(Action::Quit);
+    }
+    if let Some(filter) = answer.strip_prefix('/') {
+        return Ok(Action::Filter(filter.trim().to_owned()));
+    }
+    let mut parts = answer.split_whitespace();
+    let target = parts.next().unwrap_or_default();
+    let kind = match parts.next().unwrap_or("m") {
+        "m" => Some(Kind::Mandatory),
+        "o" => Some(Kind::Optional),
+        "-" => None,
+        other => return Err(format!("'{}' is not one of: m, o, -", other)),
+    };
+    let id = match target.parse::<usize>() {
+        Ok(row) => shown.get(row.wrapping_sub(1)).map(|p| p.id.clone()).ok_or_else(|| format!("There is no row {}", row))?,
+        Err(_) => target.to_uppercase(),
+    };
+    Ok(Action::Select(id, kind))
+}
+
+/// The current permissions of the service
+fn selection_of(service: &Value) -> Selection {
+    let mut selection = Selection::new();
+    for kind in &[Kind::Optional, Kind::Mandatory] {
+        let ids = service.get("permissions").and_then(|p| p.get(kind.key())).and_then(|v| v.as_sequence());
+        for id in ids.into_iter().flatten().filter_map(|v| v.as_str()) {
+            selection.insert(id.to_owned(), *kind);
+        }
+    }
+    selection
+}
+
+/// Replaces the permissions section of the service with the selection, sorted by id
+fn apply_selection(service: &mut Value, selection: &Selection) {
+    let mut permissions = Mapping::new();
+    for kind in &[Kind::Mandatory, Kind::Optional] {
+        let ids: Vec<Value> = selection.iter().filter(|(_, k)| *k == kind).map(|(id, _)| Value::String(id.clone())).collect();
+        permissions.insert(Value::String(kind.key().to_owned()), Value::Sequence(ids));
+    }
+    if let Value::Mapping(service) = service {
+        service.insert(Value::String("permissions".to_owned()), Value::Mapping(permissions));
+    }
+}
+
+fn print_table(shown: &[&AddonPermission], selection: &Selection) {
+    let mut table = Table::new();
+    table.add_row(row!["#", "Permission", "Label", "Description", "Selected"]);
+    for (index, permission) in shown.iter().enumerate() {
+        let number = index + 1;
+        let (id, label, description) = (&permission.id, &permission.label, &permission.description);
+        let selected = selection.get(id).map(|k| k.key()).unwrap_or("");
+        table.add_row(row![number, id, label, description, selected]);
+    }
+    table.printstd();
+}
+
+/// Lists the permissions that match the filter
+pub(crate) fn list(filter_term: &str) -> bool {
+    let permissions = registry::permissions();
+    let shown = filter(&permissions, filter_term);
+    if output::is_json() {
+        println!("{}", serde_json::to_string_pretty(&shown).expect("serializable permissions"));
+        return true;
+    }
+    print_table(&shown, &Selection::new());
+    true
+}
+
+/// The service to edit: The given one, the only one, or the one the user chooses
+fn choose_service(term: &Term, data: &Value, service: Option<&String>) -> Option<String> {
+    let services: Vec<&str> = data.get("services").and_then(|s| s.as_mapping()).into_iter().flatten()
+        .filter_map(|(k, _)| k.as_str()).collect();
+    if let Some(service) = service {
+        if services.contains(&service.as_str()) {
+            return Some(service.clone());
+        }
+        error!("{} There is no service {}. Services: {}", error_codes::INPUT_FILE_INVALID, service, services.join(", "));
+        return None;
+    }
+    match services.len() {
+        0 => {
+            error!("{} The file has no services", error_codes::INPUT_FILE_INVALID);
+            None
+        }
+        1 => Some(services[0].to_owned()),
+        _ => Some(prompt_choice(term, "Service", services[0], &services)),
+    }
+}
+
+/// Lets the user pick the permissions of a service interactively and writes them into the input file
+pub(crate) fn pick(input_file: &Path, service: Option<&String>, filter_term: &str) -> bool {
+    if !output::is_interactive() {
+        error!("{} Picking permissions requires an interactive terminal. Use --list to print the catalogue.", error_codes::NOT_INTERACTIVE);
+        return false;
+    }
+    let mut data: Value = match std::fs::read(input_file).map_err(failure::Error::from)
+        .and_then(|v| serde_yaml::from_slice(&v).map_err(failure::Error::from)) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} Failed to read {}: {}", error_codes::FILE_ACCESS, input_file.display(), e);
+            return false;
+        }
+    };
+    let term = Term::stdout();
+    let service = match choose_service(&term, &data, service) {
+        Some(v) => v,
+        None => return false,
+    };
+    let permissions = registry::permissions();
+    let mut selection = selection_of(&data["services"][service.as_str()]);
+    let mut filter_term = filter_term.to_owned();
+    loop {
+        let shown = filter(&permissions, &filter_term);
+        print_table(&shown, &selection);
+        let answer = prompt(&term, "Permission or row with m (mandatory), o (optional) or - (remove), \
+                                    /text to filter, q to quit, empty to save", "");
+        match parse_action(&answer, &shown) {
+            Ok(Action::Filter(v)) => filter_term = v,
+            Ok(Action::Select(id, kind)) => {
+                if !permissions.contains_key(&id) {
+                    let _ = term.write_line(&format!("Unknown permission {}", id));
+                    continue;
+                }
+                match kind {
+                    Some(kind) => selection.insert(id, kind),
+                    None => selection.remove(&id),
+                };
+            }
+            Ok(Action::Save) => break,
+            Ok(Action::Quit) => return true,
+            Err(message) => {
+                let _ = term.write_line(&message);
+            }
+        }
+    }
+    apply_selection(&mut data["services"][service.as_str()], &selection);
+    let written = serde_yaml::to_string(&data).map_err(failure::Error::from)
+        .and_then(|v| std::fs::write(input_file, v).map_err(failure::Error::from));
+    if let Err(e) = written {
+        error!("{} Failed to write {}: {}", error_codes::FILE_ACCESS, input_file.display(), e);
+        return false;
+    }
+    output::line(&format!("Wrote {} permissions of the service {}", selection.len(), service));
+    warn!("{} was rewritten. Comments and formatting are not preserved.", input_file.display());
+    true
+}
+
+#[test]
+fn permissions_test() {
+    let mut permissions = AddonPermissions::new();
+    for (id, label) in &[("HW_GPIO", "Generic input/output pins"), ("HW_W1", "OneWire Hardware")] {
+        permissions.insert(id.to_string(), AddonPermission { id: id.to_string(), label: label.to_string(), ..Default::default() });
+    }
+    let shown = filter(&permissions, "onewire");
+    assert_eq!(shown.len(), 1);
+    assert_eq!(parse_action("1 o", &shown), Ok(Action::Select("HW_W1".to_owned(), Some(Kind::Optional))));
+    assert_eq!(parse_action("hw_gpio", &shown), Ok(Action::Select("HW_GPIO".to_owned(), Some(Kind::Mandatory))));
+    assert_eq!(parse_action("/pin", &shown), Ok(Action::Filter("pin".to_owned())));
+    assert_eq!(parse_action("", &shown), Ok(Action::Save));
+    assert!(parse_action("2 m", &shown).is_err());
+    assert!(parse_action("HW_GPIO x", &shown).is_err());
+
+    let mut service: Value = serde_yaml::from_str("image: app\npermissions: {mandatory: [HW_W1], optional: []}\n").unwrap();
+    let mut selection = selection_of(&service);
+    assert_eq!(selection.get("HW_W1"), Some(&Kind::Mandatory));
+    selection.insert("HW_GPIO".to_owned(), Kind::Optional);
+    apply_selection(&mut service, &selection);
+    assert_eq!(service["permissions"]["optional"][0].as_str(), Some("HW_GPIO"));
+    assert_eq!(service["permissions"]["mandatory"][0].as_str(), Some("HW_W1"));
+}
@@ -0,0 +1,156 @@
+//! An ordered sequence of stages over a shared context. Subcommands compose the stages they need, like validate-only,
+//! build-only or publish, and share the orchestration: Cancellation between stages, hooks and reporters.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A stage returns None if it failed. It logs the reason itself.
+type Stage<'a, C> = Box<dyn FnMut(&mut C) -> Option<()> + 'a>;
+type Hook<'a, C> = Box<dyn FnMut(&str, &mut C) + 'a>;
+
+/// Cancels a running pipeline before its next stage. Clones share the state, so another thread can cancel.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Completed,
+    /// The stage that failed
+    Failed(&'static str),
+    /// The stage that did not run anymore
+    Cancelled(&'static str),
+}
+
+impl Outcome {
+    pub fn is_completed(&self) -> bool {
+        *self == Outcome::Completed
+    }
+}
+
+/// Is told about each stage, for example to show the progress
+pub trait Reporter {
+    /// `index` counts from 0 up to `count`
+    fn started(&self, _stage: &str, _index: usize, _count: usize) {}
+    fn finished(&self, _stage: &str, _success: bool) {}
+}
+
+pub struct Pipeline<'a, C> {
+    stages: Vec<(&'static str, Stage<'a, C>)>,
+    before_each: Vec<Hook<'a, C>>,
+    after_each: Vec<Hook<'a, C>>,
+    reporters: Vec<Box<dyn Reporter + 'a>>,
+    cancellation: CancellationToken,
+}
+
+impl<'a, C> Default for Pipeline<'a, C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, C> Pipeline<'a, C> {
+    pub fn new() -> Self {
+        Pipeline { stages: Vec::new(), before_each: Vec::new(), after_each: Vec::new(), reporters: Vec::new(),
+            cancellation: CancellationToken::new() }
+    }
+
+    /// Appends a stage
+    pub fn stage<F: FnMut(&mut C) -> Option<()> + 'a>(mut self, name: &'static str, stage: F) -> Self {
+        self.stages.push((name, Box::new(stage)));
+        self
+    }
+
+    /// Called with the name of the stage before it runs
+    pub fn before_each<F: FnMut(&str, &mut C) + 'a>(mut self, hook: F) -> Self {
+        self.before_each.push(Box::new(hook));
+        self
+    }
+
+    /// Called with the name of the stage after it succeeded
+    pub fn after_each<F: FnMut(&str, &mut C) + 'a>(mut self, hook: F) -> Self {
+        self.after_each.push(Box::new(hook));
+        self
+    }
+
+    pub fn reporter<R: Reporter + 'a>(mut self, reporter: R) -> Self {
+        self.reporters.push(Box::new(reporter));
+        self
+    }
+
+    /// Uses the given token instead of an own one, see [`Pipeline::cancellation_token`]
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// The names of the stages, in order
+    pub fn stages(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// Runs the stages in order until one fails or the pipeline is cancelled
+    pub fn run(mut self, context: &mut C) -> Outcome {
+        let count = self.stages.len();
+        for (index, (name, stage)) in self.stages.iter_mut().enumerate() {
+            let name = *name;
+            if self.cancellation.is_cancelled() {
+                return Outcome::Cancelled(name);
+            }
+            for hook in self.before_each.iter_mut() {
+                hook(name, context);
+            }
+            for reporter in &self.reporters {
+                reporter.started(name, index, count);
+            }
+            let success = stage(context).is_some();
+            for reporter in &self.reporters {
+                reporter.finished(name, success);
+            }
+            if !success {
+                return Outcome::Failed(name);
+            }
+            for hook in self.after_each.iter_mut() {
+                hook(name, context);
+            }
+        }
+        Outcome::Completed
+    }
+}
+
+#[test]
+fn pipeline_test() {
+    let mut context: Vec<String> = Vec::new();
+    let outcome = Pipeline::new()
+        .stage("a", |c: &mut Vec<String>| { c.push("a".to_owned()); Some(()) })
+        .stage("b", |_: &mut Vec<String>| None)
+        .stage("c", |c: &mut Vec<String>| { c.push("c".to_owned()); Some(()) })
+        .after_each(|name, c: &mut Vec<String>| c.push(format!("after {}", name)))
+        .run(&mut context);
+    assert_eq!(outcome, Outcome::Failed("b"));
+    assert_eq!(context, vec!["a", "after a"]);
+
+    let mut context: Vec<String> = Vec::new();
+    let pipeline = Pipeline::new().stage("a", |c: &mut Vec<String>| { c.push("a".to_owned()); Some(()) });
+    let token = pipeline.cancellation_token();
+    let pipeline = pipeline.after_each(move |_, _| token.cancel()).stage("b", |c: &mut Vec<String>| { c.push("b".to_owned()); Some(()) });
+    assert_eq!(pipeline.stages(), vec!["a", "b"]);
+    assert_eq!(pipeline.run(&mut context), Outcome::Cancelled("b"));
+    assert_eq!(context, vec!["a"]);
+}
@@ -0,0 +1,158 @@
+//! Detection of the installed podman version and the features it supports.
+use crate::error_codes;
+use crate::namespace;
+use log::{debug, error, info};
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+/// The minimum podman version that is able to build and push images for the registry
+pub(crate) const MIN_VERSION: (u64, u64, u64) = (1, 5, 0);
+
+/// Features of the container engine that the pipeline makes use of if available.
+#[derive(Debug)]
+pub(crate) struct PodmanCapabilities {
+    pub(crate) version: semver::Version,
+    /// `podman manifest` for multi-arch manifest lists
+    pub(crate) manifest_lists: bool,
+    /// `podman push --digestfile` to record the pushed image digest
+    pub(crate) digest_file: bool,
+    /// `podman save --format oci-archive`
+    pub(crate) oci_archive: bool,
+    /// `podman build --secret`
+    pub(crate) build_secrets: bool,
+}
+
+impl PodmanCapabilities {
+    /// A list of feature names that are not supported by the installed engine
+    pub(crate) fn missing_features(&self) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        if !self.manifest_lists {
+            missing.push("manifest lists");
+        }
+        if !self.digest_file {
+            missing.push("push --digestfile");
+        }
+        if !self.oci_archive {
+            missing.push("oci-archive");
+        }
+        if !self.build_secrets {
+            missing.push("build secrets");
+        }
+        missing
+    }
+}
+
+#[derive(Deserialize)]
+struct PodmanVersion {
+    #[serde(rename = "Version")]
+    version: String,
+}
+
+/// podman 1.x prints the version at the top level, newer versions group it under "Client".
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PodmanVersionResult {
+    Nested {
+        #[serde(rename = "Client")]
+        client: PodmanVersion,
+    },
+    Flat(PodmanVersion),
+}
+
+#[derive(Deserialize)]
+struct PodmanStore {
+    /// podman 1.x names it "GraphRoot"
+    #[serde(rename = "graphRoot", alias = "GraphRoot")]
+    graph_root: String,
+}
+
+#[derive(Deserialize)]
+struct PodmanInfo {
+    store: PodmanStore,
+}
+
+/// Returns false and prints an error if the image storage of podman belongs to another user. With a shared HOME
+/// or an inherited CONTAINERS_STORAGE_CONF, rootless podman would fail with permission errors or build on the
+/// image layers of the other user.
+pub(crate) fn check_storage() -> bool {
+    let info: Result<PodmanInfo, _> = Command::new("podman")
+        .args(["info", "--format", "json"])
+        .output()
+        .and_then(|f| serde_json::from_slice(&f.stdout).map_err(std::io::Error::from));
+    let graph_root = match info {
+        Ok(v) => v.store.graph_root,
+        Err(e) => {
+            debug!("Could not read the podman storage location: {:?}", e);
+            return true;
+        }
+    };
+    if namespace::is_foreign(Path::new(&graph_root)) {
+        error!("{} The podman storage {} belongs to another user. Use your own HOME and unset CONTAINERS_STORAGE_CONF, \
+                or configure your own storage in ~/.config/containers/storage.conf.", error_codes::ENGINE_STORAGE, graph_root);
+        return false;
+    }
+    info!("Podman storage: {}", graph_root);
+    true
+}
+
+/// Returns true if `podman <args> --help` succeeds and the help text contains `needle`.
+fn help_contains(args: &[&str], needle: &str) -> bool {
+    Command::new("podman")
+        .args(args)
+        .arg("--help")
+        .output()
+        .map(|o| o.status.success() && String::from_utf8_lossy(&o.stdout).contains(needle))
+        .unwrap_or(false)
+}
+
+/// Checks for podman and probes its features. Returns None and prints a targeted error message if
+/// podman is missing or too old.
+pub(crate) fn detect() -> Option<PodmanCapabilities> {
+    let version: Result<PodmanVersionResult, _> = Command::new("podman")
+        .arg("version")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .and_then(|f| serde_json::from_slice(&f.stdout).map_err(std::io::Error::from));
+
+    let version = match version {
+        Ok(PodmanVersionResult::Nested { client }) => client.version,
+        Ok(PodmanVersionResult::Flat(v)) => v.version,
+        Err(e) => {
+            error!("{} 'podman' is required to build software containers. Please check https://podman.io/getting-started/installation. {:?}", error_codes::ENGINE_MISSING, e);
+            return None;
+        }
+    };
+
+    let version = match semver::Version::from_str(&version) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} Could not parse the podman version '{}': {:?}", error_codes::ENGINE_VERSION, version, e);
+            return None;
+        }
+    };
+
+    let (major, minor, patch) = MIN_VERSION;
+    if version < semver::Version::new(major, minor, patch) {
+        error!("{} 'podman' {}.{}.{} or better is required. Please check https://podman.io/getting-started/installation.",
+               error_codes::ENGINE_VERSION, major, minor, patch);
+        return None;
+    }
+
+    let capabilities = PodmanCapabilities {
+        version,
+        manifest_lists: help_contains(&["manifest"], "create"),
+        digest_file: help_contains(&["push"], "--digestfile"),
+        oci_archive: help_contains(&["save"], "oci-archive"),
+        build_secrets: help_contains(&["build"], "--secret"),
+    };
+
+    info!("Found Podman version {}", capabilities.version);
+    let missing = capabilities.missing_features();
+    if !missing.is_empty() {
+        info!("Podman features not available: {}", missing.join(", "));
+    }
+    Some(capabilities)
+}
@@ -0,0 +1,168 @@
+//! Integrity of the validation policy data that the registry publishes, like the addon type profiles.
+//! The registry signs a manifest with the sha256 digest of each policy file (ed25519). `policy update` verifies it
+//! and pins the registry key and the digests in `.ohx-policy.lock` of the current directory. With a lock file,
+//! fetched data that does not match its pin is rejected: A compromised CDN or a MITM can not relax the validation
+//! rules that a CI relies on. Commit the lock file. The key of a registry is trusted on the first `policy update`.
+use crate::cache;
+use crate::dto::addons;
+use crate::endpoints;
+use crate::error_codes;
+use crate::output;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Relative to the current directory, like the project configuration file
+pub(crate) const LOCK_FILE: &str = ".ohx-policy.lock";
+
+/// The policy file names, below the registry url
+pub(crate) const TYPE_PROFILES: &str = "type-profiles";
+pub(crate) const PERMISSIONS: &str = "permissions";
+
+#[derive(Deserialize)]
+struct Manifest {
+    /// Hex encoded ed25519 public key of the registry
+    public_key: String,
+    /// Digests like "sha256:..." by file name
+    files: BTreeMap<String, String>,
+    /// Hex encoded ed25519 signature of the canonical JSON of `files`
+    signature: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Lock {
+    registry_url: String,
+    public_key: String,
+    files: BTreeMap<String, String>,
+}
+
+fn read_lock() -> Option<Lock> {
+    let data = std::fs::read_to_string(LOCK_FILE).ok()?;
+    match toml::from_str(&data) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            warn!("Ignoring the invalid {}: {}", LOCK_FILE, e);
+            None
+        }
+    }
+}
+
+fn digest(data: &str) -> String {
+    format!("sha256:{:x}", Sha256::digest(data.as_bytes()))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok())).collect()
+}
+
+/// Checks fetched policy data against its pin. Unpinned data is accepted.
+pub(crate) fn verify(name: &str, data: &str) -> bool {
+    let lock = match read_lock() {
+        Some(v) => v,
+        None => {
+            info!("The policy data is not pinned. Run `policy update` to create {}.", LOCK_FILE);
+            return true;
+        }
+    };
+    if lock.registry_url != endpoints::registry_url() {
+        warn!("{} pins the policy of {}, not of {}. The policy data is not checked.", LOCK_FILE, lock.registry_url, endpoints::registry_url());
+        return true;
+    }
+    let actual = digest(data);
+    match lock.files.get(name) {
+        Some(pinned) if *pinned == actual => true,
+        Some(pinned) => {
+            error!("{} The {} of the registry ({}) do not match the pin in {} ({}). Run `policy update` if the registry \
+                    changed its policy.", error_codes::POLICY_MISMATCH, name, actual, LOCK_FILE, pinned);
+            false
+        }
+        None => {
+            warn!("{} does not pin the {}. Run `policy update`.", LOCK_FILE, name);
+            true
+        }
+    }
+}
+
+/// Fetches the signed manifest and the policy files, verifies them and writes the lock file.
+/// A changed registry key is only accepted with `trust_new_key`.
+pub(crate) fn update(client: &reqwest::Client, trust_new_key: bool) -> bool {
+    let registry_url = endpoints::registry_url();
+    let url = format!("{}/policy-manifest", registry_url);
+    let manifest: Manifest = match client.get(&url).send().and_then(|r| r.error_for_status()).and_then(|mut r| r.json()) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} Failed to download the policy manifest {}: {}", error_codes::REGISTRY_UNREACHABLE, url, e);
+            return false;
+        }
+    };
+
+    let previous = read_lock().filter(|l| l.registry_url == registry_url);
+    match &previous {
+        Some(lock) if lock.public_key != manifest.public_key && !trust_new_key => {
+            error!("{} The policy key of {} changed from {} to {}. Use --trust-new-key if the registry announced a new key.",
+                   error_codes::POLICY_MISMATCH, registry_url, lock.public_key, manifest.public_key);
+            return false;
+        }
+        Some(lock) if lock.public_key == manifest.public_key => {}
+        _ => warn!("Trusting the policy key {} of {}", manifest.public_key, registry_url),
+    }
+
+    let message = addons::to_canonical_json(&manifest.files).expect("serializable manifest");
+    let verified = match (decode_hex(&manifest.public_key), decode_hex(&manifest.signature)) {
+        (Some(key), Some(signature)) => ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, key)
+            .verify(&message, &signature).is_ok(),
+        _ => false,
+    };
+    if !verified {
+        error!("{} The signature of the policy manifest {} is invalid", error_codes::POLICY_MISMATCH, url);
+        return false;
+    }
+
+    for (name, expected) in &manifest.files {
+        let file_url = format!("{}/{}", registry_url, name);
+        let data = match client.get(&file_url).send().and_then(|r| r.error_for_status()).and_then(|mut r| r.text()) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{} Failed to download {}: {}", error_codes::REGISTRY_UNREACHABLE, file_url, e);
+                return false;
+            }
+        };
+        if digest(&data) != *expected {
+            error!("{} {} does not match the digest of the signed policy manifest", error_codes::POLICY_MISMATCH, file_url);
+            return false;
+        }
+        // The next validation reads the verified data from the cache
+        cache::put(cache::HTTP, &file_url, &data);
+    }
+
+    let previous_files = previous.map(|l| l.files).unwrap_or_default();
+    for (name, pin) in &manifest.files {
+        match previous_files.get(name) {
+            Some(v) if v == pin => output::line(&format!("{}: unchanged", name)),
+            Some(v) => output::line(&format!("{}: {} (was {})", name, pin, v)),
+            None => output::line(&format!("{}: {}", name, pin)),
+        }
+    }
+    let lock = Lock { registry_url, public_key: manifest.public_key, files: manifest.files };
+    let data = format!("# Pins the registry policy data. Update with `ohx-addon-publish policy update`.\n{}",
+                       toml::to_string(&lock).expect("serializable lock"));
+    if let Err(e) = std::fs::write(LOCK_FILE, data) {
+        error!("{} Failed to write {}: {}", error_codes::FILE_ACCESS, Path::new(LOCK_FILE).display(), e);
+        return false;
+    }
+    output::line(&format!("Wrote {}", LOCK_FILE));
+    true
+}
+
+#[test]
+fn decode_hex_test() {
+    assert_eq!(decode_hex("00ff10"), Some(vec![0, 255, 16]));
+    assert_eq!(decode_hex("0"), None);
+    assert_eq!(decode_hex("zz"), None);
+}
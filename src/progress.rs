@@ -0,0 +1,127 @@
+//! Live publish status for the registry website, with `publish --report-progress`. A publish job is created
+//! before the build and each stage and the result are posted to it. Older servers without publish jobs answer
+//! with 404, the publish then continues without status updates. A failed status update never fails the publish.
+use crate::endpoints;
+use crate::error_codes;
+use crate::login::UserSession;
+use ohx_addon_publish::pipeline::Reporter;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+/// Sent with the registry entry, so that the server assigns the entry to the job
+pub(crate) const PUBLISH_JOB_HEADER: &str = "X-Publish-Job";
+
+struct Job {
+    client: reqwest::Client,
+    session: UserSession,
+    url: String,
+    id: String,
+    /// After a failed update no further updates are sent
+    failed: bool,
+}
+
+thread_local! {
+    static JOB: RefCell<Option<Job>> = const { RefCell::new(None) };
+}
+
+#[derive(Serialize)]
+struct JobRequest<'a> {
+    addon_id: &'a str,
+    version: &'a str,
+    sandbox: bool,
+}
+
+#[derive(Deserialize)]
+struct JobCreated {
+    job_id: String,
+}
+
+#[derive(Serialize)]
+struct Progress<'a> {
+    stage: &'a str,
+}
+
+#[derive(Serialize)]
+struct JobResult {
+    success: bool,
+    /// The first logged error, like "OHX-E030"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
+
+/// Creates the publish job. Without a job, [`stage`] and [`finish`] do nothing.
+pub(crate) fn start(client: &reqwest::Client, session: &UserSession, addon_id: &str, version: &str, sandbox: bool) {
+    let url = format!("{}/publish-job", endpoints::registry_url());
+    let request = JobRequest { addon_id, version, sandbox };
+    let id = match session.authorize(client.post(&url)).json(&request).send() {
+        Ok(mut response) => match response.status().as_u16() {
+            200 | 201 => match response.json::<JobCreated>() {
+                Ok(v) => v.job_id,
+                Err(e) => {
+                    warn!("Invalid publish job response: {}. The publish status is not shown on the website.", e);
+                    return;
+                }
+            },
+            404 | 405 | 501 => {
+                info!("The registry does not support publish jobs. The publish status is not shown on the website.");
+                return;
+            }
+            status => {
+                warn!("Failed to create a publish job ({}). The publish status is not shown on the website.", status);
+                return;
+            }
+        },
+        Err(e) => {
+            warn!("Failed to create a publish job: {}. The publish status is not shown on the website.", e);
+            return;
+        }
+    };
+    info!("Publish job {}", id);
+    JOB.with(|j| *j.borrow_mut() = Some(Job { client: client.clone(), session: session.clone(), url, id, failed: false }));
+}
+
+/// The id of the publish job, if there is one
+pub(crate) fn job_id() -> Option<String> {
+    JOB.with(|j| j.borrow().as_ref().map(|job| job.id.clone()))
+}
+
+fn send<T: Serialize>(path: &str, body: &T) {
+    JOB.with(|j| {
+        let mut job = j.borrow_mut();
+        let job = match job.as_mut() {
+            Some(v) if !v.failed => v,
+            _ => return,
+        };
+        let url = format!("{}/{}/{}", job.url, job.id, path);
+        let result = job.session.authorize(job.client.post(&url)).json(body).send();
+        let error = match result {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => response.status().to_string(),
+            Err(e) => e.to_string(),
+        };
+        warn!("Failed to update the publish status ({}). No further updates are sent.", error);
+        job.failed = true;
+    });
+}
+
+/// Reports that a stage like "build" or "upload" started. The previous stage is done.
+pub(crate) fn stage(stage: &str) {
+    send("progress", &Progress { stage });
+}
+
+/// Reports each stage of a publish pipeline to the job
+pub(crate) struct JobReporter;
+
+impl Reporter for JobReporter {
+    fn started(&self, name: &str, _index: usize, _count: usize) {
+        stage(name);
+    }
+}
+
+/// Reports the result and ends the job
+pub(crate) fn finish(success: bool) {
+    let error_code = if success { None } else { error_codes::first_error_code() };
+    send("result", &JobResult { success, error_code });
+    JOB.with(|j| *j.borrow_mut() = None);
+}
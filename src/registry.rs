@@ -1,20 +1,47 @@
+use crate::buildah;
+use crate::cache;
 use crate::dto::{addons, BuildInstruction};
+use crate::dto::type_profiles::{self, TypeProfiles};
+use crate::endpoints;
+use crate::error_codes;
+use crate::http;
+use crate::policy;
+use crate::progress;
+use crate::report;
+use crate::state;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{Write, Read};
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, Duration};
-use log::error;
-use crate::dto::addons::AddonFileEntry;
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use crate::dto::addons::{AddonFileEntry, AttachmentReferrer};
 use crate::login::UserSession;
 
+/// The registry index is cached in this file for a few minutes. Other index urls have their own file.
+pub(crate) fn registry_cache_file() -> PathBuf {
+    let index_url = endpoints::index_url();
+    let file = match index_url == addons::REGISTRY_DATA_URL {
+        true => ".ohx_registry_cache".to_owned(),
+        false => format!(".ohx_registry_cache_{}", &format!("{:x}", Sha256::digest(index_url.as_bytes()))[..16]),
+    };
+    dirs::config_dir().unwrap().join(file)
+}
+
 pub(crate) fn addon_registry(client: &reqwest::Client) -> Option<addons::AddonEntryMap> {
-    let registry_cache = dirs::config_dir().unwrap().join(".ohx_registry_cache");
+    let registry_cache = registry_cache_file();
     let cache_time: Option<Duration> = registry_cache.metadata().and_then(|m| m.modified()).ok().and_then(|m| SystemTime::now().duration_since(m).ok());
     let mut registry_content: Option<addons::AddonEntryMap> = None;
+    // Offline, the cache is used regardless of its age
     if let Some(duration) = cache_time {
-        if duration.as_secs() < 500 {
+        if duration.as_secs() < 500 || http::is_offline() {
             let mut buffer = Vec::new();
             if let Ok(mut f) = File::open(&registry_cache) {
-                if let Ok(_) = f.read_to_end(&mut buffer) {
+                if f.read_to_end(&mut buffer).is_ok() {
                     if let Ok(v) = serde_json::from_slice(&buffer) {
                         registry_content = Some(v)
                     }
@@ -25,15 +52,19 @@ pub(crate) fn addon_registry(client: &reqwest::Client) -> Option<addons::AddonEn
 
     let registry_cache = match registry_content {
         Some(v) => v,
+        None if http::is_offline() => {
+            error!("{} No cached registry index at {}", error_codes::OFFLINE, registry_cache.display());
+            return None;
+        }
         None => {
-            match addons::get_addons_registry(&client) {
+            match addons::get_addons_registry(client, &endpoints::index_url()) {
                 Ok(v) => {
                     // Write to cache
                     File::create(&registry_cache).unwrap().write_all(&serde_json::to_vec(&v).unwrap()).unwrap();
                     v
                 }
                 Err(e) => {
-                    error!("Failed to update registry cache: {:?}", e);
+                    error!("{} Failed to update registry cache: {:?}", error_codes::FILE_ACCESS, e);
                     return None;
                 }
             }
@@ -43,37 +74,296 @@ pub(crate) fn addon_registry(client: &reqwest::Client) -> Option<addons::AddonEn
     Some(registry_cache)
 }
 
-pub(crate) fn post_to_registry(client: &reqwest::Client, build_instructions: &mut Vec<BuildInstruction>,
-                        input_file: &AddonFileEntry,
-                        session: &UserSession) -> bool {
+thread_local! {
+    static TYPE_PROFILES: RefCell<Option<TypeProfiles>> = const { RefCell::new(None) };
+    static PERMISSIONS: RefCell<Option<addons::AddonPermissions>> = const { RefCell::new(None) };
+}
+
+/// Fetches a policy file of the registry, cached like all responses. None if it does not match its pin,
+/// see [`policy::verify`].
+fn fetch_policy<T: DeserializeOwned>(client: &reqwest::Client, name: &str) -> Option<Result<T, failure::Error>> {
+    let url = format!("{}/{}", endpoints::registry_url(), name);
+    // The digest is taken of the raw response
+    let data = cache::cached_get::<String, _>(&url, || Ok(client.get(&url).send()?.error_for_status()?.text()?));
+    match data {
+        Ok(data) if !policy::verify(name, &data) => None,
+        Ok(data) => Some(serde_json::from_str(&data).map_err(failure::Error::from)),
+        Err(e) => Some(Err(e)),
+    }
+}
+
+/// Loads the addon type profiles of the registry. Offline or if the registry does not publish them,
+/// the bundled profiles are used. Returns false if the profiles do not match their pin.
+pub(crate) fn load_type_profiles(client: &reqwest::Client) -> bool {
+    let profiles = match fetch_policy(client, policy::TYPE_PROFILES) {
+        Some(Ok(v)) => v,
+        Some(Err(e)) => {
+            info!("Using the bundled addon type profiles: {}", e);
+            type_profiles::bundled()
+        }
+        None => return false,
+    };
+    TYPE_PROFILES.with(|p| *p.borrow_mut() = Some(profiles));
+    true
+}
+
+/// Loads the permission catalogue of the registry, so that permissions are known without a CLI release.
+/// Offline or if the registry does not publish it, the bundled catalogue is used. Returns false if it does not match its pin.
+pub(crate) fn load_permissions(client: &reqwest::Client) -> bool {
+    let permissions = match fetch_policy(client, policy::PERMISSIONS) {
+        Some(Ok(v)) => v,
+        Some(Err(e)) => {
+            info!("Using the bundled permission catalogue: {}", e);
+            addons::bundled_permissions()
+        }
+        None => return false,
+    };
+    PERMISSIONS.with(|p| *p.borrow_mut() = Some(permissions));
+    true
+}
+
+/// The loaded permission catalogue, or the bundled one
+pub(crate) fn permissions() -> addons::AddonPermissions {
+    PERMISSIONS.with(|p| p.borrow().clone()).unwrap_or_else(addons::bundled_permissions)
+}
+
+/// The loaded addon type profiles, or the bundled ones
+pub(crate) fn type_profiles() -> TypeProfiles {
+    TYPE_PROFILES.with(|p| p.borrow().clone()).unwrap_or_else(type_profiles::bundled)
+}
+
+/// Creates the registry payload out of the addon description and the build results
+pub(crate) fn create_payload(build_instructions: &[BuildInstruction], input_file: &AddonFileEntry,
+                             wasm_artifacts: &BTreeMap<String, String>,
+                             referrers: Vec<AttachmentReferrer>) -> addons::AddonFileEntryPlusStats {
+    // Architectures excluded by --arch are not published
+    let build_instructions: Vec<&BuildInstruction> = build_instructions.iter().filter(|b| !b.skipped).collect();
+    let variants = input_file.x_variants.iter().map(|(name, variant)| {
+        let images: Vec<&&BuildInstruction> = build_instructions.iter().filter(|b| b.variant.as_ref() == Some(name)).collect();
+        (name.clone(), addons::VariantEntry {
+            tag_suffix: variant.tag_suffix(name),
+            archs: images.iter().map(|e| e.arch.to_owned()).collect(),
+            sizes: images.iter().map(|e| (e.arch.to_owned(), e.image_size)).collect(),
+        })
+    }).collect();
+    // The top level entries describe the default variant. Recipe images have no variant.
+    let default_variant = input_file.x_variants.iter().find(|(name, variant)| variant.tag_suffix(name).is_empty()).map(|(name, _)| name);
+    let build_instructions: Vec<&BuildInstruction> = build_instructions.into_iter()
+        .filter(|b| b.variant.is_none() || b.variant.as_ref() == default_variant).collect();
+    let instructions = std::cmp::max(build_instructions.len() as i64, 1);
     let mut reg_entry = addons::AddonFileEntryPlusStats {
         services: input_file.services.clone(),
         x_ohx_registry: input_file.x_ohx_registry.clone(),
         x_runtime: input_file.x_runtime.clone(),
         archs: build_instructions.iter().map(|e| e.arch.to_owned()).collect(),
         // Average of all arch sizes
-        size: (build_instructions.iter().fold(0, |acc, build_instruction| acc + build_instruction.image_size) / build_instructions.len() as i64),
+        size: (build_instructions.iter().fold(0, |acc, build_instruction| acc + build_instruction.image_size) / instructions),
+        sizes: build_instructions.iter().map(|e| (e.arch.to_owned(), e.image_size)).collect(),
+        platforms: build_instructions.iter().map(|e| (e.arch.to_owned(), buildah::platform(&e.arch))).collect(),
+        referrers,
+        provenance: None,
+        rebuild_reason: None,
+        suppressions: Vec::new(),
+        variants,
+        // Validation rejects dependency cycles
+        startup_order: addons::startup_order(&input_file.services).unwrap_or_default(),
     };
-    for (_service_id, service) in &mut reg_entry.services {
+    for (service_id, service) in &mut reg_entry.services {
+        // WASM services point to their pushed OCI artifact
+        if let Some(artifact) = wasm_artifacts.get(service_id) {
+            service.wasm = None;
+            service.image = Some(artifact.clone());
+            continue;
+        }
         // Only replace entries that have a "build" set
         if service.build.is_none() {
             continue;
         }
         service.build = None;
-        service.image = Some(format!("docker.io/openhabx/{}:{}", &input_file.x_ohx_registry.id, &input_file.x_ohx_registry.version))
+        service.image = Some(endpoints::image_name(&input_file.x_ohx_registry.id, &input_file.x_ohx_registry.version))
+    }
+    reg_entry
+}
+
+/// A feature flag of the registry server: Accepts a per-architecture size map
+pub(crate) const FEATURE_PER_ARCH_SIZE: &str = "per-arch-size";
+
+#[derive(Default, Deserialize)]
+pub(crate) struct ServerCapabilities {
+    #[serde(default)]
+    pub(crate) features: Vec<String>,
+}
+
+/// Ask the registry server for its supported features.
+/// Older servers do not know this endpoint, in which case no optional features are assumed.
+pub(crate) fn server_capabilities(client: &reqwest::Client) -> ServerCapabilities {
+    match client.get(&format!("{}/capabilities", endpoints::registry_url())).send() {
+        Ok(mut response) if response.status() == 200 => response.json().unwrap_or_default(),
+        Ok(response) => {
+            info!("Registry server capabilities not available ({}). Assuming an older server.", response.status());
+            ServerCapabilities::default()
+        }
+        Err(e) => {
+            info!("Failed to query registry server capabilities: {:?}", e);
+            ServerCapabilities::default()
+        }
+    }
+}
+
+/// The sha256 digest of the canonical JSON form of the payload
+pub(crate) fn payload_digest(payload: &addons::AddonFileEntryPlusStats) -> Result<String, failure::Error> {
+    Ok(format!("sha256:{:x}", Sha256::digest(&addons::to_canonical_json(payload)?)))
+}
+
+enum LazyState {
+    NotLoaded,
+    Loading(std::thread::JoinHandle<Option<addons::AddonEntryMap>>),
+    Loaded(Option<addons::AddonEntryMap>),
+}
+
+/// The registry index, loaded on first use. [`LazyRegistry::prefetch`] starts loading in the background
+/// if it is known that the index will be required.
+pub(crate) struct LazyRegistry {
+    client: reqwest::Client,
+    state: LazyState,
+}
+
+impl LazyRegistry {
+    pub(crate) fn new(client: &reqwest::Client) -> Self {
+        LazyRegistry { client: client.clone(), state: LazyState::NotLoaded }
     }
 
-    match client.post("https://registry.openhabx.com/addon").bearer_auth(&session.access_token).json(&reg_entry).send() {
+    pub(crate) fn prefetch(&mut self) {
+        if let LazyState::NotLoaded = self.state {
+            let client = self.client.clone();
+            self.state = LazyState::Loading(std::thread::spawn(move || addon_registry(&client)));
+        }
+    }
+
+    /// Returns the registry index. A failed download is not retried.
+    pub(crate) fn get(&mut self) -> Option<&addons::AddonEntryMap> {
+        let loaded = match std::mem::replace(&mut self.state, LazyState::NotLoaded) {
+            LazyState::NotLoaded => addon_registry(&self.client),
+            LazyState::Loading(handle) => handle.join().unwrap_or(None),
+            LazyState::Loaded(v) => v,
+        };
+        self.state = LazyState::Loaded(loaded);
+        match &self.state {
+            LazyState::Loaded(v) => v.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Sends a registry mutation with an idempotency key. The key is kept in the build state until the registry answered,
+/// so that a retry after a timeout, also in a later run, can not apply the mutation twice.
+/// Connection errors and timeouts are retried with the same key, see [`http::retry_policy`].
+fn send_mutation(build_directory: &Path, operation: &str, request: &dyn Fn() -> reqwest::RequestBuilder)
+                 -> Result<reqwest::Response, reqwest::Error> {
+    let idempotency_key = state::idempotency_key(build_directory, operation);
+    let retry = http::retry_policy();
+    let mut backoff = retry.initial_backoff;
+    let mut result = request().header(IDEMPOTENCY_KEY_HEADER, idempotency_key.as_str()).send();
+    for _ in 1..retry.attempts {
+        match &result {
+            Err(e) => warn!("Retrying {}: {}", operation, e),
+            Ok(_) => break,
+        }
+        std::thread::sleep(backoff);
+        backoff *= 2;
+        result = request().header(IDEMPOTENCY_KEY_HEADER, idempotency_key.as_str()).send();
+    }
+    if result.is_ok() {
+        state::acknowledge(build_directory, operation);
+    }
+    result
+}
+
+/// Post the registry entry. Sandbox entries are not publicly listed and expire automatically.
+pub(crate) fn post_to_registry(client: &reqwest::Client, mut reg_entry: addons::AddonFileEntryPlusStats,
+                        session: &UserSession, sandbox: bool, build_directory: &Path) -> bool {
+    // Older servers only know the average size
+    if !server_capabilities(client).features.iter().any(|f| f == FEATURE_PER_ARCH_SIZE) {
+        reg_entry.sizes.clear();
+    }
+    let body = addons::to_canonical_json(&reg_entry).expect("serializable registry payload");
+    let url = match sandbox {
+        true => format!("{}/sandbox/addon", endpoints::registry_url()),
+        false => format!("{}/addon", endpoints::registry_url()),
+    };
+
+    let operation = format!("POST {} sha256:{:x}", url, Sha256::digest(&body));
+    let job_id = progress::job_id();
+    let request = || {
+        let request = session.authorize(client.post(&url))
+            .header(reqwest::header::CONTENT_TYPE, "application/json").body(body.clone());
+        match &job_id {
+            Some(id) => request.header(progress::PUBLISH_JOB_HEADER, id.as_str()),
+            None => request,
+        }
+    };
+    match send_mutation(build_directory, &operation, &request) {
         Ok(mut response) => {
+            let text = response.text().unwrap_or_default();
+            report::registry_response(&url, response.status().as_u16(), &text);
             if response.status() != 200 {
-                error!("Unexpected response!\n{:?}", response.text().unwrap());
+                error!("{} Unexpected response!\n{:?}", error_codes::REGISTRY_REJECTED, text);
                 return false;
             }
         }
         Err(err) => {
-            error!("Failed to contact https://vault.openhabx.com/get/docker-access.json!\n{:?}", err);
+            error!("{} Failed to contact {}!\n{:?}", error_codes::REGISTRY_UNREACHABLE, url, err);
             return false;
         }
     };
     true
-}
\ No newline at end of file
+}
+/// Remove a published version of an addon from the registry. Only the owner is allowed to do this.
+pub(crate) fn unpublish(client: &reqwest::Client, session: &UserSession, addon_id: &str, version: &str,
+                        build_directory: &Path) -> bool {
+    let url = format!("{}/addon/{}/{}", endpoints::registry_url(), addon_id, version);
+    let request = || session.authorize(client.delete(&url));
+    match send_mutation(build_directory, &format!("DELETE {}", url), &request) {
+        Ok(mut response) => match response.status().as_u16() {
+            200 | 204 => true,
+            403 => {
+                error!("{} You are not the owner of {}", error_codes::NOT_OWNER, addon_id);
+                false
+            }
+            404 => {
+                error!("{} Version {} of {} is not published", error_codes::NOT_FOUND, version, addon_id);
+                false
+            }
+            _ => {
+                error!("{} Unexpected response!\n{:?}", error_codes::REGISTRY_REJECTED, response.text().unwrap_or_default());
+                false
+            }
+        },
+        Err(err) => {
+            error!("{} Failed to contact {}!\n{:?}", error_codes::REGISTRY_UNREACHABLE, url, err);
+            false
+        }
+    }
+}
+
+/// Remove an entry from the sandbox registry. Expired entries count as removed.
+pub(crate) fn delete_sandbox_entry(client: &reqwest::Client, session: &UserSession, addon_id: &str, version: &str,
+                                   build_directory: &Path) -> bool {
+    let url = format!("{}/sandbox/addon/{}/{}", endpoints::registry_url(), addon_id, version);
+    let request = || session.authorize(client.delete(&url));
+    match send_mutation(build_directory, &format!("DELETE {}", url), &request) {
+        Ok(mut response) => match response.status().as_u16() {
+            200 | 204 | 404 => true,
+            _ => {
+                error!("{} Unexpected response!\n{:?}", error_codes::REGISTRY_REJECTED, response.text().unwrap_or_default());
+                false
+            }
+        },
+        Err(err) => {
+            error!("{} Failed to contact {}!\n{:?}", error_codes::REGISTRY_UNREACHABLE, url, err);
+            false
+        }
+    }
+}
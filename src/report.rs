@@ -0,0 +1,90 @@
+//! The final JSON document of `--output json`: Validation results, the per-architecture build and upload state,
+//! image digests and the registry response. Collected while the pipeline runs and printed at the end.
+use crate::dto::schema::ValidationIssue;
+use crate::dto::BuildInstruction;
+use serde::Serialize;
+use std::cell::RefCell;
+
+#[derive(Serialize)]
+pub(crate) struct ArchReport {
+    pub(crate) arch: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) variant: Option<String>,
+    pub(crate) image_name: String,
+    pub(crate) skipped: bool,
+    pub(crate) build: bool,
+    pub(crate) uploaded: bool,
+    pub(crate) digest: Option<String>,
+    pub(crate) image_size: i64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RegistryResponse {
+    pub(crate) url: String,
+    pub(crate) status: u16,
+    /// The response body. JSON responses are embedded as JSON, others as string.
+    pub(crate) body: serde_json::Value,
+}
+
+#[derive(Default, Serialize)]
+pub(crate) struct Report {
+    pub(crate) command: String,
+    pub(crate) success: bool,
+    pub(crate) addon_id: Option<String>,
+    pub(crate) version: Option<String>,
+    /// None if validation did not run
+    pub(crate) valid: Option<bool>,
+    /// The errors and warnings of the validation, with rule and position
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) issues: Vec<ValidationIssue>,
+    /// All logged errors and warnings
+    pub(crate) errors: Vec<String>,
+    pub(crate) warnings: Vec<String>,
+    pub(crate) archs: Vec<ArchReport>,
+    pub(crate) registry: Option<RegistryResponse>,
+}
+
+thread_local! {
+    static REPORT: RefCell<Report> = RefCell::new(Report::default());
+}
+
+/// Changes the report. Only the main thread reports.
+pub(crate) fn update<F: FnOnce(&mut Report)>(f: F) {
+    REPORT.with(|r| f(&mut r.borrow_mut()));
+}
+
+/// The recorded validation issues
+pub(crate) fn issues() -> Vec<ValidationIssue> {
+    REPORT.with(|r| r.borrow().issues.clone())
+}
+
+/// Records the current build and upload state
+pub(crate) fn archs(build_instructions: &[BuildInstruction]) {
+    let archs = build_instructions.iter().map(|b| ArchReport {
+        arch: b.arch.clone(),
+        variant: b.variant.clone(),
+        image_name: b.image_name.clone(),
+        skipped: b.skipped,
+        build: b.build,
+        uploaded: b.uploaded,
+        digest: b.digest.clone(),
+        image_size: b.image_size,
+    }).collect();
+    update(|r| r.archs = archs);
+}
+
+/// Records a registry response
+pub(crate) fn registry_response(url: &str, status: u16, body: &str) {
+    let body = serde_json::from_str(body).unwrap_or_else(|_| serde_json::Value::String(body.to_owned()));
+    update(|r| r.registry = Some(RegistryResponse { url: url.to_owned(), status, body }));
+}
+
+/// Prints the report to stdout
+pub(crate) fn print(command: &str, success: bool) {
+    REPORT.with(|r| {
+        let mut report = r.borrow_mut();
+        report.command = command.to_owned();
+        report.success = success;
+        println!("{}", serde_json::to_string_pretty(&*report).expect("serializable report"));
+    });
+}
@@ -0,0 +1,47 @@
+//! The `search` subcommand: Search over the cached registry index, including ratings and download counts.
+use crate::dto::{addons, search};
+use crate::endpoints;
+use crate::registry;
+use crate::SearchOpt;
+use log::warn;
+use prettytable::{cell, row, Table};
+
+pub(crate) fn search(client: &reqwest::Client, opt: &SearchOpt) -> bool {
+    let registry = match registry::addon_registry(client) {
+        Some(v) => v,
+        None => return false,
+    };
+
+    // Stats are optional. Without them rating and downloads are shown as "-"
+    let stats = match addons::get_addons_registry_metadata(client, &endpoints::stats_url()) {
+        Ok(v) => {
+            if !v.invalid.is_empty() {
+                warn!("Skipped {} malformed registry stats entries", v.invalid.len());
+            }
+            Some(v.stats)
+        }
+        Err(e) => {
+            warn!("Registry stats not available: {:?}", e);
+            None
+        }
+    };
+
+    let result = search::filter_addons(&registry, stats.as_ref(), opt.term.as_deref(),
+                                       &opt.filters, opt.sort);
+    if result.is_empty() {
+        println!("No addons found");
+        return true;
+    }
+
+    let mut table = Table::new();
+    table.add_row(row!["Title", "ID", "Version", "Rating", "Downloads"]);
+    for entry in result {
+        let e = &entry.entry;
+        let stat = stats.as_ref().and_then(|s| s.get(&e.id));
+        let rating = stat.map(|s| format!("{:.1} ({})", search::average_rating(s), s.v)).unwrap_or("-".to_owned());
+        let downloads = stat.map(|s| s.d.to_string()).unwrap_or("-".to_owned());
+        table.add_row(row![e.title, e.id, e.version, rating, downloads]);
+    }
+    table.printstd();
+    true
+}
@@ -0,0 +1,296 @@
+//! The `security check` subcommand: Looks up the packages of the SBOM of a published addon in the
+//! OSV vulnerability database (https://osv.dev). The SBOM is the `x-attachments` SBOM of the images.
+//! Images without one are scanned with syft (https://github.com/anchore/syft), if installed.
+use crate::endpoints;
+use crate::error_codes;
+use crate::dto::addons::AttachmentKind;
+use crate::info::format_timestamp;
+use crate::namespace;
+use crate::oras;
+use crate::registry;
+use log::{error, info, warn};
+use prettytable::{cell, row, Table};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+pub(crate) const OSV_URL: &str = "https://api.osv.dev";
+
+/// The OSV batch endpoint accepts at most this many queries per request
+const OSV_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
+pub(crate) enum Severity {
+    Unknown,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl FromStr for Severity {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "low" => Severity::Low,
+            "medium" | "moderate" => Severity::Medium,
+            "high" => Severity::High,
+            "critical" => Severity::Critical,
+            _ => return Err(failure::err_msg(format!("Unknown severity '{}'. Allowed: low, medium, high, critical", s))),
+        })
+    }
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Unknown => "unknown",
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+/// The parts of SPDX and CycloneDX JSON documents that name packages
+#[derive(Deserialize)]
+struct Sbom {
+    #[serde(default)]
+    packages: Vec<SpdxPackage>,
+    #[serde(default)]
+    components: Vec<CycloneDxComponent>,
+}
+
+#[derive(Deserialize)]
+struct SpdxPackage {
+    #[serde(default, rename = "externalRefs")]
+    external_refs: Vec<SpdxExternalRef>,
+}
+
+#[derive(Deserialize)]
+struct SpdxExternalRef {
+    #[serde(rename = "referenceType")]
+    reference_type: String,
+    #[serde(rename = "referenceLocator")]
+    reference_locator: String,
+}
+
+#[derive(Deserialize)]
+struct CycloneDxComponent {
+    purl: Option<String>,
+}
+
+/// The package urls of an SBOM, like "pkg:apk/alpine/musl@1.1.22-r3"
+fn purls(sbom: &[u8]) -> Result<BTreeSet<String>, String> {
+    let sbom: Sbom = serde_json::from_slice(sbom).map_err(|e| format!("Invalid SBOM: {}", e))?;
+    let spdx = sbom.packages.into_iter().flat_map(|p| p.external_refs)
+        .filter(|r| r.reference_type == "purl")
+        .map(|r| r.reference_locator);
+    let cyclone_dx = sbom.components.into_iter().filter_map(|c| c.purl);
+    Ok(spdx.chain(cyclone_dx).collect())
+}
+
+/// Downloads the SBOM attached to the image, or None if there is none
+fn attached_sbom(image_name: &str, directory: &Path) -> Result<Option<Vec<u8>>, String> {
+    let digests = oras::discover(image_name, AttachmentKind::Sbom.artifact_type())?;
+    let digest = match digests.last() {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let repository = image_name.rsplitn(2, ':').last().unwrap_or(image_name);
+    std::fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+    oras::pull(&format!("{}@{}", repository, digest), directory)?;
+    let file = std::fs::read_dir(directory).map_err(|e| e.to_string())?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .find(|p| p.is_file())
+        .ok_or_else(|| format!("The SBOM artifact {} contains no file", digest))?;
+    std::fs::read(&file).map(Some).map_err(|e| e.to_string())
+}
+
+/// Generates an SPDX SBOM of the published image with syft
+fn generate_sbom(image_name: &str) -> Result<Vec<u8>, String> {
+    let output = Command::new("syft")
+        .args([image_name, "-o", "spdx-json", "-q"])
+        .output()
+        .map_err(|e| format!("syft is not installed ({:?}). Attach an SBOM to the addon or install https://github.com/anchore/syft.", e))?;
+    match output.status.success() {
+        true => Ok(output.stdout),
+        false => Err(String::from_utf8_lossy(&output.stderr).trim().to_owned()),
+    }
+}
+
+#[derive(Serialize)]
+struct OsvQuery<'a> {
+    package: OsvPackage<'a>,
+}
+
+#[derive(Serialize)]
+struct OsvPackage<'a> {
+    purl: &'a str,
+}
+
+#[derive(Serialize)]
+struct OsvBatchQuery<'a> {
+    queries: Vec<OsvQuery<'a>>,
+}
+
+#[derive(Deserialize)]
+struct OsvBatchResponse {
+    results: Vec<OsvResult>,
+}
+
+#[derive(Deserialize)]
+struct OsvResult {
+    #[serde(default)]
+    vulns: Vec<OsvVulnId>,
+}
+
+#[derive(Deserialize)]
+struct OsvVulnId {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct OsvVuln {
+    id: String,
+    #[serde(default)]
+    summary: String,
+    /// RFC 3339
+    published: Option<String>,
+    /// The GitHub advisories and others have a "severity" here
+    database_specific: Option<serde_json::Value>,
+}
+
+struct Vulnerability {
+    id: String,
+    summary: String,
+    severity: Severity,
+    /// Unix timestamp in seconds
+    published: Option<i64>,
+    packages: BTreeSet<String>,
+}
+
+/// The vulnerability ids per package url
+fn query_osv(client: &reqwest::Client, osv_url: &str, purls: &BTreeSet<String>) -> Result<BTreeMap<String, BTreeSet<String>>, failure::Error> {
+    let purls: Vec<&String> = purls.iter().collect();
+    let mut vulns: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for chunk in purls.chunks(OSV_BATCH_SIZE) {
+        let query = OsvBatchQuery { queries: chunk.iter().map(|purl| OsvQuery { package: OsvPackage { purl } }).collect() };
+        let response: OsvBatchResponse = client.post(&format!("{}/v1/querybatch", osv_url)).json(&query)
+            .send()?.error_for_status()?.json()?;
+        for (purl, result) in chunk.iter().zip(response.results) {
+            for vuln in result.vulns {
+                vulns.entry(vuln.id).or_default().insert(purl.to_string());
+            }
+        }
+    }
+    Ok(vulns)
+}
+
+fn vulnerability(client: &reqwest::Client, osv_url: &str, id: &str, packages: BTreeSet<String>) -> Result<Vulnerability, failure::Error> {
+    let vuln: OsvVuln = client.get(&format!("{}/v1/vulns/{}", osv_url, id)).send()?.error_for_status()?.json()?;
+    let severity = vuln.database_specific.as_ref()
+        .and_then(|d| d.get("severity"))
+        .and_then(|s| s.as_str())
+        .and_then(|s| Severity::from_str(s).ok())
+        .unwrap_or(Severity::Unknown);
+    let published = vuln.published.as_ref()
+        .and_then(|p| chrono::DateTime::parse_from_rfc3339(p).ok())
+        .map(|p| p.timestamp());
+    Ok(Vulnerability { id: vuln.id, summary: vuln.summary, severity, published, packages })
+}
+
+/// Checks the published version of the addon. Fails if vulnerabilities that were published after the addon
+/// have the severity `fail_on` or higher. With `all`, older vulnerabilities fail the check as well.
+pub(crate) fn check(client: &reqwest::Client, addon_id: &str, fail_on: Severity, all: bool, osv_url: &str) -> bool {
+    let entry = match registry::addon_registry(client).and_then(|r| r.get(addon_id).cloned()) {
+        Some(v) => v,
+        None => {
+            error!("{} {} is not in the registry", error_codes::NOT_FOUND, addon_id);
+            return false;
+        }
+    };
+    let version = &entry.entry.version;
+    if entry.archs.is_empty() {
+        error!("{} The registry entry of {} lists no architectures. Publish the addon again.", error_codes::NOT_FOUND, addon_id);
+        return false;
+    }
+    let directory = namespace::user_directory().join(format!("sbom-{}", std::process::id()));
+
+    let mut packages = BTreeSet::new();
+    for arch in &entry.archs {
+        let image_name = endpoints::image_name(&format!("{}_{}", addon_id, arch), version);
+        let sbom = match attached_sbom(&image_name, &directory.join(arch)) {
+            Ok(Some(v)) => Ok(v),
+            Ok(None) => {
+                info!("{} has no SBOM attached. Generating one.", image_name);
+                generate_sbom(&image_name)
+            }
+            Err(e) => {
+                warn!("Failed to download the SBOM of {}: {}. Generating one.", image_name, e);
+                generate_sbom(&image_name)
+            }
+        };
+        match sbom.and_then(|sbom| purls(&sbom)) {
+            Ok(v) => packages.extend(v),
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&directory);
+                error!("{} No SBOM for {}: {}", error_codes::NOT_FOUND, image_name, e);
+                return false;
+            }
+        }
+    }
+    let _ = std::fs::remove_dir_all(&directory);
+    if packages.is_empty() {
+        warn!("The SBOM of {} {} lists no packages", addon_id, version);
+        return true;
+    }
+
+    let vulnerabilities = query_osv(client, osv_url, &packages).and_then(|ids| {
+        ids.into_iter().map(|(id, packages)| vulnerability(client, osv_url, &id, packages)).collect::<Result<Vec<_>, _>>()
+    });
+    let mut vulnerabilities = match vulnerabilities {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} The vulnerability database is not reachable: {}", error_codes::REGISTRY_UNREACHABLE, e);
+            return false;
+        }
+    };
+    vulnerabilities.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.id.cmp(&b.id)));
+
+    println!("{} {}: {} packages, {} vulnerabilities", addon_id, version, packages.len(), vulnerabilities.len());
+    let is_new = |v: &Vulnerability| v.published.map(|p| p > entry.last_updated).unwrap_or(true);
+    if !vulnerabilities.is_empty() {
+        let mut table = Table::new();
+        table.add_row(row!["Id", "Severity", "Published", "New", "Packages", "Summary"]);
+        for v in &vulnerabilities {
+            let published = v.published.map(format_timestamp).unwrap_or_else(|| "-".to_owned());
+            let new = if is_new(v) { "yes" } else { "" };
+            let packages: Vec<&str> = v.packages.iter().map(|p| p.as_str()).collect();
+            table.add_row(row![v.id, v.severity.name(), published, new, packages.join("\n"), v.summary]);
+        }
+        table.printstd();
+    }
+
+    let failed = vulnerabilities.iter().filter(|v| v.severity >= fail_on && (all || is_new(v))).count();
+    if failed > 0 {
+        error!("{} {} {} has {} {} vulnerabilities of severity {} or higher", error_codes::VULNERABLE, addon_id, version,
+               failed, if all { "known" } else { "new" }, fail_on.name());
+        return false;
+    }
+    true
+}
+
+#[test]
+fn purls_test() {
+    let spdx = br#"{"packages": [{"externalRefs": [{"referenceCategory": "PACKAGE-MANAGER", "referenceType": "purl",
+        "referenceLocator": "pkg:apk/alpine/musl@1.1.22-r3"}, {"referenceType": "cpe23Type", "referenceLocator": "cpe:2.3:a"}]}]}"#;
+    assert_eq!(purls(spdx).unwrap().into_iter().collect::<Vec<_>>(), vec!["pkg:apk/alpine/musl@1.1.22-r3"]);
+    let cyclone_dx = br#"{"components": [{"purl": "pkg:cargo/serde@1.0.0"}, {"name": "no-purl"}]}"#;
+    assert_eq!(purls(cyclone_dx).unwrap().len(), 1);
+}
@@ -0,0 +1,201 @@
+//! Dated snapshots of the registry index for registry maintainers: `index snapshot`, for example nightly from cron,
+//! stores the current index. `index diff <date>` shows the addons that were added, updated, removed or changed
+//! their status since then. Snapshots are kept in the cache directory, `cache clear` does not remove them.
+use crate::cache;
+use crate::dto::addons::{AddonEntryMap, AddonRegistryEntry};
+use crate::endpoints;
+use crate::error_codes;
+use crate::output;
+use crate::registry;
+use chrono::NaiveDate;
+use log::error;
+use prettytable::{cell, row, Table};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Snapshots of different registries are kept apart
+fn directory() -> PathBuf {
+    let registry = format!("{:x}", Sha256::digest(endpoints::index_url().as_bytes()));
+    cache::directory().join("snapshots").join(&registry[..16])
+}
+
+fn snapshot_file(date: NaiveDate) -> PathBuf {
+    directory().join(format!("{}.json", date.format(DATE_FORMAT)))
+}
+
+pub(crate) fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(date, DATE_FORMAT).map_err(|e| format!("Expected a date like 2020-01-31: {}", e))
+}
+
+/// The dates of all snapshots, oldest first
+fn dates() -> Vec<NaiveDate> {
+    let mut dates: Vec<NaiveDate> = match std::fs::read_dir(directory()) {
+        Ok(v) => v.filter_map(|e| e.ok())
+            .filter_map(|e| e.file_name().to_str().filter(|n| n.ends_with(".json")).and_then(|n| parse_date(n.trim_end_matches(".json")).ok()))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    dates.sort();
+    dates
+}
+
+/// Stores the current registry index as the snapshot of today. An earlier snapshot of today is replaced.
+pub(crate) fn snapshot(client: &reqwest::Client) -> bool {
+    let index = match registry::addon_registry(client) {
+        Some(v) => v,
+        None => return false,
+    };
+    let file = snapshot_file(chrono::Local::now().date_naive());
+    let written = std::fs::create_dir_all(directory())
+        .and_then(|_| std::fs::write(&file, serde_json::to_vec(&index).expect("serializable registry index")));
+    if let Err(e) = written {
+        error!("{} Failed to write {}: {}", error_codes::FILE_ACCESS, file.display(), e);
+        return false;
+    }
+    output::line(&format!("Stored the snapshot {} with {} addons", file.display(), index.len()));
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ChangeKind {
+    Added,
+    Updated,
+    StatusChanged,
+    Removed,
+}
+
+impl ChangeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Updated => "updated",
+            ChangeKind::StatusChanged => "status changed",
+            ChangeKind::Removed => "removed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Change {
+    pub(crate) kind: ChangeKind,
+    pub(crate) id: String,
+    /// The version, or the status for status changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) before: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) after: Option<String>,
+}
+
+fn status(entry: &AddonRegistryEntry) -> String {
+    format!("{:?}", entry.entry.status.code)
+}
+
+/// The changes from the `old` to the `new` index, by addon id. An addon can be updated and change its status.
+pub(crate) fn changes(old: &AddonEntryMap, new: &AddonEntryMap) -> Vec<Change> {
+    let change = |kind, id: &String, before: Option<String>, after: Option<String>| Change { kind, id: id.clone(), before, after };
+    let mut changes = Vec::new();
+    for (id, entry) in new {
+        let previous = match old.get(id) {
+            Some(v) => v,
+            None => {
+                changes.push(change(ChangeKind::Added, id, None, Some(entry.entry.version.clone())));
+                continue;
+            }
+        };
+        // A republished version has the same version, but a new update time
+        if previous.entry.version != entry.entry.version || previous.last_updated != entry.last_updated {
+            changes.push(change(ChangeKind::Updated, id, Some(previous.entry.version.clone()), Some(entry.entry.version.clone())));
+        }
+        if previous.entry.status.code != entry.entry.status.code {
+            changes.push(change(ChangeKind::StatusChanged, id, Some(status(previous)), Some(status(entry))));
+        }
+    }
+    for (id, entry) in old.iter().filter(|(id, _)| !new.contains_key(*id)) {
+        changes.push(change(ChangeKind::Removed, id, Some(entry.entry.version.clone()), None));
+    }
+    changes
+}
+
+/// The newest snapshot on or before `date`
+fn snapshot_at(date: NaiveDate, dates: &[NaiveDate]) -> Option<(NaiveDate, AddonEntryMap)> {
+    let found = match dates.iter().rev().find(|d| **d <= date) {
+        Some(v) => *v,
+        None => {
+            let first = dates.first().map(|d| d.format(DATE_FORMAT).to_string()).unwrap_or_else(|| "none, run `index snapshot`".to_owned());
+            error!("{} No index snapshot on or before {}. The first snapshot: {}", error_codes::SNAPSHOT_NOT_FOUND, date.format(DATE_FORMAT), first);
+            return None;
+        }
+    };
+    let file = snapshot_file(found);
+    let index = std::fs::read(&file).map_err(|e| e.to_string())
+        .and_then(|data| serde_json::from_slice(&data).map_err(|e| e.to_string()));
+    match index {
+        Ok(v) => Some((found, v)),
+        Err(e) => {
+            error!("{} Failed to read {}: {}", error_codes::FILE_ACCESS, file.display(), e);
+            None
+        }
+    }
+}
+
+/// Prints the changes between the snapshot of `from` and the snapshot of `to`, or the newest snapshot.
+/// Without a snapshot of the exact date, the newest snapshot before it is used.
+pub(crate) fn diff(from: NaiveDate, to: Option<NaiveDate>, json: bool) -> bool {
+    let dates = dates();
+    let (from, old) = match snapshot_at(from, &dates) {
+        Some(v) => v,
+        None => return false,
+    };
+    let to = to.or_else(|| dates.last().cloned()).unwrap_or(from);
+    let (to, new) = match snapshot_at(to, &dates) {
+        Some(v) => v,
+        None => return false,
+    };
+    let changes = changes(&old, &new);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&changes).expect("serializable changes"));
+        return true;
+    }
+    output::line(&format!("Changes from {} to {}", from.format(DATE_FORMAT), to.format(DATE_FORMAT)));
+    if changes.is_empty() {
+        output::line("No changes");
+        return true;
+    }
+    let mut table = Table::new();
+    table.add_row(row!["Change", "ID", "Before", "After"]);
+    for change in &changes {
+        let before = change.before.clone().unwrap_or_default();
+        let after = change.after.clone().unwrap_or_default();
+        table.add_row(row![change.kind.name(), change.id, before, after]);
+    }
+    table.printstd();
+    true
+}
+
+#[test]
+fn changes_test() {
+    use crate::dto::addons::StatusCode;
+    let entry = |version: &str, last_updated: i64| {
+        let mut entry = AddonRegistryEntry::default();
+        entry.entry.version = version.to_owned();
+        entry.last_updated = last_updated;
+        entry
+    };
+    let mut old = AddonEntryMap::new();
+    old.insert("kept".to_owned(), entry("1.0.0", 1));
+    old.insert("updated".to_owned(), entry("1.0.0", 1));
+    old.insert("removed".to_owned(), entry("1.0.0", 1));
+    let mut new = old.clone();
+    new.remove("removed");
+    new.insert("updated".to_owned(), entry("1.1.0", 2));
+    new.insert("added".to_owned(), entry("0.1.0", 2));
+    new.get_mut("kept").unwrap().entry.status.code = StatusCode::UNMAINTAINED;
+
+    let kinds: Vec<(ChangeKind, String)> = changes(&old, &new).into_iter().map(|c| (c.kind, c.id)).collect();
+    assert_eq!(kinds, vec![(ChangeKind::Added, "added".to_owned()), (ChangeKind::StatusChanged, "kept".to_owned()),
+                           (ChangeKind::Updated, "updated".to_owned()), (ChangeKind::Removed, "removed".to_owned())]);
+}
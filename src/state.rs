@@ -0,0 +1,231 @@
+//! Build and upload state of the last run, stored in the build directory.
+//! `--resume` uses it to skip images that are already build or pushed.
+//! Remote artifacts of sandbox and failed publishes are tracked for `cleanup-remote`,
+//! registry mutations until the registry acknowledged them.
+use crate::dto::addons::AddonFileEntry;
+use crate::dto::BuildInstruction;
+use crate::docker_registry;
+use crate::build_cache;
+use crate::output;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct ArchState {
+    pub(crate) build: bool,
+    pub(crate) uploaded: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) digest: Option<String>,
+    pub(crate) image_size: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) content_hash: Option<String>,
+}
+
+/// Something that a sandbox or failed publish left behind in the docker registry or the addon registry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub(crate) enum RemoteArtifact {
+    /// An image or wasm artifact tag. The addon id selects the docker credentials.
+    Image { addon_id: String, reference: String },
+    SandboxEntry { addon_id: String, version: String },
+}
+
+impl std::fmt::Display for RemoteArtifact {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RemoteArtifact::Image { reference, .. } => write!(f, "image {}", reference),
+            RemoteArtifact::SandboxEntry { addon_id, version } => write!(f, "sandbox entry {} {}", addon_id, version),
+        }
+    }
+}
+
+/// A registry mutation that was sent, but not answered by the registry yet
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct PendingRequest {
+    /// Method, url and body digest
+    pub(crate) operation: String,
+    pub(crate) idempotency_key: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct BuildState {
+    pub(crate) addon_id: String,
+    pub(crate) version: String,
+    pub(crate) archs: BTreeMap<String, ArchState>,
+    /// Kept across versions, until removed by `cleanup-remote`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) remote: Vec<RemoteArtifact>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) pending: Vec<PendingRequest>,
+}
+
+pub(crate) fn state_file(build_directory: &Path) -> PathBuf {
+    build_directory.join("state.json")
+}
+
+fn read(build_directory: &Path) -> Option<BuildState> {
+    std::fs::read(state_file(build_directory)).ok().and_then(|v| serde_json::from_slice(&v).ok())
+}
+
+fn write(build_directory: &Path, state: &BuildState) {
+    let result = std::fs::create_dir_all(build_directory)
+        .and_then(|_| std::fs::write(state_file(build_directory), serde_json::to_vec_pretty(state).unwrap()));
+    if let Err(e) = result {
+        warn!("Failed to store the build state in {}: {:?}", build_directory.display(), e);
+    }
+}
+
+/// Stores the build and upload results. Failures are only logged, the state is an optimisation.
+pub(crate) fn save(build_directory: &Path, input_file: &AddonFileEntry, build_instructions: &[BuildInstruction]) {
+    let previous = read(build_directory).unwrap_or_default();
+    let state = BuildState {
+        addon_id: input_file.x_ohx_registry.id.clone(),
+        version: input_file.x_ohx_registry.version.clone(),
+        archs: build_instructions.iter().filter(|b| !b.skipped).map(|b| (b.key(), ArchState {
+            build: b.build,
+            uploaded: b.uploaded,
+            digest: b.digest.clone(),
+            image_size: b.image_size,
+            content_hash: b.content_hash.clone(),
+        })).collect(),
+        remote: previous.remote,
+        pending: previous.pending,
+    };
+    write(build_directory, &state);
+}
+
+/// The tracked remote artifacts
+pub(crate) fn remote_artifacts(build_directory: &Path) -> Vec<RemoteArtifact> {
+    read(build_directory).map(|s| s.remote).unwrap_or_default()
+}
+
+/// Records remote artifacts for `cleanup-remote`. Already tracked artifacts are not added twice.
+pub(crate) fn track_remote(build_directory: &Path, artifacts: &[RemoteArtifact]) {
+    if artifacts.is_empty() {
+        return;
+    }
+    let mut state = read(build_directory).unwrap_or_default();
+    for artifact in artifacts {
+        if !state.remote.contains(artifact) {
+            state.remote.push(artifact.clone());
+        }
+    }
+    write(build_directory, &state);
+}
+
+/// Stops tracking the given remote artifacts, because they are published or deleted.
+/// If images were deleted, the pushed state of the last run is reset, so that `--resume` pushes again.
+pub(crate) fn untrack_remote(build_directory: &Path, artifacts: &[RemoteArtifact], deleted: bool) {
+    let mut state = match read(build_directory) {
+        Some(v) => v,
+        None => return,
+    };
+    state.remote.retain(|a| !artifacts.contains(a));
+    if deleted && artifacts.iter().any(|a| matches!(a, RemoteArtifact::Image { .. })) {
+        for arch_state in state.archs.values_mut() {
+            arch_state.uploaded = false;
+            arch_state.digest = None;
+        }
+    }
+    write(build_directory, &state);
+}
+
+/// The idempotency key of a registry mutation. A pending request for the same operation reuses its key,
+/// otherwise a new key is created and stored until `acknowledge`.
+pub(crate) fn idempotency_key(build_directory: &Path, operation: &str) -> String {
+    let mut state = read(build_directory).unwrap_or_default();
+    if let Some(pending) = state.pending.iter().find(|p| p.operation == operation) {
+        info!("Reusing the idempotency key of an unanswered request: {}", operation);
+        return pending.idempotency_key.clone();
+    }
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let mut sha = Sha256::new();
+    sha.input(format!("{} {} {}", operation, now, std::process::id()).as_bytes());
+    let idempotency_key = format!("{:x}", sha.result())[..32].to_owned();
+    state.pending.push(PendingRequest { operation: operation.to_owned(), idempotency_key: idempotency_key.clone() });
+    write(build_directory, &state);
+    idempotency_key
+}
+
+/// The registry answered the request. Its idempotency key is not needed anymore.
+pub(crate) fn acknowledge(build_directory: &Path, operation: &str) {
+    let mut state = match read(build_directory) {
+        Some(v) => v,
+        None => return,
+    };
+    state.pending.retain(|p| p.operation != operation);
+    write(build_directory, &state);
+}
+
+/// The state of the last run, if it was for the same addon version
+fn load(build_directory: &Path, input_file: &AddonFileEntry) -> Option<BuildState> {
+    let state: BuildState = match read(build_directory) {
+        Some(v) => v,
+        None => {
+            info!("No previous build state found in {}", build_directory.display());
+            return None;
+        }
+    };
+    if state.addon_id != input_file.x_ohx_registry.id || state.version != input_file.x_ohx_registry.version {
+        info!("The previous build state is for {} {}", state.addon_id, state.version);
+        return None;
+    }
+    Some(state)
+}
+
+/// Computes the content hashes of all architectures. Images that were pushed by an earlier run
+/// of this version with the same content hash are marked as build and pushed.
+pub(crate) fn skip_unchanged(build_directory: &Path, input_file: &AddonFileEntry, addon_dir: &Path,
+                             build_instructions: &mut [BuildInstruction]) {
+    for build_instruction in build_instructions.iter_mut().filter(|b| !b.skipped) {
+        build_instruction.content_hash = build_cache::content_hash(addon_dir, build_directory, build_instruction);
+    }
+    let state = match load(build_directory, input_file) {
+        Some(v) => v,
+        None => return,
+    };
+    for build_instruction in build_instructions.iter_mut().filter(|b| !b.skipped && !b.uploaded) {
+        let arch_state = match state.archs.get(&build_instruction.key()) {
+            Some(v) => v,
+            None => continue,
+        };
+        if arch_state.uploaded && arch_state.content_hash.is_some() && arch_state.content_hash == build_instruction.content_hash {
+            output::line(&format!("{} is unchanged since the last push. Not building it again.", build_instruction.image_name));
+            build_instruction.build = true;
+            build_instruction.uploaded = true;
+            build_instruction.digest = arch_state.digest.clone();
+            build_instruction.image_size = arch_state.image_size;
+        }
+    }
+}
+
+/// Restores the results of the last run, if it was for the same addon version.
+/// Build images are only taken over if they still exist locally.
+pub(crate) fn resume(cli: &docker_registry::EngineCli, build_directory: &Path, input_file: &AddonFileEntry, build_instructions: &mut [BuildInstruction]) {
+    let state = match load(build_directory, input_file) {
+        Some(v) => v,
+        None => return,
+    };
+    for build_instruction in build_instructions.iter_mut().filter(|b| !b.skipped) {
+        let arch_state = match state.archs.get(&build_instruction.key()) {
+            Some(v) => v,
+            None => continue,
+        };
+        if arch_state.uploaded {
+            info!("Resuming: {} is already pushed", build_instruction.image_name);
+            build_instruction.build = true;
+            build_instruction.uploaded = true;
+            build_instruction.digest = arch_state.digest.clone();
+            build_instruction.image_size = arch_state.image_size;
+        } else if arch_state.build {
+            if let Some(size) = docker_registry::inspect_image_size(cli, &build_instruction.image_name) {
+                info!("Resuming: {} is already build", build_instruction.image_name);
+                build_instruction.build = true;
+                build_instruction.image_size = size;
+            }
+        }
+    }
+}
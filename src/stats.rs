@@ -0,0 +1,110 @@
+//! The `stats` subcommand: Download counts, ratings and issues of the addons owned by the logged in user.
+use crate::endpoints;
+use crate::error_codes;
+use crate::dto::addons;
+use crate::dto::search;
+use crate::login::UserSession;
+use crate::registry;
+use log::error;
+use prettytable::{cell, row, Table};
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum StatsSort {
+    Id,
+    Downloads,
+    Votes,
+    Rating,
+    Issues,
+}
+
+impl FromStr for StatsSort {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "id" => StatsSort::Id,
+            "downloads" => StatsSort::Downloads,
+            "votes" => StatsSort::Votes,
+            "rating" => StatsSort::Rating,
+            "issues" => StatsSort::Issues,
+            _ => {
+                return Err(failure::err_msg(format!(
+                    "Unknown sort order '{}'. Allowed: id, downloads, votes, rating, issues",
+                    s
+                )))
+            }
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct AddonStatsRow<'a> {
+    id: &'a str,
+    title: &'a str,
+    downloads: i64,
+    votes: u64,
+    rating: f64,
+    issues: u64,
+}
+
+pub(crate) fn stats(client: &reqwest::Client, session: &UserSession, sort: StatsSort, json: bool) -> bool {
+    let stats = {
+        let (client, stats_url) = (client.clone(), endpoints::stats_url());
+        std::thread::spawn(move || addons::get_addons_registry_metadata(&client, &stats_url).map_err(|e| e.to_string()))
+    };
+    let registry = match registry::addon_registry(client) {
+        Some(v) => v,
+        None => return false,
+    };
+    let stats = match stats.join().expect("stats thread") {
+        Ok(v) => v.stats,
+        Err(e) => {
+            error!("{} Registry stats not available: {}", error_codes::REGISTRY_UNREACHABLE, e);
+            return false;
+        }
+    };
+
+    // Addons without stats entries have not been downloaded or rated yet
+    let empty = addons::AddonStats::default();
+    let mut rows: Vec<AddonStatsRow> = registry.values()
+        .filter(|e| e.owner == session.user_id)
+        .map(|e| {
+            let s = stats.get(&e.entry.id).unwrap_or(&empty);
+            AddonStatsRow {
+                id: &e.entry.id,
+                title: &e.entry.title,
+                downloads: s.d,
+                votes: s.v,
+                rating: search::average_rating(s),
+                issues: s.iss,
+            }
+        })
+        .collect();
+
+    match sort {
+        StatsSort::Id => rows.sort_by(|a, b| a.id.cmp(b.id)),
+        StatsSort::Downloads => rows.sort_by_key(|r| std::cmp::Reverse(r.downloads)),
+        StatsSort::Votes => rows.sort_by_key(|r| std::cmp::Reverse(r.votes)),
+        StatsSort::Rating => rows.sort_by(|a, b| b.rating.partial_cmp(&a.rating).unwrap_or(std::cmp::Ordering::Equal)),
+        StatsSort::Issues => rows.sort_by_key(|r| std::cmp::Reverse(r.issues)),
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows).unwrap());
+        return true;
+    }
+    if rows.is_empty() {
+        println!("You have not published any addons yet");
+        return true;
+    }
+
+    let mut table = Table::new();
+    table.add_row(row!["ID", "Title", "Downloads", "Votes", "Rating", "Issues"]);
+    for r in &rows {
+        table.add_row(row![r.id, r.title, r.downloads, r.votes, format!("{:.1}", r.rating), r.issues]);
+    }
+    table.printstd();
+    true
+}
@@ -0,0 +1,151 @@
+//! The result of `validate --format json|sarif` for editors and CI tools, printed to stdout.
+//! Both list every issue with its severity, rule, message, path and YAML line and column. Errors have no lint rule,
+//! in SARIF their rule is the error code. Suppressed warnings are listed with their justification.
+use crate::dto::lint;
+use crate::dto::schema::{Severity, ValidationIssue};
+use crate::error_codes;
+use serde::Serialize;
+use serde_json::json;
+use std::str::FromStr;
+
+pub(crate) const FORMATS: [&str; 3] = ["human", "json", "sarif"];
+
+const SARIF_SCHEMA: &str = "https://raw.githubusercontent.com/oasis-tcs/sarif-spec/master/Schemata/sarif-schema-2.1.0.json";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Format {
+    Human,
+    Json,
+    /// SARIF 2.1.0, for code scanning of CI systems
+    Sarif,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            "sarif" => Ok(Format::Sarif),
+            _ => Err(format!("Unknown report format {}. Allowed: {}", s, FORMATS.join(", "))),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonIssue<'a> {
+    severity: Severity,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule: Option<&'a str>,
+    message: &'a str,
+    path: &'a str,
+    line: Option<usize>,
+    column: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suppressed: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    file: &'a str,
+    valid: bool,
+    errors: usize,
+    warnings: usize,
+    issues: Vec<JsonIssue<'a>>,
+}
+
+fn json_report(file: &str, valid: bool, issues: &[ValidationIssue]) -> serde_json::Value {
+    let report = JsonReport {
+        file,
+        valid,
+        errors: issues.iter().filter(|i| i.is_error()).count(),
+        warnings: issues.iter().filter(|i| !i.is_error() && i.suppressed.is_none()).count(),
+        issues: issues.iter().map(|i| JsonIssue {
+            severity: i.severity,
+            rule: i.rule.as_deref(),
+            message: &i.message,
+            path: &i.path,
+            line: i.location.map(|(line, _)| line),
+            column: i.location.map(|(_, column)| column),
+            suppressed: i.suppressed.as_deref(),
+        }).collect(),
+    };
+    serde_json::to_value(report).expect("serializable report")
+}
+
+fn sarif_report(file: &str, issues: &[ValidationIssue]) -> serde_json::Value {
+    let mut rules = vec![json!({
+        "id": error_codes::INPUT_FILE_INVALID.code,
+        "shortDescription": { "text": error_codes::INPUT_FILE_INVALID.title },
+        "defaultConfiguration": { "level": "error" },
+    })];
+    rules.extend(lint::RULES.iter().map(|(id, description)| json!({
+        "id": id,
+        "shortDescription": { "text": description },
+        "defaultConfiguration": { "level": "warning" },
+    })));
+    let results: Vec<serde_json::Value> = issues.iter().map(|issue| {
+        let mut location = json!({ "physicalLocation": { "artifactLocation": { "uri": file } } });
+        if let Some((line, column)) = issue.location {
+            location["physicalLocation"]["region"] = json!({ "startLine": line, "startColumn": column });
+        }
+        if !issue.path.is_empty() {
+            location["logicalLocations"] = json!([{ "fullyQualifiedName": issue.path }]);
+        }
+        let mut result = json!({
+            "ruleId": issue.rule.as_deref().unwrap_or(error_codes::INPUT_FILE_INVALID.code),
+            "level": if issue.is_error() { "error" } else { "warning" },
+            "message": { "text": issue.message },
+            "locations": [location],
+        });
+        if let Some(justification) = &issue.suppressed {
+            result["suppressions"] = json!([{ "kind": "inSource", "justification": justification }]);
+        }
+        result
+    }).collect();
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": { "driver": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+                "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                "rules": rules,
+            }},
+            "results": results,
+        }],
+    })
+}
+
+/// Prints the report of a machine readable format. Nothing is printed for the human format.
+pub(crate) fn print(format: Format, file: &str, valid: bool, issues: &[ValidationIssue]) {
+    let report = match format {
+        Format::Human => return,
+        Format::Json => json_report(file, valid, issues),
+        Format::Sarif => sarif_report(file, issues),
+    };
+    println!("{}", serde_json::to_string_pretty(&report).expect("serializable report"));
+}
+
+#[test]
+fn validation_report_test() {
+    let issues = vec![
+        ValidationIssue { severity: Severity::Error, rule: None, path: "services.app.ports[0]".to_owned(),
+                          message: "A port must be a number! a".to_owned(), location: Some((4, 9)), suppressed: None },
+        ValidationIssue { severity: Severity::Warning, rule: Some("meta/homepage".to_owned()), path: String::new(),
+                          message: "No homepage".to_owned(), location: None, suppressed: Some("Internal addon".to_owned()) },
+    ];
+    let report = json_report("addons.yml", false, &issues);
+    assert_eq!(report["errors"], 1);
+    assert_eq!(report["warnings"], 0);
+    assert_eq!(report["issues"][0]["line"], 4);
+    assert_eq!(report["issues"][1]["rule"], "meta/homepage");
+
+    let sarif = sarif_report("addons.yml", &issues);
+    let results = &sarif["runs"][0]["results"];
+    assert_eq!(results[0]["ruleId"], "OHX-E002");
+    assert_eq!(results[0]["locations"][0]["physicalLocation"]["region"]["startColumn"], 9);
+    assert_eq!(results[1]["suppressions"][0]["justification"], "Internal addon");
+}
@@ -0,0 +1,65 @@
+//! The lint warnings of a run. Warnings that an `# ohx-allow:` annotation of addons.yml covers are only logged
+//! with `-v`. `--max-warnings` fails the run if more warnings remain, `--strict` if any remain.
+use crate::dto::lint::Suppression;
+use crate::dto::schema::ValidationIssue;
+use crate::error_codes;
+use log::{error, info, warn};
+use std::cell::RefCell;
+
+#[derive(Default)]
+struct Warnings {
+    suppressions: Vec<Suppression>,
+    reported: usize,
+}
+
+thread_local! {
+    static WARNINGS: RefCell<Warnings> = RefCell::new(Warnings::default());
+}
+
+/// Starts counting for a validated addons.yml file with its annotations
+pub(crate) fn reset(suppressions: Vec<Suppression>) {
+    WARNINGS.with(|w| *w.borrow_mut() = Warnings { suppressions, reported: 0 });
+}
+
+/// Logs the warning of a rule, unless it is suppressed. Image warnings have no path.
+pub(crate) fn report(rule: &'static str, path: &str, message: &str) {
+    WARNINGS.with(|w| {
+        let mut w = w.borrow_mut();
+        match w.suppressions.iter().find(|s| s.matches(rule, path)) {
+            Some(suppression) => info!("Suppressed [{}] {} ({})", rule, message, suppression.justification),
+            None => {
+                w.reported += 1;
+                warn!("[{}] {}", rule, message);
+            }
+        }
+    });
+}
+
+/// A lint warning of the addons.yml file. The validation already applied the annotations.
+pub(crate) fn report_issue(file: &str, issue: &ValidationIssue) {
+    match &issue.suppressed {
+        Some(justification) => info!("Suppressed {}:{} ({})", file, issue, justification),
+        None => {
+            WARNINGS.with(|w| w.borrow_mut().reported += 1);
+            warn!("{}:{}", file, issue);
+        }
+    }
+}
+
+/// False if more than `max_warnings` warnings were logged, or any with `strict`
+pub(crate) fn within_budget(max_warnings: Option<usize>, strict: bool) -> bool {
+    let reported = WARNINGS.with(|w| w.borrow().reported);
+    if strict && reported > 0 {
+        error!("{} {} warnings. --strict allows none. Fix them or annotate accepted ones with '# ohx-allow: <rule> \"<justification>\"'.",
+               error_codes::TOO_MANY_WARNINGS, reported);
+        return false;
+    }
+    match max_warnings {
+        Some(max) if reported > max => {
+            error!("{} {} warnings, but only {} are allowed. Fix them or annotate accepted ones with '# ohx-allow: <rule> \"<justification>\"'.",
+                   error_codes::TOO_MANY_WARNINGS, reported, max);
+            false
+        }
+        _ => true,
+    }
+}
@@ -0,0 +1,131 @@
+//! Packaging of WASM services: compiles and validates the module and pushes it as OCI artifact.
+//! Modules are validated with the addon and compiled with the images, so that nothing is pushed for an invalid module.
+use crate::endpoints;
+use crate::error_codes;
+use crate::dto::addons::{AddonFileEntry, ServiceKind, WasmModule};
+use crate::docker_registry;
+use crate::oras;
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+pub(crate) const WASM_CONFIG_MEDIA_TYPE: &str = "application/vnd.wasm.config.v1+json";
+pub(crate) const WASM_LAYER_MEDIA_TYPE: &str = "application/vnd.wasm.content.layer.v1+wasm";
+
+/// Returns true if the addon has at least one wasm service
+pub(crate) fn has_wasm_services(input_file: &AddonFileEntry) -> bool {
+    input_file.services.values().any(|s| s.kind == ServiceKind::Wasm)
+}
+
+/// The OCI artifact reference of a wasm service
+pub(crate) fn artifact_reference(input_file: &AddonFileEntry, service_id: &str) -> String {
+    let registry = &input_file.x_ohx_registry;
+    endpoints::image_name(&format!("{}_{}_wasm", &registry.id, service_id), &registry.version)
+}
+
+/// The artifact references of all wasm services, without building or pushing anything
+pub(crate) fn artifact_references(input_file: &AddonFileEntry) -> BTreeMap<String, String> {
+    input_file.services.iter()
+        .filter(|(_, s)| s.kind == ServiceKind::Wasm)
+        .map(|(id, _)| (id.clone(), artifact_reference(input_file, id)))
+        .collect()
+}
+
+/// Checks the wasm magic number and version (`\0asm` followed by version 1)
+fn validate_module(path: &Path) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    if data.len() < 8 || &data[0..4] != b"\0asm" {
+        return Err(format!("{} is not a WebAssembly module", path.display()));
+    }
+    if data[4..8] != [1, 0, 0, 0] {
+        return Err(format!("{} has an unsupported WebAssembly version", path.display()));
+    }
+    Ok(())
+}
+
+/// The wasm services with their module settings
+fn wasm_services(input_file: &AddonFileEntry) -> impl Iterator<Item = (&String, &WasmModule)> {
+    input_file.services.iter().filter_map(|(service_id, service)| match (&service.kind, &service.wasm) {
+        (ServiceKind::Wasm, Some(wasm)) => Some((service_id, wasm)),
+        _ => None,
+    })
+}
+
+fn check_module(addon_dir: &Path, service_id: &str, wasm: &WasmModule) -> Option<()> {
+    if let Err(e) = validate_module(&addon_dir.join(&wasm.module)) {
+        error!("{} Invalid wasm module for {}: {}", error_codes::WASM_INVALID, service_id, e);
+        return None;
+    }
+    Some(())
+}
+
+/// Validates the modules that are part of the addon directory. Modules with a build command do not exist before
+/// the build, [`build_modules`] validates them.
+pub(crate) fn validate_modules(input_file: &AddonFileEntry, addon_dir: &Path) -> Option<()> {
+    for (service_id, wasm) in wasm_services(input_file).filter(|(_, wasm)| wasm.build.is_none()) {
+        check_module(addon_dir, service_id, wasm)?;
+    }
+    Some(())
+}
+
+/// Compiles the modules that have a build command and validates all modules
+pub(crate) fn build_modules(input_file: &AddonFileEntry, addon_dir: &Path) -> Option<()> {
+    for (service_id, wasm) in wasm_services(input_file) {
+        if let Some(build) = &wasm.build {
+            info!("Compiling wasm module of {}: {}", service_id, build);
+            let status = Command::new("sh").arg("-c").arg(build).current_dir(addon_dir).status();
+            if !status.map(|s| s.success()).unwrap_or(false) {
+                error!("{} Failed to compile the wasm module of {}", error_codes::WASM_INVALID, service_id);
+                return None;
+            }
+        }
+        check_module(addon_dir, service_id, wasm)?;
+    }
+    Some(())
+}
+
+/// Pushes each module, as build by [`build_modules`], as OCI artifact.
+/// Returns the artifact reference per service id. Artifacts are tagged with the `sandbox` id, if given.
+pub(crate) fn push_modules(input_file: &AddonFileEntry, addon_dir: &Path, build_directory: &Path,
+                           docker_credentials: &str, sandbox: Option<&str>) -> Option<BTreeMap<String, String>> {
+    let mut artifacts = BTreeMap::new();
+
+    for (service_id, wasm) in wasm_services(input_file) {
+        let module = addon_dir.join(&wasm.module);
+        let config = build_directory.join(format!("wasm_config_{}.json", service_id));
+        let written = std::fs::create_dir_all(build_directory)
+            .and_then(|_| std::fs::write(&config, br#"{"architecture":"wasm","os":"wasi"}"#));
+        if let Err(e) = written {
+            error!("{} Failed to write the wasm artifact config to {}: {:?}", error_codes::FILE_ACCESS, config.display(), e);
+            return None;
+        }
+
+        let mut reference = artifact_reference(input_file, service_id);
+        if let Some(sandbox) = sandbox {
+            reference = docker_registry::sandbox_reference(&reference, sandbox);
+        }
+        if !push_artifact(&reference, &module, &config, docker_credentials) {
+            return None;
+        }
+        artifacts.insert(service_id.clone(), reference);
+    }
+    Some(artifacts)
+}
+
+fn push_artifact(reference: &str, module: &Path, config: &Path, docker_credentials: &str) -> bool {
+    let args = vec![
+        "push".to_owned(),
+        "--config".to_owned(),
+        format!("{}:{}", config.display(), WASM_CONFIG_MEDIA_TYPE),
+        reference.to_owned(),
+        format!("{}:{}", module.display(), WASM_LAYER_MEDIA_TYPE),
+    ];
+    if oras::oras(&args, docker_credentials) {
+        info!("Pushed wasm module {}", reference);
+        true
+    } else {
+        error!("{} Failed to push wasm module {}", error_codes::PUSH_FAILED, reference);
+        false
+    }
+}
@@ -0,0 +1,63 @@
+//! The `watch` subcommand: Runs a callback whenever a file of the addon directory changes.
+use crate::error_codes;
+use log::{debug, error};
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+/// The changed path of an event. Notices are followed by a debounced event and ignored.
+fn changed_path(event: DebouncedEvent) -> Option<PathBuf> {
+    match event {
+        DebouncedEvent::Create(path) | DebouncedEvent::Write(path) | DebouncedEvent::Chmod(path)
+        | DebouncedEvent::Remove(path) | DebouncedEvent::Rename(_, path) => Some(path),
+        DebouncedEvent::Rescan => Some(PathBuf::new()),
+        DebouncedEvent::Error(e, path) => {
+            error!("{} Watching failed for {:?}: {:?}", error_codes::WATCH, path, e);
+            None
+        }
+        DebouncedEvent::NoticeWrite(_) | DebouncedEvent::NoticeRemove(_) => None,
+    }
+}
+
+/// Watches `dir` recursively and calls `on_change` after each change. Changes below `ignored`
+/// directories, for example the build directory, and in .git are not reported. Only returns on errors.
+pub(crate) fn watch(dir: &Path, ignored: &[PathBuf], mut on_change: impl FnMut()) -> bool {
+    let ignored: Vec<PathBuf> = ignored.iter().filter_map(|p| p.canonicalize().ok()).collect();
+    let (tx, rx) = channel();
+    let mut watcher = match watcher(tx, Duration::from_secs(1)) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{} Failed to start watching: {:?}", error_codes::WATCH, e);
+            return false;
+        }
+    };
+    if let Err(e) = watcher.watch(dir, RecursiveMode::Recursive) {
+        error!("{} Failed to watch {}: {:?}", error_codes::WATCH, dir.display(), e);
+        return false;
+    }
+
+    on_change();
+    println!("\nWatching {} for changes. Press Ctrl+C to stop.", dir.display());
+    loop {
+        let event = match rx.recv() {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{} Watching stopped: {:?}", error_codes::WATCH, e);
+                return false;
+            }
+        };
+        let path = match changed_path(event) {
+            Some(v) => v,
+            None => continue,
+        };
+        if path.components().any(|c| c.as_os_str() == ".git") || ignored.iter().any(|i| path.starts_with(i)) {
+            continue;
+        }
+        debug!("Changed: {}", path.display());
+        // Further events of the same change are dropped, the callback sees the final state
+        while rx.try_recv().is_ok() {}
+        on_change();
+        println!("\nWatching {} for changes. Press Ctrl+C to stop.", dir.display());
+    }
+}
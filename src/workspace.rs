@@ -0,0 +1,141 @@
+//! `workspace build|publish`: All addons of a repository in one run. Each directory below the root with an
+//! addon description file is an addon. Every addon is validated and build, published are only those
+//! whose version is not in the registry yet. One failing addon does not stop the others.
+use crate::dto::addons::AddonEntryMap;
+use crate::error_codes;
+use crate::input_discovery;
+use crate::output;
+use log::error;
+use prettytable::{cell, row, Table};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Directories that never contain addons: Build directories, dependencies and hidden directories like .git
+const SKIPPED_DIRECTORIES: [&str; 3] = ["out", "target", "node_modules"];
+
+fn skipped(name: &str) -> bool {
+    name.starts_with('.') || SKIPPED_DIRECTORIES.contains(&name)
+}
+
+fn collect(directory: &Path, files: &mut Vec<PathBuf>, ambiguous: &mut Vec<PathBuf>) {
+    let found = input_discovery::files_in(directory);
+    match found.len() {
+        0 => {}
+        1 => files.push(found[0].clone()),
+        _ => ambiguous.extend(found),
+    }
+    let mut children: Vec<PathBuf> = directory.read_dir().into_iter().flatten().filter_map(|e| e.ok())
+        .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+        .filter(|e| !skipped(&e.file_name().to_string_lossy()))
+        .map(|e| e.path())
+        .collect();
+    children.sort();
+    for child in children {
+        collect(&child, files, ambiguous);
+    }
+}
+
+/// The addon description files below the root, sorted by directory.
+/// None and an error if there are none, or if a directory has several.
+pub(crate) fn discover(root: &Path) -> Option<Vec<PathBuf>> {
+    let (mut files, mut ambiguous) = (Vec::new(), Vec::new());
+    collect(root, &mut files, &mut ambiguous);
+    if !ambiguous.is_empty() {
+        let listing: Vec<String> = ambiguous.iter().map(|f| format!("  {}", f.display())).collect();
+        error!("{} Several addon description files in one directory:\n{}", error_codes::INPUT_FILE_AMBIGUOUS, listing.join("\n"));
+        return None;
+    }
+    if files.is_empty() {
+        error!("{} No addon description file below {}", error_codes::INPUT_FILE_NOT_FOUND, root.display());
+        return None;
+    }
+    Some(files)
+}
+
+/// The build directory of one addon, so that the addons do not share their build state: `out/<addon directory>`
+pub(crate) fn build_directory(build_directory: &Path, root: &Path, input_file: &Path) -> PathBuf {
+    let directory = input_file.parent().unwrap_or_else(|| Path::new(""));
+    let relative = directory.strip_prefix(root).unwrap_or(directory);
+    match relative.as_os_str().is_empty() {
+        true => build_directory.join("root"),
+        false => build_directory.join(relative.to_string_lossy().replace(std::path::MAIN_SEPARATOR, "_")),
+    }
+}
+
+/// True if the registry has no entry for the addon or a different version
+pub(crate) fn is_unpublished(index: &AddonEntryMap, id: &str, version: &str) -> bool {
+    index.get(id).map(|entry| entry.entry.version != version).unwrap_or(true)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Status {
+    Built,
+    Published,
+    /// The version is already published
+    Unchanged,
+    Failed,
+}
+
+impl Status {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Status::Built => "built",
+            Status::Published => "published",
+            Status::Unchanged => "unchanged",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Outcome {
+    pub(crate) input_file: String,
+    /// None if the description is not valid
+    pub(crate) id: Option<String>,
+    pub(crate) version: Option<String>,
+    pub(crate) status: Status,
+}
+
+/// Prints a table of all addons, or a JSON document with `--output json`. Returns true if no addon failed.
+pub(crate) fn print_summary(outcomes: &[Outcome]) -> bool {
+    let success = outcomes.iter().all(|o| o.status != Status::Failed);
+    if output::is_json() {
+        let document = serde_json::json!({ "success": success, "addons": outcomes });
+        println!("{}", serde_json::to_string_pretty(&document).expect("serializable summary"));
+        return success;
+    }
+    let mut table = Table::new();
+    table.add_row(row!["File", "Addon", "Version", "Result"]);
+    for outcome in outcomes {
+        let id = outcome.id.as_deref().unwrap_or("-");
+        let version = outcome.version.as_deref().unwrap_or("-");
+        let (file, status) = (&outcome.input_file, outcome.status.name());
+        table.add_row(row![file, id, version, status]);
+    }
+    output::line("");
+    table.printstd();
+    let failed = outcomes.iter().filter(|o| o.status == Status::Failed).count();
+    output::line(&format!("{} addons, {} failed", outcomes.len(), failed));
+    success
+}
+
+#[test]
+fn workspace_test() {
+    let root = crate::namespace::user_directory().join("workspace-test");
+    let _ = std::fs::remove_dir_all(&root);
+    for directory in &["mqtt", "zigbee/bridge", "out/mqtt", ".git"] {
+        std::fs::create_dir_all(root.join(directory)).unwrap();
+        std::fs::write(root.join(directory).join("addons.yml"), "").unwrap();
+    }
+    std::fs::write(root.join("zigbee").join("common.yml"), "").unwrap();
+    let files = discover(&root).unwrap();
+    assert_eq!(files, vec![root.join("mqtt").join("addons.yml"), root.join("zigbee").join("bridge").join("addons.yml")]);
+    assert_eq!(build_directory(Path::new("out"), &root, &files[1]), Path::new("out").join("zigbee_bridge"));
+
+    std::fs::write(root.join("mqtt").join("addons.yaml"), "").unwrap();
+    assert!(discover(&root).is_none());
+    let _ = std::fs::remove_dir_all(&root);
+
+    assert!(is_unpublished(&AddonEntryMap::new(), "mqtt", "1.0.0"));
+}